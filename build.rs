@@ -7,11 +7,9 @@ use std::io::Write;
 /*
     NOTE:
 
-    This file is not needed. The only purpose this serves is to fix build errors caused by shaderc.
-    More info here: https://github.com/google/shaderc-rs/issues/41
-
-    However, the end user would not really need to compile shaders (they are baked into executable anyway).
-    So, keeping this as-is seems like the best solution for now.
+    Compiles every shader under ./shaders to a `.spv` file next to it, which the source embeds with
+    `include_bytes!`. The `.spv` files are generated on each build and not committed, so they can't
+    go stale. shaderc may need its library path set: https://github.com/google/shaderc-rs/issues/41
 */
 
 // TODO: This doesn't seem to work. Windows path variable is still required.
@@ -34,7 +32,8 @@ fn compile_and_save_shader(shader_path: &path::Path, compiler: &mut shaderc::Com
     let spirv = compiler.compile_into_spirv(
         &source_text, 
         shader_type, 
-        shader_path.file_name().unwrap().to_str().unwrap(), 
+        // Full path so includes resolve relative to the shader
+        shader_path.to_str().unwrap(), 
         "main", 
         options,
     ).unwrap();
@@ -60,6 +59,7 @@ fn compile_shaders<P: AsRef<path::Path>>(path: P, compiler: &mut shaderc::Compil
                 "hlsl" => {
                     let mut options = shaderc::CompileOptions::new().unwrap();
                     options.set_source_language(shaderc::SourceLanguage::HLSL);
+                    options.set_include_callback(resolve_include);
 
                     let file_name = entry_path.file_name().unwrap().to_str().unwrap();
 
@@ -67,8 +67,10 @@ fn compile_shaders<P: AsRef<path::Path>>(path: P, compiler: &mut shaderc::Compil
                         shaderc::ShaderKind::Fragment
                     } else if file_name.contains("vert") {
                         shaderc::ShaderKind::Vertex
+                    } else if file_name.contains("comp") {
+                        shaderc::ShaderKind::Compute
                     } else {
-                        panic!("Could not determine hlsl shader type. Put 'frag' or 'vert' somewhere in the file name: {:?}", entry_path);
+                        panic!("Could not determine hlsl shader type. Put 'frag', 'vert' or 'comp' somewhere in the file name: {:?}", entry_path);
                     };
 
                     compile_and_save_shader(entry_path.as_path(), compiler, shader_type, Some(&options));
@@ -90,10 +92,29 @@ fn compile_shaders<P: AsRef<path::Path>>(path: P, compiler: &mut shaderc::Compil
                     compile_and_save_shader(entry_path.as_path(), compiler, shaderc::ShaderKind::Compute, None);
                 }
 
+                // Included by other shaders (see `resolve_include`)
+                "hlsli" => {
+                    // Nothing to do
+                }
+
                 _ => {
                     // panic!("Unrecognized shader type at {:?}", entry_path);
                 }
             }
         }
     }
-}
\ No newline at end of file
+}
+
+/// Resolves `#include "file"` relative to the including shader
+fn resolve_include(requested: &str, _include_type: shaderc::IncludeType, requesting: &str, _depth: usize) -> shaderc::IncludeCallbackResult {
+    let requesting_directory = path::Path::new(requesting).parent().unwrap_or_else(|| path::Path::new(""));
+    let resolved_path = requesting_directory.join(requested);
+
+    let content = fs::read_to_string(&resolved_path)
+        .map_err(|e| format!("Failed to include {:?}: {}", resolved_path, e))?;
+
+    Ok(shaderc::ResolvedInclude {
+        resolved_name: resolved_path.to_string_lossy().into_owned(),
+        content,
+    })
+}
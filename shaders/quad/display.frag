@@ -0,0 +1,71 @@
+#version 450
+
+// Displays the ray tracer's linear accumulation buffer, or its sample counts
+
+layout(location = 0) in vec2 v_tex_coords;
+
+layout(set = 0, binding = 0) uniform texture2D u_texture;
+layout(set = 0, binding = 1) uniform sampler u_sampler;
+
+layout(set = 1, binding = 0)
+uniform DisplayUniforms {
+    float exposure;         // Exposure in stops
+    uint tonemapper;        // See `Tonemapper` in display.rs
+    uint view;              // See `DisplayView` in display.rs
+    float uniform_samples;  // Samples per pixel without adaptive sampling
+};
+
+layout(location = 0) out vec4 out_color;
+
+#define TONEMAP_NONE 0
+#define TONEMAP_REINHARD 1
+#define TONEMAP_ACES 2
+
+#define VIEW_COLOR 0
+#define VIEW_SAMPLE_HEATMAP 1
+
+// Blue through green to red
+vec3 heatmap(float t) {
+    return clamp(vec3(1.5 - abs(4. * t - 3.), 1.5 - abs(4. * t - 2.), 1.5 - abs(4. * t - 1.)), 0., 1.);
+}
+
+// Narkowicz's fit of the ACES filmic curve
+vec3 aces_filmic(vec3 x) {
+    return clamp((x * (2.51 * x + 0.03)) / (x * (2.43 * x + 0.59) + 0.14), 0., 1.);
+}
+
+vec3 linear_to_srgb(vec3 color) {
+    vec3 low = color * 12.92;
+    vec3 high = 1.055 * pow(color, vec3(1. / 2.4)) - 0.055;
+    return mix(high, low, lessThanEqual(color, vec3(0.0031308)));
+}
+
+void main() {
+    // Accumulation rows start at the top of the window
+    vec2 tex_coords = vec2(v_tex_coords.x, 1. - v_tex_coords.y);
+    vec4 accumulated = texture(sampler2D(u_texture, u_sampler), tex_coords);
+
+    if (view == VIEW_SAMPLE_HEATMAP) {
+        // Log scale from a quarter to four times the uniform sample count
+        float ratio = max(accumulated.a, 1.) / uniform_samples;
+        out_color = vec4(heatmap(clamp(log2(ratio) / 4. + 0.5, 0., 1.)), 1.);
+        return;
+    }
+
+    // Alpha holds the number of accumulated samples
+    vec3 color = accumulated.rgb / max(accumulated.a, 1.);
+    color *= exp2(exposure);
+
+    switch (tonemapper) {
+        case TONEMAP_REINHARD:
+            color = color / (1. + color);
+            break;
+        case TONEMAP_ACES:
+            color = aces_filmic(color);
+            break;
+        default:
+            break;
+    }
+
+    out_color = vec4(linear_to_srgb(clamp(color, 0., 1.)), 1.);
+}
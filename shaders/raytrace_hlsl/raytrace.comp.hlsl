@@ -0,0 +1,13 @@
+// Compute backend: one invocation per pixel, dispatched in 8x8 work groups (see raytrace.rs)
+#include "raytrace.hlsli"
+
+[numthreads(8, 8, 1)]
+void main(uint3 thread_id : SV_DispatchThreadID) {
+    // The dispatch is rounded up to whole work groups
+    if (any(thread_id.xy >= uint2(window_size))) {
+        return;
+    }
+
+    // Sample at pixel centers like SV_POSITION, so both backends render identical images
+    render_pixel(float2(thread_id.xy) + 0.5);
+}
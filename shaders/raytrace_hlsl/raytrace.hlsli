@@ -0,0 +1,2743 @@
+/*
+   Interfaces don't work in glslang's hlsl to spirv
+*/
+
+#define dot2(x) dot(x, x)
+
+// Linear color accumulator for multi-sample averaging (rgb = sum, a = sample count)
+layout(set = 0, binding = 0) RWTexture2D<float4> storage_image;
+// Luminance of each pixel's samples for adaptive sampling and the denoiser
+// (r = mean, g = variance, b = samples they cover, a = relative error of the mean)
+layout(set = 0, binding = 1) RWTexture2D<float4> sample_statistics;
+// Pixels left above `adaptive_threshold` by this frame. Copied into `active_pixels` for the next one.
+layout(set = 0, binding = 2) RWStructuredBuffer<uint> active_pixel_count;
+// First hit features for the denoiser, summed like the color (rgb = albedo, a = depth; rgb = normal,
+// a = samples they cover, which restarts from 0 on resume unlike the color's)
+layout(set = 0, binding = 3) RWTexture2D<float4> feature_albedo_depth;
+layout(set = 0, binding = 4) RWTexture2D<float4> feature_normal;
+
+// Raytracer parameters/inputs
+layout(set = 1, binding = 0)
+cbuffer Uniforms {
+    // Explicit offsets for debugging
+    /* layout(offset = 0)  */ float2 window_size;     // Render dimensions, independent of the window
+    /* layout(offset = 8)  */ uint sample_number;     // The current sample number (starting at 1)
+    /* layout(offset = 12) */ uint samples_per_pixel; // Rays fired per pixel
+    /* layout(offset = 16) */ uint max_ray_bounces;   // Max bounces per ray (path length)
+    /* layout(offset = 20) */ float v_fov;            // Vertical field of view
+
+    /* layout(offset = 32) */ float3 camera_position; // Camera location (look from)
+    /* layout(offset = 48) */ float3 camera_lookat;   // Camera lookat position
+    /* layout(offset = 60) */ uint num_spheres;       // Number of spheres in the scene buffer
+
+    /* layout(offset = 64) */ float3 sky_horizon;     // Sky color looking sideways
+    /* layout(offset = 76) */ uint num_triangles;     // Number of triangles in the scene buffer
+    /* layout(offset = 80) */ float3 sky_zenith;      // Sky color looking straight up
+    /* layout(offset = 92) */ uint num_lights;        // Number of directly sampled lights
+
+    /* layout(offset = 96) */  float environment_intensity; // Environment map radiance scale
+    /* layout(offset = 100) */ float environment_rotation;  // Environment map rotation about y (radians)
+    /* layout(offset = 104) */ uint has_environment;        // Environment map replaces the sky gradient
+    /* layout(offset = 108) */ float adaptive_threshold;    // Relative error pixels stop sampling at (0 disables)
+    /* layout(offset = 112) */ uint active_pixels;          // Pixels still sampling (0 if not counted yet)
+    /* layout(offset = 116) */ uint frame_number;           // Frames rendered so far (starting at 1), unlike
+                                                            // `sample_number` not restarted by reprojection
+    /* layout(offset = 120) */ uint num_planes;             // Number of planes in the scene buffer
+    /* layout(offset = 124) */ float shutter_open;          // Camera rays are fired at random times
+    /* layout(offset = 128) */ float shutter_close;         // in between, blurring moving shapes
+};
+
+
+// TODO: For fake inerfaces, inherit from a base class which has:
+//       One member variable to identify which super-type (like tagged union)
+//       The pre-implemented methods would then call a function defined elsewhere
+//       using a switch-case on the super-type.
+//       This would allow for the likes of storing different shapes in the same array
+//       TODO: Try implementing this idea for Material and see how it works.
+
+// TODO: To fix the Material/HitRecord issue, try defining each in their own header, then have each import the other's header.
+
+
+const float PI = 3.141592;
+const float FAR_PLANE_DIST = 10000.0;
+
+
+/********** Random Number Generation **********/
+
+// Hashes translated from Dave_Hoskins shader: https://www.shadertoy.com/view/4djSRW
+float hash11(float p) {
+    p = frac(p * 0.1031);
+    p *= p + 33.33;
+    p *= p + p;
+    return frac(p);
+}
+float hash12(float2 p) {
+    float3 p3 = frac(float3(p.xyx) * 0.1031);
+    p3 += dot(p3, p3.yzx + 33.33);
+    return frac((p3.x + p3.y) * p3.z);
+}
+
+static float2 rand_state;
+// Random float on [0, 1)
+float random() {
+    rand_state.x = hash12(rand_state * 79.1233);
+    rand_state.y = hash12(rand_state * 173.9);
+
+    return rand_state.x;
+}
+// Random float on [min, max)
+float rand_range(float _min, float _max) {
+    return _min + (_max - _min) * random();
+}
+
+// Translated from https://www.shadertoy.com/view/lssBD7
+float3 random_in_unit_sphere() {
+    float phi = 2 * PI * random();
+    float cos_theta = 2 * random() - 1;
+    float u = random();
+
+    float theta = acos(cos_theta);
+    float r = pow(u, 1./3.);
+
+    float x = r * sin(theta) * cos(phi);
+    float y = r * sin(theta) * sin(phi);
+    float z = r * cos_theta;
+
+    return float3(x, y, z);
+}
+
+float3 random_unit_vector() {
+    float a = rand_range(0, 2*PI);
+    float z = rand_range(-1, 1);
+    float r = sqrt(1 - z*z);
+    return float3(r*cos(a), r*sin(a), z);
+}
+
+float3 random_in_hemisphere(float3 normal) {
+    float3 in_unit_sphere = random_in_unit_sphere();
+
+    if (dot(in_unit_sphere, normal) > 0.0) {
+        return in_unit_sphere;
+    } else {
+        return -in_unit_sphere;
+    }
+}
+
+// From https://www.shadertoy.com/view/MtycDD
+float2 random_in_unit_disk() {
+    random();
+    float2 h = rand_state * float2(1, 2*PI);
+    float phi = h.y;
+    float r = sqrt(h.x);
+    return r * float2(sin(phi), cos(phi));
+}
+
+/********** Ray **********/
+// Classes & interfaces in HLSL put GLSL to shame
+class Ray {
+    float3 origin;
+    float3 direction;
+    float time; // When the ray was fired, for moving shapes
+
+    float3 position(float t) {
+        return origin + t*direction;
+    }
+};
+
+
+// Orthonormal basis around `w`
+void create_basis(float3 w, out float3 u, out float3 v) {
+    float3 a = (abs(w.x) > 0.9) ? float3(0, 1, 0) : float3(1, 0, 0);
+    v = normalize(cross(w, a));
+    u = cross(w, v);
+}
+
+
+/********** Materials **********/
+
+// NOTE: Must match scene.rs. Emissive comes last, every type before it has a wavefront shading queue.
+#define MAT_METAL 1
+#define MAT_LAMBERTIAN 2
+#define MAT_DIELECTRIC 3
+#define MAT_CONDUCTOR 4
+#define MAT_ROUGH_DIELECTRIC 5
+#define MAT_EMISSIVE 6
+
+// NOTE: Layout must match `GpuMaterial` in scene.rs
+struct Material {
+    float3 albedo; // Emitted radiance for emissive materials, tint for conductors
+    uint type;
+    float3 conductor_eta; // Complex index of refraction of conductors
+    float metalic_fuzz;
+    float3 conductor_k;
+    float dielectric_index_of_refraction;
+    float alpha;          // GGX roughness of conductors and rough dielectrics
+};
+
+float schlick_approx(float cosine, float index_of_refraction) {
+    float r0 = (1 - index_of_refraction) / (1 + index_of_refraction);
+    r0 = r0 * r0;
+    return r0 + (1 - r0) * pow((1 - cosine), 5);
+}
+
+// Unpolarized reflectance of a dielectric boundary. `eta` is the index of refraction on the far side
+// relative to the near side, `cos_i` the cosine on the near side.
+float fresnel_dielectric(float cos_i, float eta) {
+    float sin2_t = (1 - cos_i * cos_i) / (eta * eta);
+    // Total internal reflection
+    if (sin2_t >= 1) {
+        return 1;
+    }
+    float cos_t = sqrt(1 - sin2_t);
+
+    float r_s = (cos_i - eta * cos_t) / (cos_i + eta * cos_t);
+    float r_p = (eta * cos_i - cos_t) / (eta * cos_i + cos_t);
+    return 0.5 * (r_s * r_s + r_p * r_p);
+}
+
+// Unpolarized reflectance of a conductor with complex index of refraction `eta + ik`, seen from air
+float3 fresnel_conductor(float cos_i, float3 eta, float3 k) {
+    float cos2 = cos_i * cos_i;
+    float sin2 = 1 - cos2;
+
+    float3 t0 = eta * eta - k * k - sin2;
+    float3 a2_plus_b2 = sqrt(t0 * t0 + 4 * eta * eta * k * k);
+    float3 a = sqrt(0.5 * (a2_plus_b2 + t0));
+
+    float3 t1 = a2_plus_b2 + cos2;
+    float3 t2 = 2 * cos_i * a;
+    float3 r_s = (t1 - t2) / (t1 + t2);
+
+    float3 t3 = cos2 * a2_plus_b2 + sin2 * sin2;
+    float3 t4 = t2 * sin2;
+    float3 r_p = r_s * (t3 - t4) / (t3 + t4);
+
+    return 0.5 * (r_s + r_p);
+}
+
+/********** GGX Microfacets **********/
+// Directions are in the shading frame (normal along z) and point away from the surface
+
+// Distribution of microfacet normals `m`
+float ggx_d(float3 m, float alpha) {
+    float alpha2 = alpha * alpha;
+    float t = m.z * m.z * (alpha2 - 1) + 1;
+    return alpha2 / (PI * t * t);
+}
+
+// Smith's auxiliary function
+float ggx_lambda(float3 w, float alpha) {
+    float cos2 = w.z * w.z;
+    float tan2 = max(0, 1 - cos2) / cos2;
+    return (sqrt(1 + alpha * alpha * tan2) - 1) / 2;
+}
+
+// Fraction of microfacets facing `w` that are visible from it
+float ggx_g1(float3 w, float alpha) {
+    return 1 / (1 + ggx_lambda(w, alpha));
+}
+
+// Height-correlated masking and shadowing
+float ggx_g2(float3 wo, float3 wi, float alpha) {
+    return 1 / (1 + ggx_lambda(wo, alpha) + ggx_lambda(wi, alpha));
+}
+
+// Samples a microfacet normal visible from `wo` (Heitz 2018, "Sampling the GGX Distribution of Visible Normals")
+float3 sample_ggx_vndf(float3 wo, float alpha) {
+    // Stretch to a hemisphere configuration
+    float3 v_h = normalize(float3(alpha * wo.x, alpha * wo.y, wo.z));
+
+    float length2 = v_h.x * v_h.x + v_h.y * v_h.y;
+    float3 t1 = length2 > 0 ? float3(-v_h.y, v_h.x, 0) / sqrt(length2) : float3(1, 0, 0);
+    float3 t2 = cross(v_h, t1);
+
+    // Point on the projected hemisphere
+    float r = sqrt(random());
+    float phi = 2 * PI * random();
+    float p1 = r * cos(phi);
+    float p2 = r * sin(phi);
+    float s = 0.5 * (1 + v_h.z);
+    p2 = (1 - s) * sqrt(1 - p1 * p1) + s * p2;
+
+    float3 n_h = p1 * t1 + p2 * t2 + sqrt(max(0, 1 - p1 * p1 - p2 * p2)) * v_h;
+
+    // Unstretch
+    return normalize(float3(alpha * n_h.x, alpha * n_h.y, max(1e-6, n_h.z)));
+}
+
+// Density of `sample_ggx_vndf` returning `m`
+float ggx_vndf_pdf(float3 wo, float3 m, float alpha) {
+    return ggx_g1(wo, alpha) * max(0, dot(wo, m)) * ggx_d(m, alpha) / wo.z;
+}
+
+
+
+/********** Interfaces **********/
+
+struct HitRecord {
+    float3 position;
+    float3 normal;
+    float distance;
+    bool is_front_face;
+    uint material_index;
+    uint primitive; // Encoded primitive reference (see bvh.rs)
+    float2 uv;      // Surface coordinates, for texturing
+
+    void set_face_normal(Ray ray, float3 outward_normal) {
+        is_front_face = dot(ray.direction, outward_normal) < 0;
+        normal = is_front_face ? outward_normal : -outward_normal;
+    }
+};
+
+// Static methods don't work either.....
+namespace Material_ {
+    Material create_metal(float3 albedo, float metalic_fuzz) {
+        Material mat = {albedo, MAT_METAL, float3(0), metalic_fuzz, float3(0), 0, 0}; 
+        return mat;
+    }
+
+    Material create_lambertian(float3 albedo) {
+        Material mat = {albedo, MAT_LAMBERTIAN, float3(0), 0, float3(0), 0, 0}; 
+        return mat;
+    }
+
+    Material create_dielectric(float index_of_refraction) {
+        Material mat = {float3(1), MAT_DIELECTRIC, float3(0), 0, float3(0), index_of_refraction, 0};
+        return mat;
+    }
+
+    // Shading frame around the hit's normal, which faces the incoming ray
+    float3 to_local(HitRecord record, float3 direction) {
+        float3 u, v;
+        create_basis(record.normal, u, v);
+        return float3(dot(direction, u), dot(direction, v), dot(direction, record.normal));
+    }
+
+    float3 to_world(HitRecord record, float3 direction) {
+        float3 u, v;
+        create_basis(record.normal, u, v);
+        return direction.x * u + direction.y * v + direction.z * record.normal;
+    }
+
+    // Index of refraction across the surface, relative to the side the ray arrived from
+    float relative_index_of_refraction(Material material, HitRecord record) {
+        return record.is_front_face ? material.dielectric_index_of_refraction : 1 / material.dielectric_index_of_refraction;
+    }
+
+    // Materials with a BSDF `eval_bsdf` can evaluate, so lights are sampled directly.
+    // Mirror-like metal and smooth glass only scatter.
+    bool has_bsdf(Material material) {
+        return material.type == MAT_LAMBERTIAN || material.type == MAT_CONDUCTOR || material.type == MAT_ROUGH_DIELECTRIC;
+    }
+
+    // FIXME: I can't put this inside Material because of circular dependency, and 
+    // there is no struct/class forward declaration in HLSL.....
+    bool scatter_ray(Material material, Ray ray_in, HitRecord record, out float3 attenuation, out Ray scattered_ray) {
+        scattered_ray.time = ray_in.time;
+
+        switch (material.type) {
+            // Matte
+            case MAT_LAMBERTIAN: {
+                float3 scatter_direction = record.normal + random_unit_vector();
+                scattered_ray.origin = record.position;
+                scattered_ray.direction = scatter_direction;
+                
+                attenuation = material.albedo;
+                return true;
+            }
+            // Metal
+            case MAT_METAL: {
+                float3 reflected = reflect(normalize(ray_in.direction), record.normal);
+                scattered_ray.origin = record.position;
+                scattered_ray.direction = reflected + material.metalic_fuzz*random_in_unit_sphere();
+                
+                attenuation = material.albedo;
+                return dot(scattered_ray.direction, record.normal) > 0;
+            }
+            // Glass
+            case MAT_DIELECTRIC: {
+                attenuation = float3(1);
+                
+                float etai_over_etat = (record.is_front_face) ? (1/material.dielectric_index_of_refraction) : material.dielectric_index_of_refraction;
+
+                float3 unit_direction = normalize(ray_in.direction);
+
+                float cos_theta = min(dot(-unit_direction, record.normal), 1);
+                float sin_theta = sqrt(1 - cos_theta*cos_theta);
+
+                if (etai_over_etat * sin_theta > 1) {
+                    float3 reflected = reflect(unit_direction, record.normal);
+                    scattered_ray.origin = record.position;
+                    scattered_ray.direction = reflected;
+                    return true;
+                }
+                
+                float reflect_chance = schlick_approx(cos_theta, etai_over_etat);
+                if (random() < reflect_chance) {
+                    float3 reflected = reflect(unit_direction, record.normal);
+                    scattered_ray.origin = record.position;
+                    scattered_ray.direction = reflected;
+                    return true;
+                }
+
+                float3 refracted = refract(unit_direction, record.normal, etai_over_etat);
+                scattered_ray.origin = record.position;
+                scattered_ray.direction = refracted;
+                return true;
+            }
+
+            // GGX microfacet metal. Facets are sampled by visibility, so the weight is only the
+            // Fresnel reflectance and the shadowing of the reflected direction.
+            case MAT_CONDUCTOR: {
+                float3 wo = to_local(record, -normalize(ray_in.direction));
+                float3 m = sample_ggx_vndf(wo, material.alpha);
+                float3 wi = reflect(-wo, m);
+                if (wi.z <= 0) {
+                    return false;
+                }
+
+                scattered_ray.origin = record.position;
+                scattered_ray.direction = to_world(record, wi);
+
+                attenuation = material.albedo * fresnel_conductor(dot(wo, m), material.conductor_eta, material.conductor_k)
+                    * ggx_g2(wo, wi, material.alpha) / ggx_g1(wo, material.alpha);
+                return true;
+            }
+            // GGX microfacet glass. Reflects or refracts through a visible facet, chosen by its Fresnel
+            // reflectance, which cancels out of the weight.
+            case MAT_ROUGH_DIELECTRIC: {
+                float3 wo = to_local(record, -normalize(ray_in.direction));
+                float3 m = sample_ggx_vndf(wo, material.alpha);
+                float eta = relative_index_of_refraction(material, record);
+
+                float3 wi;
+                if (random() < fresnel_dielectric(dot(wo, m), eta)) {
+                    wi = reflect(-wo, m);
+                    if (wi.z <= 0) {
+                        return false;
+                    }
+                } else {
+                    wi = refract(-wo, m, 1 / eta);
+                    if (wi.z >= 0) {
+                        return false;
+                    }
+                }
+
+                scattered_ray.origin = record.position;
+                scattered_ray.direction = to_world(record, wi);
+
+                // Like smooth glass, radiance isn't scaled by the change in solid angle when refracting
+                attenuation = ggx_g2(wo, wi, material.alpha) / ggx_g1(wo, material.alpha);
+                return true;
+            }
+
+            // Lights absorb (emission is handled by `fire_ray`)
+            case MAT_EMISSIVE: {
+                return false;
+            }
+
+            // Unreachable
+            default: return false;
+        }
+    }
+
+    // BSDF times the cosine of `wi`, for light arriving from `wi` and leaving towards `wo`. Both point
+    // away from the surface. Zero for materials without a BSDF (see `has_bsdf`).
+    float3 eval_bsdf(Material material, HitRecord record, float3 wo, float3 wi) {
+        switch (material.type) {
+            case MAT_LAMBERTIAN: {
+                return material.albedo / PI * max(dot(wi, record.normal), 0);
+            }
+            case MAT_CONDUCTOR: {
+                wo = to_local(record, wo);
+                wi = to_local(record, wi);
+                if (wo.z <= 0 || wi.z <= 0) {
+                    return 0;
+                }
+
+                float3 m = normalize(wo + wi);
+                float3 fresnel = fresnel_conductor(dot(wo, m), material.conductor_eta, material.conductor_k);
+                return material.albedo * fresnel * ggx_d(m, material.alpha) * ggx_g2(wo, wi, material.alpha) / (4 * wo.z);
+            }
+            case MAT_ROUGH_DIELECTRIC: {
+                wo = to_local(record, wo);
+                wi = to_local(record, wi);
+                if (wo.z <= 0 || wi.z == 0) {
+                    return 0;
+                }
+                float eta = relative_index_of_refraction(material, record);
+
+                // Reflection
+                if (wi.z > 0) {
+                    float3 m = normalize(wo + wi);
+                    float fresnel = fresnel_dielectric(dot(wo, m), eta);
+                    return fresnel * ggx_d(m, material.alpha) * ggx_g2(wo, wi, material.alpha) / (4 * wo.z);
+                }
+
+                // Refraction, through the facet bending `wo` into `wi`
+                float3 m = -normalize(wo + eta * wi);
+                m *= sign(m.z);
+                float cos_o = dot(wo, m);
+                float cos_i = dot(wi, m);
+                if (cos_o <= 0 || cos_i >= 0) {
+                    return 0;
+                }
+
+                float denominator = cos_o + eta * cos_i;
+                float fresnel = fresnel_dielectric(cos_o, eta);
+                return (1 - fresnel) * ggx_d(m, material.alpha) * ggx_g2(wo, wi, material.alpha)
+                    * eta * eta * cos_o * -cos_i / (wo.z * denominator * denominator);
+            }
+
+            default: return 0;
+        }
+    }
+
+    // Solid angle density of `scatter_ray` choosing `wi`, with the same conventions as `eval_bsdf`
+    float bsdf_pdf(Material material, HitRecord record, float3 wo, float3 wi) {
+        switch (material.type) {
+            case MAT_LAMBERTIAN: {
+                // Cosine weighted
+                return max(dot(wi, record.normal), 0) / PI;
+            }
+            case MAT_CONDUCTOR: {
+                wo = to_local(record, wo);
+                wi = to_local(record, wi);
+                if (wo.z <= 0 || wi.z <= 0) {
+                    return 0;
+                }
+
+                float3 m = normalize(wo + wi);
+                return ggx_vndf_pdf(wo, m, material.alpha) / (4 * dot(wo, m));
+            }
+            case MAT_ROUGH_DIELECTRIC: {
+                wo = to_local(record, wo);
+                wi = to_local(record, wi);
+                if (wo.z <= 0 || wi.z == 0) {
+                    return 0;
+                }
+                float eta = relative_index_of_refraction(material, record);
+
+                if (wi.z > 0) {
+                    float3 m = normalize(wo + wi);
+                    return fresnel_dielectric(dot(wo, m), eta) * ggx_vndf_pdf(wo, m, material.alpha) / (4 * dot(wo, m));
+                }
+
+                float3 m = -normalize(wo + eta * wi);
+                m *= sign(m.z);
+                float cos_o = dot(wo, m);
+                float cos_i = dot(wi, m);
+                if (cos_o <= 0 || cos_i >= 0) {
+                    return 0;
+                }
+
+                float denominator = cos_o + eta * cos_i;
+                return (1 - fresnel_dielectric(cos_o, eta)) * ggx_vndf_pdf(wo, m, material.alpha)
+                    * eta * eta * -cos_i / (denominator * denominator);
+            }
+
+            default: return 0;
+        }
+    }
+};
+
+
+
+// FIXME: Interfaces don't work with spirv.....
+// interface IHittable {
+//     bool intersect(Ray ray, float dist_min, float dist_max, out HitRecord record);
+// };
+
+
+/********** Shapes **********/
+
+// NOTE: Layout must match `GpuSphere` in scene.rs
+class Sphere {
+    float3 center;   // At time 0
+    float radius;
+    float3 velocity; // Distance moved per unit of time
+    uint material_index;
+
+    // Moves the sphere to where it is at `time`
+    void move_to(float time) {
+        center += time * velocity;
+    }
+
+    // Longitude and latitude, with v increasing upwards
+    float2 uv(float3 position) {
+        float3 direction = (position - center) / abs(radius);
+        return float2((atan2(-direction.z, direction.x) + PI) / (2 * PI), acos(clamp(-direction.y, -1, 1)) / PI);
+    }
+
+    // Check sphere hit using quadratic formula
+    bool intersect(Ray ray, float dist_min, float dist_max, out HitRecord record) {
+        float3 direction = ray.origin - center;
+
+        float a = dot2(ray.direction);
+        float half_b = dot(direction, ray.direction);
+        float c = dot2(direction) - radius * radius;
+        float discriminant = half_b * half_b - a * c;
+
+        if (discriminant > 0) {
+            float root = sqrt(discriminant);
+            float distance = (-half_b - root) / a;
+
+            if (distance < dist_max && distance > dist_min) {
+                record.distance = distance;
+                record.position = ray.position(distance);
+                float3 outward_normal = (record.position - center) / radius;
+                record.set_face_normal(ray, outward_normal);
+                record.uv = uv(record.position);
+
+                record.material_index = material_index;
+
+                return true;
+            }
+
+            distance = (-half_b + root) / a;
+            if (distance < dist_max && distance > dist_min) {
+                record.distance = distance;
+                record.position = ray.position(distance);
+                float3 outward_normal = (record.position - center) / radius;
+                record.set_face_normal(ray, outward_normal);
+                record.uv = uv(record.position);
+
+                record.material_index = material_index;
+
+                return true;
+            }
+        }
+
+        return false;
+    } // intersect()
+};
+
+// NOTE: Layout must match `GpuRectangle` in scene.rs
+class Rectangle {
+    float3 corner;
+    uint material_index;
+    float3 edge_u;
+    float _padding1;
+    float3 edge_v;
+    float _padding2;
+
+    float area() {
+        return length(cross(edge_u, edge_v));
+    }
+
+    // Plane intersection followed by a parallelogram bounds check (see "Ray Tracing: The Next Week")
+    bool intersect(Ray ray, float dist_min, float dist_max, out HitRecord record) {
+        float3 n = cross(edge_u, edge_v);
+        float3 normal = normalize(n);
+
+        float denominator = dot(normal, ray.direction);
+        // Ray is parallel to the plane
+        if (abs(denominator) < 1e-8) {
+            return false;
+        }
+
+        float distance = dot(corner - ray.origin, normal) / denominator;
+        if (distance >= dist_max || distance <= dist_min) {
+            return false;
+        }
+
+        float3 planar_position = ray.position(distance) - corner;
+        float3 w = n / dot2(n);
+        float alpha = dot(w, cross(planar_position, edge_v));
+        float beta = dot(w, cross(edge_u, planar_position));
+
+        if (alpha < 0 || alpha > 1 || beta < 0 || beta > 1) {
+            return false;
+        }
+
+        record.distance = distance;
+        record.position = ray.position(distance);
+        record.set_face_normal(ray, normal);
+        record.uv = float2(alpha, beta);
+
+        record.material_index = material_index;
+
+        return true;
+    } // intersect()
+};
+
+// NOTE: Layout must match `GpuPlane` in scene.rs
+class Plane {
+    float3 origin; // Any point on the plane
+    uint material_index;
+    float3 normal;
+    float _padding;
+
+    bool intersect(Ray ray, float dist_min, float dist_max, out HitRecord record) {
+        float denominator = dot(normal, ray.direction);
+        // Ray is parallel to the plane
+        if (abs(denominator) < 1e-8) {
+            return false;
+        }
+
+        float distance = dot(origin - ray.origin, normal) / denominator;
+        if (distance >= dist_max || distance <= dist_min) {
+            return false;
+        }
+
+        record.distance = distance;
+        record.position = ray.position(distance);
+        record.set_face_normal(ray, normal);
+
+        // World units along the plane, so textures repeat every unit
+        float3 u, v;
+        create_basis(normal, u, v);
+        float3 offset = record.position - origin;
+        record.uv = float2(dot(offset, u), dot(offset, v));
+
+        record.material_index = material_index;
+
+        return true;
+    } // intersect()
+};
+
+// Fraction of a turn `offset` is around `axis`, in [0, 1]
+float azimuth(float3 axis, float3 offset) {
+    float3 u, v;
+    create_basis(axis, u, v);
+    return (atan2(dot(offset, v), dot(offset, u)) + PI) / (2 * PI);
+}
+
+// NOTE: Layout must match `GpuDisk` in scene.rs
+class Disk {
+    float3 center;
+    float radius;
+    float3 normal;
+    uint material_index;
+
+    float area() {
+        return PI * radius * radius;
+    }
+
+    bool intersect(Ray ray, float dist_min, float dist_max, out HitRecord record) {
+        float denominator = dot(normal, ray.direction);
+        // Ray is parallel to the disk
+        if (abs(denominator) < 1e-8) {
+            return false;
+        }
+
+        float distance = dot(center - ray.origin, normal) / denominator;
+        if (distance >= dist_max || distance <= dist_min) {
+            return false;
+        }
+
+        float3 offset = ray.position(distance) - center;
+        float distance_squared = dot2(offset);
+        if (distance_squared > radius * radius) {
+            return false;
+        }
+
+        record.distance = distance;
+        record.position = ray.position(distance);
+        record.set_face_normal(ray, normal);
+
+        // Angle around the normal, then distance from the center
+        record.uv = float2(azimuth(normal, offset), sqrt(distance_squared) / radius);
+
+        record.material_index = material_index;
+
+        return true;
+    } // intersect()
+};
+
+// Stands in for the infinite ends of intervals
+const float UNBOUNDED = 1e30;
+
+// Stretch of a ray inside a convex shape, with the outward normals where it enters and exits.
+// Ends may be unbounded, their normals are then meaningless.
+struct Interval {
+    float enter;
+    float exit;
+    float3 enter_normal;
+    float3 exit_normal;
+
+    // Narrows this to the overlap with `other`, returning whether there is any
+    bool intersect(Interval other) {
+        if (other.enter > enter) {
+            enter = other.enter;
+            enter_normal = other.enter_normal;
+        }
+        if (other.exit < exit) {
+            exit = other.exit;
+            exit_normal = other.exit_normal;
+        }
+
+        return enter <= exit;
+    }
+
+    // Widens this to cover `other`. Only meaningful if they overlap.
+    void cover(Interval other) {
+        if (other.enter < enter) {
+            enter = other.enter;
+            enter_normal = other.enter_normal;
+        }
+        if (other.exit > exit) {
+            exit = other.exit;
+            exit_normal = other.exit_normal;
+        }
+    }
+
+    // Distance and outward normal of the first boundary within the range: the entry, or the exit if
+    // the ray starts inside
+    bool first_hit(float dist_min, float dist_max, out float distance, out float3 normal) {
+        distance = enter > dist_min ? enter : exit;
+        normal = enter > dist_min ? enter_normal : exit_normal;
+
+        return distance < dist_max && distance > dist_min;
+    }
+};
+
+namespace Interval_ {
+    Interval everything() {
+        Interval interval = {-UNBOUNDED, UNBOUNDED, float3(0), float3(0)};
+        return interval;
+    }
+
+    Interval from(float enter, float3 enter_normal) {
+        Interval interval = {enter, UNBOUNDED, enter_normal, float3(0)};
+        return interval;
+    }
+
+    Interval until(float exit, float3 exit_normal) {
+        Interval interval = {-UNBOUNDED, exit, float3(0), exit_normal};
+        return interval;
+    }
+}
+
+bool sphere_interval(float3 center, float radius, Ray ray, out Interval interval) {
+    float3 direction = ray.origin - center;
+
+    float a = dot2(ray.direction);
+    float half_b = dot(direction, ray.direction);
+    float c = dot2(direction) - radius * radius;
+    float discriminant = half_b * half_b - a * c;
+    if (discriminant < 0) {
+        return false;
+    }
+
+    float root = sqrt(discriminant);
+    interval.enter = (-half_b - root) / a;
+    interval.exit = (-half_b + root) / a;
+    interval.enter_normal = (ray.position(interval.enter) - center) / radius;
+    interval.exit_normal = (ray.position(interval.exit) - center) / radius;
+
+    return true;
+}
+
+// Rotates `v` by the unit quaternion `q` (vector part in xyz)
+float3 quaternion_rotate(float4 q, float3 v) {
+    return v + 2 * cross(q.xyz, cross(q.xyz, v) + q.w * v);
+}
+
+// Rotation by `b`, then by `a`
+float4 quaternion_multiply(float4 a, float4 b) {
+    return float4(a.w * b.xyz + b.w * a.xyz + cross(a.xyz, b.xyz), a.w * b.w - dot(a.xyz, b.xyz));
+}
+
+// Outward normal in the box's frame, and coordinates in [-1, 1] on the face, of a point on a box
+// relative to its half extents
+float3 box_face(float3 relative, out float2 face_position) {
+    // The face is the one the point is furthest out towards, relative to the box's size
+    float3 face = abs(relative);
+    if (face.x >= face.y && face.x >= face.z) {
+        face_position = relative.zy;
+        return float3(sign(relative.x), 0, 0);
+    } else if (face.y >= face.z) {
+        face_position = relative.xz;
+        return float3(0, sign(relative.y), 0);
+    } else {
+        face_position = relative.xy;
+        return float3(0, 0, sign(relative.z));
+    }
+}
+
+// NOTE: Layout must match `GpuBox` in scene.rs
+class Box {
+    float3 center;   // At time 0
+    uint material_index;
+    float3 half_extents;
+    float _padding1;
+    float4 rotation; // Local to world, at time 0
+    float3 velocity; // Distance moved per unit of time
+    float _padding2;
+    float3 angular_velocity; // Spin axis scaled by radians per unit of time
+    float _padding3;
+
+    // Moves and turns the box to where it is at `time`
+    void move_to(float time) {
+        center += time * velocity;
+
+        float angle = length(angular_velocity) * time;
+        if (angle != 0) {
+            float3 axis = normalize(angular_velocity);
+            rotation = quaternion_multiply(float4(sin(angle / 2) * axis, cos(angle / 2)), rotation);
+        }
+    }
+
+    // Slab test in the box's own frame
+    bool interval(Ray ray, out Interval result) {
+        float4 inverse_rotation = float4(-rotation.xyz, rotation.w);
+        float3 origin = quaternion_rotate(inverse_rotation, ray.origin - center);
+        float3 direction = quaternion_rotate(inverse_rotation, ray.direction);
+
+        float3 t0 = (-half_extents - origin) / direction;
+        float3 t1 = (half_extents - origin) / direction;
+        float3 t_near = min(t0, t1);
+        float3 t_far = max(t0, t1);
+
+        result.enter = max(t_near.x, max(t_near.y, t_near.z));
+        result.exit = min(t_far.x, min(t_far.y, t_far.z));
+        if (result.enter > result.exit) {
+            return false;
+        }
+
+        float2 face_position;
+        result.enter_normal = quaternion_rotate(rotation, box_face((origin + result.enter * direction) / half_extents, face_position));
+        result.exit_normal = quaternion_rotate(rotation, box_face((origin + result.exit * direction) / half_extents, face_position));
+
+        return true;
+    }
+
+    bool intersect(Ray ray, float dist_min, float dist_max, out HitRecord record) {
+        Interval box_interval;
+        float distance;
+        float3 outward_normal;
+        if (!interval(ray, box_interval) || !box_interval.first_hit(dist_min, dist_max, distance, outward_normal)) {
+            return false;
+        }
+
+        record.distance = distance;
+        record.position = ray.position(distance);
+        record.set_face_normal(ray, outward_normal);
+
+        float4 inverse_rotation = float4(-rotation.xyz, rotation.w);
+        float3 local_position = quaternion_rotate(inverse_rotation, record.position - center);
+        float2 face_position;
+        box_face(local_position / half_extents, face_position);
+        // Each face spans [0, 1]
+        record.uv = face_position * 0.5 + 0.5;
+
+        record.material_index = material_index;
+
+        return true;
+    } // intersect()
+};
+
+// Gradient of |p_perp|^2 - r(s)^2 on a cone's side, `t` along the ray (see `capped_cone_interval`)
+float3 cone_side_normal(float3 o_perp, float3 d_perp, float3 w, float r0, float r1, float slope, float t) {
+    float radius = r0 + r1 * t;
+    return normalize(o_perp + t * d_perp - radius * slope * w);
+}
+
+// Cone from `a` to `b` with its radius changing linearly between them, capped at both ends.
+// Equal radii give a cylinder.
+bool capped_cone_interval(float3 a, float3 b, float radius_a, float radius_b, Ray ray, out Interval interval) {
+    float height = length(b - a);
+    float3 w = (b - a) / height;
+    float slope = (radius_b - radius_a) / height;
+
+    float3 o = ray.origin - a;
+    float3 d = ray.direction;
+    float o_axis = dot(o, w);
+    float d_axis = dot(d, w);
+    float3 o_perp = o - o_axis * w;
+    float3 d_perp = d - d_axis * w;
+
+    // Between the caps
+    Interval caps = Interval_::everything();
+    if (abs(d_axis) < 1e-8) {
+        if (o_axis < 0 || o_axis > height) {
+            return false;
+        }
+    } else {
+        float t0 = -o_axis / d_axis;
+        float t1 = (height - o_axis) / d_axis;
+        caps.enter = min(t0, t1);
+        caps.exit = max(t0, t1);
+        caps.enter_normal = t0 < t1 ? -w : w;
+        caps.exit_normal = -caps.enter_normal;
+    }
+
+    // Inside the infinite double cone |p_perp| <= radius_a + slope * s, which is
+    // qa t^2 + 2 qb t + qc <= 0 along the ray
+    float r0 = radius_a + slope * o_axis;
+    float r1 = slope * d_axis;
+    float qa = dot2(d_perp) - r1 * r1;
+    float qb = dot(o_perp, d_perp) - r0 * r1;
+    float qc = dot2(o_perp) - r0 * r0;
+
+    interval = Interval_::everything();
+    if (abs(qa) < 1e-8 * dot2(d)) {
+        // Parallel to the cone's surface
+        if (abs(qb) < 1e-12) {
+            if (qc > 0) {
+                return false;
+            }
+        } else {
+            float t = -qc / (2 * qb);
+            float3 normal = cone_side_normal(o_perp, d_perp, w, r0, r1, slope, t);
+            if (qb > 0) {
+                interval = Interval_::until(t, normal);
+            } else {
+                interval = Interval_::from(t, normal);
+            }
+        }
+    } else {
+        float discriminant = qb * qb - qa * qc;
+        if (discriminant < 0) {
+            // Never on the surface: always outside, or (through both nappes' insides) always inside
+            if (qa > 0) {
+                return false;
+            }
+        } else {
+            float root = sqrt(discriminant);
+            float t0 = min((-qb - root) / qa, (-qb + root) / qa);
+            float t1 = max((-qb - root) / qa, (-qb + root) / qa);
+            float3 normal0 = cone_side_normal(o_perp, d_perp, w, r0, r1, slope, t0);
+            float3 normal1 = cone_side_normal(o_perp, d_perp, w, r0, r1, slope, t1);
+
+            if (qa > 0) {
+                interval.enter = t0;
+                interval.exit = t1;
+                interval.enter_normal = normal0;
+                interval.exit_normal = normal1;
+            } else {
+                // Through both nappes. Only one of them is between the caps.
+                Interval before = Interval_::until(t0, normal0);
+                Interval after = Interval_::from(t1, normal1);
+                bool is_before = before.intersect(caps);
+                bool is_after = after.intersect(caps);
+
+                if (is_before && is_after) {
+                    is_before = before.exit - before.enter > after.exit - after.enter;
+                }
+                if (is_before) {
+                    interval = before;
+                } else {
+                    interval = after;
+                }
+                return is_before || is_after;
+            }
+        }
+    }
+
+    return interval.intersect(caps);
+}
+
+// Azimuth, then distance along the axis (including the caps)
+float2 axial_uv(float3 a, float3 b, float padding, float3 position) {
+    float height = length(b - a);
+    float3 w = (b - a) / height;
+    float3 offset = position - a;
+    return float2(azimuth(w, offset), (dot(offset, w) + padding) / (height + 2 * padding));
+}
+
+// NOTE: Layout must match `GpuCylinder` in scene.rs
+class Cylinder {
+    float3 a;
+    float radius;
+    float3 b;
+    uint material_index;
+
+    bool intersect(Ray ray, float dist_min, float dist_max, out HitRecord record) {
+        Interval interval;
+        float distance;
+        float3 outward_normal;
+        if (!capped_cone_interval(a, b, radius, radius, ray, interval)
+                || !interval.first_hit(dist_min, dist_max, distance, outward_normal)) {
+            return false;
+        }
+
+        record.distance = distance;
+        record.position = ray.position(distance);
+        record.set_face_normal(ray, outward_normal);
+        record.uv = axial_uv(a, b, 0, record.position);
+
+        record.material_index = material_index;
+
+        return true;
+    } // intersect()
+};
+
+// NOTE: Layout must match `GpuCone` in scene.rs
+class Cone {
+    float3 a;
+    float radius_a;
+    float3 b;
+    float radius_b;
+    uint material_index;
+    uint _padding1;
+    uint _padding2;
+    uint _padding3;
+
+    bool intersect(Ray ray, float dist_min, float dist_max, out HitRecord record) {
+        Interval interval;
+        float distance;
+        float3 outward_normal;
+        if (!capped_cone_interval(a, b, radius_a, radius_b, ray, interval)
+                || !interval.first_hit(dist_min, dist_max, distance, outward_normal)) {
+            return false;
+        }
+
+        record.distance = distance;
+        record.position = ray.position(distance);
+        record.set_face_normal(ray, outward_normal);
+        record.uv = axial_uv(a, b, 0, record.position);
+
+        record.material_index = material_index;
+
+        return true;
+    } // intersect()
+};
+
+// NOTE: Layout must match `GpuCapsule` in scene.rs
+class Capsule {
+    float3 a;
+    float radius;
+    float3 b;
+    uint material_index;
+
+    // Points within `radius` of the segment from `a` to `b`: a cylinder and the spheres capping it
+    bool interval(Ray ray, out Interval result) {
+        // The capsule is convex, so the pieces overlap wherever the ray crosses it
+        bool is_hit = capped_cone_interval(a, b, radius, radius, ray, result);
+
+        Interval cap;
+        if (sphere_interval(a, radius, ray, cap)) {
+            if (is_hit) {
+                result.cover(cap);
+            } else {
+                result = cap;
+            }
+            is_hit = true;
+        }
+        if (sphere_interval(b, radius, ray, cap)) {
+            if (is_hit) {
+                result.cover(cap);
+            } else {
+                result = cap;
+            }
+            is_hit = true;
+        }
+
+        return is_hit;
+    }
+
+    bool intersect(Ray ray, float dist_min, float dist_max, out HitRecord record) {
+        Interval capsule_interval;
+        float distance;
+        float3 outward_normal;
+        if (!interval(ray, capsule_interval)
+                || !capsule_interval.first_hit(dist_min, dist_max, distance, outward_normal)) {
+            return false;
+        }
+
+        record.distance = distance;
+        record.position = ray.position(distance);
+        record.set_face_normal(ray, outward_normal);
+        record.uv = axial_uv(a, b, radius, record.position);
+
+        record.material_index = material_index;
+
+        return true;
+    } // intersect()
+};
+
+// Cube root that keeps the sign
+float cbrt(float x) {
+    return sign(x) * pow(abs(x), 1.0 / 3.0);
+}
+
+// Largest real root of t^3 + a t^2 + b t + c
+float largest_cubic_root(float a, float b, float c) {
+    // Depressed cubic x^3 + p x + q, with t = x - a / 3
+    float p = b - a * a / 3;
+    float q = 2 * a * a * a / 27 - a * b / 3 + c;
+
+    float discriminant = q * q / 4 + p * p * p / 27;
+    float x;
+    if (discriminant >= 0) {
+        // One real root (Cardano)
+        float root = sqrt(discriminant);
+        x = cbrt(-q / 2 + root) + cbrt(-q / 2 - root);
+    } else {
+        // Three real roots, p < 0 (trigonometric)
+        float r = sqrt(-p / 3);
+        float phi = acos(clamp(-q / (2 * r * r * r), -1, 1));
+        x = 2 * r * cos(phi / 3);
+    }
+
+    // Cardano's formula cancels badly, and quartics amplify the error (see `solve_quartic`)
+    float t = x - a / 3;
+    for (int i = 0; i < 2; i++) {
+        float f = ((t + a) * t + b) * t + c;
+        float derivative = (3 * t + 2 * a) * t + b;
+        if (derivative != 0) {
+            t -= f / derivative;
+        }
+    }
+    return t;
+}
+
+// Adds the real roots of t^2 + linear t + constant, if any, to `roots`
+void add_quadratic_roots(float linear, float constant, inout float4 roots, inout uint count) {
+    float discriminant = linear * linear / 4 - constant;
+    if (discriminant >= 0) {
+        float root = sqrt(discriminant);
+        roots[count] = -linear / 2 - root;
+        roots[count + 1] = -linear / 2 + root;
+        count += 2;
+    }
+}
+
+// Real roots of t^4 + b t^3 + c t^2 + d t + e (Ferrari), polished with Newton's method. Returns
+// their count, the roots themselves are unordered.
+uint solve_quartic(float b, float c, float d, float e, out float4 roots) {
+    // Depressed quartic y^4 + p y^2 + q y + r, with t = y - b / 4
+    float b2 = b * b;
+    float p = c - 3 * b2 / 8;
+    float q = d - b * c / 2 + b2 * b / 8;
+    float r = e - b * d / 4 + b2 * c / 16 - 3 * b2 * b2 / 256;
+
+    roots = float4(0);
+    uint count = 0;
+
+    // Ferrari's method loses all precision as q goes to zero, so nearly biquadratic quartics are
+    // solved as if they were, and left to the polishing below
+    float scale = max(sqrt(abs(p)), sqrt(sqrt(abs(r))));
+    if (abs(q) < 1e-3 * scale * scale * scale) {
+        // Biquadratic: a quadratic in y^2
+        float discriminant = p * p / 4 - r;
+        if (discriminant >= 0) {
+            float y2_low = -p / 2 - sqrt(discriminant);
+            float y2_high = -p / 2 + sqrt(discriminant);
+            if (y2_low >= 0) {
+                add_quadratic_roots(0, -y2_low, roots, count);
+            }
+            if (y2_high >= 0) {
+                add_quadratic_roots(0, -y2_high, roots, count);
+            }
+        }
+    } else {
+        // (y^2 + s)^2 = (alpha y - beta)^2 for the largest root s of the resolvent cubic,
+        // which always has 2s - p > 0 when q != 0
+        float s = largest_cubic_root(-p / 2, -r, p * r / 2 - q * q / 8);
+        float alpha = sqrt(max(2 * s - p, 0));
+        if (alpha > 0) {
+            float beta = q / (2 * alpha);
+            add_quadratic_roots(-alpha, s + beta, roots, count);
+            add_quadratic_roots(alpha, s - beta, roots, count);
+        }
+    }
+
+    for (uint i = 0; i < count; i++) {
+        float t = roots[i] - b / 4;
+        for (int j = 0; j < 2; j++) {
+            float f = (((t + b) * t + c) * t + d) * t + e;
+            float derivative = ((4 * t + 3 * b) * t + 2 * c) * t + d;
+            if (derivative != 0) {
+                t -= f / derivative;
+            }
+        }
+        roots[i] = t;
+    }
+
+    return count;
+}
+
+// NOTE: Layout must match `GpuTorus` in scene.rs
+class Torus {
+    float3 center;
+    float major_radius;
+    float3 axis; // Normalized
+    float minor_radius;
+    uint material_index;
+    uint _padding1;
+    uint _padding2;
+    uint _padding3;
+
+    bool intersect(Ray ray, float dist_min, float dist_max, out HitRecord record) {
+        // Local frame with the axis along z, and a unit length direction
+        float3 u, v;
+        create_basis(axis, u, v);
+        float3x3 to_local = float3x3(u, v, axis);
+        float ray_length = length(ray.direction);
+        float3 d = mul(to_local, ray.direction / ray_length);
+
+        // The quartic's coefficients lose precision far from the torus, so distances are measured
+        // from the ray's closest approach to the center. This also removes the cubic term.
+        float3 local_origin = mul(to_local, ray.origin - center);
+        float shift = -dot(local_origin, d);
+        float3 o = local_origin + shift * d;
+        if (length(o) > major_radius + minor_radius) {
+            return false;
+        }
+
+        // (|p|^2 + R^2 - r^2)^2 = 4 R^2 (p_x^2 + p_y^2)
+        float k = dot2(o) + major_radius * major_radius - minor_radius * minor_radius;
+        float major2 = 4 * major_radius * major_radius;
+        float4 roots;
+        uint count = solve_quartic(
+            0,
+            2 * k - major2 * dot2(d.xy),
+            -major2 * 2 * dot(o.xy, d.xy),
+            k * k - major2 * dot2(o.xy),
+            roots
+        );
+
+        float distance = dist_max;
+        for (uint i = 0; i < count; i++) {
+            float root_distance = (roots[i] + shift) / ray_length;
+            if (root_distance > dist_min && root_distance < distance) {
+                distance = root_distance;
+            }
+        }
+        if (distance >= dist_max) {
+            return false;
+        }
+
+        // Away from the closest point on the center circle
+        float3 p = o + (distance * ray_length - shift) * d;
+        float3 ring = float3(normalize(p.xy) * major_radius, 0);
+        float3 n = normalize(p - ring);
+
+        record.distance = distance;
+        record.position = ray.position(distance);
+        record.set_face_normal(ray, n.x * u + n.y * v + n.z * axis);
+        // Around the axis, then around the tube
+        record.uv = float2(
+            (atan2(p.y, p.x) + PI) / (2 * PI),
+            (atan2(p.z, length(p.xy) - major_radius) + PI) / (2 * PI)
+        );
+
+        record.material_index = material_index;
+
+        return true;
+    } // intersect()
+};
+
+// Every shape array packed into 16 byte records (see `pack_shapes` in raytrace.rs), so all of them
+// take one storage buffer binding. The first records hold the record each section starts at, indexed
+// by primitive kind or `SHAPES_*`.
+layout(set = 2, binding = 1) StructuredBuffer<float4> shapes;
+
+// NOTE: Must match the `SHAPES_*` constants in raytrace.rs
+#define SHAPES_VERTICES 12
+#define SHAPES_CSG_NODES 13
+#define SHAPES_SDF_NODES 14
+
+// First record of item `index` in `section`, whose items are `size` records long
+uint shape_record(uint section, uint index, uint size) {
+    return asuint(shapes[section / 4][section % 4]) + index * size;
+}
+
+// NOTE: Layout must match `GpuVertex` in mesh.rs
+struct Vertex {
+    float3 position;
+    float3 normal;
+};
+
+Vertex load_vertex(uint index) {
+    uint record = shape_record(SHAPES_VERTICES, index, 2);
+    Vertex vertex = { shapes[record].xyz, shapes[record + 1].xyz };
+    return vertex;
+}
+
+// NOTE: Layout must match `GpuTriangle` in mesh.rs
+class Triangle {
+    uint3 indices;
+    uint material_index;
+
+    // Moller-Trumbore intersection
+    bool intersect(Ray ray, float dist_min, float dist_max, out HitRecord record) {
+        Vertex v0 = load_vertex(indices.x);
+        Vertex v1 = load_vertex(indices.y);
+        Vertex v2 = load_vertex(indices.z);
+
+        float3 edge1 = v1.position - v0.position;
+        float3 edge2 = v2.position - v0.position;
+
+        float3 p = cross(ray.direction, edge2);
+        float determinant = dot(edge1, p);
+
+        // Ray is parallel to the triangle
+        if (abs(determinant) < 1e-8) {
+            return false;
+        }
+        float inverse_determinant = 1 / determinant;
+
+        float3 to_origin = ray.origin - v0.position;
+        float u = dot(to_origin, p) * inverse_determinant;
+        if (u < 0 || u > 1) {
+            return false;
+        }
+
+        float3 q = cross(to_origin, edge1);
+        float v = dot(ray.direction, q) * inverse_determinant;
+        if (v < 0 || u + v > 1) {
+            return false;
+        }
+
+        float distance = dot(edge2, q) * inverse_determinant;
+        if (distance < dist_max && distance > dist_min) {
+            record.distance = distance;
+            record.position = ray.position(distance);
+
+            // Smooth shading from vertex normals, oriented to the winding order
+            float3 geometric_normal = normalize(cross(edge1, edge2));
+            float3 outward_normal = normalize((1 - u - v) * v0.normal + u * v1.normal + v * v2.normal);
+            if (dot(outward_normal, geometric_normal) < 0) {
+                outward_normal = -outward_normal;
+            }
+            record.set_face_normal(ray, outward_normal);
+            // Barycentric, meshes have no texture coordinates
+            record.uv = float2(u, v);
+
+            record.material_index = material_index;
+
+            return true;
+        }
+
+        return false;
+    } // intersect()
+};
+
+
+/********** Camera **********/
+
+class Camera {
+    float3 position;
+    float3 bottom_left;
+    float3 horizontal;
+    float3 vertical;
+    float v_fov;
+    float3 u, v, w;
+    float lens_radius;
+
+    // Only an open shutter draws a random time
+    Ray create_ray(float2 uv) {
+        float2 direction = lens_radius * random_in_unit_disk();
+        float3 offset = u * direction.x + v * direction.y;
+
+        float time = shutter_open;
+        if (shutter_close > shutter_open) {
+            time = rand_range(shutter_open, shutter_close);
+        }
+
+        Ray ray = { position + offset, 
+                    bottom_left + uv.x*horizontal + uv.y*vertical - position - offset,
+                    time
+        };
+        return ray;
+    }
+
+    // Inverse of `create_ray` without the lens offset: where `direction` from the camera's position
+    // crosses the image, in the same uv. Outside [0, 1] if it misses the image or points behind it.
+    float2 project(float3 direction) {
+        float forward = dot(direction, -w);
+        if (forward <= 0) {
+            return -1;
+        }
+
+        float focal_dist = dot(position - bottom_left, w);
+        float3 on_focal_plane = direction * focal_dist / forward;
+        return float2(dot(on_focal_plane, u) / length(horizontal), dot(on_focal_plane, v) / length(vertical)) + 0.5;
+    }
+};
+
+namespace Camera_ {
+    Camera create(float3 position, float3 lookat, float3 v_up, float v_fov, float aperature, float focal_dist) {
+        float theta = radians(v_fov);
+
+        float viewport_height = 2 * tan(theta/2);
+        float viewport_width = viewport_height * (window_size.x / window_size.y);
+        
+        float3 w = normalize(position - lookat);
+        float3 u = normalize(cross(v_up, w));
+        float3 v = cross(w, u);
+
+        float3 horizontal = viewport_width * u;
+        float3 vertical = viewport_height * v;
+        float3 bottom_left = position - horizontal/2 - vertical/2 - focal_dist * w;
+
+        Camera camera = {position, bottom_left, horizontal, vertical, v_fov, u, v, w, aperature / 2};
+        return camera;
+    }
+}
+
+
+/********** Scene **********/
+
+// Scene contents are uploaded from a scene file (see scene.rs)
+layout(set = 2, binding = 0) StructuredBuffer<Material> materials;
+
+// NOTE: Must match bvh.rs
+#define PRIM_SPHERE 0
+#define PRIM_TRIANGLE 1
+#define PRIM_RECTANGLE 2
+#define PRIM_PLANE 3 // Never in the BVH
+#define PRIM_DISK 4
+#define PRIM_BOX 5
+#define PRIM_CYLINDER 6
+#define PRIM_CONE 7
+#define PRIM_CAPSULE 8
+#define PRIM_TORUS 9
+#define PRIM_CSG 10
+#define PRIM_SDF 11
+#define PRIM_KIND_SHIFT 28
+#define PRIM_INDEX_MASK ((1 << PRIM_KIND_SHIFT) - 1)
+
+// Readers of the primitives in `shapes`. NOTE: Must match the layouts of the `Gpu*` structs.
+
+Sphere load_sphere(uint index) {
+    uint record = shape_record(PRIM_SPHERE, index, 2);
+    float4 a = shapes[record];
+    float4 b = shapes[record + 1];
+
+    Sphere sphere;
+    sphere.center = a.xyz;
+    sphere.radius = a.w;
+    sphere.velocity = b.xyz;
+    sphere.material_index = asuint(b.w);
+    return sphere;
+}
+
+Triangle load_triangle(uint index) {
+    uint4 a = asuint(shapes[shape_record(PRIM_TRIANGLE, index, 1)]);
+
+    Triangle triangle;
+    triangle.indices = a.xyz;
+    triangle.material_index = a.w;
+    return triangle;
+}
+
+Rectangle load_rectangle(uint index) {
+    uint record = shape_record(PRIM_RECTANGLE, index, 3);
+    float4 a = shapes[record];
+
+    Rectangle rectangle;
+    rectangle.corner = a.xyz;
+    rectangle.material_index = asuint(a.w);
+    rectangle.edge_u = shapes[record + 1].xyz;
+    rectangle.edge_v = shapes[record + 2].xyz;
+    return rectangle;
+}
+
+Plane load_plane(uint index) {
+    uint record = shape_record(PRIM_PLANE, index, 2);
+    float4 a = shapes[record];
+
+    Plane plane;
+    plane.origin = a.xyz;
+    plane.material_index = asuint(a.w);
+    plane.normal = shapes[record + 1].xyz;
+    return plane;
+}
+
+Disk load_disk(uint index) {
+    uint record = shape_record(PRIM_DISK, index, 2);
+    float4 a = shapes[record];
+    float4 b = shapes[record + 1];
+
+    Disk disk;
+    disk.center = a.xyz;
+    disk.radius = a.w;
+    disk.normal = b.xyz;
+    disk.material_index = asuint(b.w);
+    return disk;
+}
+
+Box load_box(uint index) {
+    uint record = shape_record(PRIM_BOX, index, 5);
+    float4 a = shapes[record];
+
+    Box box;
+    box.center = a.xyz;
+    box.material_index = asuint(a.w);
+    box.half_extents = shapes[record + 1].xyz;
+    box.rotation = shapes[record + 2];
+    box.velocity = shapes[record + 3].xyz;
+    box.angular_velocity = shapes[record + 4].xyz;
+    return box;
+}
+
+Cylinder load_cylinder(uint index) {
+    uint record = shape_record(PRIM_CYLINDER, index, 2);
+    float4 a = shapes[record];
+    float4 b = shapes[record + 1];
+
+    Cylinder cylinder;
+    cylinder.a = a.xyz;
+    cylinder.radius = a.w;
+    cylinder.b = b.xyz;
+    cylinder.material_index = asuint(b.w);
+    return cylinder;
+}
+
+Cone load_cone(uint index) {
+    uint record = shape_record(PRIM_CONE, index, 3);
+    float4 a = shapes[record];
+    float4 b = shapes[record + 1];
+
+    Cone cone;
+    cone.a = a.xyz;
+    cone.radius_a = a.w;
+    cone.b = b.xyz;
+    cone.radius_b = b.w;
+    cone.material_index = asuint(shapes[record + 2].x);
+    return cone;
+}
+
+Capsule load_capsule(uint index) {
+    uint record = shape_record(PRIM_CAPSULE, index, 2);
+    float4 a = shapes[record];
+    float4 b = shapes[record + 1];
+
+    Capsule capsule;
+    capsule.a = a.xyz;
+    capsule.radius = a.w;
+    capsule.b = b.xyz;
+    capsule.material_index = asuint(b.w);
+    return capsule;
+}
+
+Torus load_torus(uint index) {
+    uint record = shape_record(PRIM_TORUS, index, 3);
+    float4 a = shapes[record];
+    float4 b = shapes[record + 1];
+
+    Torus torus;
+    torus.center = a.xyz;
+    torus.major_radius = a.w;
+    torus.axis = b.xyz;
+    torus.minor_radius = b.w;
+    torus.material_index = asuint(shapes[record + 2].x);
+    return torus;
+}
+
+// NOTE: Must match the `CSG_*` constants in scene.rs
+#define CSG_LEAF 0
+#define CSG_UNION 1
+#define CSG_INTERSECTION 2
+#define CSG_DIFFERENCE 3
+#define CSG_MAX_SPANS 4
+#define CSG_MAX_DEPTH 4
+
+// NOTE: Layout must match `GpuCsgNode` in scene.rs
+struct CsgNode {
+    uint operation; // One of the `CSG_*` defines. Operations combine the two span lists evaluated before them.
+    uint primitive; // Encoded primitive of a leaf
+};
+
+// Two nodes per record
+CsgNode load_csg_node(uint index) {
+    float4 a = shapes[shape_record(SHAPES_CSG_NODES, index / 2, 1)];
+    uint2 node = asuint(index % 2 == 0 ? a.xy : a.zw);
+
+    CsgNode csg_node = { node.x, node.y };
+    return csg_node;
+}
+
+// Sorted, disjoint spans of a ray inside part of a CSG shape
+struct SpanList {
+    uint count;
+    Interval spans[CSG_MAX_SPANS];
+
+    // Entry (even) or exit (odd) of a span, with its outward normal
+    float boundary(uint i, out float3 normal) {
+        Interval span = spans[i / 2];
+        normal = i % 2 == 1 ? span.exit_normal : span.enter_normal;
+        return i % 2 == 1 ? span.exit : span.enter;
+    }
+};
+
+// Walks the boundaries of both lists in order, tracking whether the ray is inside each, and keeps
+// those where that changes whether it is inside the combination
+SpanList combine_spans(uint operation, SpanList a, SpanList b) {
+    SpanList combined;
+    combined.count = 0;
+    uint i = 0;
+    uint j = 0;
+    bool inside_a = false;
+    bool inside_b = false;
+    bool inside = false;
+    float enter = 0;
+    float3 enter_normal = float3(0);
+
+    while (i < 2 * a.count || j < 2 * b.count) {
+        float3 normal_a, normal_b;
+        float distance_a = i < 2 * a.count ? a.boundary(i, normal_a) : UNBOUNDED;
+        float distance_b = j < 2 * b.count ? b.boundary(j, normal_b) : UNBOUNDED;
+
+        float distance;
+        float3 normal;
+        if (j == 2 * b.count || (i < 2 * a.count && distance_a <= distance_b)) {
+            inside_a = !inside_a;
+            i++;
+            distance = distance_a;
+            normal = normal_a;
+        } else {
+            inside_b = !inside_b;
+            j++;
+            distance = distance_b;
+            // Cut out surfaces face into the shape that was cut
+            normal = operation == CSG_DIFFERENCE ? -normal_b : normal_b;
+        }
+
+        bool now_inside;
+        switch (operation) {
+            case CSG_UNION: now_inside = inside_a || inside_b; break;
+            case CSG_INTERSECTION: now_inside = inside_a && inside_b; break;
+            default: now_inside = inside_a && !inside_b; break;
+        }
+
+        if (now_inside && !inside) {
+            enter = distance;
+            enter_normal = normal;
+        } else if (!now_inside && inside && combined.count < CSG_MAX_SPANS) {
+            Interval span = {enter, distance, enter_normal, normal};
+            combined.spans[combined.count] = span;
+            combined.count++;
+        }
+        inside = now_inside;
+    }
+
+    return combined;
+}
+
+SpanList csg_leaf_spans(uint primitive, Ray ray) {
+    uint index = primitive & PRIM_INDEX_MASK;
+    SpanList list;
+    bool is_hit = false;
+
+    switch (primitive >> PRIM_KIND_SHIFT) {
+        case PRIM_SPHERE: {
+            Sphere sphere = load_sphere(index);
+            is_hit = sphere_interval(sphere.center, sphere.radius, ray, list.spans[0]);
+            break;
+        }
+        case PRIM_BOX: {
+            Box box = load_box(index);
+            is_hit = box.interval(ray, list.spans[0]);
+            break;
+        }
+        case PRIM_CYLINDER: {
+            Cylinder cylinder = load_cylinder(index);
+            is_hit = capped_cone_interval(cylinder.a, cylinder.b, cylinder.radius, cylinder.radius, ray, list.spans[0]);
+            break;
+        }
+        case PRIM_CONE: {
+            Cone cone = load_cone(index);
+            is_hit = capped_cone_interval(cone.a, cone.b, cone.radius_a, cone.radius_b, ray, list.spans[0]);
+            break;
+        }
+        case PRIM_CAPSULE: {
+            Capsule capsule = load_capsule(index);
+            is_hit = capsule.interval(ray, list.spans[0]);
+            break;
+        }
+        default: break;
+    }
+
+    list.count = is_hit ? 1 : 0;
+    return list;
+}
+
+// NOTE: Layout must match `GpuCsg` in scene.rs
+class Csg {
+    uint first_node; // Its tree is `node_count` nodes from here in `csg_nodes`, in postfix order
+    uint node_count;
+    uint material_index;
+    uint _padding;
+
+    // Evaluates the tree's postfix program on a stack of span lists
+    bool intersect(Ray ray, float dist_min, float dist_max, out HitRecord record) {
+        SpanList stack[CSG_MAX_DEPTH];
+        uint depth = 0;
+        for (uint i = first_node; i < first_node + node_count; i++) {
+            CsgNode node = load_csg_node(i);
+            if (node.operation == CSG_LEAF) {
+                stack[depth] = csg_leaf_spans(node.primitive, ray);
+                depth++;
+            } else {
+                depth--;
+                stack[depth - 1] = combine_spans(node.operation, stack[depth - 1], stack[depth]);
+            }
+        }
+
+        for (uint i = 0; i < stack[0].count; i++) {
+            float distance;
+            float3 outward_normal;
+            if (stack[0].spans[i].first_hit(dist_min, dist_max, distance, outward_normal)) {
+                record.distance = distance;
+                record.position = ray.position(distance);
+                record.set_face_normal(ray, outward_normal);
+                // CSG surfaces have no texture coordinates
+                record.uv = float2(0);
+
+                record.material_index = material_index;
+
+                return true;
+            }
+        }
+
+        return false;
+    } // intersect()
+};
+
+Csg load_csg(uint index) {
+    uint4 a = asuint(shapes[shape_record(PRIM_CSG, index, 1)]);
+
+    Csg csg;
+    csg.first_node = a.x;
+    csg.node_count = a.y;
+    csg.material_index = a.z;
+    return csg;
+}
+
+// NOTE: Must match the `SDF_*` constants in scene.rs
+#define SDF_SPHERE 0
+#define SDF_ROUNDED_BOX 1
+#define SDF_TORUS 2
+#define SDF_MANDELBULB 3
+#define SDF_UNION 4
+#define SDF_INTERSECTION 5
+#define SDF_DIFFERENCE 6
+#define SDF_MAX_DEPTH 8
+
+#define SDF_MAX_STEPS 256
+#define SDF_HIT_DISTANCE 1e-4  // Sphere tracing stops this close to the surface
+#define SDF_NORMAL_OFFSET 5e-4 // Of the finite differences for normals
+
+// NOTE: Layout must match `GpuSdfNode` in scene.rs
+struct SdfNode {
+    float3 center;
+    uint kind;         // One of the `SDF_*` defines. Operations combine the two distances evaluated before them.
+    float4 parameters; // Depends on the kind (see scene.rs)
+    float4 rotation;   // Local to world
+};
+
+SdfNode load_sdf_node(uint index) {
+    uint record = shape_record(SHAPES_SDF_NODES, index, 3);
+    float4 a = shapes[record];
+
+    SdfNode node = { a.xyz, asuint(a.w), shapes[record + 1], shapes[record + 2] };
+    return node;
+}
+
+// Polynomial smooth minimum, blending over `smoothness` (see Inigo Quilez's "Smooth Minimum")
+float smooth_min(float a, float b, float smoothness) {
+    if (smoothness <= 0) {
+        return min(a, b);
+    }
+
+    float h = max(smoothness - abs(a - b), 0) / smoothness;
+    return min(a, b) - h * h * smoothness / 4;
+}
+
+// Distance estimate from the running derivative of the escape time iteration, around the z axis
+float mandelbulb_distance(float3 position, float power, uint iterations) {
+    float3 z = position;
+    float r = length(z);
+    float derivative = 1;
+
+    for (uint i = 0; i < iterations; i++) {
+        if (r > 2) {
+            break;
+        }
+
+        // Raise to the power in spherical coordinates
+        float theta = acos(clamp(z.z / r, -1, 1)) * power;
+        float phi = atan2(z.y, z.x) * power;
+        derivative = power * pow(r, power - 1) * derivative + 1;
+        z = pow(r, power) * float3(sin(theta) * cos(phi), sin(theta) * sin(phi), cos(theta)) + position;
+        r = length(z);
+    }
+
+    return 0.5 * log(r) * r / derivative;
+}
+
+// Evaluates an SDF tree's postfix program on a stack of distances
+float sdf_distance(uint first_node, uint node_count, float3 position) {
+    float stack[SDF_MAX_DEPTH];
+    uint depth = 0;
+
+    for (uint i = first_node; i < first_node + node_count; i++) {
+        SdfNode node = load_sdf_node(i);
+        float4 parameters = node.parameters;
+        float3 local = quaternion_rotate(float4(-node.rotation.xyz, node.rotation.w), position - node.center);
+
+        float distance;
+        switch (node.kind) {
+            case SDF_SPHERE: {
+                distance = length(local) - parameters.x;
+                break;
+            }
+            case SDF_ROUNDED_BOX: {
+                float3 q = abs(local) - (parameters.xyz - parameters.w);
+                distance = length(max(q, 0)) + min(max(q.x, max(q.y, q.z)), 0) - parameters.w;
+                break;
+            }
+            case SDF_TORUS: {
+                float2 q = float2(length(local.xz) - parameters.x, local.y);
+                distance = length(q) - parameters.y;
+                break;
+            }
+            case SDF_MANDELBULB: {
+                distance = mandelbulb_distance(local.xzy / parameters.x, parameters.y, uint(parameters.z)) * parameters.x;
+                break;
+            }
+            default: {
+                depth -= 2;
+                float a = stack[depth];
+                float b = stack[depth + 1];
+                if (node.kind == SDF_UNION) {
+                    distance = smooth_min(a, b, parameters.x);
+                } else if (node.kind == SDF_INTERSECTION) {
+                    distance = -smooth_min(-a, -b, parameters.x);
+                } else {
+                    distance = -smooth_min(-a, b, parameters.x);
+                }
+                break;
+            }
+        }
+
+        stack[depth] = distance;
+        depth++;
+    }
+
+    return stack[0];
+}
+
+// NOTE: Layout must match `GpuSdf` in scene.rs
+class Sdf {
+    float3 bounds_min; // Sphere tracing starts and ends at the bounds
+    uint first_node;   // Its tree is `node_count` nodes from here in `sdf_nodes`, in postfix order
+    float3 bounds_max;
+    uint node_count;
+    uint material_index;
+    uint _padding1;
+    uint _padding2;
+    uint _padding3;
+
+    float signed_distance(float3 position) {
+        return sdf_distance(first_node, node_count, position);
+    }
+
+    // Outward normal from the distance's gradient, by finite differences on a tetrahedron
+    float3 normal(float3 position) {
+        float2 k = float2(1, -1);
+        return normalize(
+            k.xyy * signed_distance(position + k.xyy * SDF_NORMAL_OFFSET) +
+            k.yyx * signed_distance(position + k.yyx * SDF_NORMAL_OFFSET) +
+            k.yxy * signed_distance(position + k.yxy * SDF_NORMAL_OFFSET) +
+            k.xxx * signed_distance(position + k.xxx * SDF_NORMAL_OFFSET)
+        );
+    }
+
+    // Sphere tracing within the bounds
+    bool intersect(Ray ray, float dist_min, float dist_max, out HitRecord record) {
+        float3 t0 = (bounds_min - ray.origin) / ray.direction;
+        float3 t1 = (bounds_max - ray.origin) / ray.direction;
+        float3 t_near = min(t0, t1);
+        float3 t_far = max(t0, t1);
+        float t = max(dist_min, max(t_near.x, max(t_near.y, t_near.z)));
+        float end = min(dist_max, min(t_far.x, min(t_far.y, t_far.z)));
+
+        // Distances are in world units, the ray's direction need not be
+        float ray_length = length(ray.direction);
+        // From inside the shape, march towards its surface from within
+        float side = signed_distance(ray.position(t)) < 0 ? -1 : 1;
+
+        for (uint i = 0; i < SDF_MAX_STEPS; i++) {
+            if (t > end) {
+                return false;
+            }
+
+            float distance = side * signed_distance(ray.position(t));
+            if (distance < SDF_HIT_DISTANCE) {
+                record.distance = t;
+                record.position = ray.position(t);
+                record.set_face_normal(ray, normal(record.position));
+                // SDF surfaces have no texture coordinates
+                record.uv = float2(0);
+
+                record.material_index = material_index;
+
+                return true;
+            }
+
+            t += distance / ray_length;
+        }
+
+        return false;
+    } // intersect()
+};
+
+Sdf load_sdf(uint index) {
+    uint record = shape_record(PRIM_SDF, index, 3);
+    float4 a = shapes[record];
+    float4 b = shapes[record + 1];
+
+    Sdf sdf;
+    sdf.bounds_min = a.xyz;
+    sdf.first_node = asuint(a.w);
+    sdf.bounds_max = b.xyz;
+    sdf.node_count = asuint(b.w);
+    sdf.material_index = asuint(shapes[record + 2].x);
+    return sdf;
+}
+
+// NOTE: Must match `BVH_STACK_SIZE` in bvh.rs, which limits the tree depth so the stack never
+// overflows. The check below only guards against a mismatch.
+#define BVH_STACK_SIZE 32
+
+// NOTE: Layout must match `GpuBvhNode` in bvh.rs
+struct BvhNode {
+    float3 aabb_min;
+    uint right_or_first;  // Interior: right child index. Leaf: first primitive reference.
+    float3 aabb_max;
+    uint primitive_count; // Zero for interior nodes
+};
+
+layout(set = 2, binding = 2) StructuredBuffer<BvhNode> bvh_nodes;
+layout(set = 2, binding = 3) StructuredBuffer<uint> bvh_primitives;
+
+// Slab test against the closest hit so far
+bool intersect_aabb(float3 aabb_min, float3 aabb_max, float3 origin, float3 inverse_direction, float dist_min, float dist_max) {
+    float3 t0 = (aabb_min - origin) * inverse_direction;
+    float3 t1 = (aabb_max - origin) * inverse_direction;
+
+    float3 t_near = min(t0, t1);
+    float3 t_far = max(t0, t1);
+
+    float t_enter = max(dist_min, max(t_near.x, max(t_near.y, t_near.z)));
+    float t_exit = min(dist_max, min(t_far.x, min(t_far.y, t_far.z)));
+
+    return t_enter <= t_exit;
+}
+
+bool intersect_primitive(uint primitive, Ray ray, float dist_min, float dist_max, out HitRecord record) {
+    uint index = primitive & PRIM_INDEX_MASK;
+    record.primitive = primitive;
+
+    switch (primitive >> PRIM_KIND_SHIFT) {
+        case PRIM_SPHERE: {
+            Sphere sphere = load_sphere(index);
+            sphere.move_to(ray.time);
+            return sphere.intersect(ray, dist_min, dist_max, record);
+        }
+        case PRIM_TRIANGLE: {
+            Triangle triangle = load_triangle(index);
+            return triangle.intersect(ray, dist_min, dist_max, record);
+        }
+        case PRIM_RECTANGLE: {
+            Rectangle rectangle = load_rectangle(index);
+            return rectangle.intersect(ray, dist_min, dist_max, record);
+        }
+        case PRIM_DISK: {
+            Disk disk = load_disk(index);
+            return disk.intersect(ray, dist_min, dist_max, record);
+        }
+        case PRIM_BOX: {
+            Box box = load_box(index);
+            box.move_to(ray.time);
+            return box.intersect(ray, dist_min, dist_max, record);
+        }
+        case PRIM_CYLINDER: {
+            Cylinder cylinder = load_cylinder(index);
+            return cylinder.intersect(ray, dist_min, dist_max, record);
+        }
+        case PRIM_CONE: {
+            Cone cone = load_cone(index);
+            return cone.intersect(ray, dist_min, dist_max, record);
+        }
+        case PRIM_CAPSULE: {
+            Capsule capsule = load_capsule(index);
+            return capsule.intersect(ray, dist_min, dist_max, record);
+        }
+        case PRIM_TORUS: {
+            Torus torus = load_torus(index);
+            return torus.intersect(ray, dist_min, dist_max, record);
+        }
+        case PRIM_CSG: {
+            Csg csg = load_csg(index);
+            return csg.intersect(ray, dist_min, dist_max, record);
+        }
+        case PRIM_SDF: {
+            Sdf sdf = load_sdf(index);
+            return sdf.intersect(ray, dist_min, dist_max, record);
+        }
+
+        // Unreachable
+        default: return false;
+    }
+}
+
+
+/********** Main **********/
+
+// Closest hit by stack-based BVH traversal (see `Bvh::closest_hit` in bvh.rs)
+bool scene(Ray ray, float dist_min, float dist_max, inout HitRecord record) {
+    bool hit_anything = false;
+    float closest_hit = dist_max;
+
+    HitRecord temp_record;
+
+    // Planes are unbounded, so they are tested outside the BVH. Testing them first lets a
+    // ground plane cull everything below it.
+    for (uint i = 0; i < num_planes; ++i) {
+        Plane plane = load_plane(i);
+        if ( plane.intersect(ray, dist_min, closest_hit, temp_record) ) {
+            temp_record.primitive = (PRIM_PLANE << PRIM_KIND_SHIFT) | i;
+            hit_anything = true;
+            closest_hit = temp_record.distance;
+            record = temp_record;
+        }
+    }
+
+    // The root of an empty BVH is a leaf without primitives
+    BvhNode root = bvh_nodes[0];
+    if (root.primitive_count == 0 && root.right_or_first == 0) {
+        return hit_anything;
+    }
+
+    float3 inverse_direction = 1 / ray.direction;
+
+    uint stack[BVH_STACK_SIZE];
+    uint stack_size = 0;
+    stack[stack_size++] = 0;
+
+    while (stack_size > 0) {
+        uint node_index = stack[--stack_size];
+        BvhNode node = bvh_nodes[node_index];
+
+        if ( !intersect_aabb(node.aabb_min, node.aabb_max, ray.origin, inverse_direction, dist_min, closest_hit) ) {
+            continue;
+        }
+
+        if (node.primitive_count > 0) {
+            for (uint i = 0; i < node.primitive_count; ++i) {
+                if ( intersect_primitive(bvh_primitives[node.right_or_first + i], ray, dist_min, closest_hit, temp_record) ) {
+                    hit_anything = true;
+                    closest_hit = temp_record.distance;
+                    record = temp_record;
+                }
+            }
+        } else if (stack_size + 2 <= BVH_STACK_SIZE) {
+            stack[stack_size++] = node.right_or_first;
+            stack[stack_size++] = node_index + 1;
+        }
+    }
+
+    return hit_anything;
+}
+
+/********** Environment **********/
+
+// Equirectangular environment map and its luminance CDFs (see environment.rs)
+layout(set = 3, binding = 0) Texture2D<float4> environment_map;
+layout(set = 3, binding = 1) StructuredBuffer<float> environment_marginal_cdf;
+layout(set = 3, binding = 2) StructuredBuffer<float> environment_conditional_cdf;
+
+// Rotates about the y axis
+float3 rotate_y(float3 direction, float angle) {
+    float c = cos(angle);
+    float s = sin(angle);
+    return float3(c * direction.x + s * direction.z, direction.y, -s * direction.x + c * direction.z);
+}
+
+// Map coordinates on [0, 1) for a world direction
+float2 environment_uv(float3 direction) {
+    float3 d = rotate_y(normalize(direction), -environment_rotation);
+    float u = atan2(d.z, d.x) / (2 * PI);
+    float v = acos(clamp(d.y, -1, 1)) / PI;
+    return float2(frac(u), v);
+}
+
+uint2 environment_texel(float2 uv) {
+    uint width, height;
+    environment_map.GetDimensions(width, height);
+    return min(uint2(uv * float2(width, height)), uint2(width - 1, height - 1));
+}
+
+float3 environment_color(float3 direction) {
+    return environment_map.Load(int3(environment_texel(environment_uv(direction)), 0)).rgb * environment_intensity;
+}
+
+// First index in [0, count) whose CDF value exceeds `u`. One copy per buffer since HLSL cannot pass buffers around.
+uint search_cdf_marginal(float u, uint count) {
+    uint low = 0;
+    uint high = count - 1;
+    while (low < high) {
+        uint middle = (low + high) / 2;
+        if (environment_marginal_cdf[middle] > u) {
+            high = middle;
+        } else {
+            low = middle + 1;
+        }
+    }
+    return low;
+}
+uint search_cdf_conditional(float u, uint row_start, uint count) {
+    uint low = 0;
+    uint high = count - 1;
+    while (low < high) {
+        uint middle = (low + high) / 2;
+        if (environment_conditional_cdf[row_start + middle] > u) {
+            high = middle;
+        } else {
+            low = middle + 1;
+        }
+    }
+    return low;
+}
+
+// Probability of a texel (both CDFs are inclusive)
+float environment_texel_probability(uint2 texel, uint width) {
+    float row = environment_marginal_cdf[texel.y] - (texel.y > 0 ? environment_marginal_cdf[texel.y - 1] : 0);
+
+    uint row_start = texel.y * width;
+    float column = environment_conditional_cdf[row_start + texel.x] - (texel.x > 0 ? environment_conditional_cdf[row_start + texel.x - 1] : 0);
+
+    return row * column;
+}
+
+// Solid angle pdf of importance sampling `direction`
+float environment_pdf(float3 direction) {
+    uint width, height;
+    environment_map.GetDimensions(width, height);
+
+    float2 uv = environment_uv(direction);
+    float sin_theta = sin(uv.y * PI);
+    if (sin_theta <= 0) {
+        return 0;
+    }
+
+    // Texel probability -> uv density -> solid angle density
+    float uv_pdf = environment_texel_probability(environment_texel(uv), width) * width * height;
+    return uv_pdf / (2 * PI * PI * sin_theta);
+}
+
+// Picks a texel proportional to luminance (weighted by solid angle), then a point within it
+float3 sample_environment(out float pdf) {
+    uint width, height;
+    environment_map.GetDimensions(width, height);
+
+    uint y = search_cdf_marginal(random(), height);
+    uint x = search_cdf_conditional(random(), y * width, width);
+
+    float2 uv = (float2(x, y) + float2(random(), random())) / float2(width, height);
+    float phi = uv.x * 2 * PI;
+    float theta = uv.y * PI;
+
+    float3 direction = float3(sin(theta) * cos(phi), cos(theta), sin(theta) * sin(phi));
+    direction = rotate_y(direction, environment_rotation);
+
+    pdf = environment_pdf(direction);
+    return direction;
+}
+
+float3 sky_color(Ray ray) {
+    if (has_environment != 0) {
+        return environment_color(ray.direction);
+    }
+
+    float3 unit_direction = normalize(ray.direction);
+    float t = 0.5 * (unit_direction.y + 1.);
+    return (1 - t) * sky_horizon + t * sky_zenith;
+}
+
+
+/********** Lights **********/
+
+// Emissive spheres, rectangles and disks (encoded primitive references)
+layout(set = 2, binding = 4) StructuredBuffer<uint> lights;
+
+// Stands in for a primitive reference when the environment map is sampled
+#define ENVIRONMENT_LIGHT 0xFFFFFFFF
+
+// Area lights plus the environment map (when present)
+uint light_count() {
+    return num_lights + (has_environment != 0 ? 1 : 0);
+}
+
+// Cosine of the cone containing `sphere` as seen from `position`. Returns 1 if inside the sphere.
+float sphere_cos_theta_max(Sphere sphere, float3 position) {
+    float radius = abs(sphere.radius);
+    float distance_squared = dot2(sphere.center - position);
+    if (distance_squared <= radius * radius) {
+        return 1;
+    }
+    return sqrt(1 - radius * radius / distance_squared);
+}
+
+// Solid angle pdf of the light sampling strategy choosing `record` (a hit on `primitive`) from `position`
+// at `time`
+float light_pdf(uint primitive, float3 position, float time, HitRecord record) {
+    uint index = primitive & PRIM_INDEX_MASK;
+    float pdf = 0;
+
+    switch (primitive >> PRIM_KIND_SHIFT) {
+        case PRIM_SPHERE: {
+            Sphere sphere = load_sphere(index);
+            sphere.move_to(time);
+            float cos_theta_max = sphere_cos_theta_max(sphere, position);
+            if (cos_theta_max < 1) {
+                pdf = 1 / (2 * PI * (1 - cos_theta_max));
+            }
+            break;
+        }
+        case PRIM_RECTANGLE: {
+            float3 to_light = record.position - position;
+            float cos_light = abs(dot(record.normal, normalize(to_light)));
+            if (cos_light > 0) {
+                pdf = dot2(to_light) / (load_rectangle(index).area() * cos_light);
+            }
+            break;
+        }
+        case PRIM_DISK: {
+            float3 to_light = record.position - position;
+            float cos_light = abs(dot(record.normal, normalize(to_light)));
+            if (cos_light > 0) {
+                pdf = dot2(to_light) / (load_disk(index).area() * cos_light);
+            }
+            break;
+        }
+
+        // Other emissive shapes are never sampled directly
+        default: break;
+    }
+
+    return pdf / light_count();
+}
+
+// Picks a light uniformly, then a direction towards where it is at `time`. Returns false if no direction
+// could be sampled.
+bool sample_light(float3 position, float time, out float3 direction, out uint light_primitive, out float pdf) {
+    uint light = min(uint(random() * light_count()), light_count() - 1);
+
+    // The environment comes after the area lights
+    if (light == num_lights) {
+        light_primitive = ENVIRONMENT_LIGHT;
+        direction = sample_environment(pdf);
+        pdf /= light_count();
+        return pdf > 0;
+    }
+
+    light_primitive = lights[light];
+    uint index = light_primitive & PRIM_INDEX_MASK;
+
+    switch (light_primitive >> PRIM_KIND_SHIFT) {
+        // Uniform over the cone subtended by the sphere
+        case PRIM_SPHERE: {
+            Sphere sphere = load_sphere(index);
+            sphere.move_to(time);
+            float cos_theta_max = sphere_cos_theta_max(sphere, position);
+            if (cos_theta_max >= 1) {
+                return false;
+            }
+
+            float cos_theta = 1 + random() * (cos_theta_max - 1);
+            float sin_theta = sqrt(max(0, 1 - cos_theta * cos_theta));
+            float phi = 2 * PI * random();
+
+            float3 w = normalize(sphere.center - position);
+            float3 u, v;
+            create_basis(w, u, v);
+
+            direction = normalize(u * cos(phi) * sin_theta + v * sin(phi) * sin_theta + w * cos_theta);
+            pdf = 1 / (2 * PI * (1 - cos_theta_max));
+            break;
+        }
+        // Uniform over the rectangle's area, converted to solid angle
+        case PRIM_RECTANGLE: {
+            Rectangle rectangle = load_rectangle(index);
+            float3 light_position = rectangle.corner + random() * rectangle.edge_u + random() * rectangle.edge_v;
+
+            float3 to_light = light_position - position;
+            direction = normalize(to_light);
+
+            float cos_light = abs(dot(normalize(cross(rectangle.edge_u, rectangle.edge_v)), direction));
+            if (cos_light < 1e-6) {
+                return false;
+            }
+            pdf = dot2(to_light) / (rectangle.area() * cos_light);
+            break;
+        }
+        // Uniform over the disk's area, converted to solid angle
+        case PRIM_DISK: {
+            Disk disk = load_disk(index);
+            float3 u, v;
+            create_basis(disk.normal, u, v);
+
+            float r = disk.radius * sqrt(random());
+            float phi = 2 * PI * random();
+            float3 light_position = disk.center + r * (cos(phi) * u + sin(phi) * v);
+
+            float3 to_light = light_position - position;
+            direction = normalize(to_light);
+
+            float cos_light = abs(dot(disk.normal, direction));
+            if (cos_light < 1e-6) {
+                return false;
+            }
+            pdf = dot2(to_light) / (disk.area() * cos_light);
+            break;
+        }
+
+        default: return false;
+    }
+
+    pdf /= light_count();
+    return true;
+}
+
+// Material of an emissive primitive in `lights`
+uint light_material_index(uint light_primitive) {
+    uint index = light_primitive & PRIM_INDEX_MASK;
+
+    switch (light_primitive >> PRIM_KIND_SHIFT) {
+        case PRIM_SPHERE: return load_sphere(index).material_index;
+        case PRIM_RECTANGLE: return load_rectangle(index).material_index;
+        case PRIM_DISK: return load_disk(index).material_index;
+
+        // Unreachable, `lights` only holds the kinds `sample_light` samples
+        default: return 0;
+    }
+}
+
+// Radiance arriving along `direction` from a light chosen by `sample_light`, if nothing is in the way
+float3 light_emission(uint light_primitive, float3 direction) {
+    if (light_primitive == ENVIRONMENT_LIGHT) {
+        return environment_color(direction);
+    }
+    return materials[light_material_index(light_primitive)].albedo;
+}
+
+float power_heuristic(float pdf_a, float pdf_b) {
+    float a2 = pdf_a * pdf_a;
+    return a2 / (a2 + pdf_b * pdf_b);
+}
+
+// Next-event estimation for a surface with a BSDF, seen from `wo` at `time`. MIS weighted against BSDF sampling.
+float3 sample_direct_light(Material material, HitRecord record, float3 wo, float time) {
+    float3 direction;
+    uint light_primitive;
+    float pdf;
+    if ( !sample_light(record.position, time, direction, light_primitive, pdf) ) {
+        return 0;
+    }
+
+    float3 bsdf_cos = Material_::eval_bsdf(material, record, wo, direction);
+    if (all(bsdf_cos <= 0)) {
+        return 0;
+    }
+
+    // Shadow ray must reach the sampled light first
+    Ray shadow_ray = { record.position, direction, time };
+    HitRecord light_record;
+    bool hit = scene(shadow_ray, 0.001, FAR_PLANE_DIST, light_record);
+
+    if (light_primitive == ENVIRONMENT_LIGHT) {
+        if (hit) {
+            return 0;
+        }
+    } else if ( !hit || light_record.primitive != light_primitive ) {
+        return 0;
+    }
+    float bsdf_pdf = Material_::bsdf_pdf(material, record, wo, direction);
+
+    return bsdf_cos * light_emission(light_primitive, direction) * power_heuristic(pdf, bsdf_pdf) / pdf;
+}
+
+/********** Denoiser Features **********/
+
+// What a camera ray hits first. The denoiser uses these to find edges in the image.
+struct Features {
+    float3 albedo; // Divided out of the color before filtering, so textures aren't blurred
+    float depth;   // Distance from the camera
+    float3 normal; // Facing the camera
+};
+
+Features hit_features(Ray ray, Material material, HitRecord record) {
+    Features features;
+    switch (material.type) {
+        case MAT_METAL:
+        case MAT_LAMBERTIAN:
+            features.albedo = material.albedo;
+            break;
+        // Reflectance facing the surface
+        case MAT_CONDUCTOR:
+            features.albedo = material.albedo * fresnel_conductor(1, material.conductor_eta, material.conductor_k);
+            break;
+        // Glass and lights have no albedo to preserve
+        default:
+            features.albedo = 1;
+            break;
+    }
+    features.depth = length(record.position - ray.origin);
+    features.normal = record.normal;
+    return features;
+}
+
+Features miss_features(Ray ray) {
+    Features features;
+    features.albedo = 1;
+    features.depth = FAR_PLANE_DIST;
+    features.normal = -normalize(ray.direction);
+    return features;
+}
+
+// Set by `fire_ray`
+static Features first_hit;
+
+float3 fire_ray(Ray ray) {
+    HitRecord record;
+    Ray scattered_ray;
+
+    float3 attenuation;
+    float3 throughput = 1;
+    float3 color = 0;
+
+    // Whether the previous bounce sampled lights (emission hit by the scattered ray is then MIS weighted)
+    bool sampled_lights = false;
+    float scatter_pdf = 0;
+    float3 scatter_origin = ray.origin;
+
+    for (uint depth = 0; depth < max_ray_bounces; ++depth) {
+        // If hit scene
+        if ( scene(ray, 0.001, FAR_PLANE_DIST, record) ) {
+            Material material = materials[record.material_index];
+
+            if (depth == 0) {
+                first_hit = hit_features(ray, material, record);
+            }
+
+            if (material.type == MAT_EMISSIVE) {
+                float weight = 1;
+                if (sampled_lights) {
+                    weight = power_heuristic(scatter_pdf, light_pdf(record.primitive, scatter_origin, ray.time, record));
+                }
+                color += throughput * material.albedo * weight;
+                break;
+            }
+
+            // Mirror-like metal and smooth glass have no BSDF to evaluate, they rely on scattering
+            float3 wo = -normalize(ray.direction);
+            sampled_lights = Material_::has_bsdf(material) && light_count() > 0;
+            if (sampled_lights) {
+                color += throughput * sample_direct_light(material, record, wo, ray.time);
+            }
+
+            // If ray scattered
+            if ( Material_::scatter_ray(material, ray, record, attenuation, scattered_ray) ) {
+                scatter_pdf = Material_::bsdf_pdf(material, record, wo, normalize(scattered_ray.direction));
+                scatter_origin = record.position;
+
+                ray = scattered_ray;
+                throughput *= attenuation;
+            } else {
+                break;
+            }
+        } else {
+            if (depth == 0) {
+                first_hit = miss_features(ray);
+            }
+
+            float weight = 1;
+            if (sampled_lights && has_environment != 0) {
+                weight = power_heuristic(scatter_pdf, environment_pdf(ray.direction) / light_count());
+            }
+            color += throughput * sky_color(ray) * weight;
+            break;
+        }
+    }
+
+    return color;
+}
+
+
+// Starts the random sequence for the pixel centered at `pixel_coords` in this frame
+void seed_random(float2 pixel_coords) {
+    rand_state = (pixel_coords / window_size) + frame_number * 15.23;
+}
+
+// Camera at a pose given like the uniforms' (`lookat` is a direction)
+Camera create_camera_at(float3 position, float3 lookat, float fov) {
+    // TODO: The camera needs to be redone so these can be removed
+    // TODO: Calculate focus distance by querying the distance to scene from camera
+    float3 cam_position = {0, 0, 5};
+    float3 default_lookat = {0, 0, -1};
+    float3 v_up = {0, 1, 0};
+    float focal_dist = length(cam_position - default_lookat);
+
+    Camera camera = Camera_::create(
+        position,       // Position
+        // This is because my image is still upside down...
+        lookat * float3(1, -1, 1) + position, // Lookat
+        v_up,           // Up vector
+        fov,            // Vertical field of view
+        0.0,            // Aperature size
+        focal_dist      // Focal plane dist
+    );
+    return camera;
+}
+
+// Camera described by the uniforms
+Camera create_camera() {
+    return create_camera_at(camera_position, camera_lookat, v_fov);
+}
+
+// Jittered ray through the pixel centered at `pixel_coords`
+Ray create_pixel_ray(Camera camera, float2 pixel_coords) {
+    float2 uv = (pixel_coords + float2(random(), random())) / window_size;
+    // uv is still flipped.......
+    uv.y = 1 - uv.y;
+
+    return camera.create_ray(uv);
+}
+
+/********** Adaptive Sampling **********/
+
+// Samples a pixel needs before its error estimate is trusted
+#define ADAPTIVE_MIN_SAMPLES 16
+// Converged pixels still take a frame of samples this often, in case their estimate was unlucky
+#define ADAPTIVE_RECHECK_INTERVAL 32
+// Most samples a pixel takes per frame, as a multiple of `samples_per_pixel`
+// NOTE: Must match `MAX_SAMPLE_BOOST` in raytrace.rs
+#define ADAPTIVE_MAX_BOOST 4
+// Keeps near black pixels from needing an error of exactly zero
+#define ADAPTIVE_ERROR_FLOOR 0.01
+
+float luminance(float3 color) {
+    return dot(color, float3(0.2126, 0.7152, 0.0722));
+}
+
+// Samples to take at `image_coords` this frame. Converged pixels take none, and the
+// samples they save go to the pixels still above the threshold.
+uint pixel_sample_budget(uint2 image_coords) {
+    if (adaptive_threshold <= 0 || sample_number == 1) {
+        return samples_per_pixel;
+    }
+
+    float4 statistics = sample_statistics[image_coords];
+    if (statistics.b >= ADAPTIVE_MIN_SAMPLES && statistics.a < adaptive_threshold) {
+        return sample_number % ADAPTIVE_RECHECK_INTERVAL == 0 ? samples_per_pixel : 0;
+    }
+
+    if (active_pixels == 0) {
+        return samples_per_pixel;
+    }
+    uint pixel_count = uint(window_size.x) * uint(window_size.y);
+    return samples_per_pixel * clamp(pixel_count / active_pixels, 1, ADAPTIVE_MAX_BOOST);
+}
+
+// A frame's samples for one pixel, summed
+struct SampleSums {
+    float3 color;
+    float luminance_squares; // Squared luminance of each sample, for the variance
+    float3 albedo;           // `Features`
+    float depth;
+    float3 normal;
+    uint count;
+};
+
+SampleSums empty_sample_sums() {
+    SampleSums sums;
+    sums.color = 0;
+    sums.luminance_squares = 0;
+    sums.albedo = 0;
+    sums.depth = 0;
+    sums.normal = 0;
+    sums.count = 0;
+    return sums;
+}
+
+void add_sample(inout SampleSums sums, float3 color, Features features) {
+    float sample_luminance = luminance(color);
+    sums.color += color;
+    sums.luminance_squares += sample_luminance * sample_luminance;
+    sums.albedo += features.albedo;
+    sums.depth += features.depth;
+    sums.normal += features.normal;
+    sums.count += 1;
+}
+
+// Merges a frame's samples into the pixel's luminance mean and variance (Chan et al.'s parallel update)
+void update_statistics(uint2 image_coords, SampleSums sums) {
+    float4 statistics = 0;
+    if (sample_number > 1) {
+        statistics = sample_statistics[image_coords];
+    }
+
+    float count_a = statistics.b;
+    float count_b = sums.count;
+    float count = count_a + count_b;
+
+    float mean_b = luminance(sums.color) / count_b;
+    float m2_b = max(sums.luminance_squares - count_b * mean_b * mean_b, 0);
+
+    float delta = mean_b - statistics.r;
+    float mean = statistics.r + delta * count_b / count;
+    float m2 = statistics.g * count_a + m2_b + delta * delta * count_a * count_b / count;
+    float variance = m2 / count;
+
+    float error = sqrt(variance / count) / max(mean, ADAPTIVE_ERROR_FLOOR);
+    sample_statistics[image_coords] = float4(mean, variance, count, error);
+
+    if (adaptive_threshold > 0 && (count < ADAPTIVE_MIN_SAMPLES || error >= adaptive_threshold)) {
+        InterlockedAdd(active_pixel_count[0], 1);
+    }
+}
+
+// Adds a frame's samples to the storage image, the feature images and the pixel's statistics.
+// Returns the running average.
+float3 accumulate(uint2 image_coords, SampleSums sums) {
+    // Accumulate linear radiance. Tonemapping and sRGB encoding happen in the display pass.
+    float4 accumulated = float4(sums.color, sums.count);
+    float4 albedo_depth = float4(sums.albedo, sums.depth);
+    float4 normal = float4(sums.normal, sums.count);
+    if (sample_number > 1) {
+        accumulated += storage_image[image_coords];
+        albedo_depth += feature_albedo_depth[image_coords];
+        normal += feature_normal[image_coords];
+    }
+    // Alpha holds the number of accumulated samples, which differs between pixels when sampling adaptively
+    storage_image[image_coords] = accumulated;
+    feature_albedo_depth[image_coords] = albedo_depth;
+    feature_normal[image_coords] = normal;
+
+    update_statistics(image_coords, sums);
+
+    return accumulated.rgb / accumulated.a;
+}
+
+// Adds a frame of samples for the pixel centered at `pixel_coords` to the storage image.
+// Shared by the pixel and compute shader entry points. Returns the pixel's running average.
+float3 render_pixel(float2 pixel_coords) {
+    uint2 image_coords = uint2(pixel_coords);
+
+    uint samples = pixel_sample_budget(image_coords);
+    if (samples == 0) {
+        float4 accumulated = storage_image[image_coords];
+        return accumulated.rgb / accumulated.a;
+    }
+
+    seed_random(pixel_coords);
+
+    Camera camera = create_camera();
+
+    SampleSums sums = empty_sample_sums();
+    for (uint i = 0; i < samples; ++i) {
+        float3 color = fire_ray(create_pixel_ray(camera, pixel_coords));
+        add_sample(sums, color, first_hit);
+    }
+
+    return accumulate(image_coords, sums);
+}
@@ -0,0 +1,104 @@
+// Temporal reprojection: carries the accumulation over to a new camera pose (see reproject.rs).
+//
+// Runs before the first frame after the camera moves, in 8x8 work groups. Each pixel traces its center
+// ray from the new pose, finds where that point was in the previous image, and keeps the sums
+// accumulated there if the previous first hit depth and normal agree. Everything else (disocclusions,
+// pixels that were off screen) starts over from zero.
+#include "raytrace.hlsli"
+
+// The targets (bindings 0 to 4) as they were before the camera moved
+layout(set = 0, binding = 5) RWTexture2D<float4> history_image;
+layout(set = 0, binding = 6) RWTexture2D<float4> history_statistics;
+layout(set = 0, binding = 7) RWTexture2D<float4> history_albedo_depth;
+layout(set = 0, binding = 8) RWTexture2D<float4> history_normal;
+
+layout(set = 0, binding = 9)
+cbuffer Reprojection {
+    float3 previous_position; // Camera pose the history was rendered from, like the uniforms'
+    float previous_v_fov;
+    float3 previous_lookat;
+    float max_history;        // Samples kept per pixel, so stale lighting fades out
+};
+
+// Depth differences allowed, relative to the depth expected from the previous pose
+#define REPROJECT_DEPTH_TOLERANCE 0.05
+// Smallest cosine allowed between the history's average normal and the new one
+#define REPROJECT_NORMAL_TOLERANCE 0.9
+
+// Scales a pixel's sums down to at most `max_history` samples. Averages are unchanged.
+float history_weight(float count) {
+    return count > max_history ? max_history / count : 1;
+}
+
+[numthreads(8, 8, 1)]
+void main(uint3 thread_id : SV_DispatchThreadID) {
+    // The dispatch is rounded up to whole work groups
+    if (any(thread_id.xy >= uint2(window_size))) {
+        return;
+    }
+    uint2 image_coords = thread_id.xy;
+
+    // The pixel center's first hit from the new pose, like `create_pixel_ray` without the jitter
+    float2 uv = (float2(image_coords) + 0.5) / window_size;
+    uv.y = 1 - uv.y;
+    Ray ray = create_camera().create_ray(uv);
+    // Moving shapes where their blur is centered
+    ray.time = (shutter_open + shutter_close) / 2;
+
+    HitRecord record;
+    bool hit = scene(ray, 0.001, FAR_PLANE_DIST, record);
+
+    // The sky is infinitely far away, so only its direction matters
+    float3 previous_direction = hit ? record.position - previous_position : ray.direction;
+    float expected_depth = hit ? length(previous_direction) : FAR_PLANE_DIST;
+    float new_depth = hit ? length(record.position - ray.origin) : FAR_PLANE_DIST;
+
+    float2 previous_uv = create_camera_at(previous_position, previous_lookat, previous_v_fov).project(previous_direction);
+    previous_uv.y = 1 - previous_uv.y;
+
+    float4 image = 0;
+    float4 statistics = 0;
+    float4 albedo_depth = 0;
+    float4 normal = 0;
+
+    if (all(previous_uv >= 0) && all(previous_uv < 1)) {
+        uint2 previous_coords = uint2(previous_uv * window_size);
+        float4 history_features = history_normal[previous_coords];
+
+        // Features restart from zero on resume, so such pixels can't be checked
+        if (history_features.a > 0) {
+            float4 history_albedo = history_albedo_depth[previous_coords];
+            float history_depth = history_albedo.a / history_features.a;
+
+            bool valid;
+            if (hit) {
+                valid = abs(history_depth - expected_depth) <= REPROJECT_DEPTH_TOLERANCE * expected_depth
+                    && dot(normalize(history_features.rgb), record.normal) >= REPROJECT_NORMAL_TOLERANCE;
+            } else {
+                valid = history_depth >= (1 - REPROJECT_DEPTH_TOLERANCE) * FAR_PLANE_DIST;
+            }
+
+            if (valid) {
+                image = history_image[previous_coords];
+                image *= history_weight(image.a);
+
+                // Mean and variance carry over, the error grows with fewer samples
+                statistics = history_statistics[previous_coords];
+                statistics.b *= history_weight(statistics.b);
+                if (statistics.b > 0) {
+                    statistics.a = sqrt(statistics.g / statistics.b) / max(statistics.r, ADAPTIVE_ERROR_FLOOR);
+                }
+
+                float feature_weight = history_weight(history_features.a);
+                normal = history_features * feature_weight;
+                // Depths are measured from the new pose from here on
+                albedo_depth = float4(history_albedo.rgb * feature_weight, new_depth * normal.a);
+            }
+        }
+    }
+
+    storage_image[image_coords] = image;
+    sample_statistics[image_coords] = statistics;
+    feature_albedo_depth[image_coords] = albedo_depth;
+    feature_normal[image_coords] = normal;
+}
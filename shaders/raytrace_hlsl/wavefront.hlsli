@@ -0,0 +1,91 @@
+/*
+   Wavefront path tracing (see wavefront.rs).
+
+   Instead of one thread following a path through every bounce (`fire_ray`), each stage is a kernel
+   working through a queue of paths, with the path state kept in storage buffers in between:
+
+     init, then per sample: generate, max_ray_bounces x (shadow, extend, shade_*), shadow. Then finalize.
+
+   Each shading kernel only sees one material type, so its threads don't diverge on the material.
+   Random numbers are drawn in the same order as `fire_ray`, so both render the same image up to rounding.
+*/
+#include "raytrace.hlsli"
+#include "wavefront_queues.hlsli"
+
+// `PathState::flags`
+#define PATH_FRONT_FACE 1
+#define PATH_SAMPLED_LIGHTS 2
+#define PATH_CAMERA_RAY 4 // Not extended yet, so the next hit gives the denoiser features
+
+// NOTE: Size must match `PATH_STATE_SIZE` in wavefront.rs
+struct PathState {
+    float3 origin;      // Ray origin. After a bounce, the previous hit (for MIS weights).
+    float scatter_pdf;  // Solid angle pdf of `direction` when sampled by a lambertian bounce
+    float3 direction;
+    float hit_distance; // Set by the extend kernel
+    float3 throughput;
+    uint hit_material;  // Material index of the hit
+    float3 radiance;    // Summed over this frame's samples
+    uint flags;         // PATH_*
+    float3 hit_normal;  // Facing against `direction`
+    float luminance_squares;      // Summed over this frame's finished samples, for adaptive sampling
+    float2 rand_state;
+    float sample_start_luminance; // Luminance of `radiance` when the current sample started
+    uint remaining_samples;       // Samples left to generate this frame (see `pixel_sample_budget`)
+    float3 albedo_sum;  // First hit `Features` summed over this frame's samples
+    float depth_sum;
+    float3 normal_sum;
+    float time;         // Of the current sample's rays
+};
+
+// Sampled light contribution, added to the path's radiance if nothing blocks it
+// NOTE: Size must match `SHADOW_RAY_SIZE` in wavefront.rs
+struct ShadowRay {
+    float3 origin;
+    uint light_primitive; // Must be hit first. `ENVIRONMENT_LIGHT`: nothing may be hit.
+    float3 direction;
+    uint path;
+    float3 contribution;
+    float time;
+};
+
+layout(set = 0, binding = 5) RWStructuredBuffer<PathState> paths;
+layout(set = 0, binding = 7) RWStructuredBuffer<uint> ray_queue;
+// `MATERIAL_QUEUE_COUNT` queues of `path_capacity` entries
+layout(set = 0, binding = 8) RWStructuredBuffer<uint> material_queues;
+layout(set = 0, binding = 9) RWStructuredBuffer<ShadowRay> shadow_queue;
+
+// The slice of the image traced by this wave. Path `i` traces pixel `wave_first_pixel + i`.
+layout(set = 0, binding = 10)
+cbuffer Wave {
+    uint wave_first_pixel;
+    uint wave_path_count;
+    uint path_capacity; // Paths (and entries per queue) allocated
+};
+
+// Reserves an entry at the end of `queue`
+uint push(uint queue) {
+    uint slot;
+    InterlockedAdd(queue_counts[queue], 1, slot);
+    return slot;
+}
+
+// Center of the pixel traced by `path_index`
+float2 path_pixel_coords(uint path_index) {
+    uint pixel = wave_first_pixel + path_index;
+    uint width = uint(window_size.x);
+    return float2(pixel % width, pixel / width) + 0.5;
+}
+
+void add_features(inout PathState path, Features features) {
+    path.albedo_sum += features.albedo;
+    path.depth_sum += features.depth;
+    path.normal_sum += features.normal;
+}
+
+// Adds the squared luminance of the sample that just ended to `luminance_squares`
+void finish_sample(inout PathState path) {
+    float sample_luminance = luminance(path.radiance) - path.sample_start_luminance;
+    path.luminance_squares += sample_luminance * sample_luminance;
+    path.sample_start_luminance = luminance(path.radiance);
+}
@@ -0,0 +1,55 @@
+// Finds the closest hit of every queued path. Emission and the sky end paths here.
+// Other hits are queued for shading by material type.
+#include "wavefront.hlsli"
+
+[numthreads(WAVEFRONT_GROUP_SIZE, 1, 1)]
+void main(uint3 thread_id : SV_DispatchThreadID) {
+    if (thread_id.x >= queue_counts[RAY_QUEUE]) {
+        return;
+    }
+
+    uint path_index = ray_queue[thread_id.x];
+    PathState path = paths[path_index];
+
+    Ray ray = { path.origin, path.direction, path.time };
+    bool sampled_lights = (path.flags & PATH_SAMPLED_LIGHTS) != 0;
+    bool camera_ray = (path.flags & PATH_CAMERA_RAY) != 0;
+    path.flags &= ~PATH_CAMERA_RAY;
+
+    HitRecord record;
+    if ( scene(ray, 0.001, FAR_PLANE_DIST, record) ) {
+        Material material = materials[record.material_index];
+
+        if (camera_ray) {
+            add_features(path, hit_features(ray, material, record));
+        }
+
+        if (material.type == MAT_EMISSIVE) {
+            float weight = 1;
+            if (sampled_lights) {
+                weight = power_heuristic(path.scatter_pdf, light_pdf(record.primitive, path.origin, path.time, record));
+            }
+            path.radiance += path.throughput * material.albedo * weight;
+        } else {
+            path.hit_distance = record.distance;
+            path.hit_normal = record.normal;
+            path.hit_material = record.material_index;
+            path.flags = (path.flags & ~PATH_FRONT_FACE) | (record.is_front_face ? PATH_FRONT_FACE : 0);
+
+            uint queue = material.type - 1;
+            material_queues[queue * path_capacity + push(MATERIAL_QUEUE_START + queue)] = path_index;
+        }
+    } else {
+        if (camera_ray) {
+            add_features(path, miss_features(ray));
+        }
+
+        float weight = 1;
+        if (sampled_lights && has_environment != 0) {
+            weight = power_heuristic(path.scatter_pdf, environment_pdf(ray.direction) / light_count());
+        }
+        path.radiance += path.throughput * sky_color(ray) * weight;
+    }
+
+    paths[path_index] = path;
+}
@@ -0,0 +1,29 @@
+// Adds each pixel's samples from this frame to the storage image
+#include "wavefront.hlsli"
+
+[numthreads(WAVEFRONT_GROUP_SIZE, 1, 1)]
+void main(uint3 thread_id : SV_DispatchThreadID) {
+    uint path_index = thread_id.x;
+    if (path_index >= wave_path_count) {
+        return;
+    }
+
+    // Statistics are only written here, so the budget is the same as in the init kernel
+    uint2 image_coords = uint2(path_pixel_coords(path_index));
+    uint samples = pixel_sample_budget(image_coords);
+    if (samples == 0) {
+        return;
+    }
+
+    PathState path = paths[path_index];
+    finish_sample(path);
+
+    SampleSums sums;
+    sums.color = path.radiance;
+    sums.luminance_squares = path.luminance_squares;
+    sums.albedo = path.albedo_sum;
+    sums.depth = path.depth_sum;
+    sums.normal = path.normal_sum;
+    sums.count = samples;
+    accumulate(image_coords, sums);
+}
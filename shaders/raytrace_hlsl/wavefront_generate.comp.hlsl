@@ -0,0 +1,34 @@
+// Starts a camera ray for every path in the wave with samples left (one sample per pixel)
+#include "wavefront.hlsli"
+
+[numthreads(WAVEFRONT_GROUP_SIZE, 1, 1)]
+void main(uint3 thread_id : SV_DispatchThreadID) {
+    uint path_index = thread_id.x;
+    if (path_index >= wave_path_count) {
+        return;
+    }
+
+    PathState path = paths[path_index];
+    if (path.remaining_samples == 0) {
+        return;
+    }
+    path.remaining_samples -= 1;
+
+    // The previous sample's paths have all ended by now
+    finish_sample(path);
+
+    rand_state = path.rand_state;
+
+    Ray ray = create_pixel_ray(create_camera(), path_pixel_coords(path_index));
+
+    path.origin = ray.origin;
+    path.direction = ray.direction;
+    path.time = ray.time;
+    path.throughput = 1;
+    path.scatter_pdf = 0;
+    path.flags = PATH_CAMERA_RAY;
+    path.rand_state = rand_state;
+    paths[path_index] = path;
+
+    ray_queue[push(RAY_QUEUE)] = path_index;
+}
@@ -0,0 +1,24 @@
+// Seeds every path's random numbers, clears its radiance and sets its sample budget at the start of a frame
+#include "wavefront.hlsli"
+
+[numthreads(WAVEFRONT_GROUP_SIZE, 1, 1)]
+void main(uint3 thread_id : SV_DispatchThreadID) {
+    uint path_index = thread_id.x;
+    if (path_index >= wave_path_count) {
+        return;
+    }
+
+    float2 pixel_coords = path_pixel_coords(path_index);
+    seed_random(pixel_coords);
+
+    PathState path = paths[path_index];
+    path.radiance = 0;
+    path.luminance_squares = 0;
+    path.sample_start_luminance = 0;
+    path.albedo_sum = 0;
+    path.depth_sum = 0;
+    path.normal_sum = 0;
+    path.remaining_samples = pixel_sample_budget(uint2(pixel_coords));
+    path.rand_state = rand_state;
+    paths[path_index] = path;
+}
@@ -0,0 +1,15 @@
+/*
+   Single thread kernels run between wavefront stages (see wavefront.rs).
+   They reset consumed queues and size the indirect dispatches of the next stage.
+*/
+#include "wavefront_queues.hlsli"
+
+// Indirect dispatch arguments (x, y, z groups) for each queue, in `queue_counts` order
+layout(set = 1, binding = 0) RWStructuredBuffer<uint> dispatch_args;
+
+void set_dispatch_args(uint queue) {
+    uint groups = (queue_counts[queue] + WAVEFRONT_GROUP_SIZE - 1) / WAVEFRONT_GROUP_SIZE;
+    dispatch_args[queue * 3 + 0] = groups;
+    dispatch_args[queue * 3 + 1] = 1;
+    dispatch_args[queue * 3 + 2] = 1;
+}
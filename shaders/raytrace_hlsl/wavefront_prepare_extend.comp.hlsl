@@ -0,0 +1,12 @@
+// Before the shadow and extend kernels: shading queues are consumed, ray and shadow queues are ready
+#include "wavefront_prepare.hlsli"
+
+[numthreads(1, 1, 1)]
+void main() {
+    for (uint i = 0; i < MATERIAL_QUEUE_COUNT; ++i) {
+        queue_counts[MATERIAL_QUEUE_START + i] = 0;
+    }
+
+    set_dispatch_args(RAY_QUEUE);
+    set_dispatch_args(SHADOW_QUEUE);
+}
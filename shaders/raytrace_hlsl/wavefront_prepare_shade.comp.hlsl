@@ -0,0 +1,12 @@
+// Before the shading kernels: ray and shadow queues are consumed, shading queues are ready
+#include "wavefront_prepare.hlsli"
+
+[numthreads(1, 1, 1)]
+void main() {
+    queue_counts[RAY_QUEUE] = 0;
+    queue_counts[SHADOW_QUEUE] = 0;
+
+    for (uint i = 0; i < MATERIAL_QUEUE_COUNT; ++i) {
+        set_dispatch_args(MATERIAL_QUEUE_START + i);
+    }
+}
@@ -0,0 +1,16 @@
+/*
+   Queue bookkeeping shared by every wavefront kernel (see wavefront.rs)
+*/
+
+// Threads per work group. NOTE: Must match `GROUP_SIZE` in wavefront.rs
+#define WAVEFRONT_GROUP_SIZE 64
+
+// Indices into `queue_counts`. Material queue `i` holds paths that hit material type `i + 1`.
+// NOTE: Must match wavefront.rs
+#define RAY_QUEUE 0
+#define SHADOW_QUEUE 1
+#define MATERIAL_QUEUE_START 2
+#define MATERIAL_QUEUE_COUNT 5
+
+// Number of entries pushed to each queue, reset by the prepare kernels once consumed
+layout(set = 0, binding = 6) RWStructuredBuffer<uint> queue_counts;
@@ -0,0 +1,80 @@
+/*
+   Shading kernel for paths that hit material type `SHADE_MATERIAL` (defined before including).
+   Every thread shades the same material type, so scattering doesn't diverge.
+*/
+#include "wavefront.hlsli"
+
+// Next-event estimation like `sample_direct_light`, with the shadow ray left to the shadow kernel
+void queue_direct_light(uint path_index, float3 throughput, Material material, HitRecord record, float3 wo, float time) {
+    float3 direction;
+    uint light_primitive;
+    float pdf;
+    if ( !sample_light(record.position, time, direction, light_primitive, pdf) ) {
+        return;
+    }
+
+    float3 bsdf_cos = Material_::eval_bsdf(material, record, wo, direction);
+    if (all(bsdf_cos <= 0)) {
+        return;
+    }
+
+    float3 emitted = light_emission(light_primitive, direction);
+    float bsdf_pdf = Material_::bsdf_pdf(material, record, wo, direction);
+
+    ShadowRay shadow_ray;
+    shadow_ray.origin = record.position;
+    shadow_ray.light_primitive = light_primitive;
+    shadow_ray.direction = direction;
+    shadow_ray.path = path_index;
+    shadow_ray.contribution = throughput * bsdf_cos * emitted * power_heuristic(pdf, bsdf_pdf) / pdf;
+    shadow_ray.time = time;
+
+    shadow_queue[push(SHADOW_QUEUE)] = shadow_ray;
+}
+
+[numthreads(WAVEFRONT_GROUP_SIZE, 1, 1)]
+void main(uint3 thread_id : SV_DispatchThreadID) {
+    uint queue = SHADE_MATERIAL - 1;
+    if (thread_id.x >= queue_counts[MATERIAL_QUEUE_START + queue]) {
+        return;
+    }
+
+    uint path_index = material_queues[queue * path_capacity + thread_id.x];
+    PathState path = paths[path_index];
+    rand_state = path.rand_state;
+
+    Ray ray = { path.origin, path.direction, path.time };
+
+    HitRecord record;
+    record.distance = path.hit_distance;
+    record.position = ray.position(path.hit_distance);
+    record.normal = path.hit_normal;
+    record.is_front_face = (path.flags & PATH_FRONT_FACE) != 0;
+    record.material_index = path.hit_material;
+    record.primitive = 0;
+    record.uv = 0; // Not kept between kernels, no material reads it
+
+    Material material = materials[record.material_index];
+
+    // Mirror-like metal and smooth glass have no BSDF to evaluate, they rely on scattering
+    float3 wo = -normalize(ray.direction);
+    bool sampled_lights = Material_::has_bsdf(material) && light_count() > 0;
+    if (sampled_lights) {
+        queue_direct_light(path_index, path.throughput, material, record, wo, path.time);
+    }
+
+    float3 attenuation;
+    Ray scattered_ray;
+    if ( Material_::scatter_ray(material, ray, record, attenuation, scattered_ray) ) {
+        path.scatter_pdf = Material_::bsdf_pdf(material, record, wo, normalize(scattered_ray.direction));
+        path.origin = scattered_ray.origin;
+        path.direction = scattered_ray.direction;
+        path.throughput *= attenuation;
+        path.flags = sampled_lights ? PATH_SAMPLED_LIGHTS : 0;
+
+        ray_queue[push(RAY_QUEUE)] = path_index;
+    }
+
+    path.rand_state = rand_state;
+    paths[path_index] = path;
+}
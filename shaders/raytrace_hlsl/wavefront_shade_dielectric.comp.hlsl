@@ -0,0 +1,2 @@
+#define SHADE_MATERIAL MAT_DIELECTRIC
+#include "wavefront_shade.hlsli"
@@ -0,0 +1,2 @@
+#define SHADE_MATERIAL MAT_LAMBERTIAN
+#include "wavefront_shade.hlsli"
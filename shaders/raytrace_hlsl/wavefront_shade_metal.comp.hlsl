@@ -0,0 +1,2 @@
+#define SHADE_MATERIAL MAT_METAL
+#include "wavefront_shade.hlsli"
@@ -0,0 +1,2 @@
+#define SHADE_MATERIAL MAT_ROUGH_DIELECTRIC
+#include "wavefront_shade.hlsli"
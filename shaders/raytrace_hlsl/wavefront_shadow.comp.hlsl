@@ -0,0 +1,27 @@
+// Traces the shadow rays queued by shading. Unblocked lights add their contribution to the path.
+#include "wavefront.hlsli"
+
+[numthreads(WAVEFRONT_GROUP_SIZE, 1, 1)]
+void main(uint3 thread_id : SV_DispatchThreadID) {
+    if (thread_id.x >= queue_counts[SHADOW_QUEUE]) {
+        return;
+    }
+
+    ShadowRay shadow_ray = shadow_queue[thread_id.x];
+
+    Ray ray = { shadow_ray.origin, shadow_ray.direction, shadow_ray.time };
+    HitRecord light_record;
+    bool hit = scene(ray, 0.001, FAR_PLANE_DIST, light_record);
+
+    bool reached_light;
+    if (shadow_ray.light_primitive == ENVIRONMENT_LIGHT) {
+        reached_light = !hit;
+    } else {
+        reached_light = hit && light_record.primitive == shadow_ray.light_primitive;
+    }
+
+    // Each path queues at most one shadow ray per bounce, so this doesn't race
+    if (reached_light) {
+        paths[shadow_ray.path].radiance += shadow_ray.contribution;
+    }
+}
@@ -16,8 +16,8 @@ pub struct ApplicationState {
 }
 
 impl ApplicationState {
-    pub fn new() -> Self {
-        let camera = Camera::new(0.02);
+    pub fn new(scene: &crate::scene::Scene) -> Self {
+        let camera = Camera::from_description(&scene.camera, 0.02);
         
         Self {
             camera, 
@@ -38,9 +38,12 @@ impl ApplicationState {
 }
 
 impl Runnable for ApplicationState {
-    fn init(&mut self, sdl2: &SDL2) {
+    fn init(&mut self, sdl2: &SDL2, raytracer: &mut RayTracer) {
         // Always begin with relative_mouse_mode on
         sdl2.set_relative_mouse_mode(true);
+
+        // Start from the scene's camera pose
+        raytracer.update_camera(&self.camera);
     }
 
     fn update(&mut self, sdl2: &SDL2, raytracer: &mut RayTracer, event: &Event) -> Message {
@@ -79,8 +82,10 @@ impl Runnable for ApplicationState {
                 if self.relative_mouse_mode {
                     self.camera.update_angle(xrel as f32, yrel as f32);
                     self.camera_changed_this_frame = true;
+                    // `update_camera` reprojects or restarts the accumulation
+                    raytracer.pause_rendering = false;
 
-                    Message::RestartRender
+                    Message::ConsumeEvent
                 } else {
                     Message::Nothing
                 }
@@ -90,10 +95,42 @@ impl Runnable for ApplicationState {
                 if self.camera.update_fov(-2.0 * y as f32) {
                     println!("Vertical FoV: {}", self.camera.v_fov);
                     self.camera_changed_this_frame = true;
+                    raytracer.pause_rendering = false;
                 }
+                Message::ConsumeEvent
+            }
+
+            // Environment map controls
+            Event::KeyDown { keycode: Some(Keycode::LeftBracket), .. } if raytracer.has_environment() => {
+                raytracer.rotate_environment(-15.0);
+                Message::RestartRender
+            }
+
+            Event::KeyDown { keycode: Some(Keycode::RightBracket), .. } if raytracer.has_environment() => {
+                raytracer.rotate_environment(15.0);
+                Message::RestartRender
+            }
+
+            Event::KeyDown { keycode: Some(Keycode::Minus), .. } if raytracer.has_environment() => {
+                raytracer.scale_environment_intensity(1.0 / 1.25);
                 Message::RestartRender
             }
 
+            Event::KeyDown { keycode: Some(Keycode::Equals), .. } if raytracer.has_environment() => {
+                raytracer.scale_environment_intensity(1.25);
+                Message::RestartRender
+            }
+
+            Event::KeyDown { keycode: Some(Keycode::F12), .. } => {
+                Message::Screenshot
+            }
+
+            Event::KeyDown { keycode: Some(Keycode::P), .. } => {
+                raytracer.temporal_reprojection = !raytracer.temporal_reprojection;
+                println!("Temporal reprojection {}", if raytracer.temporal_reprojection {"on"} else {"off"});
+                Message::ConsumeEvent
+            }
+
             Event::KeyDown { keycode: Some(Keycode::R), .. } => {
                 println!("Restarting render");
 
@@ -0,0 +1,569 @@
+use cgmath::Vector3;
+
+// Primitive references pack the kind into the top bits and the buffer index into the rest.
+// Must match the `PRIM_*` defines in the ray tracing shader.
+pub const PRIM_SPHERE: u32 = 0;
+pub const PRIM_TRIANGLE: u32 = 1;
+pub const PRIM_RECTANGLE: u32 = 2;
+/// Planes are unbounded and never in the hierarchy, but hit records still refer to them
+pub const PRIM_PLANE: u32 = 3;
+pub const PRIM_DISK: u32 = 4;
+pub const PRIM_BOX: u32 = 5;
+pub const PRIM_CYLINDER: u32 = 6;
+pub const PRIM_CONE: u32 = 7;
+pub const PRIM_CAPSULE: u32 = 8;
+pub const PRIM_TORUS: u32 = 9;
+pub const PRIM_CSG: u32 = 10;
+pub const PRIM_SDF: u32 = 11;
+
+/// Entries in the traversal stack. Must match `BVH_STACK_SIZE` in the ray tracing shader.
+pub const BVH_STACK_SIZE: usize = 32;
+/// Deepest leaf the builder creates, with the root at depth zero. Traversal holds at most one
+/// pending sibling per level, plus both children of the node it just popped.
+pub const BVH_MAX_DEPTH: usize = BVH_STACK_SIZE - 1;
+
+const PRIM_KIND_SHIFT: u32 = 28;
+const PRIM_INDEX_MASK: u32 = (1 << PRIM_KIND_SHIFT) - 1;
+
+pub fn encode_primitive(kind: u32, index: u32) -> u32 {
+    debug_assert!(index <= PRIM_INDEX_MASK);
+    (kind << PRIM_KIND_SHIFT) | index
+}
+
+/// Returns (kind, index)
+pub fn decode_primitive(primitive: u32) -> (u32, u32) {
+    (primitive >> PRIM_KIND_SHIFT, primitive & PRIM_INDEX_MASK)
+}
+
+/// Axis-aligned bounding box
+#[derive(Copy, Clone, Debug)]
+pub struct Aabb {
+    pub min: Vector3<f32>,
+    pub max: Vector3<f32>,
+}
+
+impl Aabb {
+    /// Contains nothing. Growing it by any box yields that box.
+    pub fn empty() -> Self {
+        Self {
+            min: Vector3::new(f32::INFINITY, f32::INFINITY, f32::INFINITY),
+            max: Vector3::new(f32::NEG_INFINITY, f32::NEG_INFINITY, f32::NEG_INFINITY),
+        }
+    }
+
+    pub fn from_points(points: &[Vector3<f32>]) -> Self {
+        let mut aabb = Self::empty();
+        for &point in points {
+            aabb.grow_point(point);
+        }
+        aabb
+    }
+
+    pub fn grow_point(&mut self, point: Vector3<f32>) {
+        self.min = Vector3::new(self.min.x.min(point.x), self.min.y.min(point.y), self.min.z.min(point.z));
+        self.max = Vector3::new(self.max.x.max(point.x), self.max.y.max(point.y), self.max.z.max(point.z));
+    }
+
+    /// Component-wise, so growing by an empty box leaves this one unchanged
+    pub fn grow(&mut self, other: &Aabb) {
+        self.min = Vector3::new(self.min.x.min(other.min.x), self.min.y.min(other.min.y), self.min.z.min(other.min.z));
+        self.max = Vector3::new(self.max.x.max(other.max.x), self.max.y.max(other.max.y), self.max.z.max(other.max.z));
+    }
+
+    /// Empty if they don't overlap
+    pub fn overlap(&self, other: &Aabb) -> Aabb {
+        Self {
+            min: Vector3::new(self.min.x.max(other.min.x), self.min.y.max(other.min.y), self.min.z.max(other.min.z)),
+            max: Vector3::new(self.max.x.min(other.max.x), self.max.y.min(other.max.y), self.max.z.min(other.max.z)),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.min.x > self.max.x || self.min.y > self.max.y || self.min.z > self.max.z
+    }
+
+    pub fn center(&self) -> Vector3<f32> {
+        (self.min + self.max) * 0.5
+    }
+
+    pub fn surface_area(&self) -> f32 {
+        let extent = self.max - self.min;
+        if extent.x < 0.0 {
+            // Empty
+            0.0
+        } else {
+            2.0 * (extent.x * extent.y + extent.y * extent.z + extent.z * extent.x)
+        }
+    }
+
+    /// Slab test. Returns the entry distance if the ray overlaps the box within [t_min, t_max].
+    pub fn intersect(&self, origin: Vector3<f32>, inverse_direction: Vector3<f32>, t_min: f32, t_max: f32) -> Option<f32> {
+        let mut t_enter = t_min;
+        let mut t_exit = t_max;
+
+        for axis in 0..3 {
+            let t0 = (self.min[axis] - origin[axis]) * inverse_direction[axis];
+            let t1 = (self.max[axis] - origin[axis]) * inverse_direction[axis];
+
+            t_enter = t_enter.max(t0.min(t1));
+            t_exit = t_exit.min(t0.max(t1));
+        }
+
+        if t_enter <= t_exit {
+            Some(t_enter)
+        } else {
+            None
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+/// Matches `BvhNode` in the shader (std430)
+///
+/// Nodes are stored depth-first, so an interior node's left child directly follows it.
+pub struct GpuBvhNode {                 // OFFSET + SIZE
+    pub aabb_min: Vector3<f32>,         // 0 + 12
+    /// Interior: index of the right child. Leaf: first index into the primitive list.
+    pub right_or_first: u32,            // 12 + 4
+    pub aabb_max: Vector3<f32>,         // 16 + 12
+    /// Zero for interior nodes
+    pub primitive_count: u32,           // 28 + 4
+}
+unsafe impl bytemuck::Pod for GpuBvhNode {}
+unsafe impl bytemuck::Zeroable for GpuBvhNode {}
+
+/// Bounding volume hierarchy built with the surface area heuristic (SAH)
+pub struct Bvh {
+    pub nodes: Vec<GpuBvhNode>,
+    /// Encoded primitive references (see `encode_primitive`) ordered by leaf
+    pub primitives: Vec<u32>,
+}
+
+struct BuildPrimitive {
+    primitive: u32,
+    bounds: Aabb,
+    center: Vector3<f32>,
+}
+
+impl Bvh {
+    const BIN_COUNT: usize = 12;
+    const MAX_LEAF_SIZE: usize = 4;
+    /// Relative cost of a node traversal step versus a primitive intersection
+    const TRAVERSAL_COST: f32 = 1.0;
+
+    /// Builds a hierarchy over encoded primitives and their bounds
+    pub fn build(primitives: &[(u32, Aabb)]) -> Self {
+        let mut build_primitives: Vec<BuildPrimitive> = primitives.iter()
+            .map(|&(primitive, bounds)| BuildPrimitive {
+                primitive,
+                bounds,
+                center: bounds.center(),
+            })
+            .collect();
+
+        let mut bvh = Self {
+            nodes: Vec::with_capacity(2 * primitives.len().max(1)),
+            primitives: Vec::with_capacity(primitives.len()),
+        };
+
+        if build_primitives.is_empty() {
+            // Single empty leaf so the shader always has a root to read
+            bvh.push_leaf(&Aabb::empty(), &[]);
+        } else {
+            bvh.build_recursive(&mut build_primitives, 0);
+        }
+
+        bvh
+    }
+
+    fn push_leaf(&mut self, bounds: &Aabb, primitives: &[BuildPrimitive]) {
+        self.nodes.push(GpuBvhNode {
+            aabb_min: bounds.min,
+            right_or_first: self.primitives.len() as u32,
+            aabb_max: bounds.max,
+            primitive_count: primitives.len() as u32,
+        });
+        self.primitives.extend(primitives.iter().map(|p| p.primitive));
+    }
+
+    fn build_recursive(&mut self, primitives: &mut [BuildPrimitive], depth: usize) {
+        let mut bounds = Aabb::empty();
+        let mut center_bounds = Aabb::empty();
+        for primitive in primitives.iter() {
+            bounds.grow(&primitive.bounds);
+            center_bounds.grow_point(primitive.center);
+        }
+
+        if primitives.len() <= Self::MAX_LEAF_SIZE || depth == BVH_MAX_DEPTH {
+            self.push_leaf(&bounds, primitives);
+            return;
+        }
+
+        // Lopsided SAH splits (e.g. exponentially spaced primitives) can nest deeper than the
+        // shader's stack. Once only a balanced tree still fits, split at the median instead.
+        let leaf_count = primitives.len().div_ceil(Self::MAX_LEAF_SIZE);
+        let balanced_depth = leaf_count.next_power_of_two().trailing_zeros() as usize;
+
+        let mut middle = if balanced_depth >= BVH_MAX_DEPTH - depth {
+            0
+        } else {
+            match Self::find_sah_split(primitives, &bounds, &center_bounds) {
+                Some((axis, position)) => partition(primitives, |p| p.center[axis] < position),
+                None => {
+                    self.push_leaf(&bounds, primitives);
+                    return;
+                }
+            }
+        };
+
+        // Median split along the widest axis, also used when all centers fall on one side
+        if middle == 0 || middle == primitives.len() {
+            let extent = center_bounds.max - center_bounds.min;
+            let axis = if extent.x >= extent.y && extent.x >= extent.z { 0 } else if extent.y >= extent.z { 1 } else { 2 };
+            primitives.sort_by(|a, b| a.center[axis].partial_cmp(&b.center[axis]).unwrap());
+            middle = primitives.len() / 2;
+        }
+
+        let node_index = self.nodes.len();
+        self.nodes.push(GpuBvhNode {
+            aabb_min: bounds.min,
+            right_or_first: 0,
+            aabb_max: bounds.max,
+            primitive_count: 0,
+        });
+
+        let (left, right) = primitives.split_at_mut(middle);
+        self.build_recursive(left, depth + 1);
+        self.nodes[node_index].right_or_first = self.nodes.len() as u32;
+        self.build_recursive(right, depth + 1);
+    }
+
+    /// Binned SAH. Returns (axis, split position) if splitting is cheaper than a leaf.
+    fn find_sah_split(primitives: &[BuildPrimitive], bounds: &Aabb, center_bounds: &Aabb) -> Option<(usize, f32)> {
+        let leaf_cost = primitives.len() as f32;
+        let parent_area = bounds.surface_area();
+
+        let mut best: Option<(usize, f32)> = None;
+        let mut best_cost = leaf_cost;
+
+        for axis in 0..3 {
+            let extent = center_bounds.max[axis] - center_bounds.min[axis];
+            if extent <= 0.0 {
+                continue;
+            }
+
+            let mut bin_bounds = [Aabb::empty(); Self::BIN_COUNT];
+            let mut bin_counts = [0usize; Self::BIN_COUNT];
+
+            let scale = Self::BIN_COUNT as f32 / extent;
+            for primitive in primitives {
+                let bin = (((primitive.center[axis] - center_bounds.min[axis]) * scale) as usize).min(Self::BIN_COUNT - 1);
+                bin_counts[bin] += 1;
+                bin_bounds[bin].grow(&primitive.bounds);
+            }
+
+            // Sweep from the right so each split's right side cost is known
+            let mut right_areas = [0.0; Self::BIN_COUNT];
+            let mut right_counts = [0usize; Self::BIN_COUNT];
+            let mut right_bounds = Aabb::empty();
+            let mut right_count = 0;
+            for bin in (1..Self::BIN_COUNT).rev() {
+                right_bounds.grow(&bin_bounds[bin]);
+                right_count += bin_counts[bin];
+                right_areas[bin] = right_bounds.surface_area();
+                right_counts[bin] = right_count;
+            }
+
+            let mut left_bounds = Aabb::empty();
+            let mut left_count = 0;
+            for bin in 0..Self::BIN_COUNT - 1 {
+                left_bounds.grow(&bin_bounds[bin]);
+                left_count += bin_counts[bin];
+
+                let right_count = right_counts[bin + 1];
+                if left_count == 0 || right_count == 0 {
+                    continue;
+                }
+
+                let cost = Self::TRAVERSAL_COST
+                    + (left_bounds.surface_area() * left_count as f32 + right_areas[bin + 1] * right_count as f32) / parent_area;
+
+                if cost < best_cost {
+                    best_cost = cost;
+                    best = Some((axis, center_bounds.min[axis] + (bin + 1) as f32 / scale));
+                }
+            }
+        }
+
+        best
+    }
+
+    /// Closest hit using the same fixed-size stack traversal as the shader.
+    ///
+    /// `intersect` is given an encoded primitive and the current closest distance,
+    /// and returns the hit distance if the primitive is hit closer than that.
+    pub fn closest_hit<F>(&self, origin: Vector3<f32>, direction: Vector3<f32>, t_min: f32, t_max: f32, mut intersect: F) -> Option<(u32, f32)>
+        where F: FnMut(u32, f32) -> Option<f32>
+    {
+        // The root of an empty hierarchy is a leaf without primitives
+        if self.primitives.is_empty() {
+            return None;
+        }
+
+        let inverse_direction = Vector3::new(1.0 / direction.x, 1.0 / direction.y, 1.0 / direction.z);
+
+        let mut closest: Option<(u32, f32)> = None;
+        let mut closest_distance = t_max;
+
+        let mut stack = [0usize; BVH_STACK_SIZE];
+        let mut stack_size = 1;
+
+        while stack_size > 0 {
+            stack_size -= 1;
+            let node_index = stack[stack_size];
+            let node = &self.nodes[node_index];
+            let bounds = Aabb { min: node.aabb_min, max: node.aabb_max };
+
+            if bounds.intersect(origin, inverse_direction, t_min, closest_distance).is_none() {
+                continue;
+            }
+
+            if node.primitive_count > 0 {
+                let first = node.right_or_first as usize;
+                for &primitive in &self.primitives[first..first + node.primitive_count as usize] {
+                    if let Some(distance) = intersect(primitive, closest_distance) {
+                        closest_distance = distance;
+                        closest = Some((primitive, distance));
+                    }
+                }
+            } else if stack_size + 2 <= BVH_STACK_SIZE {
+                stack[stack_size] = node.right_or_first as usize;
+                stack[stack_size + 1] = node_index + 1;
+                stack_size += 2;
+            }
+        }
+
+        closest
+    }
+}
+
+/// In-place partition. Returns the number of elements satisfying `predicate` (now at the front).
+fn partition<T, F: Fn(&T) -> bool>(items: &mut [T], predicate: F) -> usize {
+    let mut middle = 0;
+    for i in 0..items.len() {
+        if predicate(&items[i]) {
+            items.swap(i, middle);
+            middle += 1;
+        }
+    }
+    middle
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cgmath::InnerSpace;
+
+    /// Small deterministic generator so tests are reproducible without extra dependencies
+    struct XorShift(u32);
+
+    impl XorShift {
+        fn next(&mut self) -> f32 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 17;
+            self.0 ^= self.0 << 5;
+            (self.0 >> 8) as f32 / (1 << 24) as f32
+        }
+
+        fn range(&mut self, min: f32, max: f32) -> f32 {
+            min + (max - min) * self.next()
+        }
+
+        fn vector(&mut self, min: f32, max: f32) -> Vector3<f32> {
+            Vector3::new(self.range(min, max), self.range(min, max), self.range(min, max))
+        }
+    }
+
+    fn intersect_sphere(center: Vector3<f32>, radius: f32, origin: Vector3<f32>, direction: Vector3<f32>, t_min: f32, t_max: f32) -> Option<f32> {
+        let oc = origin - center;
+        let a = direction.magnitude2();
+        let half_b = oc.dot(direction);
+        let c = oc.magnitude2() - radius * radius;
+        let discriminant = half_b * half_b - a * c;
+        if discriminant < 0.0 {
+            return None;
+        }
+
+        let root = discriminant.sqrt();
+        [(-half_b - root) / a, (-half_b + root) / a].iter()
+            .cloned()
+            .find(|&t| t > t_min && t < t_max)
+    }
+
+    fn intersect_triangle(v: &[Vector3<f32>; 3], origin: Vector3<f32>, direction: Vector3<f32>, t_min: f32, t_max: f32) -> Option<f32> {
+        let edge1 = v[1] - v[0];
+        let edge2 = v[2] - v[0];
+        let p = direction.cross(edge2);
+        let determinant = edge1.dot(p);
+        if determinant.abs() < 1e-8 {
+            return None;
+        }
+
+        let to_origin = origin - v[0];
+        let u = to_origin.dot(p) / determinant;
+        let q = to_origin.cross(edge1);
+        let v = direction.dot(q) / determinant;
+        let t = edge2.dot(q) / determinant;
+
+        if u >= 0.0 && v >= 0.0 && u + v <= 1.0 && t > t_min && t < t_max {
+            Some(t)
+        } else {
+            None
+        }
+    }
+
+    /// Random scene of spheres and triangles, compared against brute force for random rays
+    fn compare_with_brute_force(seed: u32, sphere_count: usize, triangle_count: usize) {
+        let mut rng = XorShift(seed);
+
+        let spheres: Vec<(Vector3<f32>, f32)> = (0..sphere_count)
+            .map(|_| (rng.vector(-10.0, 10.0), rng.range(0.1, 1.5)))
+            .collect();
+
+        let triangles: Vec<[Vector3<f32>; 3]> = (0..triangle_count)
+            .map(|_| {
+                let base = rng.vector(-10.0, 10.0);
+                [base, base + rng.vector(-2.0, 2.0), base + rng.vector(-2.0, 2.0)]
+            })
+            .collect();
+
+        let mut primitives = Vec::new();
+        for (i, &(center, radius)) in spheres.iter().enumerate() {
+            let extent = Vector3::new(radius, radius, radius);
+            primitives.push((encode_primitive(PRIM_SPHERE, i as u32), Aabb { min: center - extent, max: center + extent }));
+        }
+        for (i, triangle) in triangles.iter().enumerate() {
+            primitives.push((encode_primitive(PRIM_TRIANGLE, i as u32), Aabb::from_points(triangle)));
+        }
+
+        let bvh = Bvh::build(&primitives);
+
+        let mut sorted_primitives = bvh.primitives.clone();
+        sorted_primitives.sort_unstable();
+        let mut expected: Vec<u32> = primitives.iter().map(|p| p.0).collect();
+        expected.sort_unstable();
+        assert_eq!(sorted_primitives, expected, "every primitive must be in exactly one leaf");
+
+        let intersect = |primitive: u32, origin, direction, t_max| {
+            match decode_primitive(primitive) {
+                (PRIM_SPHERE, index) => {
+                    let (center, radius) = spheres[index as usize];
+                    intersect_sphere(center, radius, origin, direction, 0.001, t_max)
+                }
+                (PRIM_TRIANGLE, index) => intersect_triangle(&triangles[index as usize], origin, direction, 0.001, t_max),
+                _ => unreachable!(),
+            }
+        };
+
+        for _ in 0..2000 {
+            let origin = rng.vector(-15.0, 15.0);
+            let direction = rng.vector(-1.0, 1.0);
+
+            let mut brute_force: Option<(u32, f32)> = None;
+            for &(primitive, _) in &primitives {
+                let t_max = brute_force.map_or(f32::MAX, |hit| hit.1);
+                if let Some(distance) = intersect(primitive, origin, direction, t_max) {
+                    brute_force = Some((primitive, distance));
+                }
+            }
+
+            let traversed = bvh.closest_hit(origin, direction, 0.001, f32::MAX, |primitive, t_max| {
+                intersect(primitive, origin, direction, t_max)
+            });
+
+            match (brute_force, traversed) {
+                (None, None) => {}
+                (Some(expected), Some(actual)) => {
+                    assert!((expected.1 - actual.1).abs() < 1e-4, "distance mismatch: {:?} vs {:?}", expected, actual);
+                }
+                _ => panic!("hit mismatch: brute force {:?}, bvh {:?}", brute_force, traversed),
+            }
+        }
+    }
+
+    #[test]
+    fn matches_brute_force_spheres() {
+        compare_with_brute_force(0x1234_5678, 200, 0);
+    }
+
+    #[test]
+    fn matches_brute_force_triangles() {
+        compare_with_brute_force(0x9e37_79b9, 0, 300);
+    }
+
+    #[test]
+    fn matches_brute_force_mixed() {
+        for seed in 1..6 {
+            compare_with_brute_force(seed * 7919, 50, 150);
+        }
+    }
+
+    #[test]
+    fn small_and_empty_scenes() {
+        compare_with_brute_force(42, 1, 0);
+        compare_with_brute_force(43, 3, 2);
+
+        let bvh = Bvh::build(&[]);
+        assert_eq!(bvh.nodes.len(), 1);
+        assert!(bvh.closest_hit(Vector3::new(0.0, 0.0, 0.0), Vector3::new(0.0, 0.0, -1.0), 0.001, f32::MAX, |_, _| Some(1.0)).is_none());
+    }
+
+    fn max_depth(bvh: &Bvh, node_index: usize) -> usize {
+        let node = &bvh.nodes[node_index];
+        if node.primitive_count > 0 {
+            0
+        } else {
+            1 + max_depth(bvh, node_index + 1).max(max_depth(bvh, node.right_or_first as usize))
+        }
+    }
+
+    #[test]
+    fn degenerate_input_fits_traversal_stack() {
+        // Exponentially spaced spheres make each SAH split peel off only the largest few
+        let spheres: Vec<(Vector3<f32>, f32)> = (0..120)
+            .map(|i| (Vector3::new(2.0f32.powi(i), 0.0, 0.0), 0.01))
+            .collect();
+        let mut primitives: Vec<(u32, Aabb)> = spheres.iter().enumerate()
+            .map(|(i, &(center, radius))| {
+                let extent = Vector3::new(radius, radius, radius);
+                (encode_primitive(PRIM_SPHERE, i as u32), Aabb { min: center - extent, max: center + extent })
+            })
+            .collect();
+
+        // Many identical boxes on top, which only a median split can separate
+        let stacked = Aabb { min: Vector3::new(-1.0, -1.0, -1.0), max: Vector3::new(-0.5, -0.5, -0.5) };
+        primitives.extend((0..1000).map(|i| (encode_primitive(PRIM_BOX, i), stacked)));
+
+        let bvh = Bvh::build(&primitives);
+        let depth = max_depth(&bvh, 0);
+        assert!(depth <= BVH_MAX_DEPTH, "depth {} exceeds {}", depth, BVH_MAX_DEPTH);
+
+        // A ray along the row passes through every sphere, so traversal must reach all of them
+        let mut visited = Vec::new();
+        bvh.closest_hit(Vector3::new(-2.0, 0.0, 0.0), Vector3::new(1.0, 0.0, 0.0), 0.001, f32::MAX, |primitive, _| {
+            if let (PRIM_SPHERE, index) = decode_primitive(primitive) {
+                visited.push(index);
+            }
+            None
+        });
+        visited.sort_unstable();
+        assert_eq!(visited, (0..spheres.len() as u32).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn primitive_encoding_round_trips() {
+        let encoded = encode_primitive(PRIM_TRIANGLE, 123_456);
+        assert_eq!(decode_primitive(encoded), (PRIM_TRIANGLE, 123_456));
+    }
+}
@@ -30,6 +30,24 @@ impl Camera {
         }
     }
 
+    /// Starts the camera at a scene file's pose
+    pub fn from_description(description: &crate::scene::CameraDescription, sensitivity: f32) -> Self {
+        let position: cgmath::Vector3<f32> = description.position.into();
+        let look_at: cgmath::Vector3<f32> = description.look_at.into();
+        let direction = cgmath::InnerSpace::normalize(look_at - position);
+
+        let mut camera = Self::new(sensitivity);
+        camera.position = position;
+        camera.v_fov = description.v_fov;
+
+        // Inverse of `update_target`. Pitch is negated because the shader flips the lookat's y.
+        camera.pitch = Self::clamp_pitch((-direction.y).asin().to_degrees());
+        camera.yaw = (-direction.x).atan2(-direction.z).to_degrees();
+        camera.update_target();
+
+        camera
+    }
+
     /// Returns true if fov was adjusted within bounds
     pub fn update_fov(&mut self, df: f32) -> bool {
         if self.v_fov + df > 160. || self.v_fov + df < 10. {
@@ -55,18 +73,16 @@ impl Camera {
 
     pub fn update_angle(&mut self, dx: f32, dy: f32) {
         self.yaw -= dx * self.sensitivity;
-        self.pitch += dy * self.sensitivity;
-
-        // Don't look up or down to the point of looking upside down
-        if self.pitch > 89.0 {
-            self.pitch = 89.0;
-        } else if self.pitch < -89.0 {
-            self.pitch = -89.0;
-        }
+        self.pitch = Self::clamp_pitch(self.pitch + dy * self.sensitivity);
 
         self.update_target();
     }
 
+    /// Don't look up or down to the point of looking upside down
+    fn clamp_pitch(pitch: f32) -> f32 {
+        pitch.clamp(-89.0, 89.0)
+    }
+
     fn update_target(&mut self) {
         let yaw_radians = self.yaw.to_radians();
         let pitch_radians = self.pitch.to_radians();
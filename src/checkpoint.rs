@@ -0,0 +1,227 @@
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+
+use crate::raytrace::Uniforms;
+use crate::scene::CameraDescription;
+use crate::texture::HdrImage;
+
+/// Identifies checkpoint files. Bump the version when the layout or `Uniforms` change.
+const MAGIC: &[u8; 8] = b"RTCKPT04";
+
+/// Where interactive renders are checkpointed
+pub struct CheckpointOptions {
+    /// Base path. Each render size gets its own file (see `path_for`).
+    pub path: PathBuf,
+    /// Also save periodically, not only on exit and before a resize
+    pub autosave: Option<std::time::Duration>,
+}
+
+impl CheckpointOptions {
+    /// `render.ckpt` at 1920x1080 is `render.1920x1080.ckpt`, so resizing the
+    /// window doesn't overwrite the checkpoint of the previous size
+    pub fn path_for(&self, width: u32, height: u32) -> PathBuf {
+        let stem = self.path.file_stem().unwrap_or_default().to_string_lossy();
+        let mut file_name = format!("{}.{}x{}", stem, width, height);
+        if let Some(extension) = self.path.extension() {
+            file_name += &format!(".{}", extension.to_string_lossy());
+        }
+
+        self.path.with_file_name(file_name)
+    }
+}
+
+/// An interrupted accumulation that can be continued later.
+///
+/// Layout (little endian): magic, scene hash (u64), `Uniforms` as uploaded to the GPU,
+/// then the raw accumulation texture (see `RayTracer::read_accumulation_sums`).
+pub struct Checkpoint {
+    /// `Scene::content_hash` of the scene that was rendering
+    pub scene_hash: u64,
+    /// Shader inputs, including the sample number, camera and image size
+    pub uniforms: Uniforms,
+    pub accumulation: HdrImage,
+}
+
+impl Checkpoint {
+    pub fn width(&self) -> u32 {
+        self.uniforms.dimensions.x as u32
+    }
+
+    pub fn height(&self) -> u32 {
+        self.uniforms.dimensions.y as u32
+    }
+
+    /// Camera pose the checkpoint was rendered from
+    pub fn camera(&self) -> CameraDescription {
+        let position = self.uniforms.camera_position;
+
+        CameraDescription {
+            position: position.into(),
+            look_at: (position + self.uniforms.camera_direction()).into(),
+            v_fov: self.uniforms.camera_v_fov,
+            shutter: [self.uniforms.shutter_open, self.uniforms.shutter_close],
+        }
+    }
+
+    /// True if `uniforms` render the same view (camera and environment), so the accumulation can continue
+    pub fn has_view(&self, uniforms: &Uniforms) -> bool {
+        let a = &self.uniforms;
+        a.camera_position == uniforms.camera_position
+            && a.camera_lookat == uniforms.camera_lookat
+            && a.camera_v_fov == uniforms.camera_v_fov
+            && a.environment_intensity == uniforms.environment_intensity
+            && a.environment_rotation == uniforms.environment_rotation
+    }
+
+    /// Writes to a temporary file first so an interrupted save keeps the previous checkpoint
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), String> {
+        let path = path.as_ref();
+        let temporary_path = path.with_extension("tmp");
+
+        let write = || -> std::io::Result<()> {
+            if let Some(directory) = path.parent() {
+                std::fs::create_dir_all(directory)?;
+            }
+
+            let mut file = std::io::BufWriter::new(std::fs::File::create(&temporary_path)?);
+            file.write_all(MAGIC)?;
+            file.write_all(&self.scene_hash.to_le_bytes())?;
+            file.write_all(bytemuck::bytes_of(&self.uniforms))?;
+            file.write_all(bytemuck::cast_slice(&self.accumulation.pixels))?;
+            file.into_inner()?.sync_all()?;
+
+            std::fs::rename(&temporary_path, path)
+        };
+
+        write().map_err(|e| format!("Failed to save checkpoint {:?}: {}", path, e))
+    }
+
+    /// Fails unless the checkpoint was rendered at `width` x `height`. The header is checked
+    /// against that size and the file length before the accumulation is allocated.
+    pub fn load<P: AsRef<Path>>(path: P, width: u32, height: u32) -> Result<Self, String> {
+        let path = path.as_ref();
+        let error = |e: String| format!("Failed to load checkpoint {:?}: {}", path, e);
+
+        let file = std::fs::File::open(path).map_err(|e| error(e.to_string()))?;
+        let file_length = file.metadata().map_err(|e| error(e.to_string()))?.len();
+        let mut file = std::io::BufReader::new(file);
+
+        let mut magic = [0u8; 8];
+        file.read_exact(&mut magic).map_err(|e| error(e.to_string()))?;
+        if &magic != MAGIC {
+            return Err(error("Not a checkpoint, or written by an incompatible version".to_string()));
+        }
+
+        let mut scene_hash = [0u8; 8];
+        let mut uniforms: Uniforms = bytemuck::Zeroable::zeroed();
+        file.read_exact(&mut scene_hash).map_err(|e| error(e.to_string()))?;
+        file.read_exact(bytemuck::bytes_of_mut(&mut uniforms)).map_err(|e| error(e.to_string()))?;
+
+        let checkpoint_size = (uniforms.dimensions.x as u32, uniforms.dimensions.y as u32);
+        if checkpoint_size != (width, height) {
+            return Err(error(format!("Rendered at {}x{}, not {}x{}", checkpoint_size.0, checkpoint_size.1, width, height)));
+        }
+
+        let pixel_count = width as usize * height as usize;
+        let header_length = MAGIC.len() + scene_hash.len() + std::mem::size_of::<Uniforms>();
+        let expected_length = pixel_count.checked_mul(std::mem::size_of::<[f32; 4]>())
+            .and_then(|length| length.checked_add(header_length));
+        if expected_length.map(|length| length as u64) != Some(file_length) {
+            return Err(error(format!("Truncated or corrupt, {} bytes for a {}x{} render", file_length, width, height)));
+        }
+
+        let mut pixels = vec![[0.0f32; 4]; pixel_count];
+        file.read_exact(bytemuck::cast_slice_mut(&mut pixels)).map_err(|e| error(e.to_string()))?;
+
+        Ok(Self {
+            scene_hash: u64::from_le_bytes(scene_hash),
+            uniforms,
+            accumulation: HdrImage { width, height, pixels },
+        })
+    }
+
+    /// Loads the checkpoint at `path` if it was rendered from the same scene at the same size.
+    /// Mismatches are reported and give `None`, starting the render from scratch.
+    pub fn load_matching<P: AsRef<Path>>(path: P, scene_hash: u64, width: u32, height: u32) -> Option<Self> {
+        if !path.as_ref().exists() {
+            return None;
+        }
+
+        let checkpoint = match Self::load(&path, width, height) {
+            Ok(checkpoint) => checkpoint,
+            Err(e) => {
+                println!("{}, starting over", e);
+                return None;
+            }
+        };
+
+        if checkpoint.scene_hash != scene_hash {
+            println!("Checkpoint {:?} is for a different scene, starting over", path.as_ref());
+            None
+        } else {
+            Some(checkpoint)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn checkpoint(width: u32, height: u32) -> Checkpoint {
+        let mut uniforms: Uniforms = bytemuck::Zeroable::zeroed();
+        uniforms.dimensions = (width as f32, height as f32).into();
+        uniforms.sample_number = 17;
+        uniforms.camera_position = (1.0, 2.0, 3.0).into();
+
+        let pixels = (0..width * height).map(|i| [i as f32, 0.5, -1.0, 17.0]).collect();
+        Checkpoint {
+            scene_hash: 0x0123_4567_89ab_cdef,
+            uniforms,
+            accumulation: HdrImage { width, height, pixels },
+        }
+    }
+
+    fn temporary_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("checkpoint-test-{}-{}.ckpt", std::process::id(), name))
+    }
+
+    #[test]
+    fn save_load_round_trip() {
+        let path = temporary_path("round-trip");
+        let saved = checkpoint(7, 5);
+        saved.save(&path).unwrap();
+
+        let loaded = Checkpoint::load(&path, 7, 5).unwrap();
+        assert_eq!(loaded.scene_hash, saved.scene_hash);
+        assert_eq!(bytemuck::bytes_of(&loaded.uniforms), bytemuck::bytes_of(&saved.uniforms));
+        assert_eq!((loaded.accumulation.width, loaded.accumulation.height), (7, 5));
+        assert_eq!(loaded.accumulation.pixels, saved.accumulation.pixels);
+
+        assert!(Checkpoint::load_matching(&path, saved.scene_hash, 7, 5).is_some());
+        assert!(Checkpoint::load_matching(&path, saved.scene_hash + 1, 7, 5).is_none());
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn rejects_mismatched_size_and_length() {
+        let path = temporary_path("mismatch");
+        checkpoint(7, 5).save(&path).unwrap();
+        assert!(Checkpoint::load(&path, 5, 7).is_err());
+
+        // A header claiming a huge image must not be trusted before the length is checked
+        let mut huge = checkpoint(1, 1);
+        huge.uniforms.dimensions = (65535.0, 65535.0).into();
+        huge.save(&path).unwrap();
+        assert!(Checkpoint::load(&path, 65535, 65535).is_err());
+
+        // Truncated accumulation
+        checkpoint(7, 5).save(&path).unwrap();
+        let bytes = std::fs::read(&path).unwrap();
+        std::fs::write(&path, &bytes[..bytes.len() - 4]).unwrap();
+        assert!(Checkpoint::load(&path, 7, 5).is_err());
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
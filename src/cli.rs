@@ -0,0 +1,264 @@
+use std::path::PathBuf;
+
+use clap::{CommandFactory, ErrorKind, Parser};
+
+use crate::display::RenderResolution;
+use crate::raytrace::Backend;
+use crate::scene::{PreviewSettings, Scene};
+
+/// Interactive GPU path tracer. Settings not given here come from the scene file.
+#[derive(Parser)]
+#[clap(version)]
+pub struct Args {
+    /// Scene file (RON)
+    #[clap(long, default_value = "./res/scenes/default.ron")]
+    pub scene: PathBuf,
+
+    /// Window (or headless image) width in pixels
+    #[clap(long, default_value_t = 1920, value_parser = clap::value_parser!(u32).range(1..=16384))]
+    pub width: u32,
+
+    /// Window (or headless image) height in pixels
+    #[clap(long, default_value_t = 1080, value_parser = clap::value_parser!(u32).range(1..=16384))]
+    pub height: u32,
+
+    /// Render at this size (e.g. 3840x2160) instead of the window's, shown letterboxed in the window
+    #[clap(long, value_name = "WxH", value_parser = parse_size, conflicts_with_all = &["aspect", "headless"])]
+    pub render_size: Option<(u32, u32)>,
+
+    /// Crop the render to this aspect ratio (e.g. 1:1 or 2.39), as large as fits in the window
+    #[clap(long, value_name = "W:H", value_parser = parse_aspect_ratio, conflicts_with = "headless")]
+    pub aspect: Option<f32>,
+
+    /// Frames to accumulate before rendering pauses
+    #[clap(long, value_parser = clap::value_parser!(u32).range(1..))]
+    pub target_samples: Option<u32>,
+
+    /// Rays fired per pixel each frame
+    #[clap(long, value_parser = clap::value_parser!(u32).range(1..=1024))]
+    pub samples_per_frame: Option<u32>,
+
+    /// Max bounces per ray (path length)
+    #[clap(long, value_parser = clap::value_parser!(u32).range(1..=256))]
+    pub max_bounces: Option<u32>,
+
+    /// Relative error at which pixels stop sampling, e.g. 0.01. 0 samples every pixel equally.
+    #[clap(long, value_name = "ERROR", value_parser = parse_non_negative)]
+    pub adaptive_threshold: Option<f32>,
+
+    /// Render resolution while the camera moves, relative to the window (`I` toggles the preview)
+    #[clap(long, value_name = "SCALE", value_parser = parse_scale)]
+    pub preview_scale: Option<f32>,
+
+    /// Max bounces per ray while the camera moves
+    #[clap(long, value_parser = clap::value_parser!(u32).range(1..=256))]
+    pub preview_bounces: Option<u32>,
+
+    /// Seconds after the camera stops before rendering at full quality again
+    #[clap(long, value_name = "SECONDS", value_parser = parse_idle_delay)]
+    pub idle_delay: Option<f32>,
+
+    /// Always render at full quality, also while the camera moves
+    #[clap(long, conflicts_with_all = &["preview-scale", "preview-bounces", "idle-delay"])]
+    pub no_preview: bool,
+
+    /// Initial camera position as x,y,z
+    #[clap(long, value_name = "X,Y,Z", value_parser = parse_vector)]
+    pub camera_position: Option<[f32; 3]>,
+
+    /// Point the camera initially looks at, as x,y,z
+    #[clap(long, value_name = "X,Y,Z", value_parser = parse_vector)]
+    pub look_at: Option<[f32; 3]>,
+
+    /// Vertical field of view in degrees
+    #[clap(long, value_parser = parse_fov)]
+    pub fov: Option<f32>,
+
+    /// Save the render here on exit, and continue it on the next start with the same scene and size
+    #[clap(long, value_name = "FILE", conflicts_with = "headless")]
+    pub checkpoint: Option<PathBuf>,
+
+    /// Also save the checkpoint every this many seconds
+    #[clap(long, value_name = "SECONDS", requires = "checkpoint", value_parser = clap::value_parser!(u64).range(1..))]
+    pub autosave: Option<u64>,
+
+    /// How the ray tracing shader runs (`B` switches while running)
+    #[clap(long, value_enum, default_value_t = Backend::Fragment)]
+    pub backend: Backend,
+
+    /// Start with the denoiser on (`N` toggles while running)
+    #[clap(long, conflicts_with = "headless")]
+    pub denoise: bool,
+
+    /// Render without opening a window, then write the image to --out
+    #[clap(long)]
+    pub headless: bool,
+
+    /// Total samples per pixel for headless renders
+    #[clap(long, default_value_t = 256, requires = "headless", value_parser = clap::value_parser!(u32).range(1..))]
+    pub spp: u32,
+
+    /// Output image. `.exr` keeps linear radiance; other formats are tonemapped.
+    #[clap(long, short, default_value = "frame.png", requires = "headless")]
+    pub out: PathBuf,
+
+    /// Use the CPU reference renderer for headless renders
+    #[clap(long, requires = "headless")]
+    pub cpu: bool,
+}
+
+impl Args {
+    /// Parses the command line, exiting with usage information on invalid arguments
+    pub fn from_command_line() -> Self {
+        let args = Self::parse();
+
+        if let Err(e) = args.validate() {
+            Self::command().error(ErrorKind::ValueValidation, e).exit();
+        }
+
+        args
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        let extension = self.out.extension()
+            .and_then(|extension| extension.to_str())
+            .map(|extension| extension.to_lowercase());
+
+        match extension.as_deref() {
+            Some("exr") => {}
+            _ if image::ImageFormat::from_path(&self.out).is_ok() => {}
+            _ => return Err(format!("Unsupported output format: {:?}", self.out)),
+        }
+
+        Ok(())
+    }
+
+    /// How the render resolution follows the window
+    pub fn render_resolution(&self) -> RenderResolution {
+        match (self.render_size, self.aspect) {
+            (Some((width, height)), _) => RenderResolution::Fixed(width, height),
+            (None, Some(aspect_ratio)) => RenderResolution::Aspect(aspect_ratio),
+            (None, None) => RenderResolution::Window,
+        }
+    }
+
+    /// Overrides the scene file's render settings and camera
+    pub fn apply(&self, scene: &mut Scene) -> Result<(), String> {
+        if let Some(target_samples) = self.target_samples {
+            scene.render.target_samples = target_samples;
+        }
+        if let Some(samples_per_frame) = self.samples_per_frame {
+            scene.render.samples_per_pixel = samples_per_frame;
+        }
+        if let Some(max_bounces) = self.max_bounces {
+            scene.render.max_ray_bounces = max_bounces;
+        }
+        if let Some(adaptive_threshold) = self.adaptive_threshold {
+            scene.render.adaptive_threshold = adaptive_threshold;
+        }
+        if let Some(scale) = self.preview_scale {
+            scene.render.preview.scale = scale;
+        }
+        if let Some(max_bounces) = self.preview_bounces {
+            scene.render.preview.max_ray_bounces = Some(max_bounces);
+        }
+        if let Some(idle_delay) = self.idle_delay {
+            scene.render.preview.idle_delay = idle_delay;
+        }
+        if self.no_preview {
+            scene.render.preview.enabled = false;
+        }
+
+        if let Some(position) = self.camera_position {
+            scene.camera.position = position;
+        }
+        if let Some(look_at) = self.look_at {
+            scene.camera.look_at = look_at;
+        }
+        if let Some(fov) = self.fov {
+            scene.camera.v_fov = fov;
+        }
+
+        scene.validate()
+    }
+}
+
+fn parse_vector(value: &str) -> Result<[f32; 3], String> {
+    let components = value.split(',')
+        .map(|component| component.trim().parse::<f32>().map_err(|e| format!("{:?}: {}", component, e)))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    match components.as_slice() {
+        &[x, y, z] if components.iter().all(|component| component.is_finite()) => Ok([x, y, z]),
+        _ => Err("Expected three numbers separated by commas".to_string()),
+    }
+}
+
+fn parse_size(value: &str) -> Result<(u32, u32), String> {
+    let (width, height) = value.split_once('x').ok_or_else(|| "Expected a size like 1920x1080".to_string())?;
+    let parse = |dimension: &str| match dimension.trim().parse::<u32>() {
+        // Matches the limits of --width and --height
+        Ok(dimension) if (1..=16384).contains(&dimension) => Ok(dimension),
+        Ok(_) => Err("Dimensions must be between 1 and 16384".to_string()),
+        Err(e) => Err(format!("{:?}: {}", dimension, e)),
+    };
+
+    Ok((parse(width)?, parse(height)?))
+}
+
+/// Either a ratio (`16:9`) or a single number (`2.39`)
+fn parse_aspect_ratio(value: &str) -> Result<f32, String> {
+    let parse = |number: &str| number.trim().parse::<f32>().map_err(|e| format!("{:?}: {}", number, e));
+    let aspect_ratio = match value.split_once(':') {
+        Some((width, height)) => parse(width)? / parse(height)?,
+        None => parse(value)?,
+    };
+
+    if aspect_ratio.is_finite() && aspect_ratio > 0.0 {
+        Ok(aspect_ratio)
+    } else {
+        Err("Must be a positive ratio".to_string())
+    }
+}
+
+fn parse_non_negative(value: &str) -> Result<f32, String> {
+    let number: f32 = value.parse().map_err(|e| format!("{}", e))?;
+
+    if number.is_finite() && number >= 0.0 {
+        Ok(number)
+    } else {
+        Err("Must be a non-negative number".to_string())
+    }
+}
+
+fn parse_idle_delay(value: &str) -> Result<f32, String> {
+    let idle_delay = parse_non_negative(value)?;
+
+    // Matches the limit of `Scene::validate`
+    if idle_delay <= PreviewSettings::MAX_IDLE_DELAY {
+        Ok(idle_delay)
+    } else {
+        Err(format!("Must be at most {} seconds", PreviewSettings::MAX_IDLE_DELAY))
+    }
+}
+
+fn parse_scale(value: &str) -> Result<f32, String> {
+    let scale: f32 = value.parse().map_err(|e| format!("{}", e))?;
+
+    if scale > 0.0 && scale <= 1.0 {
+        Ok(scale)
+    } else {
+        Err("Must be greater than 0 and at most 1".to_string())
+    }
+}
+
+fn parse_fov(value: &str) -> Result<f32, String> {
+    let fov: f32 = value.parse().map_err(|e| format!("{}", e))?;
+
+    // Matches the limits of `Camera::update_fov`
+    if (10.0..=160.0).contains(&fov) {
+        Ok(fov)
+    } else {
+        Err("Must be between 10 and 160 degrees".to_string())
+    }
+}
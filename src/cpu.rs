@@ -0,0 +1,2193 @@
+// CPU reference path tracer. Mirrors raytrace.frag.hlsl function for function so the GPU
+// output can be checked against it, and so scenes can be rendered without a GPU.
+
+use cgmath::{ElementWise, InnerSpace, Rotation, Vector3};
+use rayon::prelude::*;
+
+use crate::bvh::{self, PRIM_BOX, PRIM_CAPSULE, PRIM_CONE, PRIM_CSG, PRIM_CYLINDER, PRIM_DISK, PRIM_PLANE, PRIM_RECTANGLE, PRIM_SDF, PRIM_SPHERE, PRIM_TORUS, PRIM_TRIANGLE};
+use crate::raytrace::Uniforms;
+use crate::scene::{
+    GpuBox, GpuCapsule, GpuCone, GpuCylinder, GpuDisk, GpuMaterial, GpuPlane, GpuRectangle, GpuSdfNode, GpuSphere, GpuTorus, Scene,
+    CSG_DIFFERENCE, CSG_INTERSECTION, CSG_LEAF, CSG_MAX_DEPTH, CSG_MAX_SPANS, CSG_UNION,
+    SDF_DIFFERENCE, SDF_INTERSECTION, SDF_MANDELBULB, SDF_MAX_DEPTH, SDF_ROUNDED_BOX, SDF_SPHERE, SDF_TORUS, SDF_UNION,
+    MAT_CONDUCTOR, MAT_DIELECTRIC, MAT_EMISSIVE, MAT_LAMBERTIAN, MAT_METAL, MAT_ROUGH_DIELECTRIC,
+};
+use crate::environment::Environment;
+use crate::texture::HdrImage;
+
+type Vec3 = Vector3<f32>;
+
+const PI: f32 = std::f32::consts::PI;
+const FAR_PLANE_DIST: f32 = 10000.0;
+/// Stands in for a primitive reference when the environment map is sampled
+const ENVIRONMENT_LIGHT: u32 = 0xFFFF_FFFF;
+
+/// Pixels are rendered in square tiles, one rayon task each
+const TILE_SIZE: u32 = 16;
+
+/********** Random Number Generation **********/
+
+/// Xorshift generator. The shader's hash based generator is not reproduced exactly,
+/// so images only match the GPU's statistically.
+struct Random {
+    state: u32,
+}
+
+impl Random {
+    fn new(seed: u32) -> Self {
+        // Wang hash to decorrelate neighbouring seeds
+        let mut state = (seed ^ 61) ^ (seed >> 16);
+        state = state.wrapping_mul(9);
+        state ^= state >> 4;
+        state = state.wrapping_mul(0x27d4_eb2d);
+        state ^= state >> 15;
+
+        Self { state: state.max(1) }
+    }
+
+    /// Random float on [0, 1)
+    fn next(&mut self) -> f32 {
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 17;
+        self.state ^= self.state << 5;
+
+        (self.state >> 8) as f32 / (1 << 24) as f32
+    }
+
+    /// Random float on [min, max)
+    fn range(&mut self, min: f32, max: f32) -> f32 {
+        min + (max - min) * self.next()
+    }
+
+    fn in_unit_sphere(&mut self) -> Vec3 {
+        let phi = 2.0 * PI * self.next();
+        let cos_theta = 2.0 * self.next() - 1.0;
+        let u = self.next();
+
+        let theta = cos_theta.acos();
+        let r = u.powf(1.0 / 3.0);
+
+        Vec3::new(r * theta.sin() * phi.cos(), r * theta.sin() * phi.sin(), r * cos_theta)
+    }
+
+    fn unit_vector(&mut self) -> Vec3 {
+        let a = self.range(0.0, 2.0 * PI);
+        let z = self.range(-1.0, 1.0);
+        let r = (1.0 - z * z).sqrt();
+        Vec3::new(r * a.cos(), r * a.sin(), z)
+    }
+}
+
+/********** Ray **********/
+
+struct Ray {
+    origin: Vec3,
+    direction: Vec3,
+    /// When the ray was fired, for moving shapes
+    time: f32,
+}
+
+impl Ray {
+    fn position(&self, t: f32) -> Vec3 {
+        self.origin + t * self.direction
+    }
+}
+
+struct HitRecord {
+    position: Vec3,
+    normal: Vec3,
+    distance: f32,
+    is_front_face: bool,
+    material_index: u32,
+    /// Encoded primitive reference (see bvh.rs)
+    primitive: u32,
+    /// Surface coordinates, for texturing
+    #[allow(dead_code)] // No material reads them yet
+    uv: (f32, f32),
+}
+
+impl HitRecord {
+    fn new(ray: &Ray, distance: f32, outward_normal: Vec3, uv: (f32, f32), material_index: u32) -> Self {
+        let is_front_face = ray.direction.dot(outward_normal) < 0.0;
+
+        Self {
+            position: ray.position(distance),
+            normal: if is_front_face { outward_normal } else { -outward_normal },
+            distance,
+            is_front_face,
+            material_index,
+            primitive: 0,
+            uv,
+        }
+    }
+}
+
+/********** Materials **********/
+
+fn schlick_approx(cosine: f32, index_of_refraction: f32) -> f32 {
+    let r0 = (1.0 - index_of_refraction) / (1.0 + index_of_refraction);
+    let r0 = r0 * r0;
+    r0 + (1.0 - r0) * (1.0 - cosine).powi(5)
+}
+
+fn reflect(direction: Vec3, normal: Vec3) -> Vec3 {
+    direction - 2.0 * direction.dot(normal) * normal
+}
+
+/// Same as HLSL's `refract` (assumes no total internal reflection)
+fn refract(direction: Vec3, normal: Vec3, eta: f32) -> Vec3 {
+    let cos_theta = direction.dot(normal);
+    let k = 1.0 - eta * eta * (1.0 - cos_theta * cos_theta);
+    if k < 0.0 {
+        Vec3::new(0.0, 0.0, 0.0)
+    } else {
+        eta * direction - (eta * cos_theta + k.sqrt()) * normal
+    }
+}
+
+/// Unpolarized reflectance of a dielectric boundary. `eta` is the index of refraction on the far side
+/// relative to the near side, `cos_i` the cosine on the near side.
+fn fresnel_dielectric(cos_i: f32, eta: f32) -> f32 {
+    let sin2_t = (1.0 - cos_i * cos_i) / (eta * eta);
+    // Total internal reflection
+    if sin2_t >= 1.0 {
+        return 1.0;
+    }
+    let cos_t = (1.0 - sin2_t).sqrt();
+
+    let r_s = (cos_i - eta * cos_t) / (cos_i + eta * cos_t);
+    let r_p = (eta * cos_i - cos_t) / (eta * cos_i + cos_t);
+    0.5 * (r_s * r_s + r_p * r_p)
+}
+
+/// Unpolarized reflectance of a conductor with complex index of refraction `eta + ik`, seen from air
+fn fresnel_conductor(cos_i: f32, eta: Vec3, k: Vec3) -> Vec3 {
+    let channel = |eta: f32, k: f32| {
+        let cos2 = cos_i * cos_i;
+        let sin2 = 1.0 - cos2;
+
+        let t0 = eta * eta - k * k - sin2;
+        let a2_plus_b2 = (t0 * t0 + 4.0 * eta * eta * k * k).sqrt();
+        let a = (0.5 * (a2_plus_b2 + t0)).sqrt();
+
+        let t1 = a2_plus_b2 + cos2;
+        let t2 = 2.0 * cos_i * a;
+        let r_s = (t1 - t2) / (t1 + t2);
+
+        let t3 = cos2 * a2_plus_b2 + sin2 * sin2;
+        let t4 = t2 * sin2;
+        let r_p = r_s * (t3 - t4) / (t3 + t4);
+
+        0.5 * (r_s + r_p)
+    };
+
+    Vec3::new(channel(eta.x, k.x), channel(eta.y, k.y), channel(eta.z, k.z))
+}
+
+/********** GGX Microfacets **********/
+// Directions are in the shading frame (normal along z) and point away from the surface
+
+/// Distribution of microfacet normals `m`
+fn ggx_d(m: Vec3, alpha: f32) -> f32 {
+    let alpha2 = alpha * alpha;
+    let t = m.z * m.z * (alpha2 - 1.0) + 1.0;
+    alpha2 / (PI * t * t)
+}
+
+/// Smith's auxiliary function
+fn ggx_lambda(w: Vec3, alpha: f32) -> f32 {
+    let cos2 = w.z * w.z;
+    let tan2 = (1.0 - cos2).max(0.0) / cos2;
+    ((1.0 + alpha * alpha * tan2).sqrt() - 1.0) / 2.0
+}
+
+/// Fraction of microfacets facing `w` that are visible from it
+fn ggx_g1(w: Vec3, alpha: f32) -> f32 {
+    1.0 / (1.0 + ggx_lambda(w, alpha))
+}
+
+/// Height-correlated masking and shadowing
+fn ggx_g2(wo: Vec3, wi: Vec3, alpha: f32) -> f32 {
+    1.0 / (1.0 + ggx_lambda(wo, alpha) + ggx_lambda(wi, alpha))
+}
+
+/// Samples a microfacet normal visible from `wo` (Heitz 2018, "Sampling the GGX Distribution of Visible Normals")
+fn sample_ggx_vndf(wo: Vec3, alpha: f32, random: &mut Random) -> Vec3 {
+    // Stretch to a hemisphere configuration
+    let v_h = Vec3::new(alpha * wo.x, alpha * wo.y, wo.z).normalize();
+
+    let length2 = v_h.x * v_h.x + v_h.y * v_h.y;
+    let t1 = if length2 > 0.0 { Vec3::new(-v_h.y, v_h.x, 0.0) / length2.sqrt() } else { Vec3::unit_x() };
+    let t2 = v_h.cross(t1);
+
+    // Point on the projected hemisphere
+    let r = random.next().sqrt();
+    let phi = 2.0 * PI * random.next();
+    let p1 = r * phi.cos();
+    let p2 = r * phi.sin();
+    let s = 0.5 * (1.0 + v_h.z);
+    let p2 = (1.0 - s) * (1.0 - p1 * p1).sqrt() + s * p2;
+
+    let n_h = p1 * t1 + p2 * t2 + (1.0 - p1 * p1 - p2 * p2).max(0.0).sqrt() * v_h;
+
+    // Unstretch
+    Vec3::new(alpha * n_h.x, alpha * n_h.y, n_h.z.max(1e-6)).normalize()
+}
+
+/// Density of `sample_ggx_vndf` returning `m`
+fn ggx_vndf_pdf(wo: Vec3, m: Vec3, alpha: f32) -> f32 {
+    ggx_g1(wo, alpha) * wo.dot(m).max(0.0) * ggx_d(m, alpha) / wo.z
+}
+
+/// Shading frame around the hit's normal, which faces the incoming ray
+fn to_local(record: &HitRecord, direction: Vec3) -> Vec3 {
+    let (u, v) = create_basis(record.normal);
+    Vec3::new(direction.dot(u), direction.dot(v), direction.dot(record.normal))
+}
+
+fn to_world(record: &HitRecord, direction: Vec3) -> Vec3 {
+    let (u, v) = create_basis(record.normal);
+    direction.x * u + direction.y * v + direction.z * record.normal
+}
+
+/// Index of refraction across the surface, relative to the side the ray arrived from
+fn relative_index_of_refraction(material: &GpuMaterial, record: &HitRecord) -> f32 {
+    if record.is_front_face {
+        material.index_of_refraction
+    } else {
+        1.0 / material.index_of_refraction
+    }
+}
+
+/// Materials with a BSDF `eval_bsdf` can evaluate, so lights are sampled directly.
+/// Mirror-like metal and smooth glass only scatter.
+fn has_bsdf(material: &GpuMaterial) -> bool {
+    matches!(material.material_type, MAT_LAMBERTIAN | MAT_CONDUCTOR | MAT_ROUGH_DIELECTRIC)
+}
+
+/// Facet refracting `wo` into `wi` for a relative index of refraction `eta`, with the cosines of
+/// both to it. `None` if no facet facing the surface normal does.
+fn refraction_half_vector(wo: Vec3, wi: Vec3, eta: f32) -> Option<(Vec3, f32, f32)> {
+    let m = -(wo + eta * wi).normalize();
+    let m = if m.z < 0.0 { -m } else { m };
+    let (cos_o, cos_i) = (wo.dot(m), wi.dot(m));
+
+    if m.z > 0.0 && cos_o > 0.0 && cos_i < 0.0 {
+        Some((m, cos_o, cos_i))
+    } else {
+        None
+    }
+}
+
+/// BSDF times the cosine of `wi`, for light arriving from `wi` and leaving towards `wo`. Both point
+/// away from the surface. Zero for materials without a BSDF (see `has_bsdf`).
+fn eval_bsdf(material: &GpuMaterial, record: &HitRecord, wo: Vec3, wi: Vec3) -> Vec3 {
+    let black = Vec3::new(0.0, 0.0, 0.0);
+
+    match material.material_type {
+        MAT_LAMBERTIAN => material.albedo / PI * wi.dot(record.normal).max(0.0),
+
+        MAT_CONDUCTOR => {
+            let (wo, wi) = (to_local(record, wo), to_local(record, wi));
+            if wo.z <= 0.0 || wi.z <= 0.0 {
+                return black;
+            }
+
+            let m = (wo + wi).normalize();
+            let fresnel = fresnel_conductor(wo.dot(m), material.conductor_eta, material.conductor_k);
+            material.albedo.mul_element_wise(fresnel) * ggx_d(m, material.alpha) * ggx_g2(wo, wi, material.alpha) / (4.0 * wo.z)
+        }
+
+        MAT_ROUGH_DIELECTRIC => {
+            let (wo, wi) = (to_local(record, wo), to_local(record, wi));
+            if wo.z <= 0.0 || wi.z == 0.0 {
+                return black;
+            }
+            let eta = relative_index_of_refraction(material, record);
+
+            let value = if wi.z > 0.0 {
+                // Reflection
+                let m = (wo + wi).normalize();
+                let fresnel = fresnel_dielectric(wo.dot(m), eta);
+                fresnel * ggx_d(m, material.alpha) * ggx_g2(wo, wi, material.alpha) / (4.0 * wo.z)
+            } else {
+                // Refraction, through the facet bending `wo` into `wi`
+                match refraction_half_vector(wo, wi, eta) {
+                    Some((m, cos_o, cos_i)) => {
+                        let denominator = cos_o + eta * cos_i;
+                        let fresnel = fresnel_dielectric(cos_o, eta);
+                        (1.0 - fresnel) * ggx_d(m, material.alpha) * ggx_g2(wo, wi, material.alpha)
+                            * eta * eta * cos_o * -cos_i / (wo.z * denominator * denominator)
+                    }
+                    None => 0.0,
+                }
+            };
+
+            Vec3::new(value, value, value)
+        }
+
+        _ => black,
+    }
+}
+
+/// Solid angle density of `scatter_ray` choosing `wi`, with the same conventions as `eval_bsdf`
+fn bsdf_pdf(material: &GpuMaterial, record: &HitRecord, wo: Vec3, wi: Vec3) -> f32 {
+    match material.material_type {
+        // Cosine weighted
+        MAT_LAMBERTIAN => wi.dot(record.normal).max(0.0) / PI,
+
+        MAT_CONDUCTOR => {
+            let (wo, wi) = (to_local(record, wo), to_local(record, wi));
+            if wo.z <= 0.0 || wi.z <= 0.0 {
+                return 0.0;
+            }
+
+            let m = (wo + wi).normalize();
+            ggx_vndf_pdf(wo, m, material.alpha) / (4.0 * wo.dot(m))
+        }
+
+        MAT_ROUGH_DIELECTRIC => {
+            let (wo, wi) = (to_local(record, wo), to_local(record, wi));
+            if wo.z <= 0.0 || wi.z == 0.0 {
+                return 0.0;
+            }
+            let eta = relative_index_of_refraction(material, record);
+
+            if wi.z > 0.0 {
+                let m = (wo + wi).normalize();
+                return fresnel_dielectric(wo.dot(m), eta) * ggx_vndf_pdf(wo, m, material.alpha) / (4.0 * wo.dot(m));
+            }
+
+            match refraction_half_vector(wo, wi, eta) {
+                Some((m, cos_o, cos_i)) => {
+                    let denominator = cos_o + eta * cos_i;
+                    (1.0 - fresnel_dielectric(cos_o, eta)) * ggx_vndf_pdf(wo, m, material.alpha)
+                        * eta * eta * -cos_i / (denominator * denominator)
+                }
+                None => 0.0,
+            }
+        }
+
+        _ => 0.0,
+    }
+}
+
+/// Returns the attenuation and scattered ray, or `None` if the ray is absorbed
+fn scatter_ray(material: &GpuMaterial, ray: &Ray, record: &HitRecord, random: &mut Random) -> Option<(Vec3, Ray)> {
+    match material.material_type {
+        MAT_LAMBERTIAN => {
+            let scattered = Ray { origin: record.position, direction: record.normal + random.unit_vector(), time: ray.time };
+            Some((material.albedo, scattered))
+        }
+
+        MAT_METAL => {
+            let reflected = reflect(ray.direction.normalize(), record.normal);
+            let scattered = Ray { origin: record.position, direction: reflected + material.metalic_fuzz * random.in_unit_sphere(), time: ray.time };
+
+            if scattered.direction.dot(record.normal) > 0.0 {
+                Some((material.albedo, scattered))
+            } else {
+                None
+            }
+        }
+
+        MAT_DIELECTRIC => {
+            let attenuation = Vec3::new(1.0, 1.0, 1.0);
+
+            let etai_over_etat = if record.is_front_face {
+                1.0 / material.index_of_refraction
+            } else {
+                material.index_of_refraction
+            };
+
+            let unit_direction = ray.direction.normalize();
+
+            let cos_theta = (-unit_direction).dot(record.normal).min(1.0);
+            let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
+
+            let direction = if etai_over_etat * sin_theta > 1.0 || random.next() < schlick_approx(cos_theta, etai_over_etat) {
+                reflect(unit_direction, record.normal)
+            } else {
+                refract(unit_direction, record.normal, etai_over_etat)
+            };
+
+            Some((attenuation, Ray { origin: record.position, direction, time: ray.time }))
+        }
+
+        // GGX microfacet metal. Facets are sampled by visibility, so the weight is only the
+        // Fresnel reflectance and the shadowing of the reflected direction.
+        MAT_CONDUCTOR => {
+            let wo = to_local(record, -ray.direction.normalize());
+            let m = sample_ggx_vndf(wo, material.alpha, random);
+            let wi = reflect(-wo, m);
+            if wi.z <= 0.0 {
+                return None;
+            }
+
+            let fresnel = fresnel_conductor(wo.dot(m), material.conductor_eta, material.conductor_k);
+            let attenuation = material.albedo.mul_element_wise(fresnel) * ggx_g2(wo, wi, material.alpha) / ggx_g1(wo, material.alpha);
+            Some((attenuation, Ray { origin: record.position, direction: to_world(record, wi), time: ray.time }))
+        }
+
+        // GGX microfacet glass. Reflects or refracts through a visible facet, chosen by its Fresnel
+        // reflectance, which cancels out of the weight.
+        MAT_ROUGH_DIELECTRIC => {
+            let wo = to_local(record, -ray.direction.normalize());
+            let m = sample_ggx_vndf(wo, material.alpha, random);
+            let eta = relative_index_of_refraction(material, record);
+
+            let wi = if random.next() < fresnel_dielectric(wo.dot(m), eta) {
+                let wi = reflect(-wo, m);
+                if wi.z <= 0.0 {
+                    return None;
+                }
+                wi
+            } else {
+                let wi = refract(-wo, m, 1.0 / eta);
+                if wi.z >= 0.0 {
+                    return None;
+                }
+                wi
+            };
+
+            // Like smooth glass, radiance isn't scaled by the change in solid angle when refracting
+            let weight = ggx_g2(wo, wi, material.alpha) / ggx_g1(wo, material.alpha);
+            Some((Vec3::new(weight, weight, weight), Ray { origin: record.position, direction: to_world(record, wi), time: ray.time }))
+        }
+
+        // Lights absorb (emission is handled by `fire_ray`)
+        MAT_EMISSIVE => None,
+
+        _ => None,
+    }
+}
+
+/********** Shapes **********/
+
+fn intersect_sphere(sphere: &GpuSphere, ray: &Ray, dist_min: f32, dist_max: f32) -> Option<HitRecord> {
+    let direction = ray.origin - sphere.center;
+
+    let a = ray.direction.magnitude2();
+    let half_b = direction.dot(ray.direction);
+    let c = direction.magnitude2() - sphere.radius * sphere.radius;
+    let discriminant = half_b * half_b - a * c;
+
+    if discriminant <= 0.0 {
+        return None;
+    }
+
+    let root = discriminant.sqrt();
+    for &distance in &[(-half_b - root) / a, (-half_b + root) / a] {
+        if distance < dist_max && distance > dist_min {
+            let position = ray.position(distance);
+            let outward_normal = (position - sphere.center) / sphere.radius;
+            return Some(HitRecord::new(ray, distance, outward_normal, sphere_uv(sphere, position), sphere.material_index));
+        }
+    }
+
+    None
+}
+
+// Longitude and latitude, with v increasing upwards
+fn sphere_uv(sphere: &GpuSphere, position: Vec3) -> (f32, f32) {
+    let direction = (position - sphere.center) / sphere.radius.abs();
+    (((-direction.z).atan2(direction.x) + PI) / (2.0 * PI), (-direction.y).clamp(-1.0, 1.0).acos() / PI)
+}
+
+fn rectangle_area(rectangle: &GpuRectangle) -> f32 {
+    rectangle.edge_u.cross(rectangle.edge_v).magnitude()
+}
+
+fn intersect_rectangle(rectangle: &GpuRectangle, ray: &Ray, dist_min: f32, dist_max: f32) -> Option<HitRecord> {
+    let n = rectangle.edge_u.cross(rectangle.edge_v);
+    let normal = n.normalize();
+
+    let denominator = normal.dot(ray.direction);
+    // Ray is parallel to the plane
+    if denominator.abs() < 1e-8 {
+        return None;
+    }
+
+    let distance = (rectangle.corner - ray.origin).dot(normal) / denominator;
+    if distance >= dist_max || distance <= dist_min {
+        return None;
+    }
+
+    let planar_position = ray.position(distance) - rectangle.corner;
+    let w = n / n.magnitude2();
+    let alpha = w.dot(planar_position.cross(rectangle.edge_v));
+    let beta = w.dot(rectangle.edge_u.cross(planar_position));
+
+    if !(0.0..=1.0).contains(&alpha) || !(0.0..=1.0).contains(&beta) {
+        return None;
+    }
+
+    Some(HitRecord::new(ray, distance, normal, (alpha, beta), rectangle.material_index))
+}
+
+fn intersect_plane(plane: &GpuPlane, ray: &Ray, dist_min: f32, dist_max: f32) -> Option<HitRecord> {
+    let denominator = plane.normal.dot(ray.direction);
+    // Ray is parallel to the plane
+    if denominator.abs() < 1e-8 {
+        return None;
+    }
+
+    let distance = (plane.origin - ray.origin).dot(plane.normal) / denominator;
+    if distance >= dist_max || distance <= dist_min {
+        return None;
+    }
+
+    // World units along the plane, so textures repeat every unit
+    let (u, v) = create_basis(plane.normal);
+    let offset = ray.position(distance) - plane.origin;
+
+    Some(HitRecord::new(ray, distance, plane.normal, (offset.dot(u), offset.dot(v)), plane.material_index))
+}
+
+fn disk_area(disk: &GpuDisk) -> f32 {
+    PI * disk.radius * disk.radius
+}
+
+fn intersect_disk(disk: &GpuDisk, ray: &Ray, dist_min: f32, dist_max: f32) -> Option<HitRecord> {
+    let denominator = disk.normal.dot(ray.direction);
+    // Ray is parallel to the disk
+    if denominator.abs() < 1e-8 {
+        return None;
+    }
+
+    let distance = (disk.center - ray.origin).dot(disk.normal) / denominator;
+    if distance >= dist_max || distance <= dist_min {
+        return None;
+    }
+
+    let offset = ray.position(distance) - disk.center;
+    let distance_squared = offset.magnitude2();
+    if distance_squared > disk.radius * disk.radius {
+        return None;
+    }
+
+    // Angle around the normal, then distance from the center
+    let uv = (azimuth(disk.normal, offset), distance_squared.sqrt() / disk.radius);
+
+    Some(HitRecord::new(ray, distance, disk.normal, uv, disk.material_index))
+}
+
+/// Fraction of a turn `offset` is around `axis`, in [0, 1]
+fn azimuth(axis: Vec3, offset: Vec3) -> f32 {
+    let (u, v) = create_basis(axis);
+    (offset.dot(v).atan2(offset.dot(u)) + PI) / (2.0 * PI)
+}
+
+/// Stretch of a ray inside a convex shape, with the outward normals where it enters and exits.
+/// Ends may be infinite, their normals are then meaningless.
+#[derive(Copy, Clone)]
+struct Interval {
+    enter: f32,
+    exit: f32,
+    enter_normal: Vec3,
+    exit_normal: Vec3,
+}
+
+impl Interval {
+    fn everything() -> Self {
+        let zero = Vec3::new(0.0, 0.0, 0.0);
+        Self { enter: f32::NEG_INFINITY, exit: f32::INFINITY, enter_normal: zero, exit_normal: zero }
+    }
+
+    fn intersect(&self, other: &Interval) -> Option<Interval> {
+        let (enter, enter_normal) = if self.enter > other.enter { (self.enter, self.enter_normal) } else { (other.enter, other.enter_normal) };
+        let (exit, exit_normal) = if self.exit < other.exit { (self.exit, self.exit_normal) } else { (other.exit, other.exit_normal) };
+
+        if enter > exit {
+            None
+        } else {
+            Some(Interval { enter, exit, enter_normal, exit_normal })
+        }
+    }
+
+    /// Smallest interval covering both. Only meaningful if they overlap.
+    fn cover(&self, other: &Interval) -> Interval {
+        let (enter, enter_normal) = if self.enter < other.enter { (self.enter, self.enter_normal) } else { (other.enter, other.enter_normal) };
+        let (exit, exit_normal) = if self.exit > other.exit { (self.exit, self.exit_normal) } else { (other.exit, other.exit_normal) };
+
+        Interval { enter, exit, enter_normal, exit_normal }
+    }
+
+    /// Distance and outward normal of the first boundary within the range: the entry, or the exit if
+    /// the ray starts inside
+    fn first_hit(&self, dist_min: f32, dist_max: f32) -> Option<(f32, Vec3)> {
+        let (distance, normal) = if self.enter > dist_min { (self.enter, self.enter_normal) } else { (self.exit, self.exit_normal) };
+
+        if distance >= dist_max || distance <= dist_min {
+            None
+        } else {
+            Some((distance, normal))
+        }
+    }
+}
+
+/// Outward normal in the box's frame, and coordinates in [-1, 1] on the face, of a point on a box
+/// relative to its half extents
+fn box_face(relative: Vec3) -> (Vec3, (f32, f32)) {
+    // The face is the one the point is furthest out towards, relative to the box's size
+    let (x, y, z) = (relative.x.abs(), relative.y.abs(), relative.z.abs());
+    if x >= y && x >= z {
+        (Vec3::new(relative.x.signum(), 0.0, 0.0), (relative.z, relative.y))
+    } else if y >= z {
+        (Vec3::new(0.0, relative.y.signum(), 0.0), (relative.x, relative.z))
+    } else {
+        (Vec3::new(0.0, 0.0, relative.z.signum()), (relative.x, relative.y))
+    }
+}
+
+// Slab test in the box's own frame
+fn box_interval(box_primitive: &GpuBox, ray: &Ray) -> Option<Interval> {
+    let rotation = box_primitive.rotation();
+    let inverse_rotation = rotation.conjugate();
+    let origin = inverse_rotation.rotate_vector(ray.origin - box_primitive.center);
+    let direction = inverse_rotation.rotate_vector(ray.direction);
+    let half_extents = box_primitive.half_extents;
+
+    let mut t_enter = f32::NEG_INFINITY;
+    let mut t_exit = f32::INFINITY;
+    for axis in 0..3 {
+        let t0 = (-half_extents[axis] - origin[axis]) / direction[axis];
+        let t1 = (half_extents[axis] - origin[axis]) / direction[axis];
+        t_enter = t_enter.max(t0.min(t1));
+        t_exit = t_exit.min(t0.max(t1));
+    }
+    if t_enter > t_exit {
+        return None;
+    }
+
+    let normal = |t: f32| rotation.rotate_vector(box_face((origin + t * direction).div_element_wise(half_extents)).0);
+    Some(Interval { enter: t_enter, exit: t_exit, enter_normal: normal(t_enter), exit_normal: normal(t_exit) })
+}
+
+fn intersect_box(box_primitive: &GpuBox, ray: &Ray, dist_min: f32, dist_max: f32) -> Option<HitRecord> {
+    let (distance, normal) = box_interval(box_primitive, ray)?.first_hit(dist_min, dist_max)?;
+
+    let local_position = box_primitive.rotation().conjugate().rotate_vector(ray.position(distance) - box_primitive.center);
+    let (_, (u, v)) = box_face(local_position.div_element_wise(box_primitive.half_extents));
+
+    // Each face spans [0, 1]
+    let uv = (u * 0.5 + 0.5, v * 0.5 + 0.5);
+    Some(HitRecord::new(ray, distance, normal, uv, box_primitive.material_index))
+}
+
+fn sphere_interval(center: Vec3, radius: f32, ray: &Ray) -> Option<Interval> {
+    let direction = ray.origin - center;
+
+    let a = ray.direction.magnitude2();
+    let half_b = direction.dot(ray.direction);
+    let c = direction.magnitude2() - radius * radius;
+    let discriminant = half_b * half_b - a * c;
+    if discriminant < 0.0 {
+        return None;
+    }
+
+    let root = discriminant.sqrt();
+    let (enter, exit) = ((-half_b - root) / a, (-half_b + root) / a);
+    Some(Interval {
+        enter,
+        exit,
+        enter_normal: (ray.position(enter) - center) / radius,
+        exit_normal: (ray.position(exit) - center) / radius,
+    })
+}
+
+/// Cone from `a` to `b` with its radius changing linearly between them, capped at both ends.
+/// Equal radii give a cylinder.
+fn capped_cone_interval(a: Vec3, b: Vec3, radius_a: f32, radius_b: f32, ray: &Ray) -> Option<Interval> {
+    let height = (b - a).magnitude();
+    let w = (b - a) / height;
+    let slope = (radius_b - radius_a) / height;
+
+    let o = ray.origin - a;
+    let d = ray.direction;
+    let (o_axis, d_axis) = (o.dot(w), d.dot(w));
+    let (o_perp, d_perp) = (o - o_axis * w, d - d_axis * w);
+
+    // Between the caps
+    let caps = if d_axis.abs() < 1e-8 {
+        if o_axis < 0.0 || o_axis > height {
+            return None;
+        }
+        Interval::everything()
+    } else {
+        let (t0, t1) = (-o_axis / d_axis, (height - o_axis) / d_axis);
+        if t0 < t1 {
+            Interval { enter: t0, exit: t1, enter_normal: -w, exit_normal: w }
+        } else {
+            Interval { enter: t1, exit: t0, enter_normal: w, exit_normal: -w }
+        }
+    };
+
+    // Inside the infinite double cone |p_perp| <= radius_a + slope * s, which is
+    // qa t^2 + 2 qb t + qc <= 0 along the ray
+    let r0 = radius_a + slope * o_axis;
+    let r1 = slope * d_axis;
+    let qa = d_perp.magnitude2() - r1 * r1;
+    let qb = o_perp.dot(d_perp) - r0 * r1;
+    let qc = o_perp.magnitude2() - r0 * r0;
+
+    // Gradient of |p_perp|^2 - r(s)^2
+    let side_normal = |t: f32| {
+        let radius = r0 + r1 * t;
+        (o_perp + t * d_perp - radius * slope * w).normalize()
+    };
+    let from = |t: f32| Interval { enter: t, enter_normal: side_normal(t), ..Interval::everything() };
+    let until = |t: f32| Interval { exit: t, exit_normal: side_normal(t), ..Interval::everything() };
+
+    let side = if qa.abs() < 1e-8 * d.magnitude2() {
+        // Parallel to the cone's surface
+        if qb.abs() < 1e-12 {
+            if qc > 0.0 {
+                return None;
+            }
+            Interval::everything()
+        } else if qb > 0.0 {
+            until(-qc / (2.0 * qb))
+        } else {
+            from(-qc / (2.0 * qb))
+        }
+    } else {
+        let discriminant = qb * qb - qa * qc;
+        if discriminant < 0.0 {
+            // Never on the surface: always outside, or (through both nappes' insides) always inside
+            if qa > 0.0 {
+                return None;
+            }
+            Interval::everything()
+        } else {
+            let root = discriminant.sqrt();
+            let (t0, t1) = ((-qb - root) / qa, (-qb + root) / qa);
+            let (t0, t1) = (t0.min(t1), t0.max(t1));
+
+            if qa > 0.0 {
+                Interval { enter: t0, exit: t1, enter_normal: side_normal(t0), exit_normal: side_normal(t1) }
+            } else {
+                // Through both nappes. Only one of them is between the caps.
+                let before = until(t0).intersect(&caps);
+                let after = from(t1).intersect(&caps);
+                return match (before, after) {
+                    (Some(before), Some(after)) => Some(if before.exit - before.enter > after.exit - after.enter { before } else { after }),
+                    (before, after) => before.or(after),
+                };
+            }
+        }
+    };
+
+    side.intersect(&caps)
+}
+
+/// Points within `radius` of the segment from `a` to `b`: a cylinder and the spheres capping it
+fn capsule_interval(capsule: &GpuCapsule, ray: &Ray) -> Option<Interval> {
+    let (a, b, radius) = (capsule.a, capsule.b, capsule.radius);
+
+    // The capsule is convex, so the pieces overlap wherever the ray crosses it
+    [
+        capped_cone_interval(a, b, radius, radius, ray),
+        sphere_interval(a, radius, ray),
+        sphere_interval(b, radius, ray),
+    ]
+        .iter()
+        .flatten()
+        .fold(None, |covered: Option<Interval>, interval| Some(covered.map_or(*interval, |covered| covered.cover(interval))))
+}
+
+/// Azimuth, then distance along the axis (including the caps)
+fn axial_uv(a: Vec3, b: Vec3, padding: f32, position: Vec3) -> (f32, f32) {
+    let height = (b - a).magnitude();
+    let w = (b - a) / height;
+    let offset = position - a;
+    (azimuth(w, offset), (offset.dot(w) + padding) / (height + 2.0 * padding))
+}
+
+fn intersect_cylinder(cylinder: &GpuCylinder, ray: &Ray, dist_min: f32, dist_max: f32) -> Option<HitRecord> {
+    let (distance, normal) = capped_cone_interval(cylinder.a, cylinder.b, cylinder.radius, cylinder.radius, ray)?
+        .first_hit(dist_min, dist_max)?;
+
+    let uv = axial_uv(cylinder.a, cylinder.b, 0.0, ray.position(distance));
+    Some(HitRecord::new(ray, distance, normal, uv, cylinder.material_index))
+}
+
+fn intersect_cone(cone: &GpuCone, ray: &Ray, dist_min: f32, dist_max: f32) -> Option<HitRecord> {
+    let (distance, normal) = capped_cone_interval(cone.a, cone.b, cone.radius_a, cone.radius_b, ray)?
+        .first_hit(dist_min, dist_max)?;
+
+    let uv = axial_uv(cone.a, cone.b, 0.0, ray.position(distance));
+    Some(HitRecord::new(ray, distance, normal, uv, cone.material_index))
+}
+
+fn intersect_capsule(capsule: &GpuCapsule, ray: &Ray, dist_min: f32, dist_max: f32) -> Option<HitRecord> {
+    let (distance, normal) = capsule_interval(capsule, ray)?.first_hit(dist_min, dist_max)?;
+
+    let uv = axial_uv(capsule.a, capsule.b, capsule.radius, ray.position(distance));
+    Some(HitRecord::new(ray, distance, normal, uv, capsule.material_index))
+}
+
+/// Sorted, disjoint spans of a ray inside part of a CSG shape
+#[derive(Copy, Clone)]
+struct SpanList {
+    count: usize,
+    spans: [Interval; CSG_MAX_SPANS],
+}
+
+impl SpanList {
+    fn new(interval: Option<Interval>) -> Self {
+        let mut list = Self { count: 0, spans: [Interval::everything(); CSG_MAX_SPANS] };
+        if let Some(interval) = interval {
+            list.spans[0] = interval;
+            list.count = 1;
+        }
+        list
+    }
+
+    /// Entry (even) or exit (odd) of a span, with its outward normal
+    fn boundary(&self, i: usize) -> (f32, Vec3) {
+        let span = &self.spans[i / 2];
+        if i % 2 == 1 { (span.exit, span.exit_normal) } else { (span.enter, span.enter_normal) }
+    }
+
+    /// Walks the boundaries of both lists in order, tracking whether the ray is inside each, and
+    /// keeps those where that changes whether it is inside the combination
+    fn combine(operation: u32, a: &SpanList, b: &SpanList) -> SpanList {
+        let mut combined = SpanList::new(None);
+        let (mut i, mut j) = (0, 0);
+        let (mut inside_a, mut inside_b, mut inside) = (false, false, false);
+        let mut enter = (0.0, Vec3::new(0.0, 0.0, 0.0));
+
+        while i < 2 * a.count || j < 2 * b.count {
+            let from_a = j == 2 * b.count || (i < 2 * a.count && a.boundary(i).0 <= b.boundary(j).0);
+            let (distance, mut normal) = if from_a {
+                inside_a = !inside_a;
+                i += 1;
+                a.boundary(i - 1)
+            } else {
+                inside_b = !inside_b;
+                j += 1;
+                b.boundary(j - 1)
+            };
+            // Cut out surfaces face into the shape that was cut
+            if !from_a && operation == CSG_DIFFERENCE {
+                normal = -normal;
+            }
+
+            let now_inside = match operation {
+                CSG_UNION => inside_a || inside_b,
+                CSG_INTERSECTION => inside_a && inside_b,
+                _ => inside_a && !inside_b,
+            };
+            if now_inside && !inside {
+                enter = (distance, normal);
+            } else if !now_inside && inside && combined.count < CSG_MAX_SPANS {
+                combined.spans[combined.count] = Interval { enter: enter.0, exit: distance, enter_normal: enter.1, exit_normal: normal };
+                combined.count += 1;
+            }
+            inside = now_inside;
+        }
+
+        combined
+    }
+}
+
+// Cube root that keeps the sign
+fn cbrt(x: f32) -> f32 {
+    x.signum() * x.abs().powf(1.0 / 3.0)
+}
+
+/// Largest real root of t^3 + a t^2 + b t + c
+fn largest_cubic_root(a: f32, b: f32, c: f32) -> f32 {
+    // Depressed cubic x^3 + p x + q, with t = x - a / 3
+    let p = b - a * a / 3.0;
+    let q = 2.0 * a * a * a / 27.0 - a * b / 3.0 + c;
+
+    let discriminant = q * q / 4.0 + p * p * p / 27.0;
+    let x = if discriminant >= 0.0 {
+        // One real root (Cardano)
+        let root = discriminant.sqrt();
+        cbrt(-q / 2.0 + root) + cbrt(-q / 2.0 - root)
+    } else {
+        // Three real roots, p < 0 (trigonometric)
+        let r = (-p / 3.0).sqrt();
+        let phi = (-q / (2.0 * r * r * r)).clamp(-1.0, 1.0).acos();
+        2.0 * r * (phi / 3.0).cos()
+    };
+
+    // Cardano's formula cancels badly, and quartics amplify the error (see `solve_quartic`)
+    let mut t = x - a / 3.0;
+    for _ in 0..2 {
+        let f = ((t + a) * t + b) * t + c;
+        let derivative = (3.0 * t + 2.0 * a) * t + b;
+        if derivative != 0.0 {
+            t -= f / derivative;
+        }
+    }
+    t
+}
+
+/// Real roots of t^4 + b t^3 + c t^2 + d t + e (Ferrari), polished with Newton's method. Returns them
+/// unordered, with their count.
+fn solve_quartic(b: f32, c: f32, d: f32, e: f32) -> ([f32; 4], usize) {
+    // Depressed quartic y^4 + p y^2 + q y + r, with t = y - b / 4
+    let b2 = b * b;
+    let p = c - 3.0 * b2 / 8.0;
+    let q = d - b * c / 2.0 + b2 * b / 8.0;
+    let r = e - b * d / 4.0 + b2 * c / 16.0 - 3.0 * b2 * b2 / 256.0;
+
+    let mut roots = [0.0; 4];
+    let mut count = 0;
+    let mut solve_quadratic = |linear: f32, constant: f32| {
+        let discriminant = linear * linear / 4.0 - constant;
+        if discriminant >= 0.0 {
+            let root = discriminant.sqrt();
+            roots[count] = -linear / 2.0 - root;
+            roots[count + 1] = -linear / 2.0 + root;
+            count += 2;
+        }
+    };
+
+    // Ferrari's method loses all precision as q goes to zero, so nearly biquadratic quartics are
+    // solved as if they were, and left to the polishing below
+    let scale = p.abs().sqrt().max(r.abs().sqrt().sqrt());
+    if q.abs() < 1e-3 * scale * scale * scale {
+        // Biquadratic: a quadratic in y^2
+        let discriminant = p * p / 4.0 - r;
+        if discriminant >= 0.0 {
+            for &y2 in &[-p / 2.0 - discriminant.sqrt(), -p / 2.0 + discriminant.sqrt()] {
+                if y2 >= 0.0 {
+                    solve_quadratic(0.0, -y2);
+                }
+            }
+        }
+    } else {
+        // (y^2 + s)^2 = (alpha y - beta)^2 for the largest root s of the resolvent cubic,
+        // which always has 2s - p > 0 when q != 0
+        let s = largest_cubic_root(-p / 2.0, -r, p * r / 2.0 - q * q / 8.0);
+        let alpha = (2.0 * s - p).max(0.0).sqrt();
+        if alpha > 0.0 {
+            let beta = q / (2.0 * alpha);
+            solve_quadratic(-alpha, s + beta);
+            solve_quadratic(alpha, s - beta);
+        }
+    }
+
+    for root in &mut roots[..count] {
+        let mut t = *root - b / 4.0;
+        for _ in 0..2 {
+            let f = (((t + b) * t + c) * t + d) * t + e;
+            let derivative = ((4.0 * t + 3.0 * b) * t + 2.0 * c) * t + d;
+            if derivative != 0.0 {
+                t -= f / derivative;
+            }
+        }
+        *root = t;
+    }
+
+    (roots, count)
+}
+
+fn intersect_torus(torus: &GpuTorus, ray: &Ray, dist_min: f32, dist_max: f32) -> Option<HitRecord> {
+    let (major, minor) = (torus.major_radius, torus.minor_radius);
+
+    // Local frame with the axis along z, and a unit length direction
+    let (u, v) = create_basis(torus.axis);
+    let to_local = |x: Vec3| Vec3::new(x.dot(u), x.dot(v), x.dot(torus.axis));
+    let length = ray.direction.magnitude();
+    let d = to_local(ray.direction / length);
+
+    // The quartic's coefficients lose precision far from the torus, so distances are measured
+    // from the ray's closest approach to the center. This also removes the cubic term.
+    let shift = -to_local(ray.origin - torus.center).dot(d);
+    let o = to_local(ray.origin - torus.center) + shift * d;
+    if o.magnitude() > major + minor {
+        return None;
+    }
+
+    // (|p|^2 + R^2 - r^2)^2 = 4 R^2 (p_x^2 + p_y^2)
+    let k = o.magnitude2() + major * major - minor * minor;
+    let major2 = 4.0 * major * major;
+    let (roots, count) = solve_quartic(
+        0.0,
+        2.0 * k - major2 * (d.x * d.x + d.y * d.y),
+        -major2 * 2.0 * (o.x * d.x + o.y * d.y),
+        k * k - major2 * (o.x * o.x + o.y * o.y),
+    );
+
+    let t = roots[..count].iter()
+        .map(|&root| (root + shift) / length)
+        .filter(|&distance| distance > dist_min && distance < dist_max)
+        .fold(f32::INFINITY, f32::min);
+    if t == f32::INFINITY {
+        return None;
+    }
+
+    // Away from the closest point on the center circle
+    let p = o + (t * length - shift) * d;
+    let ring = Vec3::new(p.x, p.y, 0.0).normalize() * major;
+    let n = (p - ring).normalize();
+    let outward_normal = n.x * u + n.y * v + n.z * torus.axis;
+
+    // Around the axis, then around the tube
+    let uv = (
+        (p.y.atan2(p.x) + PI) / (2.0 * PI),
+        (p.z.atan2(Vec3::new(p.x, p.y, 0.0).magnitude() - major) + PI) / (2.0 * PI),
+    );
+
+    Some(HitRecord::new(ray, t, outward_normal, uv, torus.material_index))
+}
+
+/********** Signed Distance Fields **********/
+
+const SDF_MAX_STEPS: u32 = 256;
+/// Sphere tracing stops this close to the surface
+const SDF_HIT_DISTANCE: f32 = 1e-4;
+/// Of the finite differences for normals
+const SDF_NORMAL_OFFSET: f32 = 5e-4;
+
+/// Polynomial smooth minimum, blending over `smoothness` (see Inigo Quilez's "Smooth Minimum")
+fn smooth_min(a: f32, b: f32, smoothness: f32) -> f32 {
+    if smoothness <= 0.0 {
+        return a.min(b);
+    }
+
+    let h = (smoothness - (a - b).abs()).max(0.0) / smoothness;
+    a.min(b) - h * h * smoothness / 4.0
+}
+
+/// Distance estimate from the running derivative of the escape time iteration, around the z axis
+fn mandelbulb_distance(position: Vec3, power: f32, iterations: u32) -> f32 {
+    let mut z = position;
+    let mut r = z.magnitude();
+    let mut derivative = 1.0;
+
+    for _ in 0..iterations {
+        if r > 2.0 {
+            break;
+        }
+
+        // Raise to the power in spherical coordinates
+        let theta = (z.z / r).clamp(-1.0, 1.0).acos() * power;
+        let phi = z.y.atan2(z.x) * power;
+        derivative = power * r.powf(power - 1.0) * derivative + 1.0;
+        z = r.powf(power) * Vec3::new(theta.sin() * phi.cos(), theta.sin() * phi.sin(), theta.cos()) + position;
+        r = z.magnitude();
+    }
+
+    0.5 * r.ln() * r / derivative
+}
+
+/// Evaluates an SDF tree's postfix program on a stack of distances
+fn sdf_distance(nodes: &[GpuSdfNode], position: Vec3) -> f32 {
+    let mut stack = [0.0; SDF_MAX_DEPTH];
+    let mut depth = 0;
+
+    for node in nodes {
+        let parameters = node.parameters;
+        let distance = match node.kind {
+            SDF_UNION | SDF_INTERSECTION | SDF_DIFFERENCE => {
+                depth -= 2;
+                let (a, b) = (stack[depth], stack[depth + 1]);
+                match node.kind {
+                    SDF_UNION => smooth_min(a, b, parameters.x),
+                    SDF_INTERSECTION => -smooth_min(-a, -b, parameters.x),
+                    _ => -smooth_min(-a, b, parameters.x),
+                }
+            }
+            kind => {
+                let local = node.rotation().conjugate().rotate_vector(position - node.center);
+                match kind {
+                    SDF_SPHERE => local.magnitude() - parameters.x,
+                    SDF_ROUNDED_BOX => {
+                        let rounding = parameters.w;
+                        let q = Vec3::new(local.x.abs(), local.y.abs(), local.z.abs()) - (parameters.truncate() - Vec3::new(rounding, rounding, rounding));
+                        let outside = Vec3::new(q.x.max(0.0), q.y.max(0.0), q.z.max(0.0)).magnitude();
+                        outside + q.x.max(q.y).max(q.z).min(0.0) - rounding
+                    }
+                    SDF_TORUS => {
+                        let ring = (local.x * local.x + local.z * local.z).sqrt() - parameters.x;
+                        (ring * ring + local.y * local.y).sqrt() - parameters.y
+                    }
+                    SDF_MANDELBULB => {
+                        let scale = parameters.x;
+                        let local = Vec3::new(local.x, local.z, local.y) / scale;
+                        mandelbulb_distance(local, parameters.y, parameters.z as u32) * scale
+                    }
+                    _ => f32::INFINITY,
+                }
+            }
+        };
+
+        stack[depth] = distance;
+        depth += 1;
+    }
+
+    stack[0]
+}
+
+/// Outward normal from the distance's gradient, by finite differences on a tetrahedron
+fn sdf_normal(nodes: &[GpuSdfNode], position: Vec3) -> Vec3 {
+    [Vec3::new(1.0, -1.0, -1.0), Vec3::new(-1.0, -1.0, 1.0), Vec3::new(-1.0, 1.0, -1.0), Vec3::new(1.0, 1.0, 1.0)]
+        .iter()
+        .map(|&offset| offset * sdf_distance(nodes, position + SDF_NORMAL_OFFSET * offset))
+        .fold(Vec3::new(0.0, 0.0, 0.0), |gradient, term| gradient + term)
+        .normalize()
+}
+
+/********** Camera **********/
+
+struct Camera {
+    position: Vec3,
+    bottom_left: Vec3,
+    horizontal: Vec3,
+    vertical: Vec3,
+    shutter_open: f32,
+    shutter_close: f32,
+}
+
+impl Camera {
+    fn new(uniforms: &Uniforms) -> Self {
+        let position = uniforms.camera_position;
+        let lookat = uniforms.camera_direction() + position;
+        let v_up = Vec3::unit_y();
+        // Distance between the shader's hard coded camera position and lookat
+        let focal_dist = 6.0;
+
+        let theta = uniforms.camera_v_fov.to_radians();
+        let viewport_height = 2.0 * (theta / 2.0).tan();
+        let viewport_width = viewport_height * (uniforms.dimensions.x / uniforms.dimensions.y);
+
+        let w = (position - lookat).normalize();
+        let u = v_up.cross(w).normalize();
+        let v = w.cross(u);
+
+        let horizontal = viewport_width * u;
+        let vertical = viewport_height * v;
+        let bottom_left = position - horizontal / 2.0 - vertical / 2.0 - focal_dist * w;
+
+        Self {
+            position,
+            bottom_left,
+            horizontal,
+            vertical,
+            shutter_open: uniforms.shutter_open,
+            shutter_close: uniforms.shutter_close,
+        }
+    }
+
+    /// The aperture is always closed, so rays leave from the camera's position.
+    /// Only an open shutter draws a random time.
+    fn create_ray(&self, u: f32, v: f32, random: &mut Random) -> Ray {
+        let time = if self.shutter_close > self.shutter_open {
+            random.range(self.shutter_open, self.shutter_close)
+        } else {
+            self.shutter_open
+        };
+
+        Ray {
+            origin: self.position,
+            direction: self.bottom_left + u * self.horizontal + v * self.vertical - self.position,
+            time,
+        }
+    }
+}
+
+/********** Environment **********/
+
+// Rotates about the y axis
+fn rotate_y(direction: Vec3, angle: f32) -> Vec3 {
+    let (s, c) = angle.sin_cos();
+    Vec3::new(c * direction.x + s * direction.z, direction.y, -s * direction.x + c * direction.z)
+}
+
+/// Index of the first CDF value exceeding `u`
+fn search_cdf(cdf: &[f32], u: f32) -> usize {
+    cdf.iter().position(|&value| value > u).unwrap_or(cdf.len() - 1)
+}
+
+fn power_heuristic(pdf_a: f32, pdf_b: f32) -> f32 {
+    let a2 = pdf_a * pdf_a;
+    a2 / (a2 + pdf_b * pdf_b)
+}
+
+// Orthonormal basis around `w`
+fn create_basis(w: Vec3) -> (Vec3, Vec3) {
+    let a = if w.x.abs() > 0.9 { Vec3::unit_y() } else { Vec3::unit_x() };
+    let v = w.cross(a).normalize();
+    let u = w.cross(v);
+    (u, v)
+}
+
+/// Renders the same image as `RayTracer` (see raytrace.frag.hlsl) on the CPU
+pub struct CpuRayTracer<'a> {
+    scene: &'a Scene,
+    uniforms: Uniforms,
+    camera: Camera,
+}
+
+impl<'a> CpuRayTracer<'a> {
+    /// `uniforms` gives the image size, camera, and render settings, exactly as the shader receives them
+    pub fn new(scene: &'a Scene, uniforms: Uniforms) -> Self {
+        Self {
+            scene,
+            uniforms,
+            camera: Camera::new(&uniforms),
+        }
+    }
+
+    /// Renders `frames` frames of `samples_per_pixel` samples each and returns their average.
+    ///
+    /// Rows are ordered top to bottom, like the GPU's accumulation buffer.
+    pub fn render(&self, frames: u32) -> HdrImage {
+        let width = self.uniforms.dimensions.x as u32;
+        let height = self.uniforms.dimensions.y as u32;
+
+        let tiles: Vec<(u32, u32)> = (0..height).step_by(TILE_SIZE as usize)
+            .flat_map(|y| (0..width).step_by(TILE_SIZE as usize).map(move |x| (x, y)))
+            .collect();
+
+        let rendered_tiles: Vec<Vec<[f32; 4]>> = tiles.par_iter()
+            .map(|&(tile_x, tile_y)| {
+                let mut pixels = Vec::with_capacity((TILE_SIZE * TILE_SIZE) as usize);
+                for y in tile_y..(tile_y + TILE_SIZE).min(height) {
+                    for x in tile_x..(tile_x + TILE_SIZE).min(width) {
+                        let color = self.render_pixel(x, y, frames);
+                        pixels.push([color.x, color.y, color.z, 1.0]);
+                    }
+                }
+                pixels
+            })
+            .collect();
+
+        let mut pixels = vec![[0.0; 4]; (width * height) as usize];
+        for (&(tile_x, tile_y), tile) in tiles.iter().zip(&rendered_tiles) {
+            let tile_width = (tile_x + TILE_SIZE).min(width) - tile_x;
+            for (i, row) in tile.chunks(tile_width as usize).enumerate() {
+                let start = ((tile_y + i as u32) * width + tile_x) as usize;
+                pixels[start..start + row.len()].copy_from_slice(row);
+            }
+        }
+
+        HdrImage { width, height, pixels }
+    }
+
+    fn render_pixel(&self, x: u32, y: u32, frames: u32) -> Vec3 {
+        let width = self.uniforms.dimensions.x;
+        let height = self.uniforms.dimensions.y;
+        let samples_per_pixel = self.uniforms.samples_per_pixel.max(1);
+
+        let mut color = Vec3::new(0.0, 0.0, 0.0);
+
+        for frame in 0..frames.max(1) {
+            let sample_number = self.uniforms.sample_number + frame;
+            let pixel_index = y * width as u32 + x;
+            let mut random = Random::new(pixel_index.wrapping_mul(0x9e37_79b9) ^ sample_number.wrapping_mul(0x85eb_ca6b));
+
+            let mut frame_color = Vec3::new(0.0, 0.0, 0.0);
+            for _ in 0..samples_per_pixel {
+                // Fragment coordinates are pixel centers
+                let u = (x as f32 + 0.5 + random.next()) / width;
+                let v = 1.0 - (y as f32 + 0.5 + random.next()) / height;
+
+                let ray = self.camera.create_ray(u, v, &mut random);
+                frame_color += self.fire_ray(ray, &mut random);
+            }
+            color += frame_color / samples_per_pixel as f32;
+        }
+
+        color / frames.max(1) as f32
+    }
+
+    /********** Scene **********/
+
+    fn intersect_primitive(&self, primitive: u32, ray: &Ray, dist_min: f32, dist_max: f32) -> Option<HitRecord> {
+        let (kind, index) = bvh::decode_primitive(primitive);
+        let index = index as usize;
+
+        let record = match kind {
+            PRIM_SPHERE => intersect_sphere(&self.scene.spheres[index].at(ray.time), ray, dist_min, dist_max),
+            PRIM_TRIANGLE => self.intersect_triangle(index, ray, dist_min, dist_max),
+            PRIM_RECTANGLE => intersect_rectangle(&self.scene.rectangles[index], ray, dist_min, dist_max),
+            PRIM_DISK => intersect_disk(&self.scene.disks[index], ray, dist_min, dist_max),
+            PRIM_BOX => intersect_box(&self.scene.boxes[index].at(ray.time), ray, dist_min, dist_max),
+            PRIM_CYLINDER => intersect_cylinder(&self.scene.cylinders[index], ray, dist_min, dist_max),
+            PRIM_CONE => intersect_cone(&self.scene.cones[index], ray, dist_min, dist_max),
+            PRIM_CAPSULE => intersect_capsule(&self.scene.capsules[index], ray, dist_min, dist_max),
+            PRIM_TORUS => intersect_torus(&self.scene.tori[index], ray, dist_min, dist_max),
+            PRIM_CSG => self.intersect_csg(index, ray, dist_min, dist_max),
+            PRIM_SDF => self.intersect_sdf(index, ray, dist_min, dist_max),
+            _ => None,
+        };
+
+        record.map(|record| HitRecord { primitive, ..record })
+    }
+
+    // Sphere tracing within the shape's bounds
+    fn intersect_sdf(&self, index: usize, ray: &Ray, dist_min: f32, dist_max: f32) -> Option<HitRecord> {
+        let sdf = &self.scene.sdfs[index];
+        let nodes = &self.scene.sdf_nodes[sdf.first_node as usize..][..sdf.node_count as usize];
+
+        let mut t = dist_min;
+        let mut end = dist_max;
+        for axis in 0..3 {
+            let t0 = (sdf.bounds_min[axis] - ray.origin[axis]) / ray.direction[axis];
+            let t1 = (sdf.bounds_max[axis] - ray.origin[axis]) / ray.direction[axis];
+            t = t.max(t0.min(t1));
+            end = end.min(t0.max(t1));
+        }
+
+        // Distances are in world units, the ray's direction need not be
+        let length = ray.direction.magnitude();
+        // From inside the shape, march towards its surface from within
+        let side = if sdf_distance(nodes, ray.position(t)) < 0.0 { -1.0 } else { 1.0 };
+
+        for _ in 0..SDF_MAX_STEPS {
+            if t > end {
+                return None;
+            }
+
+            let distance = side * sdf_distance(nodes, ray.position(t));
+            if distance < SDF_HIT_DISTANCE {
+                let normal = sdf_normal(nodes, ray.position(t));
+                // SDF surfaces have no texture coordinates
+                return Some(HitRecord::new(ray, t, normal, (0.0, 0.0), sdf.material_index));
+            }
+
+            t += distance / length;
+        }
+
+        None
+    }
+
+    fn csg_leaf_interval(&self, primitive: u32, ray: &Ray) -> Option<Interval> {
+        let (kind, index) = bvh::decode_primitive(primitive);
+        let index = index as usize;
+
+        match kind {
+            PRIM_SPHERE => {
+                let sphere = &self.scene.spheres[index];
+                sphere_interval(sphere.center, sphere.radius, ray)
+            }
+            PRIM_BOX => box_interval(&self.scene.boxes[index], ray),
+            PRIM_CYLINDER => {
+                let cylinder = &self.scene.cylinders[index];
+                capped_cone_interval(cylinder.a, cylinder.b, cylinder.radius, cylinder.radius, ray)
+            }
+            PRIM_CONE => {
+                let cone = &self.scene.cones[index];
+                capped_cone_interval(cone.a, cone.b, cone.radius_a, cone.radius_b, ray)
+            }
+            PRIM_CAPSULE => capsule_interval(&self.scene.capsules[index], ray),
+            _ => None,
+        }
+    }
+
+    // Evaluates the tree's postfix program on a stack of span lists
+    fn intersect_csg(&self, index: usize, ray: &Ray, dist_min: f32, dist_max: f32) -> Option<HitRecord> {
+        let csg = &self.scene.csgs[index];
+        let nodes = &self.scene.csg_nodes[csg.first_node as usize..][..csg.node_count as usize];
+
+        let mut stack = [SpanList::new(None); CSG_MAX_DEPTH];
+        let mut depth = 0;
+        for node in nodes {
+            if node.operation == CSG_LEAF {
+                stack[depth] = SpanList::new(self.csg_leaf_interval(node.primitive, ray));
+                depth += 1;
+            } else {
+                depth -= 1;
+                stack[depth - 1] = SpanList::combine(node.operation, &stack[depth - 1], &stack[depth]);
+            }
+        }
+
+        let (distance, normal) = stack[0].spans[..stack[0].count].iter()
+            .find_map(|span| span.first_hit(dist_min, dist_max))?;
+
+        // CSG surfaces have no texture coordinates
+        Some(HitRecord::new(ray, distance, normal, (0.0, 0.0), csg.material_index))
+    }
+
+    // Moller-Trumbore intersection
+    fn intersect_triangle(&self, index: usize, ray: &Ray, dist_min: f32, dist_max: f32) -> Option<HitRecord> {
+        let triangle = &self.scene.triangles[index];
+        let v0 = &self.scene.vertices[triangle.indices[0] as usize];
+        let v1 = &self.scene.vertices[triangle.indices[1] as usize];
+        let v2 = &self.scene.vertices[triangle.indices[2] as usize];
+
+        let edge1 = v1.position - v0.position;
+        let edge2 = v2.position - v0.position;
+
+        let p = ray.direction.cross(edge2);
+        let determinant = edge1.dot(p);
+
+        // Ray is parallel to the triangle
+        if determinant.abs() < 1e-8 {
+            return None;
+        }
+        let inverse_determinant = 1.0 / determinant;
+
+        let to_origin = ray.origin - v0.position;
+        let u = to_origin.dot(p) * inverse_determinant;
+        if !(0.0..=1.0).contains(&u) {
+            return None;
+        }
+
+        let q = to_origin.cross(edge1);
+        let v = ray.direction.dot(q) * inverse_determinant;
+        if v < 0.0 || u + v > 1.0 {
+            return None;
+        }
+
+        let distance = edge2.dot(q) * inverse_determinant;
+        if distance >= dist_max || distance <= dist_min {
+            return None;
+        }
+
+        // Smooth shading from vertex normals, oriented to the winding order
+        let geometric_normal = edge1.cross(edge2).normalize();
+        let mut outward_normal = ((1.0 - u - v) * v0.normal + u * v1.normal + v * v2.normal).normalize();
+        if outward_normal.dot(geometric_normal) < 0.0 {
+            outward_normal = -outward_normal;
+        }
+
+        // Barycentric, meshes have no texture coordinates
+        Some(HitRecord::new(ray, distance, outward_normal, (u, v), triangle.material_index))
+    }
+
+    /// Closest hit through the scene's BVH and planes
+    fn trace(&self, ray: &Ray, dist_min: f32, dist_max: f32) -> Option<HitRecord> {
+        let mut closest: Option<HitRecord> = None;
+
+        // Planes are unbounded, so they are tested outside the BVH
+        for (i, plane) in self.scene.planes.iter().enumerate() {
+            let closest_distance = closest.as_ref().map_or(dist_max, |record| record.distance);
+            if let Some(record) = intersect_plane(plane, ray, dist_min, closest_distance) {
+                closest = Some(HitRecord { primitive: bvh::encode_primitive(PRIM_PLANE, i as u32), ..record });
+            }
+        }
+
+        let dist_max = closest.as_ref().map_or(dist_max, |record| record.distance);
+        self.scene.bvh.closest_hit(ray.origin, ray.direction, dist_min, dist_max, |primitive, closest_distance| {
+            let record = self.intersect_primitive(primitive, ray, dist_min, closest_distance)?;
+            let distance = record.distance;
+            closest = Some(record);
+            Some(distance)
+        });
+
+        closest
+    }
+
+    /********** Environment **********/
+
+    fn environment(&self) -> Option<&Environment> {
+        if self.uniforms.has_environment != 0 {
+            self.scene.environment.as_ref()
+        } else {
+            None
+        }
+    }
+
+    // Map coordinates on [0, 1) for a world direction
+    fn environment_uv(&self, direction: Vec3) -> (f32, f32) {
+        let d = rotate_y(direction.normalize(), -self.uniforms.environment_rotation);
+        let u = d.z.atan2(d.x) / (2.0 * PI);
+        let v = d.y.clamp(-1.0, 1.0).acos() / PI;
+        (u - u.floor(), v)
+    }
+
+    fn environment_texel(environment: &Environment, (u, v): (f32, f32)) -> (usize, usize) {
+        let width = environment.image.width as usize;
+        let height = environment.image.height as usize;
+        (((u * width as f32) as usize).min(width - 1), ((v * height as f32) as usize).min(height - 1))
+    }
+
+    fn environment_color(&self, environment: &Environment, direction: Vec3) -> Vec3 {
+        let (x, y) = Self::environment_texel(environment, self.environment_uv(direction));
+        let [r, g, b, _] = environment.image.pixels[y * environment.image.width as usize + x];
+        Vec3::new(r, g, b) * self.uniforms.environment_intensity
+    }
+
+    // Solid angle pdf of importance sampling `direction`
+    fn environment_pdf(&self, environment: &Environment, direction: Vec3) -> f32 {
+        let width = environment.image.width as usize;
+        let height = environment.image.height as usize;
+        let distribution = &environment.distribution;
+
+        let uv = self.environment_uv(direction);
+        let sin_theta = (uv.1 * PI).sin();
+        if sin_theta <= 0.0 {
+            return 0.0;
+        }
+
+        // Both CDFs are inclusive
+        let (x, y) = Self::environment_texel(environment, uv);
+        let row = distribution.marginal_cdf[y] - if y > 0 { distribution.marginal_cdf[y - 1] } else { 0.0 };
+        let row_start = y * width;
+        let column = distribution.conditional_cdf[row_start + x] - if x > 0 { distribution.conditional_cdf[row_start + x - 1] } else { 0.0 };
+
+        // Texel probability -> uv density -> solid angle density
+        let uv_pdf = row * column * (width * height) as f32;
+        uv_pdf / (2.0 * PI * PI * sin_theta)
+    }
+
+    // Picks a texel proportional to luminance (weighted by solid angle), then a point within it
+    fn sample_environment(&self, environment: &Environment, random: &mut Random) -> (Vec3, f32) {
+        let width = environment.image.width as usize;
+        let height = environment.image.height as usize;
+        let distribution = &environment.distribution;
+
+        let y = search_cdf(&distribution.marginal_cdf, random.next());
+        let x = search_cdf(&distribution.conditional_cdf[y * width..(y + 1) * width], random.next());
+
+        let u = (x as f32 + random.next()) / width as f32;
+        let v = (y as f32 + random.next()) / height as f32;
+        let phi = u * 2.0 * PI;
+        let theta = v * PI;
+
+        let direction = Vec3::new(theta.sin() * phi.cos(), theta.cos(), theta.sin() * phi.sin());
+        let direction = rotate_y(direction, self.uniforms.environment_rotation);
+
+        (direction, self.environment_pdf(environment, direction))
+    }
+
+    fn sky_color(&self, ray: &Ray) -> Vec3 {
+        if let Some(environment) = self.environment() {
+            return self.environment_color(environment, ray.direction);
+        }
+
+        let unit_direction = ray.direction.normalize();
+        let t = 0.5 * (unit_direction.y + 1.0);
+        (1.0 - t) * self.uniforms.sky_horizon + t * self.uniforms.sky_zenith
+    }
+
+    /********** Lights **********/
+
+    // Area lights plus the environment map (when present)
+    fn light_count(&self) -> u32 {
+        self.uniforms.num_lights + self.environment().is_some() as u32
+    }
+
+    // Cosine of the cone containing `sphere` as seen from `position`. Returns 1 if inside the sphere.
+    fn sphere_cos_theta_max(sphere: &GpuSphere, position: Vec3) -> f32 {
+        let radius = sphere.radius.abs();
+        let distance_squared = (sphere.center - position).magnitude2();
+        if distance_squared <= radius * radius {
+            return 1.0;
+        }
+        (1.0 - radius * radius / distance_squared).sqrt()
+    }
+
+    // Solid angle pdf of the light sampling strategy choosing `record` from `position` at `time`
+    fn light_pdf(&self, position: Vec3, time: f32, record: &HitRecord) -> f32 {
+        let (kind, index) = bvh::decode_primitive(record.primitive);
+
+        let pdf = match kind {
+            PRIM_SPHERE => {
+                let cos_theta_max = Self::sphere_cos_theta_max(&self.scene.spheres[index as usize].at(time), position);
+                if cos_theta_max < 1.0 {
+                    1.0 / (2.0 * PI * (1.0 - cos_theta_max))
+                } else {
+                    0.0
+                }
+            }
+            PRIM_RECTANGLE => {
+                let to_light = record.position - position;
+                let cos_light = record.normal.dot(to_light.normalize()).abs();
+                if cos_light > 0.0 {
+                    to_light.magnitude2() / (rectangle_area(&self.scene.rectangles[index as usize]) * cos_light)
+                } else {
+                    0.0
+                }
+            }
+            PRIM_DISK => {
+                let to_light = record.position - position;
+                let cos_light = record.normal.dot(to_light.normalize()).abs();
+                if cos_light > 0.0 {
+                    to_light.magnitude2() / (disk_area(&self.scene.disks[index as usize]) * cos_light)
+                } else {
+                    0.0
+                }
+            }
+            // Other emissive shapes are never sampled directly
+            _ => 0.0,
+        };
+
+        pdf / self.light_count() as f32
+    }
+
+    /// Picks a light uniformly, then a direction towards where it is at `time`. Returns the direction, light, and pdf.
+    fn sample_light(&self, position: Vec3, time: f32, random: &mut Random) -> Option<(Vec3, u32, f32)> {
+        let light_count = self.light_count();
+        let light = ((random.next() * light_count as f32) as u32).min(light_count - 1);
+
+        // The environment comes after the area lights
+        if light == self.uniforms.num_lights {
+            let environment = self.environment()?;
+            let (direction, pdf) = self.sample_environment(environment, random);
+            let pdf = pdf / light_count as f32;
+            return if pdf > 0.0 { Some((direction, ENVIRONMENT_LIGHT, pdf)) } else { None };
+        }
+
+        let light_primitive = self.scene.lights[light as usize];
+        let (kind, index) = bvh::decode_primitive(light_primitive);
+
+        let (direction, pdf) = match kind {
+            // Uniform over the cone subtended by the sphere
+            PRIM_SPHERE => {
+                let sphere = self.scene.spheres[index as usize].at(time);
+                let cos_theta_max = Self::sphere_cos_theta_max(&sphere, position);
+                if cos_theta_max >= 1.0 {
+                    return None;
+                }
+
+                let cos_theta = 1.0 + random.next() * (cos_theta_max - 1.0);
+                let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+                let phi = 2.0 * PI * random.next();
+
+                let w = (sphere.center - position).normalize();
+                let (u, v) = create_basis(w);
+
+                let direction = (u * phi.cos() * sin_theta + v * phi.sin() * sin_theta + w * cos_theta).normalize();
+                (direction, 1.0 / (2.0 * PI * (1.0 - cos_theta_max)))
+            }
+            // Uniform over the rectangle's area, converted to solid angle
+            PRIM_RECTANGLE => {
+                let rectangle = &self.scene.rectangles[index as usize];
+                let light_position = rectangle.corner + random.next() * rectangle.edge_u + random.next() * rectangle.edge_v;
+
+                let to_light = light_position - position;
+                let direction = to_light.normalize();
+
+                let cos_light = rectangle.edge_u.cross(rectangle.edge_v).normalize().dot(direction).abs();
+                if cos_light < 1e-6 {
+                    return None;
+                }
+                (direction, to_light.magnitude2() / (rectangle_area(rectangle) * cos_light))
+            }
+            // Uniform over the disk's area, converted to solid angle
+            PRIM_DISK => {
+                let disk = &self.scene.disks[index as usize];
+                let (u, v) = create_basis(disk.normal);
+
+                let r = disk.radius * random.next().sqrt();
+                let phi = 2.0 * PI * random.next();
+                let light_position = disk.center + r * (phi.cos() * u + phi.sin() * v);
+
+                let to_light = light_position - position;
+                let direction = to_light.normalize();
+
+                let cos_light = disk.normal.dot(direction).abs();
+                if cos_light < 1e-6 {
+                    return None;
+                }
+                (direction, to_light.magnitude2() / (disk_area(disk) * cos_light))
+            }
+            _ => return None,
+        };
+
+        Some((direction, light_primitive, pdf / light_count as f32))
+    }
+
+    // Next-event estimation for a surface with a BSDF, seen from `wo` at `time`. MIS weighted against BSDF sampling.
+    fn sample_direct_light(&self, material: &GpuMaterial, record: &HitRecord, wo: Vec3, time: f32, random: &mut Random) -> Vec3 {
+        let black = Vec3::new(0.0, 0.0, 0.0);
+
+        let (direction, light_primitive, pdf) = match self.sample_light(record.position, time, random) {
+            Some(sample) => sample,
+            None => return black,
+        };
+
+        let bsdf_cos = eval_bsdf(material, record, wo, direction);
+        if bsdf_cos.x <= 0.0 && bsdf_cos.y <= 0.0 && bsdf_cos.z <= 0.0 {
+            return black;
+        }
+
+        // Shadow ray must reach the sampled light first
+        let shadow_ray = Ray { origin: record.position, direction, time };
+        let hit = self.trace(&shadow_ray, 0.001, FAR_PLANE_DIST);
+
+        let emitted = match (light_primitive, hit) {
+            (ENVIRONMENT_LIGHT, None) => match self.environment() {
+                Some(environment) => self.environment_color(environment, direction),
+                None => return black,
+            },
+            (ENVIRONMENT_LIGHT, Some(_)) => return black,
+            (_, Some(light_record)) if light_record.primitive == light_primitive => {
+                self.scene.materials[light_record.material_index as usize].albedo
+            }
+            _ => return black,
+        };
+
+        let bsdf_pdf = bsdf_pdf(material, record, wo, direction);
+
+        bsdf_cos.mul_element_wise(emitted) * power_heuristic(pdf, bsdf_pdf) / pdf
+    }
+
+    fn fire_ray(&self, mut ray: Ray, random: &mut Random) -> Vec3 {
+        let mut throughput = Vec3::new(1.0, 1.0, 1.0);
+        let mut color = Vec3::new(0.0, 0.0, 0.0);
+
+        // Whether the previous bounce sampled lights (emission hit by the scattered ray is then MIS weighted)
+        let mut sampled_lights = false;
+        let mut scatter_pdf = 0.0;
+        let mut scatter_origin = ray.origin;
+
+        for _ in 0..self.uniforms.max_ray_bounces {
+            let record = match self.trace(&ray, 0.001, FAR_PLANE_DIST) {
+                Some(record) => record,
+                None => {
+                    let mut weight = 1.0;
+                    if sampled_lights {
+                        if let Some(environment) = self.environment() {
+                            weight = power_heuristic(scatter_pdf, self.environment_pdf(environment, ray.direction) / self.light_count() as f32);
+                        }
+                    }
+                    color += throughput.mul_element_wise(self.sky_color(&ray)) * weight;
+                    break;
+                }
+            };
+
+            let material = &self.scene.materials[record.material_index as usize];
+
+            if material.material_type == MAT_EMISSIVE {
+                let mut weight = 1.0;
+                if sampled_lights {
+                    weight = power_heuristic(scatter_pdf, self.light_pdf(scatter_origin, ray.time, &record));
+                }
+                color += throughput.mul_element_wise(material.albedo) * weight;
+                break;
+            }
+
+            // Mirror-like metal and smooth glass have no BSDF to evaluate, they rely on scattering
+            let wo = -ray.direction.normalize();
+            sampled_lights = has_bsdf(material) && self.light_count() > 0;
+            if sampled_lights {
+                color += throughput.mul_element_wise(self.sample_direct_light(material, &record, wo, ray.time, random));
+            }
+
+            match scatter_ray(material, &ray, &record, random) {
+                Some((attenuation, scattered_ray)) => {
+                    scatter_pdf = bsdf_pdf(material, &record, wo, scattered_ray.direction.normalize());
+                    scatter_origin = record.position;
+
+                    ray = scattered_ray;
+                    throughput = throughput.mul_element_wise(attenuation);
+                }
+                None => break,
+            }
+        }
+
+        color
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RAYS: u32 = 1000;
+    const DIST_MIN: f32 = 0.001;
+    const MARCH_STEP: f32 = 1e-3;
+    const MARCH_DISTANCE: f32 = 12.0;
+    const TOLERANCE: f32 = 1e-3;
+    /// Sphere tracing stops short of the surface, by more along rays that graze it
+    const SDF_TOLERANCE: f32 = 1e-2;
+
+    fn shapes_scene() -> Scene {
+        let text = r#"Scene(
+            camera: (position: (0.0, 0.0, 5.0), look_at: (0.0, 0.0, 0.0), v_fov: 60.0),
+            sky: (horizon: (1.0, 1.0, 1.0), zenith: (1.0, 1.0, 1.0)),
+            render: (samples_per_pixel: 1, max_ray_bounces: 1, target_samples: 1),
+            materials: { "white": Lambertian(albedo: (1.0, 1.0, 1.0)) },
+            spheres: [],
+            cylinders: [(start: (-0.3, -0.8, 0.2), end: (0.4, 0.9, -0.1), radius: 0.5, material: "white")],
+            cones: [
+                (start: (0.2, -0.9, -0.3), end: (-0.1, 0.8, 0.4), start_radius: 0.7, material: "white"),
+                (start: (-0.6, 0.1, 0.0), end: (0.7, -0.2, 0.3), start_radius: 0.3, end_radius: 0.8, material: "white"),
+            ],
+            capsules: [(start: (-0.7, -0.4, 0.1), end: (0.6, 0.5, -0.2), radius: 0.4, material: "white")],
+            tori: [
+                (center: (0.1, -0.1, 0.0), axis: (0.3, 1.0, 0.5), major_radius: 0.9, minor_radius: 0.3, material: "white"),
+                (center: (0.0, 0.0, 0.0), major_radius: 0.6, minor_radius: 0.55, material: "white"),
+            ],
+        )"#;
+        Scene::parse(text, std::path::Path::new(".")).unwrap()
+    }
+
+    fn csg_scene() -> Scene {
+        let text = r#"Scene(
+            camera: (position: (0.0, 0.0, 5.0), look_at: (0.0, 0.0, 0.0), v_fov: 60.0),
+            sky: (horizon: (1.0, 1.0, 1.0), zenith: (1.0, 1.0, 1.0)),
+            render: (samples_per_pixel: 1, max_ray_bounces: 1, target_samples: 1),
+            materials: { "white": Lambertian(albedo: (1.0, 1.0, 1.0)) },
+            spheres: [],
+            csg: [
+                // Lens
+                (shape: Intersection(
+                    Sphere(center: (0.0, 0.0, 0.7), radius: 1.0),
+                    Sphere(center: (0.0, 0.0, -0.7), radius: 1.0),
+                ), material: "white"),
+                // Cut-away shell
+                (shape: Difference(
+                    Difference(Sphere(center: (0.0, 0.0, 0.0), radius: 0.9), Sphere(center: (0.0, 0.0, 0.0), radius: 0.7)),
+                    Box(center: (0.5, 0.5, 0.5), size: (1.0, 1.0, 1.0), rotation: (10.0, 20.0, 0.0)),
+                ), material: "white"),
+                // Drilled block with a handle
+                (shape: Union(
+                    Difference(
+                        Box(center: (0.0, 0.0, 0.0), size: (1.2, 0.8, 1.0)),
+                        Cylinder(start: (0.0, 0.0, -1.0), end: (0.0, 0.0, 1.0), radius: 0.3),
+                    ),
+                    Union(
+                        Capsule(start: (-0.6, 0.6, 0.0), end: (0.6, 0.6, 0.0), radius: 0.15),
+                        Cone(start: (0.0, -0.4, 0.0), end: (0.0, -1.0, 0.3), start_radius: 0.4),
+                    ),
+                ), material: "white"),
+            ],
+        )"#;
+        Scene::parse(text, std::path::Path::new(".")).unwrap()
+    }
+
+    fn sdf_scene() -> Scene {
+        let text = r#"Scene(
+            camera: (position: (0.0, 0.0, 5.0), look_at: (0.0, 0.0, 0.0), v_fov: 60.0),
+            sky: (horizon: (1.0, 1.0, 1.0), zenith: (1.0, 1.0, 1.0)),
+            render: (samples_per_pixel: 1, max_ray_bounces: 1, target_samples: 1),
+            materials: { "white": Lambertian(albedo: (1.0, 1.0, 1.0)) },
+            spheres: [],
+            sdfs: [
+                (shape: Union(
+                    a: Sphere(center: (-0.4, 0.0, 0.0), radius: 0.5),
+                    b: RoundedBox(center: (0.4, 0.1, 0.0), size: (0.8, 0.6, 0.7), rounding: 0.1, rotation: (10.0, 30.0, 0.0)),
+                    smoothness: 0.3,
+                ), material: "white"),
+                (shape: Difference(
+                    a: Torus(center: (0.0, 0.0, 0.0), major_radius: 0.7, minor_radius: 0.25, rotation: (60.0, 0.0, 20.0)),
+                    b: Sphere(center: (0.6, 0.0, 0.0), radius: 0.4),
+                    smoothness: 0.1,
+                ), material: "white"),
+                (shape: Mandelbulb(center: (0.0, 0.0, 0.0), scale: 1.0, rotation: (0.0, 0.0, 30.0)), material: "white"),
+            ],
+        )"#;
+        Scene::parse(text, std::path::Path::new(".")).unwrap()
+    }
+
+    fn sdf_nodes(scene: &Scene, index: usize) -> &[GpuSdfNode] {
+        let sdf = &scene.sdfs[index];
+        &scene.sdf_nodes[sdf.first_node as usize..][..sdf.node_count as usize]
+    }
+
+    /// Distance along `a` to `b` of the closest point on that segment to `position`, and the offset to it
+    fn segment_offset(a: Vec3, b: Vec3, position: Vec3) -> (f32, Vec3) {
+        let height = (b - a).magnitude();
+        let w = (b - a) / height;
+        let s = (position - a).dot(w);
+        (s, position - a - s * w)
+    }
+
+    fn inside_cone(a: Vec3, b: Vec3, radius_a: f32, radius_b: f32, position: Vec3) -> bool {
+        let height = (b - a).magnitude();
+        let (s, perpendicular) = segment_offset(a, b, position);
+        (0.0..=height).contains(&s) && perpendicular.magnitude() <= radius_a + (radius_b - radius_a) * s / height
+    }
+
+    fn inside_capsule(capsule: &GpuCapsule, position: Vec3) -> bool {
+        let height = (capsule.b - capsule.a).magnitude();
+        let (s, _) = segment_offset(capsule.a, capsule.b, position);
+        let closest = capsule.a + (capsule.b - capsule.a) * (s.clamp(0.0, height) / height);
+        (position - closest).magnitude() <= capsule.radius
+    }
+
+    fn inside_torus(torus: &GpuTorus, position: Vec3) -> bool {
+        let offset = position - torus.center;
+        let height = offset.dot(torus.axis);
+        let radial = (offset - height * torus.axis).magnitude();
+        (radial - torus.major_radius).powi(2) + height * height <= torus.minor_radius.powi(2)
+    }
+
+    fn inside_box(box_primitive: &GpuBox, position: Vec3) -> bool {
+        let local = box_primitive.rotation().conjugate().rotate_vector(position - box_primitive.center);
+        (0..3).all(|axis| local[axis].abs() <= box_primitive.half_extents[axis])
+    }
+
+    /// Evaluates a CSG shape's program on whether the point is inside each leaf
+    fn inside_csg(scene: &Scene, index: usize, position: Vec3) -> bool {
+        let csg = &scene.csgs[index];
+        let mut stack = Vec::new();
+        for node in &scene.csg_nodes[csg.first_node as usize..][..csg.node_count as usize] {
+            let inside = match node.operation {
+                CSG_LEAF => {
+                    let (kind, index) = bvh::decode_primitive(node.primitive);
+                    let index = index as usize;
+                    match kind {
+                        PRIM_SPHERE => (position - scene.spheres[index].center).magnitude() <= scene.spheres[index].radius,
+                        PRIM_BOX => inside_box(&scene.boxes[index], position),
+                        PRIM_CYLINDER => {
+                            let cylinder = &scene.cylinders[index];
+                            inside_cone(cylinder.a, cylinder.b, cylinder.radius, cylinder.radius, position)
+                        }
+                        PRIM_CONE => {
+                            let cone = &scene.cones[index];
+                            inside_cone(cone.a, cone.b, cone.radius_a, cone.radius_b, position)
+                        }
+                        PRIM_CAPSULE => inside_capsule(&scene.capsules[index], position),
+                        _ => unreachable!(),
+                    }
+                }
+                operation => {
+                    let b = stack.pop().unwrap();
+                    let a = stack.pop().unwrap();
+                    match operation {
+                        CSG_UNION => a || b,
+                        CSG_INTERSECTION => a && b,
+                        _ => a && !b,
+                    }
+                }
+            };
+            stack.push(inside);
+        }
+        stack[0]
+    }
+
+    /// First crossing of the boundary of `inside` past `DIST_MIN`, in fixed steps refined by bisection
+    fn march(ray: &Ray, inside: &dyn Fn(Vec3) -> bool) -> Option<f32> {
+        let start = inside(ray.position(DIST_MIN));
+
+        let mut previous = DIST_MIN;
+        for step in 1..=(MARCH_DISTANCE / MARCH_STEP) as u32 {
+            let t = DIST_MIN + step as f32 * MARCH_STEP;
+            if inside(ray.position(t)) != start {
+                let (mut low, mut high) = (previous, t);
+                for _ in 0..24 {
+                    let middle = 0.5 * (low + high);
+                    if inside(ray.position(middle)) == start {
+                        low = middle;
+                    } else {
+                        high = middle;
+                    }
+                }
+                return Some(0.5 * (low + high));
+            }
+            previous = t;
+        }
+
+        None
+    }
+
+    /// Whether the ray is on the same side of the surface just before and just after `distance`, i.e.
+    /// it only grazes the shape there and marching may step over it
+    fn grazes(ray: &Ray, inside: &dyn Fn(Vec3) -> bool, distance: f32) -> bool {
+        inside(ray.position(distance - 2.0 * MARCH_STEP)) == inside(ray.position(distance + 2.0 * MARCH_STEP))
+    }
+
+    /// `tolerance` is how far along the ray hits may be from where marching finds the surface
+    fn check_against_marching(name: &str, tolerance: f32, intersect: &dyn Fn(&Ray) -> Option<HitRecord>, inside: &dyn Fn(Vec3) -> bool) {
+        let mut random = Random::new(17);
+        let mut hits = 0;
+
+        for i in 0..RAYS {
+            // Alternately from outside the shape and from anywhere around it (possibly inside), aimed
+            // near it. Directions are not normalized, like camera rays.
+            let origin = if i % 2 == 0 {
+                4.0 * random.unit_vector()
+            } else {
+                Vec3::new(random.range(-1.5, 1.5), random.range(-1.5, 1.5), random.range(-1.5, 1.5))
+            };
+            let target = Vec3::new(random.range(-1.0, 1.0), random.range(-1.0, 1.0), random.range(-1.0, 1.0));
+            let ray = Ray { origin, direction: (target - origin).normalize() * random.range(0.5, 2.0), time: 0.0 };
+
+            let record = intersect(&ray);
+            let analytic = record.as_ref().map(|record| record.distance);
+            let marched = march(&ray, inside);
+
+            match (analytic, marched) {
+                (Some(analytic), Some(marched)) if (analytic - marched).abs() < tolerance => {
+                    hits += 1;
+
+                    // The outward normal points out of the shape
+                    let record = record.unwrap();
+                    let outward_normal = if record.is_front_face { record.normal } else { -record.normal };
+                    assert!((outward_normal.magnitude() - 1.0).abs() < 1e-3, "{}: normal {:?} is not normalized", name, outward_normal);
+                    assert!(
+                        !inside(record.position + 1e-3 * outward_normal) || grazes(&ray, inside, analytic),
+                        "{}: normal {:?} at {:?} points inside", name, outward_normal, record.position,
+                    );
+                }
+                _ => {
+                    // Marching steps over slivers, and the analytic solution may miss a tangent ray
+                    let first = analytic.into_iter().chain(marched).fold(f32::INFINITY, f32::min);
+                    assert!(
+                        grazes(&ray, inside, first),
+                        "{}: ray from {:?} along {:?} hit at {:?}, marching found {:?}", name, ray.origin, ray.direction, analytic, marched,
+                    );
+                }
+            }
+        }
+
+        assert!(hits > RAYS / 4, "{}: only {} of {} rays were checked", name, hits, RAYS);
+    }
+
+    #[test]
+    fn cylinder_matches_marching() {
+        let scene = shapes_scene();
+        let cylinder = &scene.cylinders[0];
+        check_against_marching(
+            "cylinder",
+            TOLERANCE,
+            &|ray| intersect_cylinder(cylinder, ray, DIST_MIN, FAR_PLANE_DIST),
+            &|position| inside_cone(cylinder.a, cylinder.b, cylinder.radius, cylinder.radius, position),
+        );
+    }
+
+    #[test]
+    fn cones_match_marching() {
+        let scene = shapes_scene();
+        for cone in &scene.cones {
+            check_against_marching(
+                "cone",
+                TOLERANCE,
+                &|ray| intersect_cone(cone, ray, DIST_MIN, FAR_PLANE_DIST),
+                &|position| inside_cone(cone.a, cone.b, cone.radius_a, cone.radius_b, position),
+            );
+        }
+    }
+
+    #[test]
+    fn capsule_matches_marching() {
+        let scene = shapes_scene();
+        let capsule = &scene.capsules[0];
+        check_against_marching(
+            "capsule",
+            TOLERANCE,
+            &|ray| intersect_capsule(capsule, ray, DIST_MIN, FAR_PLANE_DIST),
+            &|position| inside_capsule(capsule, position),
+        );
+    }
+
+    #[test]
+    fn tori_match_marching() {
+        let scene = shapes_scene();
+        for torus in &scene.tori {
+            check_against_marching(
+                "torus",
+                TOLERANCE,
+                &|ray| intersect_torus(torus, ray, DIST_MIN, FAR_PLANE_DIST),
+                &|position| inside_torus(torus, position),
+            );
+        }
+    }
+
+    #[test]
+    fn csg_matches_marching() {
+        let scene = csg_scene();
+        let tracer = CpuRayTracer::new(&scene, Uniforms::new(1, 1, &scene));
+        for index in 0..scene.csgs.len() {
+            check_against_marching(
+                "csg",
+                TOLERANCE,
+                &|ray| tracer.intersect_csg(index, ray, DIST_MIN, FAR_PLANE_DIST),
+                &|position| inside_csg(&scene, index, position),
+            );
+        }
+    }
+
+    #[test]
+    fn csg_leaves_stay_out_of_the_bvh() {
+        let scene = csg_scene();
+        assert_eq!(scene.spheres.len(), 4);
+        assert_eq!(scene.bvh.primitives.len(), scene.csgs.len());
+        assert!(scene.bvh.primitives.iter().all(|&primitive| bvh::decode_primitive(primitive).0 == PRIM_CSG));
+    }
+
+    #[test]
+    fn sdf_sphere_tracing_matches_marching() {
+        let scene = sdf_scene();
+        let tracer = CpuRayTracer::new(&scene, Uniforms::new(1, 1, &scene));
+        // The Mandelbulb's detail is finer than marching steps
+        for index in 0..2 {
+            check_against_marching(
+                "sdf",
+                SDF_TOLERANCE,
+                &|ray| tracer.intersect_sdf(index, ray, DIST_MIN, FAR_PLANE_DIST),
+                &|position| sdf_distance(sdf_nodes(&scene, index), position) <= 0.0,
+            );
+        }
+    }
+
+    #[test]
+    fn sdfs_fit_their_bounds() {
+        let scene = sdf_scene();
+        let mut random = Random::new(3);
+
+        for (index, sdf) in scene.sdfs.iter().enumerate() {
+            let bounds = sdf.bounds();
+            for _ in 0..200_000 {
+                let position = Vec3::new(random.range(-2.0, 2.0), random.range(-2.0, 2.0), random.range(-2.0, 2.0));
+                let within = (0..3).all(|axis| position[axis] >= bounds.min[axis] && position[axis] <= bounds.max[axis]);
+                assert!(sdf_distance(sdf_nodes(&scene, index), position) > 0.0 || within, "SDF {}: {:?} is outside its bounds", index, position);
+            }
+        }
+    }
+
+    #[test]
+    fn moving_shapes_fit_their_swept_bounds() {
+        let text = r#"Scene(
+            camera: (position: (0.0, 0.0, 5.0), look_at: (0.0, 0.0, 0.0), v_fov: 60.0, shutter: (0.25, 1.0)),
+            sky: (horizon: (1.0, 1.0, 1.0), zenith: (1.0, 1.0, 1.0)),
+            render: (samples_per_pixel: 1, max_ray_bounces: 1, target_samples: 1),
+            materials: { "white": Lambertian(albedo: (1.0, 1.0, 1.0)) },
+            spheres: [(center: (-0.5, 0.2, 0.0), radius: 0.4, velocity: (1.2, -0.3, 0.5), material: "white")],
+            boxes: [
+                (center: (0.3, -0.2, 0.1), size: (0.8, 0.4, 0.6), rotation: (10.0, 30.0, 0.0),
+                    velocity: (-0.6, 0.4, 0.0), material: "white"),
+                (center: (0.0, 0.1, -0.2), size: (1.2, 0.3, 0.5), velocity: (0.2, 0.0, 0.4),
+                    angular_velocity: (40.0, 120.0, -60.0), material: "white"),
+            ],
+        )"#;
+        let scene = Scene::parse(text, std::path::Path::new(".")).unwrap();
+        let shutter = scene.camera.shutter;
+
+        let mut random = Random::new(11);
+        for _ in 0..200 {
+            let time = random.range(shutter[0], shutter[1]);
+
+            let sphere = &scene.spheres[0];
+            let bounds = sphere.swept_bounds(shutter);
+            let moved = sphere.at(time);
+            let within = (0..3).all(|axis| moved.center[axis] - moved.radius >= bounds.min[axis] && moved.center[axis] + moved.radius <= bounds.max[axis]);
+            assert!(within, "Sphere at time {} is outside its bounds", time);
+
+            for box_primitive in &scene.boxes {
+                let bounds = box_primitive.swept_bounds(shutter);
+                let moved = box_primitive.at(time).bounds();
+                let within = (0..3).all(|axis| moved.min[axis] >= bounds.min[axis] - 1e-5 && moved.max[axis] <= bounds.max[axis] + 1e-5);
+                assert!(within, "Box at time {} is outside its bounds", time);
+            }
+        }
+    }
+
+    #[test]
+    fn shapes_fit_their_bounds() {
+        let scene = shapes_scene();
+
+        let check = |name: &str, bounds: crate::bvh::Aabb, inside: &dyn Fn(Vec3) -> bool| {
+            let mut random = Random::new(5);
+            for _ in 0..20_000 {
+                let position = Vec3::new(random.range(-2.0, 2.0), random.range(-2.0, 2.0), random.range(-2.0, 2.0));
+                let within = (0..3).all(|axis| position[axis] >= bounds.min[axis] && position[axis] <= bounds.max[axis]);
+                assert!(!inside(position) || within, "{}: {:?} is outside its bounds", name, position);
+            }
+        };
+
+        let cylinder = &scene.cylinders[0];
+        check("cylinder", cylinder.bounds(), &|p| inside_cone(cylinder.a, cylinder.b, cylinder.radius, cylinder.radius, p));
+        for cone in &scene.cones {
+            check("cone", cone.bounds(), &|p| inside_cone(cone.a, cone.b, cone.radius_a, cone.radius_b, p));
+        }
+        let capsule = &scene.capsules[0];
+        check("capsule", capsule.bounds(), &|p| inside_capsule(capsule, p));
+        for torus in &scene.tori {
+            check("torus", torus.bounds(), &|p| inside_torus(torus, p));
+        }
+    }
+}
@@ -0,0 +1,352 @@
+use wgpu::*;
+
+use crate::quad::Quad;
+use crate::raytrace::AccumulationTargets;
+use crate::texture::Texture;
+
+/// Filter strength, adjustable while running
+#[derive(Copy, Clone, Debug)]
+pub struct DenoiseSettings {
+    /// À-trous passes. Each doubles the filter's reach, to 65 pixels across after 5.
+    pub iterations: u32,
+    /// Luminance differences blurred over, in standard deviations of the pixel's noise
+    pub luminance_sigma: f32,
+    /// Exponent of the dot product between normals. Higher keeps corners sharper.
+    pub normal_sigma: f32,
+    /// Depth differences blurred over, relative to the pixel's depth per tap distance
+    pub depth_sigma: f32,
+}
+
+impl Default for DenoiseSettings {
+    fn default() -> Self {
+        Self {
+            iterations: 4,
+            luminance_sigma: 4.0,
+            normal_sigma: 128.0,
+            depth_sigma: 0.1,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+struct DenoiseUniforms { // OFFSET + SIZE
+    luminance_sigma: f32, // 0 + 4
+    normal_sigma: f32,    // 4 + 4
+    depth_sigma: f32,     // 8 + 4
+    _padding: f32,        // 12 + 4
+}
+unsafe impl bytemuck::Pod for DenoiseUniforms {}
+unsafe impl bytemuck::Zeroable for DenoiseUniforms {}
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+struct PassUniforms { // OFFSET + SIZE
+    step_size: i32,   // 0 + 4
+    stage: u32,       // 4 + 4
+    _padding: [u32; 2],
+}
+unsafe impl bytemuck::Pod for PassUniforms {}
+unsafe impl bytemuck::Zeroable for PassUniforms {}
+
+// Must match the `STAGE_*` defines in denoise.comp
+const STAGE_PREPARE: u32 = 0;
+const STAGE_FILTER: u32 = 1;
+const STAGE_FILTER_LAST: u32 = 2;
+
+/// Textures sized for the current render
+struct Images {
+    /// The ray tracer's accumulation and features, plus the settings
+    feature_bind_group: BindGroup,
+    /// Filtered lighting, alternately read and written by the filter stages
+    ping_pong: [Texture; 2],
+    /// Denoised result in the accumulation's format (see `Denoiser::quad`)
+    quad: Quad,
+    width: u32,
+    height: u32,
+}
+
+/// Edge-avoiding à-trous filter for noisy interactive renders (see denoise.comp).
+///
+/// Reads the ray tracer's accumulation with its first hit albedo, normal and depth features and
+/// sample statistics, and writes a filtered copy. The accumulation itself is left untouched.
+pub struct Denoiser {
+    pipeline: ComputePipeline,
+    feature_bind_group_layout: BindGroupLayout,
+    pass_bind_group_layout: BindGroupLayout,
+
+    uniform_buffer: Buffer,
+    images: Images,
+
+    pub enabled: bool,
+    pub settings: DenoiseSettings,
+}
+
+impl Denoiser {
+    const FORMAT: TextureFormat = TextureFormat::Rgba32Float;
+    /// Must match `local_size` in denoise.comp
+    const WORK_GROUP_SIZE: u32 = 8;
+    const MAX_ITERATIONS: u32 = 5;
+
+    pub fn new(device: &Device, quad_layout: &BindGroupLayout, targets: &AccumulationTargets, width: u32, height: u32) -> Self {
+        let storage_texture_entry = |binding, readonly| BindGroupLayoutEntry {
+            binding,
+            visibility: ShaderStage::COMPUTE,
+            ty: BindingType::StorageTexture {
+                dimension: TextureViewDimension::D2,
+                component_type: TextureComponentType::Uint,
+                format: Self::FORMAT,
+                readonly,
+            },
+        };
+        let uniform_entry = |binding| BindGroupLayoutEntry {
+            binding,
+            visibility: ShaderStage::COMPUTE,
+            ty: BindingType::UniformBuffer {
+                dynamic: false,
+            },
+        };
+
+        let feature_bind_group_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            bindings: &[
+                // Accumulation
+                storage_texture_entry(0, true),
+                // Sample statistics
+                storage_texture_entry(1, true),
+                // Albedo and depth
+                storage_texture_entry(2, true),
+                // Normals
+                storage_texture_entry(3, true),
+                // Settings
+                uniform_entry(4),
+            ],
+            label: Some("denoise_feature_bind_group_layout"),
+        });
+
+        let pass_bind_group_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            bindings: &[
+                // Input
+                storage_texture_entry(0, true),
+                // Output
+                storage_texture_entry(1, false),
+                // Step size and stage
+                uniform_entry(2),
+            ],
+            label: Some("denoise_pass_bind_group_layout"),
+        });
+
+        let layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            bind_group_layouts: &[
+                &feature_bind_group_layout,
+                &pass_bind_group_layout,
+            ],
+        });
+
+        let spirv = include_bytes!("../shaders/denoise/denoise.comp.spv");
+        let data = read_spirv(std::io::Cursor::new(spirv.as_ref())).unwrap();
+        let module = device.create_shader_module(&data);
+
+        let pipeline = device.create_compute_pipeline(&ComputePipelineDescriptor {
+            layout: &layout,
+            compute_stage: ProgrammableStageDescriptor {
+                module: &module,
+                entry_point: "main",
+            },
+        });
+
+        let settings = DenoiseSettings::default();
+
+        let uniform_buffer = device.create_buffer_with_data(
+            bytemuck::cast_slice(&[Self::uniforms(&settings)]),
+            BufferUsage::UNIFORM | BufferUsage::COPY_DST,
+        );
+
+        let images = Self::create_images(device, &feature_bind_group_layout, quad_layout, &uniform_buffer, targets, width, height);
+
+        Self {
+            pipeline,
+            feature_bind_group_layout,
+            pass_bind_group_layout,
+
+            uniform_buffer,
+            images,
+
+            enabled: false,
+            settings,
+        }
+    }
+
+    /// Must follow `RayTracer::resize`, which replaces the textures in `targets`
+    pub fn resize(&mut self, device: &Device, quad_layout: &BindGroupLayout, targets: &AccumulationTargets, width: u32, height: u32) {
+        self.images = Self::create_images(device, &self.feature_bind_group_layout, quad_layout, &self.uniform_buffer, targets, width, height);
+    }
+
+    /// The last `render`, in the accumulation's format (summed color and sample count), for `Display`
+    pub fn quad(&self) -> &Quad {
+        &self.images.quad
+    }
+
+    /// Letterboxes `quad` like `RayTracer::fit_to_window`
+    pub fn fit_to_window(&mut self, device: &Device, width: u32, height: u32) {
+        self.images.quad.fit_to_window(device, width, height);
+    }
+
+    pub fn adjust_iterations(&mut self, change: i32) {
+        self.settings.iterations = (self.settings.iterations as i32 + change).clamp(1, Self::MAX_ITERATIONS as i32) as u32;
+    }
+
+    pub fn scale_luminance_sigma(&mut self, factor: f32) {
+        self.settings.luminance_sigma = (self.settings.luminance_sigma * factor).clamp(0.125, 64.0);
+    }
+
+    fn uniforms(settings: &DenoiseSettings) -> DenoiseUniforms {
+        DenoiseUniforms {
+            luminance_sigma: settings.luminance_sigma,
+            normal_sigma: settings.normal_sigma,
+            depth_sigma: settings.depth_sigma,
+            _padding: 0.0,
+        }
+    }
+
+    fn create_images(device: &Device, layout: &BindGroupLayout, quad_layout: &BindGroupLayout, uniform_buffer: &Buffer, targets: &AccumulationTargets, width: u32, height: u32) -> Images {
+        let create_texture = |label, usage| {
+            let texture = device.create_texture(&TextureDescriptor {
+                label: Some(label),
+                size: Extent3d {
+                    width,
+                    height,
+                    depth: 1,
+                },
+                array_layer_count: 1,
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: TextureDimension::D2,
+                format: Self::FORMAT,
+                usage,
+            });
+            Texture::from_wgpu_texture(device, texture)
+        };
+
+        let feature_bind_group = device.create_bind_group(&BindGroupDescriptor {
+            layout,
+            bindings: &[
+                Binding {
+                    binding: 0,
+                    resource: BindingResource::TextureView(&targets.image.view),
+                },
+                Binding {
+                    binding: 1,
+                    resource: BindingResource::TextureView(&targets.statistics.view),
+                },
+                Binding {
+                    binding: 2,
+                    resource: BindingResource::TextureView(&targets.albedo_depth.view),
+                },
+                Binding {
+                    binding: 3,
+                    resource: BindingResource::TextureView(&targets.normal.view),
+                },
+                Binding {
+                    binding: 4,
+                    resource: BindingResource::Buffer {
+                        buffer: uniform_buffer,
+                        range: 0..size_of!(DenoiseUniforms) as _,
+                    },
+                },
+            ],
+            label: Some("denoise_feature_bind_group"),
+        });
+
+        let ping_pong = [
+            create_texture("denoise_ping", TextureUsage::STORAGE),
+            create_texture("denoise_pong", TextureUsage::STORAGE),
+        ];
+
+        let mut output = create_texture("denoise_output", TextureUsage::STORAGE | TextureUsage::SAMPLED);
+        output.image_dimensions = Some((width, height));
+        let quad = Quad::new(device, quad_layout, output, None);
+
+        Images {
+            feature_bind_group,
+            ping_pong,
+            quad,
+            width,
+            height,
+        }
+    }
+
+    /// Filters the ray tracer's current accumulation into `quad`
+    pub fn render(&self, device: &Device, queue: &Queue) {
+        let mut encoder = device.create_command_encoder(&CommandEncoderDescriptor {
+            label: Some("denoise_encoder"),
+        });
+
+        let staging_buffer = device.create_buffer_with_data(
+            bytemuck::cast_slice(&[Self::uniforms(&self.settings)]),
+            BufferUsage::COPY_SRC,
+        );
+
+        encoder.copy_buffer_to_buffer(
+            &staging_buffer, 0,
+            &self.uniform_buffer, 0,
+            size_of!(DenoiseUniforms) as _,
+        );
+
+        let [ping, pong] = &self.images.ping_pong;
+
+        // Stage, step size, input and output of each pass. The prepare stage doesn't read its input.
+        let mut passes = vec![(STAGE_PREPARE, 0, pong, ping)];
+        let (mut input, mut output) = (ping, pong);
+        for iteration in 0..self.settings.iterations {
+            if iteration + 1 == self.settings.iterations {
+                passes.push((STAGE_FILTER_LAST, 1 << iteration, input, self.images.quad.texture()));
+            } else {
+                passes.push((STAGE_FILTER, 1 << iteration, input, output));
+                std::mem::swap(&mut input, &mut output);
+            }
+        }
+
+        for (stage, step_size, input, output) in passes {
+            let pass_buffer = device.create_buffer_with_data(
+                bytemuck::cast_slice(&[PassUniforms { step_size, stage, _padding: [0; 2] }]),
+                BufferUsage::UNIFORM,
+            );
+
+            let pass_bind_group = device.create_bind_group(&BindGroupDescriptor {
+                layout: &self.pass_bind_group_layout,
+                bindings: &[
+                    Binding {
+                        binding: 0,
+                        resource: BindingResource::TextureView(&input.view),
+                    },
+                    Binding {
+                        binding: 1,
+                        resource: BindingResource::TextureView(&output.view),
+                    },
+                    Binding {
+                        binding: 2,
+                        resource: BindingResource::Buffer {
+                            buffer: &pass_buffer,
+                            range: 0..size_of!(PassUniforms) as _,
+                        },
+                    },
+                ],
+                label: Some("denoise_pass_bind_group"),
+            });
+
+            // Separate passes so each stage sees the previous one's writes
+            let mut compute_pass = encoder.begin_compute_pass();
+            compute_pass.set_pipeline(&self.pipeline);
+            compute_pass.set_bind_group(0, &self.images.feature_bind_group, &[]);
+            compute_pass.set_bind_group(1, &pass_bind_group, &[]);
+            // Rounded up, the shader skips invocations outside the image
+            compute_pass.dispatch(
+                (self.images.width + Self::WORK_GROUP_SIZE - 1) / Self::WORK_GROUP_SIZE,
+                (self.images.height + Self::WORK_GROUP_SIZE - 1) / Self::WORK_GROUP_SIZE,
+                1,
+            );
+        }
+
+        queue.submit(&[encoder.finish()]);
+    }
+}
@@ -0,0 +1,257 @@
+use wgpu::*;
+
+use crate::quad::Quad;
+
+/// Operator mapping linear radiance onto the displayable range
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Tonemapper {
+    /// Clamp only
+    None,
+    Reinhard,
+    AcesFilmic,
+}
+
+impl Tonemapper {
+    pub fn next(self) -> Self {
+        match self {
+            Tonemapper::None => Tonemapper::Reinhard,
+            Tonemapper::Reinhard => Tonemapper::AcesFilmic,
+            Tonemapper::AcesFilmic => Tonemapper::None,
+        }
+    }
+
+    // Must match the `TONEMAP_*` defines in display.frag
+    fn shader_id(self) -> u32 {
+        match self {
+            Tonemapper::None => 0,
+            Tonemapper::Reinhard => 1,
+            Tonemapper::AcesFilmic => 2,
+        }
+    }
+}
+
+/// What the display pass shows
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum DisplayView {
+    /// The tonemapped render
+    Color,
+    /// Samples taken per pixel, relative to sampling every pixel equally (blue: fewer, red: more)
+    SampleHeatmap,
+}
+
+impl DisplayView {
+    pub fn next(self) -> Self {
+        match self {
+            DisplayView::Color => DisplayView::SampleHeatmap,
+            DisplayView::SampleHeatmap => DisplayView::Color,
+        }
+    }
+
+    // Must match the `VIEW_*` defines in display.frag
+    fn shader_id(self) -> u32 {
+        match self {
+            DisplayView::Color => 0,
+            DisplayView::SampleHeatmap => 1,
+        }
+    }
+}
+
+/// How the render resolution follows the window. The display pass letterboxes renders of
+/// another aspect ratio (see `Quad::new`).
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum RenderResolution {
+    /// The window's size
+    Window,
+    /// A fixed size, e.g. larger than the window for supersampling
+    Fixed(u32, u32),
+    /// The largest size with this aspect ratio (width over height) that fits in the window
+    Aspect(f32),
+}
+
+impl RenderResolution {
+    pub fn render_size(self, window_width: u32, window_height: u32) -> (u32, u32) {
+        match self {
+            RenderResolution::Window => (window_width, window_height),
+            RenderResolution::Fixed(width, height) => (width, height),
+            RenderResolution::Aspect(aspect_ratio) => {
+                let width = (window_height as f32 * aspect_ratio).round() as u32;
+                if width <= window_width {
+                    (width.max(1), window_height)
+                } else {
+                    (window_width, ((window_width as f32 / aspect_ratio).round() as u32).max(1))
+                }
+            }
+        }
+    }
+}
+
+/// CPU version of display.frag for writing images to disk
+pub fn display_color(color: [f32; 3], tonemapper: Tonemapper, exposure: f32) -> [u8; 3] {
+    let scale = exposure.exp2();
+
+    let mut output = [0; 3];
+    for (channel, &value) in output.iter_mut().zip(color.iter()) {
+        let value = (value * scale).max(0.0);
+
+        let value = match tonemapper {
+            Tonemapper::None => value,
+            Tonemapper::Reinhard => value / (1.0 + value),
+            // Narkowicz's fit of the ACES filmic curve
+            Tonemapper::AcesFilmic => (value * (2.51 * value + 0.03)) / (value * (2.43 * value + 0.59) + 0.14),
+        };
+
+        *channel = (linear_to_srgb(value.clamp(0.0, 1.0)) * 255.0).round() as u8;
+    }
+
+    output
+}
+
+fn linear_to_srgb(value: f32) -> f32 {
+    if value <= 0.0031308 {
+        value * 12.92
+    } else {
+        1.055 * value.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+struct DisplayUniforms {   // OFFSET + SIZE
+    exposure: f32,         // 0 + 4
+    tonemapper: u32,       // 4 + 4
+    view: u32,             // 8 + 4
+    uniform_samples: f32,  // 12 + 4
+}
+unsafe impl bytemuck::Pod for DisplayUniforms {}
+unsafe impl bytemuck::Zeroable for DisplayUniforms {}
+
+/// Tonemaps and sRGB-encodes the ray tracer's linear accumulation buffer onto the screen
+pub struct Display {
+    pipeline: RenderPipeline,
+
+    uniform_buffer: Buffer,
+    uniform_bind_group: BindGroup,
+
+    pub tonemapper: Tonemapper,
+    /// In stops (powers of two)
+    pub exposure: f32,
+    pub view: DisplayView,
+}
+
+impl Display {
+    pub fn new(device: &Device, quad_layout: &BindGroupLayout, color_format: TextureFormat) -> Self {
+        let tonemapper = Tonemapper::AcesFilmic;
+        let exposure = 0.0;
+        let view = DisplayView::Color;
+
+        let uniform_buffer = device.create_buffer_with_data(
+            bytemuck::cast_slice(&[DisplayUniforms {
+                exposure,
+                tonemapper: tonemapper.shader_id(),
+                view: view.shader_id(),
+                uniform_samples: 1.0,
+            }]),
+            BufferUsage::UNIFORM | BufferUsage::COPY_DST,
+        );
+
+        let uniform_bind_group_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            bindings: &[
+                BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: ShaderStage::FRAGMENT,
+                    ty: BindingType::UniformBuffer {
+                        dynamic: false,
+                    },
+                },
+            ],
+            label: Some("display_uniform_bind_group_layout"),
+        });
+
+        let uniform_bind_group = device.create_bind_group(&BindGroupDescriptor {
+            layout: &uniform_bind_group_layout,
+            bindings: &[
+                Binding {
+                    binding: 0,
+                    resource: BindingResource::Buffer {
+                        buffer: &uniform_buffer,
+                        range: 0..size_of!(DisplayUniforms) as _,
+                    },
+                },
+            ],
+            label: Some("display_uniform_bind_group"),
+        });
+
+        let frag_spirv = include_bytes!("../shaders/quad/display.frag.spv");
+        let pipeline = Quad::create_render_pipeline_with_shader(
+            device,
+            &[quad_layout, &uniform_bind_group_layout],
+            frag_spirv,
+            color_format,
+            None,
+        );
+
+        Self {
+            pipeline,
+            uniform_buffer,
+            uniform_bind_group,
+            tonemapper,
+            exposure,
+            view,
+        }
+    }
+
+    pub fn adjust_exposure(&mut self, stops: f32) {
+        self.exposure += stops;
+    }
+
+    /// Clears the frame and draws `quad` (the accumulation buffer, letterboxed) over it.
+    /// `uniform_samples` is what each pixel would have taken without adaptive sampling, for the heatmap.
+    pub fn render(&self, device: &Device, queue: &Queue, frame: &TextureView, quad: &Quad, uniform_samples: u32) {
+        let mut encoder = device.create_command_encoder(&CommandEncoderDescriptor {
+            label: Some("display_encoder"),
+        });
+
+        let staging_buffer = device.create_buffer_with_data(
+            bytemuck::cast_slice(&[DisplayUniforms {
+                exposure: self.exposure,
+                tonemapper: self.tonemapper.shader_id(),
+                view: self.view.shader_id(),
+                uniform_samples: uniform_samples.max(1) as f32,
+            }]),
+            BufferUsage::COPY_SRC
+        );
+
+        encoder.copy_buffer_to_buffer(
+            &staging_buffer, 0,
+            &self.uniform_buffer, 0,
+            size_of!(DisplayUniforms) as _,
+        );
+
+        let mut render_pass = encoder.begin_render_pass(&RenderPassDescriptor {
+            color_attachments: &[
+                RenderPassColorAttachmentDescriptor {
+                    attachment: frame,
+                    resolve_target: None,
+                    load_op: LoadOp::Clear,
+                    store_op: StoreOp::Store,
+                    clear_color: Color {
+                        r: 0.1, g: 0.05, b: 0.1, a: 1.0,
+                    },
+                },
+            ],
+            depth_stencil_attachment: None,
+        });
+
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_vertex_buffer(0, &quad.vertex_buffer, 0, 0);
+        render_pass.set_index_buffer(&quad.index_buffer, 0, 0);
+        render_pass.set_bind_group(0, &quad.bind_group, &[]);
+        render_pass.set_bind_group(1, &self.uniform_bind_group, &[]);
+        // 2 triangles => 6 indices
+        render_pass.draw_indexed(0..6, 0, 0..1);
+
+        drop(render_pass);
+
+        queue.submit(&[encoder.finish()]);
+    }
+}
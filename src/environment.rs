@@ -0,0 +1,108 @@
+use serde::Deserialize;
+
+use crate::texture::HdrImage;
+
+/// Equirectangular environment map as written in a scene file
+#[derive(Deserialize)]
+pub struct EnvironmentDescription {
+    /// `.hdr` or `.exr`, relative to the scene file
+    pub path: String,
+    #[serde(default = "EnvironmentDescription::default_intensity")]
+    pub intensity: f32,
+    /// Rotation about the y axis in degrees
+    #[serde(default)]
+    pub rotation: f32,
+}
+
+impl EnvironmentDescription {
+    fn default_intensity() -> f32 { 1.0 }
+}
+
+/// Environment map used for the background and as a light source
+pub struct Environment {
+    pub image: HdrImage,
+    pub distribution: EnvironmentDistribution,
+    pub intensity: f32,
+    /// Degrees about the y axis
+    pub rotation: f32,
+}
+
+impl Environment {
+    pub fn load(description: &EnvironmentDescription, base_directory: &std::path::Path) -> Result<Self, String> {
+        let image = HdrImage::from_path(base_directory.join(&description.path))?;
+        let distribution = EnvironmentDistribution::new(&image);
+
+        Ok(Self {
+            image,
+            distribution,
+            intensity: description.intensity,
+            rotation: description.rotation,
+        })
+    }
+}
+
+/// Piecewise-constant 2D distribution for importance sampling an environment map by luminance.
+///
+/// Both CDFs are inclusive and normalized, so the probability of a texel is the difference
+/// between its CDF value and its predecessor's. The shader relies on this to evaluate PDFs.
+pub struct EnvironmentDistribution {
+    /// One entry per row (`height`)
+    pub marginal_cdf: Vec<f32>,
+    /// One row of `width` entries per image row
+    pub conditional_cdf: Vec<f32>,
+}
+
+impl EnvironmentDistribution {
+    pub fn new(image: &HdrImage) -> Self {
+        let width = image.width as usize;
+        let height = image.height as usize;
+
+        let mut conditional_cdf = Vec::with_capacity(width * height);
+        let mut row_sums = Vec::with_capacity(height);
+
+        for y in 0..height {
+            // Rows near the poles cover less solid angle
+            let sin_theta = (std::f32::consts::PI * (y as f32 + 0.5) / height as f32).sin();
+
+            let row = &image.pixels[y * width..(y + 1) * width];
+            let mut sum = 0.0;
+            let row_start = conditional_cdf.len();
+            for pixel in row {
+                sum += luminance(pixel) * sin_theta;
+                conditional_cdf.push(sum);
+            }
+
+            Self::normalize(&mut conditional_cdf[row_start..], sum);
+            row_sums.push(sum);
+        }
+
+        let mut marginal_cdf = Vec::with_capacity(height);
+        let mut sum = 0.0;
+        for row_sum in row_sums {
+            sum += row_sum;
+            marginal_cdf.push(sum);
+        }
+        Self::normalize(&mut marginal_cdf, sum);
+
+        Self {
+            marginal_cdf,
+            conditional_cdf,
+        }
+    }
+
+    /// Divides a running sum by its total. Black regions fall back to uniform.
+    fn normalize(cdf: &mut [f32], sum: f32) {
+        let count = cdf.len();
+        for (i, value) in cdf.iter_mut().enumerate() {
+            *value = if sum > 0.0 {
+                *value / sum
+            } else {
+                (i + 1) as f32 / count as f32
+            };
+        }
+    }
+}
+
+fn luminance(pixel: &[f32; 4]) -> f32 {
+    0.2126 * pixel[0] + 0.7152 * pixel[1] + 0.0722 * pixel[2]
+}
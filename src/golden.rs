@@ -0,0 +1,181 @@
+// Golden-image regression tests.
+//
+// Canonical scenes are rendered at low resolution with the CPU reference renderer (whose random
+// numbers are seeded per pixel and frame, so renders are repeatable) and compared to the PNGs in
+// `res/golden`. A failing scene writes its render and a diff heatmap to `target/golden`.
+//
+// After an intentional change to the look, regenerate the references with
+//     GOLDEN_UPDATE=1 cargo test golden
+// and check the new images in. The references were rendered on x86-64 Linux; other platforms'
+// math libraries may round differently and need their own.
+
+use std::path::{Path, PathBuf};
+
+use crate::camera::Camera;
+use crate::cpu::CpuRayTracer;
+use crate::display::{display_color, Tonemapper};
+use crate::raytrace::Uniforms;
+use crate::scene::Scene;
+
+const WIDTH: u32 = 96;
+const HEIGHT: u32 = 54;
+const FRAMES: u32 = 8;
+
+/// Renders are repeatable, so anything that changes the paths taken (even a small change to
+/// scattering) shows up as noise-level differences well below this. Identical renders are infinite.
+const MIN_PSNR: f32 = 50.0;
+
+/// Errors at or above this are saturated in the heatmap
+const HEATMAP_MAX_ERROR: f32 = 0.25;
+
+/// Tonemapped 8-bit image, as written by headless renders
+type Image = image::RgbImage;
+
+fn manifest_path(path: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join(path)
+}
+
+fn render(scene_name: &str) -> Image {
+    let scene = Scene::from_path(manifest_path(&format!("res/scenes/{}.ron", scene_name))).unwrap();
+
+    let mut uniforms = Uniforms::new(WIDTH, HEIGHT, &scene);
+    uniforms.set_camera(&Camera::from_description(&scene.camera, 0.0));
+
+    let rendered = CpuRayTracer::new(&scene, uniforms).render(FRAMES);
+
+    Image::from_fn(WIDTH, HEIGHT, |x, y| {
+        let [r, g, b, _] = rendered.pixels[(y * WIDTH + x) as usize];
+        image::Rgb(display_color([r, g, b], Tonemapper::AcesFilmic, 0.0))
+    })
+}
+
+/// Root mean square error over all channels, on [0, 1]
+fn rmse(a: &Image, b: &Image) -> f32 {
+    let squared_error: f64 = a.as_raw().iter()
+        .zip(b.as_raw())
+        .map(|(&a, &b)| {
+            let difference = (a as f64 - b as f64) / 255.0;
+            difference * difference
+        })
+        .sum();
+
+    (squared_error / a.as_raw().len() as f64).sqrt() as f32
+}
+
+/// Peak signal to noise ratio in decibels (infinite for identical images)
+fn psnr(rmse: f32) -> f32 {
+    -20.0 * rmse.log10()
+}
+
+/// Per pixel error, black (none) through red and yellow to white (`HEATMAP_MAX_ERROR` or more)
+fn diff_heatmap(a: &Image, b: &Image) -> Image {
+    Image::from_fn(a.width(), a.height(), |x, y| {
+        let (pixel_a, pixel_b) = (a.get_pixel(x, y), b.get_pixel(x, y));
+
+        let error = (0..3)
+            .map(|channel| (pixel_a[channel] as f32 - pixel_b[channel] as f32).abs() / 255.0)
+            .fold(0.0, f32::max);
+        let t = (error / HEATMAP_MAX_ERROR).min(1.0) * 3.0;
+
+        let ramp = |start: f32| ((t - start).clamp(0.0, 1.0) * 255.0) as u8;
+        image::Rgb([ramp(0.0), ramp(1.0), ramp(2.0)])
+    })
+}
+
+fn check(scene_name: &str) {
+    let actual = render(scene_name);
+    let reference_path = manifest_path(&format!("res/golden/{}.png", scene_name));
+
+    if std::env::var_os("GOLDEN_UPDATE").is_some() {
+        actual.save(&reference_path).unwrap();
+        return;
+    }
+
+    let reference = image::open(&reference_path)
+        .unwrap_or_else(|e| panic!("Missing reference {:?} ({}). Run with GOLDEN_UPDATE=1 to create it.", reference_path, e))
+        .to_rgb8();
+    assert_eq!(reference.dimensions(), actual.dimensions(), "Reference {:?} has the wrong size", reference_path);
+
+    let rmse = rmse(&actual, &reference);
+    let psnr = psnr(rmse);
+
+    if psnr < MIN_PSNR {
+        let output_directory = manifest_path("target/golden");
+        std::fs::create_dir_all(&output_directory).unwrap();
+
+        let actual_path = output_directory.join(format!("{}.actual.png", scene_name));
+        let diff_path = output_directory.join(format!("{}.diff.png", scene_name));
+        actual.save(&actual_path).unwrap();
+        diff_heatmap(&actual, &reference).save(&diff_path).unwrap();
+
+        panic!(
+            "{} differs from its reference: RMSE {:.4}, PSNR {:.2} dB (minimum {:.2} dB)\nRender: {:?}\nDiff heatmap: {:?}",
+            scene_name, rmse, psnr, MIN_PSNR, actual_path, diff_path,
+        );
+    }
+}
+
+#[test]
+fn default_scene() {
+    check("default");
+}
+
+#[test]
+fn cornell_scene() {
+    check("cornell");
+}
+
+#[test]
+fn environment_scene() {
+    check("environment");
+}
+
+#[test]
+fn mesh_scene() {
+    check("mesh");
+}
+
+#[test]
+fn materials_scene() {
+    check("materials");
+}
+
+#[test]
+fn shapes_scene() {
+    check("shapes");
+}
+
+#[test]
+fn quadrics_scene() {
+    check("quadrics");
+}
+
+#[test]
+fn csg_scene() {
+    check("csg");
+}
+
+#[test]
+fn sdf_scene() {
+    check("sdf");
+}
+
+#[test]
+fn motion_scene() {
+    check("motion");
+}
+
+#[test]
+fn heatmap_and_metrics() {
+    let black = Image::new(4, 4);
+    let mut white = Image::new(4, 4);
+    white.pixels_mut().for_each(|pixel| *pixel = image::Rgb([255; 3]));
+
+    assert_eq!(rmse(&black, &black), 0.0);
+    assert!(psnr(rmse(&black, &black)).is_infinite());
+    assert!((rmse(&black, &white) - 1.0).abs() < 1e-6);
+    assert!(psnr(rmse(&black, &white)).abs() < 1e-6);
+
+    assert_eq!(diff_heatmap(&black, &black).get_pixel(0, 0), &image::Rgb([0, 0, 0]));
+    assert_eq!(diff_heatmap(&black, &white).get_pixel(0, 0), &image::Rgb([255, 255, 255]));
+}
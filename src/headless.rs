@@ -0,0 +1,123 @@
+use wgpu::*;
+
+use crate::camera::Camera;
+use crate::cpu::CpuRayTracer;
+use crate::display::Tonemapper;
+use crate::quad::Quad;
+use crate::raytrace::{Backend, RayTracer, Uniforms};
+use crate::scene::Scene;
+use crate::texture::HdrImage;
+
+/// Settings for rendering straight to a file without opening a window
+pub struct HeadlessOptions {
+    pub width: u32,
+    pub height: u32,
+    /// Total samples per pixel, rounded up to a whole number of frames
+    pub samples_per_pixel: u32,
+    /// `.exr` keeps linear radiance. Other formats are tonemapped like the window.
+    pub output: std::path::PathBuf,
+    /// Skip the GPU and use `CpuRayTracer`
+    pub cpu: bool,
+    pub backend: Backend,
+}
+
+/// Renders `scene` offscreen and writes the result to `options.output`.
+///
+/// Falls back to the CPU renderer when no graphics adapter is available.
+pub async fn render(scene: &Scene, options: &HeadlessOptions) -> Result<(), String> {
+    let (image, metadata) = if options.cpu {
+        render_cpu(scene, options)
+    } else {
+        match request_device().await {
+            Ok((device, queue)) => render_gpu(&device, &queue, scene, options).await?,
+            Err(e) => {
+                println!("{}, falling back to the CPU renderer", e);
+                render_cpu(scene, options)
+            }
+        }
+    };
+
+    image.save(&options.output, Tonemapper::AcesFilmic, 0.0, &metadata)?;
+
+    println!("Saved {:?}", options.output);
+    Ok(())
+}
+
+/// Whole frames needed to reach the requested sample count
+fn frame_count(options: &HeadlessOptions, samples_per_frame: u32) -> u32 {
+    let samples_per_frame = samples_per_frame.max(1);
+    let frames = ((options.samples_per_pixel + samples_per_frame - 1) / samples_per_frame).max(1);
+
+    println!("Rendering {}x{} at {} samples per pixel ({} frames)...",
+        options.width, options.height, frames * samples_per_frame, frames);
+
+    frames
+}
+
+/// Returns the image along with its metadata (see `Uniforms::image_metadata`)
+fn render_cpu(scene: &Scene, options: &HeadlessOptions) -> (HdrImage, Vec<(String, String)>) {
+    let mut uniforms = Uniforms::new(options.width, options.height, scene);
+    uniforms.set_camera(&Camera::from_description(&scene.camera, 0.0));
+    // The CPU renderer samples every pixel equally
+    uniforms.adaptive_threshold = 0.0;
+
+    let frames = frame_count(options, uniforms.samples_per_pixel);
+
+    let image = CpuRayTracer::new(scene, uniforms).render(frames);
+    (image, uniforms.image_metadata(frames))
+}
+
+async fn render_gpu(device: &Device, queue: &Queue, scene: &Scene, options: &HeadlessOptions) -> Result<(HdrImage, Vec<(String, String)>), String> {
+    let quad_bind_group_layout = Quad::bind_group_layout(device);
+    let mut raytracer = RayTracer::new(device, queue, &quad_bind_group_layout, options.width, options.height, scene);
+    raytracer.update_camera(&Camera::from_description(&scene.camera, 0.0));
+    raytracer.backend = options.backend;
+
+    let frames = frame_count(options, raytracer.samples_per_frame());
+
+    let start = std::time::Instant::now();
+    for frame in 0..frames {
+        raytracer.render_frame(device, queue);
+
+        // Keep the queue from growing unbounded
+        device.poll(Maintain::Wait);
+
+        if (frame + 1) % 16 == 0 || frame + 1 == frames {
+            println!("Frame {}/{}", frame + 1, frames);
+        }
+    }
+
+    let elapsed = start.elapsed().as_secs_f32();
+    println!("{:?} backend: {:.2}s ({:.1} ms/frame)", options.backend, elapsed, elapsed * 1000.0 / frames as f32);
+
+    let image = raytracer.read_accumulation(device, queue).await?;
+    Ok((image, raytracer.uniforms().image_metadata(raytracer.frame_count())))
+}
+
+/// Prefers a Vulkan GPU, then any other backend. Software Vulkan drivers
+/// (lavapipe, SwiftShader) are picked up here when no hardware is present.
+async fn request_device() -> Result<(Device, Queue), String> {
+    let mut adapter = None;
+    for &backends in &[BackendBit::VULKAN, BackendBit::all()] {
+        adapter = Adapter::request(&RequestAdapterOptions {
+                power_preference: PowerPreference::HighPerformance,
+                compatible_surface: None,
+            },
+            backends,
+        ).await;
+
+        if adapter.is_some() {
+            break;
+        }
+    }
+
+    let adapter = adapter.ok_or_else(|| "No graphics adapter found".to_string())?;
+    println!("Using {:?}", adapter.get_info());
+
+    Ok(adapter.request_device(&DeviceDescriptor {
+        extensions: Extensions {
+            anisotropic_filtering: false,
+        },
+        limits: Limits::default(),
+    }).await)
+}
@@ -19,12 +19,59 @@ mod raytrace;
 mod camera;
 mod application;
 mod text;
+mod scene;
+mod mesh;
+mod bvh;
+mod environment;
+mod display;
+mod headless;
+mod cpu;
+mod cli;
+mod checkpoint;
+mod wavefront;
+mod denoise;
+mod reproject;
+mod preview;
+
+#[cfg(test)]
+mod golden;
 
 #[allow(unused)]
 mod timing;
 
 fn main() {
-    let mut system = futures::executor::block_on(system::System::new(1920, 1080));
+    let args = cli::Args::from_command_line();
+
+    let mut scene = scene::Scene::from_path(&args.scene).unwrap_or_else(|e| exit_with_error(&e));
+    args.apply(&mut scene).unwrap_or_else(|e| exit_with_error(&e));
+
+    if args.headless {
+        let options = headless::HeadlessOptions {
+            width: args.width,
+            height: args.height,
+            samples_per_pixel: args.spp,
+            output: args.out,
+            cpu: args.cpu,
+            backend: args.backend,
+        };
+
+        if let Err(e) = futures::executor::block_on(headless::render(&scene, &options)) {
+            exit_with_error(&e);
+        }
+        return;
+    }
+
+    let checkpoint_options = args.checkpoint.map(|path| checkpoint::CheckpointOptions {
+        path,
+        autosave: args.autosave.map(std::time::Duration::from_secs),
+    });
+
+    let mut system = futures::executor::block_on(system::System::new(args.width, args.height, args.render_resolution(), scene, args.backend, checkpoint_options, args.denoise));
     
     system.run();
 }
+
+fn exit_with_error(message: &str) -> ! {
+    eprintln!("{}", message);
+    std::process::exit(1);
+}
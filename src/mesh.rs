@@ -0,0 +1,170 @@
+use cgmath::InnerSpace;
+
+use crate::scene::MaterialDescription;
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+/// Matches `Vertex` in the shader (std430)
+pub struct GpuVertex {                  // OFFSET + SIZE
+    pub position: cgmath::Vector3<f32>, // 0 + 12
+    _padding1: u32,                     // 12 + 4
+    pub normal: cgmath::Vector3<f32>,   // 16 + 12
+    _padding2: u32,                     // 28 + 4
+}
+unsafe impl bytemuck::Pod for GpuVertex {}
+unsafe impl bytemuck::Zeroable for GpuVertex {}
+
+impl GpuVertex {
+    pub fn new(position: cgmath::Vector3<f32>, normal: cgmath::Vector3<f32>) -> Self {
+        Self {
+            position,
+            _padding1: 0,
+            normal,
+            _padding2: 0,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+/// Matches `Triangle` in the shader (std430)
+pub struct GpuTriangle {                // OFFSET + SIZE
+    /// Indices into the vertex buffer
+    pub indices: [u32; 3],              // 0 + 12
+    pub material_index: u32,            // 12 + 4
+}
+unsafe impl bytemuck::Pod for GpuTriangle {}
+unsafe impl bytemuck::Zeroable for GpuTriangle {}
+
+/// Triangles loaded from an OBJ file.
+///
+/// Vertex and material indices are local to this mesh. `Scene` offsets them when merging.
+pub struct Mesh {
+    pub vertices: Vec<GpuVertex>,
+    pub triangles: Vec<GpuTriangle>,
+    /// Materials from the accompanying MTL file
+    pub materials: Vec<MaterialDescription>,
+}
+
+impl Mesh {
+    /// Loads and triangulates an OBJ file, placing it with `scale` followed by `translation`.
+    ///
+    /// Faces without an MTL material are given a grey lambertian material (the last in `materials`).
+    pub fn from_obj<P: AsRef<std::path::Path>>(path: P, translation: cgmath::Vector3<f32>, scale: f32) -> Result<Self, String> {
+        let (models, mtl_result) = tobj::load_obj(path.as_ref(), &tobj::LoadOptions {
+            triangulate: true,
+            single_index: true,
+            ..Default::default()
+        }).map_err(|e| format!("Failed to load {:?}: {}", path.as_ref(), e))?;
+
+        // Missing MTL files are not fatal
+        let mtl_materials = mtl_result.unwrap_or_else(|e| {
+            println!("Could not load materials for {:?}: {}", path.as_ref(), e);
+            Vec::new()
+        });
+
+        let mut materials: Vec<MaterialDescription> = mtl_materials.iter()
+            .map(Self::convert_material)
+            .collect();
+        let default_material = materials.len() as u32;
+        materials.push(MaterialDescription::Lambertian { albedo: [0.5; 3] });
+
+        let mut vertices = Vec::new();
+        let mut triangles = Vec::new();
+
+        for model in &models {
+            let mesh = &model.mesh;
+            let first_vertex = vertices.len() as u32;
+
+            for (i, position) in mesh.positions.chunks(3).enumerate() {
+                let position = cgmath::Vector3::new(position[0], position[1], position[2]) * scale + translation;
+
+                let normal = if mesh.normals.len() == mesh.positions.len() {
+                    cgmath::Vector3::new(mesh.normals[3*i], mesh.normals[3*i + 1], mesh.normals[3*i + 2])
+                } else {
+                    // Accumulated from faces below
+                    cgmath::Vector3::new(0.0, 0.0, 0.0)
+                };
+
+                vertices.push(GpuVertex::new(position, normal));
+            }
+
+            let material_index = mesh.material_id.map_or(default_material, |id| id as u32);
+
+            for face in mesh.indices.chunks(3) {
+                triangles.push(GpuTriangle {
+                    indices: [first_vertex + face[0], first_vertex + face[1], first_vertex + face[2]],
+                    material_index,
+                });
+            }
+
+            if mesh.normals.len() != mesh.positions.len() {
+                Self::compute_normals(&mut vertices, &triangles[triangles.len() - mesh.indices.len() / 3..]);
+            }
+        }
+
+        Ok(Self {
+            vertices,
+            triangles,
+            materials,
+        })
+    }
+
+    /// Area-weighted vertex normals for meshes exported without them
+    fn compute_normals(vertices: &mut [GpuVertex], triangles: &[GpuTriangle]) {
+        for triangle in triangles {
+            let [a, b, c] = triangle.indices;
+            let (a, b, c) = (a as usize, b as usize, c as usize);
+
+            // Cross product length is twice the triangle's area
+            let face_normal = (vertices[b].position - vertices[a].position)
+                .cross(vertices[c].position - vertices[a].position);
+
+            vertices[a].normal += face_normal;
+            vertices[b].normal += face_normal;
+            vertices[c].normal += face_normal;
+        }
+
+        for triangle in triangles {
+            for &index in &triangle.indices {
+                let normal = &mut vertices[index as usize].normal;
+                if normal.magnitude2() > 0.0 {
+                    *normal = normal.normalize();
+                }
+            }
+        }
+    }
+
+    /// Maps MTL properties onto the closest supported material type:
+    /// - Transparent materials (`d` < 1 or glass illumination models) become dielectrics using `Ni`
+    /// - Reflective materials (illumination models 3, 5, 8) become metals tinted by `Ks`, with fuzz from `Ns`
+    /// - Everything else is lambertian using `Kd`
+    fn convert_material(material: &tobj::Material) -> MaterialDescription {
+        let illumination_model = material.illumination_model.unwrap_or(2);
+
+        let is_transparent = material.dissolve < 1.0 || illumination_model == 4 || illumination_model == 6 || illumination_model == 7;
+        let is_reflective = illumination_model == 3 || illumination_model == 5 || illumination_model == 8;
+
+        if is_transparent {
+            let index_of_refraction = if material.optical_density > 1.0 {
+                material.optical_density
+            } else {
+                1.5
+            };
+
+            MaterialDescription::Dielectric { index_of_refraction }
+        } else if is_reflective {
+            // Phong exponent (0 to 1000) to fuzz (1 to 0)
+            let fuzz = (2.0 / (material.shininess.max(0.0) + 2.0)).sqrt();
+
+            MaterialDescription::Metal {
+                albedo: material.specular,
+                fuzz,
+            }
+        } else {
+            MaterialDescription::Lambertian {
+                albedo: material.diffuse,
+            }
+        }
+    }
+}
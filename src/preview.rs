@@ -0,0 +1,82 @@
+use std::time::{Duration, Instant};
+
+use crate::scene::PreviewSettings;
+
+/// Drops to a lower render resolution and fewer bounces while the camera moves, so moving stays
+/// responsive, and returns to full quality once the camera has been still for `idle_delay`.
+///
+/// Only decides the quality. `System` switches the renderer when it changes, which keeps the full
+/// quality accumulation aside meanwhile (see `RayTracer::begin_preview`).
+pub struct Preview {
+    settings: PreviewSettings,
+    /// Bounces outside the preview
+    full_max_ray_bounces: u32,
+    /// When the camera last moved, while previewing
+    last_move: Option<Instant>,
+}
+
+impl Preview {
+    pub fn new(settings: PreviewSettings, full_max_ray_bounces: u32) -> Self {
+        Self {
+            settings,
+            full_max_ray_bounces,
+            last_move: None,
+        }
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.last_move.is_some()
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.settings.enabled
+    }
+
+    /// Returns true if this changes the quality (the preview ends when disabled)
+    pub fn set_enabled(&mut self, enabled: bool) -> bool {
+        self.settings.enabled = enabled;
+        !enabled && self.last_move.take().is_some()
+    }
+
+    /// Returns true if this starts the preview
+    pub fn camera_moved(&mut self, now: Instant) -> bool {
+        if !self.settings.enabled {
+            return false;
+        }
+
+        let started = self.last_move.is_none();
+        self.last_move = Some(now);
+        started
+    }
+
+    /// Returns true if this ends the preview, once the camera has been still long enough
+    pub fn update(&mut self, now: Instant) -> bool {
+        let idle_delay = Duration::from_secs_f32(self.settings.idle_delay);
+
+        match self.last_move {
+            Some(last_move) if now.duration_since(last_move) >= idle_delay => {
+                self.last_move = None;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Render resolution, given the full quality one
+    pub fn render_size(&self, width: u32, height: u32) -> (u32, u32) {
+        if !self.is_active() {
+            return (width, height);
+        }
+
+        let scale = |size: u32| ((size as f32 * self.settings.scale).round() as u32).max(1);
+        (scale(width), scale(height))
+    }
+
+    pub fn max_ray_bounces(&self) -> u32 {
+        if self.is_active() {
+            self.settings.max_ray_bounces.unwrap_or(PreviewSettings::DEFAULT_MAX_RAY_BOUNCES.min(self.full_max_ray_bounces))
+        } else {
+            self.full_max_ray_bounces
+        }
+    }
+}
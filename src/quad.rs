@@ -24,32 +24,48 @@ pub trait QuadBuilder {
 
 impl Quad {
     // TODO: Create quad with specified pixel dimensions/location
-    // TODO: Need to accont for window dimensions/aspect ratio
 
-    /// Creates quad sized according to the texture's dimensions
+    pub fn texture(&self) -> &texture::Texture {
+        &self.texture
+    }
+
+    /// Creates quad sized according to the texture's dimensions, letterboxed to keep its aspect ratio
+    /// in a window of `size` (square if not given)
     pub fn new(device: &Device, layout: &BindGroupLayout, texture: texture::Texture, size: Option<(u32, u32)>) -> Self {
+        let vertices = Self::fitted_vertices(texture.image_dimensions, size);
+
+        Self::create(device, layout, texture, vertices)
+    }
+
+    /// Letterboxes the quad to a new window size (see `new`)
+    pub fn fit_to_window(&mut self, device: &Device, width: u32, height: u32) {
+        let vertices = Self::fitted_vertices(self.texture.image_dimensions, Some((width, height)));
+
+        self.vertex_buffer = Self::create_vertex_buffer(device, vertices);
+    }
+
+    fn fitted_vertices(image_dimensions: Option<(u32, u32)>, window_size: Option<(u32, u32)>) -> [QuadVertex; 4] {
         let mut x = 1.0;
         let mut y = 1.0;
 
-        
-        if let Some((width, height)) = texture.image_dimensions {
+        if let Some((width, height)) = image_dimensions {
             let aspect_ratio = width as f32 / height as f32;
-            if width > height {
-                y = 1.0 / aspect_ratio;
+            let window_aspect_ratio = window_size.map_or(1.0, |(width, height)| width as f32 / height as f32);
+
+            // Fill the window along the image's relatively longer side
+            if aspect_ratio > window_aspect_ratio {
+                y = window_aspect_ratio / aspect_ratio;
             } else {
-                x = 1.0 * aspect_ratio;
+                x = aspect_ratio / window_aspect_ratio;
             }
         }
-        
 
-        let vertices = [
+        [
             QuadVertex::new(( x,  y, 0.0), (1.0, 1.0)), // Top right
             QuadVertex::new((-x,  y, 0.0), (0.0, 1.0)), // Top left
             QuadVertex::new((-x, -y, 0.0), (0.0, 0.0)), // Bottom left
             QuadVertex::new(( x, -y, 0.0), (1.0, 0.0)), // Bottom right
-        ];
-
-        Self::create(device, layout, texture, vertices)
+        ]
     }
 
     pub fn new_full_screen(device: &Device, layout: &BindGroupLayout, texture: texture::Texture) -> Self {
@@ -67,10 +83,7 @@ impl Quad {
         // 2x ccw triangle vertex indices
         let indices = [0u32, 1, 2, 0, 2, 3]; // (topR -> topL, botL), (topR, botL, botR)
 
-        let vertex_buffer = device.create_buffer_with_data(
-            bytemuck::cast_slice(&[vertices]),
-            BufferUsage::VERTEX
-        );
+        let vertex_buffer = Self::create_vertex_buffer(device, vertices);
 
         let index_buffer = device.create_buffer_with_data(
             bytemuck::cast_slice(&[indices]), 
@@ -87,6 +100,13 @@ impl Quad {
         }
     }
 
+    fn create_vertex_buffer(device: &Device, vertices: [QuadVertex; 4]) -> Buffer {
+        device.create_buffer_with_data(
+            bytemuck::cast_slice(&[vertices]),
+            BufferUsage::VERTEX
+        )
+    }
+
     /// NOTE: This must be saved for ALL use cases after being created
     ///
     /// All quads must use the same instance of this layout
@@ -140,18 +160,29 @@ impl Quad {
                                   layout: &BindGroupLayout,
                                   color_format: TextureFormat,
                                   depth_format: Option<TextureFormat>,
+    ) -> RenderPipeline {
+        let frag_spirv = include_bytes!("../shaders/quad/quad.frag.spv");
+
+        Self::create_render_pipeline_with_shader(device, &[layout], frag_spirv, color_format, depth_format)
+    }
+
+    /// Quad pipeline with a custom fragment shader.
+    ///
+    /// `layouts[0]` must be the quad layout. Any further layouts are for the shader's own bind groups.
+    pub fn create_render_pipeline_with_shader(device: &Device,
+                                              layouts: &[&BindGroupLayout],
+                                              frag_spirv: &[u8],
+                                              color_format: TextureFormat,
+                                              depth_format: Option<TextureFormat>,
     ) -> RenderPipeline {
         let layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
-            bind_group_layouts: &[
-                layout,
-            ],
+            bind_group_layouts: layouts,
         });
 
         let vert_spirv = include_bytes!("../shaders/quad/quad.vert.spv");
         let vert_data = read_spirv(std::io::Cursor::new(vert_spirv.as_ref())).unwrap();
 
-        let frag_spirv = include_bytes!("../shaders/quad/quad.frag.spv");
-        let frag_data = read_spirv(std::io::Cursor::new(frag_spirv.as_ref())).unwrap();
+        let frag_data = read_spirv(std::io::Cursor::new(frag_spirv)).unwrap();
 
         let vert_module = device.create_shader_module(&vert_data);
         let frag_module = device.create_shader_module(&frag_data);
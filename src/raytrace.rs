@@ -1,38 +1,218 @@
 use wgpu::*;
 
+use crate::scene::{GpuMaterial, Scene};
+use crate::bvh::{self, GpuBvhNode};
+use crate::environment::EnvironmentDistribution;
+use crate::texture::{HdrImage, Texture};
+use crate::quad::Quad;
+use crate::wavefront::Wavefront;
+use crate::reproject::{Reprojection, View};
+
+// Sections of the packed shape buffer besides the primitive kinds (see `RayTracer::pack_shapes`).
+// Must match the `SHAPES_*` defines in the ray tracing shader.
+const SHAPES_VERTICES: usize = 12;
+const SHAPES_CSG_NODES: usize = 13;
+const SHAPES_SDF_NODES: usize = 14;
+/// Records at the start of the packed shape buffer, holding the record each section starts at
+const SHAPES_HEADER_RECORDS: usize = 4;
+
 #[repr(C)]
 #[derive(Copy, Clone)]
 // Padding help: https://learnopengl.com/Advanced-OpenGL/Advanced-GLSL
 // Vec3/4 must be aligned to multiple of 16
-struct Uniforms {                     // OFFSET + SIZE
-    dimensions: cgmath::Vector2<f32>, // 0 + 8
-    sample_number: u32, // 8 + 4
-    samples_per_pixel: u32, // 12 + 4
+/// Shared by the GPU shader and the CPU reference renderer
+pub struct Uniforms {                     // OFFSET + SIZE
+    pub dimensions: cgmath::Vector2<f32>, // 0 + 8
+    pub sample_number: u32, // 8 + 4
+    pub samples_per_pixel: u32, // 12 + 4
 
-    max_ray_bounces: u32, // 16 + 4
+    pub max_ray_bounces: u32, // 16 + 4
 
 
-    camera_v_fov: f32, // 20 + 4
+    pub camera_v_fov: f32, // 20 + 4
 
     _padding1: [u32; 2], // 24 + 8
-    camera_position: cgmath::Vector3<f32>, // 32 + 12
+    pub camera_position: cgmath::Vector3<f32>, // 32 + 12
 
     _padding2: [u32; 1], // 44 + 4
-    camera_lookat: cgmath::Vector3<f32>, // 48 + 12
+    pub camera_lookat: cgmath::Vector3<f32>, // 48 + 12
+    pub num_spheres: u32, // 60 + 4
+
+    pub sky_horizon: cgmath::Vector3<f32>, // 64 + 12
+    pub num_triangles: u32, // 76 + 4
+    pub sky_zenith: cgmath::Vector3<f32>, // 80 + 12
+    pub num_lights: u32, // 92 + 4
+
+    pub environment_intensity: f32, // 96 + 4
+    pub environment_rotation: f32, // 100 + 4 (radians)
+    pub has_environment: u32, // 104 + 4
+    pub adaptive_threshold: f32, // 108 + 4
+    pub active_pixels: u32, // 112 + 4
+    /// Seeds the random numbers. Unlike `sample_number`, never restarts (see `RayTracer::update_camera`).
+    pub frame_number: u32, // 116 + 4
+    pub num_planes: u32, // 120 + 4
+    /// Camera rays are spread over this time interval (see `CameraDescription::shutter`)
+    pub shutter_open: f32, // 124 + 4
+    pub shutter_close: f32, // 128 + 4
 }
 unsafe impl bytemuck::Pod for Uniforms {}
 unsafe impl bytemuck::Zeroable for Uniforms {}
 
+impl Uniforms {
+    pub fn new(width: u32, height: u32, scene: &Scene) -> Self {
+        Self {
+            dimensions: (width as f32, height as f32).into(),
+            sample_number: 1,
+            samples_per_pixel: scene.render.samples_per_pixel,
+            max_ray_bounces: scene.render.max_ray_bounces,
+
+            // Camera is set by `set_camera`
+            camera_v_fov: 100.0,
+            _padding1: [0; 2],
+            camera_position: (0.0, 0.0, 5.0).into(),
+            _padding2: [0; 1],
+            camera_lookat: (0.0, 0.0, -1.0).into(),
+            num_spheres: scene.spheres.len() as u32,
+
+            sky_horizon: scene.sky.horizon.into(),
+            num_triangles: scene.triangles.len() as u32,
+            sky_zenith: scene.sky.zenith.into(),
+            num_lights: scene.lights.len() as u32,
+
+            environment_intensity: scene.environment.as_ref().map_or(1.0, |environment| environment.intensity),
+            environment_rotation: scene.environment.as_ref().map_or(0.0, |environment| environment.rotation.to_radians()),
+            has_environment: scene.environment.is_some() as u32,
+            adaptive_threshold: scene.render.adaptive_threshold,
+            // Counted by the shader (see `RayTracer::render_frame`)
+            active_pixels: 0,
+            frame_number: 1,
+            num_planes: scene.planes.len() as u32,
+            shutter_open: scene.camera.shutter[0],
+            shutter_close: scene.camera.shutter[1],
+        }
+    }
+
+    /// World space view direction. `camera_lookat` holds it with y flipped, as the shader expects
+    /// (see `Camera::from_description`).
+    pub fn camera_direction(&self) -> cgmath::Vector3<f32> {
+        cgmath::Vector3::new(self.camera_lookat.x, -self.camera_lookat.y, self.camera_lookat.z)
+    }
+
+    /// Render settings and camera pose for images holding `frames` accumulated frames
+    pub fn image_metadata(&self, frames: u32) -> Vec<(String, String)> {
+        let vector = |v: cgmath::Vector3<f32>| format!("{}, {}, {}", v.x, v.y, v.z);
+
+        let mut metadata = vec![
+            // Average per pixel when sampling adaptively
+            ("Samples".to_string(), (frames * self.samples_per_pixel).to_string()),
+            ("SamplesPerFrame".to_string(), self.samples_per_pixel.to_string()),
+            ("MaxRayBounces".to_string(), self.max_ray_bounces.to_string()),
+            ("CameraPosition".to_string(), vector(self.camera_position)),
+            ("CameraDirection".to_string(), vector(self.camera_direction())),
+            ("VerticalFov".to_string(), self.camera_v_fov.to_string()),
+        ];
+
+        if self.shutter_close > self.shutter_open {
+            metadata.push(("Shutter".to_string(), format!("{}, {}", self.shutter_open, self.shutter_close)));
+        }
+        if self.adaptive_threshold > 0.0 {
+            metadata.push(("AdaptiveThreshold".to_string(), self.adaptive_threshold.to_string()));
+        }
+
+        metadata
+    }
+
+    pub fn set_camera(&mut self, camera: &crate::camera::Camera) {
+        self.camera_lookat = camera.target;
+        self.camera_position = camera.position;
+        self.camera_v_fov = camera.v_fov;
+    }
+}
+
+/// How the ray tracing shader is run. Both write the same accumulation buffer.
+#[derive(Copy, Clone, PartialEq, Debug, clap::ValueEnum)]
+pub enum Backend {
+    /// Pixel shader over a full screen quad
+    Fragment,
+    /// Compute shader dispatched in 8x8 work groups
+    Compute,
+    /// Queue based compute passes, one per stage of a bounce (see `Wavefront`)
+    Wavefront,
+}
+
+impl Backend {
+    pub fn next(self) -> Self {
+        match self {
+            Backend::Fragment => Backend::Compute,
+            Backend::Compute => Backend::Wavefront,
+            Backend::Wavefront => Backend::Fragment,
+        }
+    }
+}
+
+/// What the ray tracing shaders write to (set 0 in raytrace.hlsli)
+pub struct AccumulationTargets<'a> {
+    /// Summed radiance in rgb, sample count in alpha
+    pub image: &'a Texture,
+    /// Per pixel luminance mean, variance, sample count and relative error of the mean
+    pub statistics: &'a Texture,
+    /// Single `u32` counting pixels that haven't converged
+    pub active_pixels: &'a Buffer,
+    /// First hit albedo in rgb and depth in alpha, summed like `image`
+    pub albedo_depth: &'a Texture,
+    /// First hit normal in rgb, summed like `image`. Alpha counts the samples in both feature images.
+    pub normal: &'a Texture,
+}
+
+/// Images accumulated next to the color, recreated with it on resize
+struct PixelTextures {
+    statistics: Texture,
+    albedo_depth: Texture,
+    normal: Texture,
+    /// Color attachment for the fragment backend, whose writes are masked. Sized like the render
+    /// rather than the window, which may be smaller.
+    fragment_target: Texture,
+}
+
+/// Full quality accumulation set aside while previewing (see `begin_preview`)
+struct SetAside {
+    texture_bind_group: BindGroup,
+    quad: Quad,
+    pixel_textures: PixelTextures,
+    /// Pose the accumulation was rendered from
+    view: View,
+    sample_number: u32,
+    max_ray_bounces: u32,
+    dimensions: (u32, u32),
+}
 
 pub struct RayTracer {
     texture_bind_group: BindGroup,
     texture_bind_group_layout: BindGroupLayout,
+    /// Linear radiance accumulation buffer for the display pass
+    quad: Quad,
+    pixel_textures: PixelTextures,
+    active_pixel_buffer: Buffer,
 
     uniforms: Uniforms,
     uniform_buffer: Buffer,
     uniform_bind_group: BindGroup,
 
-    pipeline: RenderPipeline,
+    // Scene buffers are kept alive by the bind group
+    scene_bind_group: BindGroup,
+    environment_bind_group: BindGroup,
+
+    render_pipeline: RenderPipeline,
+    compute_pipeline: ComputePipeline,
+    wavefront: Wavefront,
+    reprojection: Reprojection,
+    /// Camera pose the accumulation was rendered from, if the camera has moved since
+    reproject_from: Option<View>,
+    set_aside: Option<SetAside>,
+    /// Keep the accumulation through camera moves where it still lines up, instead of starting over
+    pub temporal_reprojection: bool,
+    /// Can be switched between frames without resetting the accumulation
+    pub backend: Backend,
 
     pub pause_rendering: bool,
     pub target_samples: u32,
@@ -40,38 +220,205 @@ pub struct RayTracer {
 
 impl RayTracer {
     const FORMAT: TextureFormat = TextureFormat::Rgba32Float;
+    /// Must match `numthreads` in raytrace.comp.hlsl
+    const WORK_GROUP_SIZE: u32 = 8;
+    /// Most samples an unconverged pixel takes per frame, in multiples of `samples_per_pixel`.
+    /// Must match `ADAPTIVE_MAX_BOOST` in raytrace.hlsli.
+    const MAX_SAMPLE_BOOST: u32 = 4;
+    /// Where `render_frame` copies the shader's count of unconverged pixels to
+    const ACTIVE_PIXELS_OFFSET: BufferAddress = std::mem::offset_of!(Uniforms, active_pixels) as BufferAddress;
 
     pub fn sample_count(&self) -> u32 {
         self.uniforms.sample_number
     }
 
     pub fn reset_samples(&mut self) {
+        self.restart_accumulation();
+        self.set_aside = None;
+    }
+
+    /// Starts the current accumulation over, keeping any set aside by `begin_preview`
+    fn restart_accumulation(&mut self) {
         self.uniforms.sample_number = 1;
+        self.reproject_from = None;
     }
 
+    /// Reprojects the accumulation onto the new pose before the next frame, or starts over
+    /// without `temporal_reprojection`
     pub fn update_camera(&mut self, camera: &crate::camera::Camera) {
+        if self.temporal_reprojection && self.uniforms.sample_number > 1 {
+            // Several moves between frames reproject once, from where the accumulation was rendered
+            if self.reproject_from.is_none() {
+                self.reproject_from = Some(View::of(&self.uniforms));
+            }
+        } else {
+            self.restart_accumulation();
+        }
+        self.uniforms.set_camera(camera);
+    }
+
+    /// Path length, e.g. shortened while the camera moves (see `Preview`). Changing it starts over.
+    pub fn set_max_ray_bounces(&mut self, max_ray_bounces: u32) {
+        if max_ray_bounces != self.uniforms.max_ray_bounces {
+            self.restart_accumulation();
+            self.uniforms.max_ray_bounces = max_ray_bounces;
+        }
+    }
+
+    /// Rotates the environment map about the y axis
+    pub fn rotate_environment(&mut self, degrees: f32) {
+        self.reset_samples();
+        self.uniforms.environment_rotation = (self.uniforms.environment_rotation + degrees.to_radians()) % (2.0 * std::f32::consts::PI);
+    }
+
+    pub fn scale_environment_intensity(&mut self, factor: f32) {
         self.reset_samples();
-        self.uniforms.camera_lookat = camera.target;
-        self.uniforms.camera_position = camera.position;
-        self.uniforms.camera_v_fov = camera.v_fov;
+        self.uniforms.environment_intensity *= factor;
+    }
+
+    /// Current shader inputs, e.g. for rendering the same view with `CpuRayTracer`
+    pub fn uniforms(&self) -> &Uniforms {
+        &self.uniforms
+    }
+
+    /// Frames in the accumulation buffer
+    pub fn frame_count(&self) -> u32 {
+        self.uniforms.sample_number - 1
+    }
+
+    pub fn samples_per_frame(&self) -> u32 {
+        self.uniforms.samples_per_pixel
+    }
+
+    pub fn has_environment(&self) -> bool {
+        self.uniforms.has_environment != 0
+    }
+
+    /// Converged pixels stop sampling and the rest take their samples (see raytrace.hlsli)
+    pub fn is_adaptive(&self) -> bool {
+        self.uniforms.adaptive_threshold > 0.0
+    }
+
+    /// Quad showing the accumulation buffer (see `Display`), letterboxed by `fit_to_window`
+    pub fn quad(&self) -> &Quad {
+        &self.quad
     }
 
-    pub fn resize(&mut self, device: &Device, width: u32, height: u32) {
+    /// Keeps the render's aspect ratio on a window of this size. Call after `resize` too.
+    pub fn fit_to_window(&mut self, device: &Device, width: u32, height: u32) {
+        self.quad.fit_to_window(device, width, height);
+    }
+
+    /// Sets the render resolution, independent of the window
+    pub fn resize(&mut self, device: &Device, quad_layout: &BindGroupLayout, width: u32, height: u32) {
         // Reset samples to reset frame blending
         self.reset_samples();
 
-        // Set the uniform dimensions
+        // Create a new texture to fit the new size
+        let textures = Self::create_texture_bind_group(
+            device, &self.texture_bind_group_layout, quad_layout, &self.active_pixel_buffer, width, height,
+        );
+        self.replace_textures(device, textures, width, height);
+    }
+
+    /// Switches to a preview of this size and bounce count. With `temporal_reprojection`, the
+    /// accumulation so far is set aside rather than dropped, for `end_preview` to carry on from.
+    pub fn begin_preview(&mut self, device: &Device, quad_layout: &BindGroupLayout, width: u32, height: u32, max_ray_bounces: u32) {
+        // A preview started again (e.g. on a window resize) keeps what was set aside first
+        let keep = self.temporal_reprojection && self.uniforms.sample_number > 1 && self.set_aside.is_none();
+        let view = self.reproject_from.unwrap_or_else(|| View::of(&self.uniforms));
+        let sample_number = self.uniforms.sample_number;
+        let full_max_ray_bounces = self.uniforms.max_ray_bounces;
+        let dimensions = (self.uniforms.dimensions.x as u32, self.uniforms.dimensions.y as u32);
+
+        self.restart_accumulation();
+        self.uniforms.max_ray_bounces = max_ray_bounces;
+
+        let textures = Self::create_texture_bind_group(
+            device, &self.texture_bind_group_layout, quad_layout, &self.active_pixel_buffer, width, height,
+        );
+        let (texture_bind_group, quad, pixel_textures) = self.replace_textures(device, textures, width, height);
+
+        if keep {
+            self.set_aside = Some(SetAside {
+                texture_bind_group,
+                quad,
+                pixel_textures,
+                view,
+                sample_number,
+                max_ray_bounces: full_max_ray_bounces,
+                dimensions,
+            });
+        }
+    }
+
+    /// Returns from the preview to this size and bounce count. The accumulation set aside by
+    /// `begin_preview` is reprojected to the current pose if it still fits, otherwise this starts over.
+    pub fn end_preview(&mut self, device: &Device, quad_layout: &BindGroupLayout, width: u32, height: u32, max_ray_bounces: u32) {
+        let set_aside = self.set_aside.take()
+            .filter(|full| self.temporal_reprojection && full.dimensions == (width, height) && full.max_ray_bounces == max_ray_bounces);
+
+        match set_aside {
+            Some(full) => {
+                self.replace_textures(device, (full.texture_bind_group, full.quad, full.pixel_textures), width, height);
+                self.uniforms.max_ray_bounces = max_ray_bounces;
+                self.uniforms.sample_number = full.sample_number;
+                self.reproject_from = Some(full.view).filter(|view| *view != View::of(&self.uniforms));
+            }
+            None => {
+                self.set_max_ray_bounces(max_ray_bounces);
+                self.resize(device, quad_layout, width, height);
+            }
+        }
+    }
+
+    /// Renders into `textures` from now on and returns the ones they replace
+    fn replace_textures(&mut self, device: &Device, textures: (BindGroup, Quad, PixelTextures), width: u32, height: u32) -> (BindGroup, Quad, PixelTextures) {
         self.uniforms.dimensions = (width as f32, height as f32).into();
 
-        // Create a new texture to fit the new size
-        self.texture_bind_group = Self::create_texture_bind_group(device, &self.texture_bind_group_layout, width, height);
+        let (texture_bind_group, quad, pixel_textures) = textures;
+        let previous = (
+            std::mem::replace(&mut self.texture_bind_group, texture_bind_group),
+            std::mem::replace(&mut self.quad, quad),
+            std::mem::replace(&mut self.pixel_textures, pixel_textures),
+        );
+
+        // Their bind groups refer to the targets
+        let targets = Self::targets(&self.quad, &self.pixel_textures, &self.active_pixel_buffer);
+        self.wavefront.resize(device, &targets, width, height);
+        self.reprojection.resize(device, &targets, width, height);
+
+        previous
+    }
+
+    /// The accumulation and feature images, e.g. for denoising
+    pub fn accumulation_targets(&self) -> AccumulationTargets {
+        Self::targets(&self.quad, &self.pixel_textures, &self.active_pixel_buffer)
+    }
+
+    fn targets<'a>(quad: &'a Quad, pixel_textures: &'a PixelTextures, active_pixel_buffer: &'a Buffer) -> AccumulationTargets<'a> {
+        AccumulationTargets {
+            image: quad.texture(),
+            statistics: &pixel_textures.statistics,
+            active_pixels: active_pixel_buffer,
+            albedo_depth: &pixel_textures.albedo_depth,
+            normal: &pixel_textures.normal,
+        }
     }
 
-    pub fn render_to_frame(&mut self, device: &Device, queue: &Queue, frame: &TextureView) {
+    /// Adds a frame of samples to the accumulation buffer using the current backend.
+    /// The result is only visible through `quad`.
+    pub fn render_frame(&mut self, device: &Device, queue: &Queue) {
         let mut encoder = device.create_command_encoder(&CommandEncoderDescriptor {
             label: Some("ray_trace_encoder"),
         });
 
+        // The reprojected accumulation counts as the first frame, so this one adds to it
+        let reproject_from = self.reproject_from.take();
+        if reproject_from.is_some() {
+            self.uniforms.sample_number = 2;
+        }
+
         let staging_buffer = device.create_buffer_with_data(
             bytemuck::cast_slice(&[self.uniforms]), 
             BufferUsage::COPY_SRC
@@ -83,34 +430,237 @@ impl RayTracer {
                 size_of!(Uniforms) as _,
         );
 
-        let mut render_pass = encoder.begin_render_pass(&RenderPassDescriptor {
-            color_attachments: &[
-                RenderPassColorAttachmentDescriptor {
-                    attachment: &frame,
-                    resolve_target: None,
-                    load_op: LoadOp::Clear,
-                    store_op: StoreOp::Store,
-                    clear_color: Color {
-                        r: 0.1, g: 0.05, b: 0.1, a: 1.0,
-                    },
-                },
-            ],
-            depth_stencil_attachment: None,
-        });
+        if let Some(previous) = &reproject_from {
+            self.reprojection.encode(
+                device,
+                &mut encoder,
+                [&self.uniform_bind_group, &self.scene_bind_group, &self.environment_bind_group],
+                &Self::targets(&self.quad, &self.pixel_textures, &self.active_pixel_buffer),
+                previous,
+                self.uniforms.samples_per_pixel,
+            );
+        }
 
-        render_pass.set_pipeline(&self.pipeline);
-        render_pass.set_bind_group(0, &self.texture_bind_group, &[]);
-        render_pass.set_bind_group(1, &self.uniform_bind_group, &[]);
-        render_pass.draw(0..6, 0..1);
+        if self.is_adaptive() {
+            // Pixels the previous frame left unconverged, as counted by the shader
+            if self.uniforms.sample_number > 1 {
+                encoder.copy_buffer_to_buffer(
+                    &self.active_pixel_buffer, 0,
+                    &self.uniform_buffer, Self::ACTIVE_PIXELS_OFFSET,
+                    size_of!(u32) as _,
+                );
+            }
 
-        drop(render_pass);
+            let zero_buffer = device.create_buffer_with_data(bytemuck::cast_slice(&[0u32]), BufferUsage::COPY_SRC);
+            encoder.copy_buffer_to_buffer(&zero_buffer, 0, &self.active_pixel_buffer, 0, size_of!(u32) as _);
+        }
+
+        match self.backend {
+            Backend::Fragment => {
+                let mut render_pass = encoder.begin_render_pass(&RenderPassDescriptor {
+                    color_attachments: &[
+                        RenderPassColorAttachmentDescriptor {
+                            attachment: &self.pixel_textures.fragment_target.view,
+                            resolve_target: None,
+                            load_op: LoadOp::Load,
+                            store_op: StoreOp::Store,
+                            clear_color: Color {
+                                r: 0.1, g: 0.05, b: 0.1, a: 1.0,
+                            },
+                        },
+                    ],
+                    depth_stencil_attachment: None,
+                });
+
+                render_pass.set_pipeline(&self.render_pipeline);
+                render_pass.set_bind_group(0, &self.texture_bind_group, &[]);
+                render_pass.set_bind_group(1, &self.uniform_bind_group, &[]);
+                render_pass.set_bind_group(2, &self.scene_bind_group, &[]);
+                render_pass.set_bind_group(3, &self.environment_bind_group, &[]);
+                render_pass.draw(0..6, 0..1);
+            }
+
+            Backend::Compute => {
+                let width = self.uniforms.dimensions.x as u32;
+                let height = self.uniforms.dimensions.y as u32;
+
+                let mut compute_pass = encoder.begin_compute_pass();
+
+                compute_pass.set_pipeline(&self.compute_pipeline);
+                compute_pass.set_bind_group(0, &self.texture_bind_group, &[]);
+                compute_pass.set_bind_group(1, &self.uniform_bind_group, &[]);
+                compute_pass.set_bind_group(2, &self.scene_bind_group, &[]);
+                compute_pass.set_bind_group(3, &self.environment_bind_group, &[]);
+                // Rounded up, the shader skips invocations outside the image
+                compute_pass.dispatch(
+                    (width + Self::WORK_GROUP_SIZE - 1) / Self::WORK_GROUP_SIZE,
+                    (height + Self::WORK_GROUP_SIZE - 1) / Self::WORK_GROUP_SIZE,
+                    1,
+                );
+            }
+
+            Backend::Wavefront => {
+                let sample_boost = if self.is_adaptive() { Self::MAX_SAMPLE_BOOST } else { 1 };
+
+                self.wavefront.encode_frame(
+                    device,
+                    &mut encoder,
+                    [&self.uniform_bind_group, &self.scene_bind_group, &self.environment_bind_group],
+                    self.uniforms.samples_per_pixel * sample_boost,
+                    self.uniforms.max_ray_bounces,
+                );
+            }
+        }
 
         queue.submit(&[encoder.finish()]);
 
         self.uniforms.sample_number += 1;
+        self.uniforms.frame_number += 1;
+    }
+
+    /// Copies the accumulation buffer back to the CPU, averaged over the samples taken so far
+    pub async fn read_accumulation(&self, device: &Device, queue: &Queue) -> Result<HdrImage, String> {
+        let mut image = self.read_accumulation_sums(device, queue).await?;
+
+        // Alpha holds the number of accumulated samples
+        for pixel in &mut image.pixels {
+            let samples = pixel[3].max(1.0);
+            *pixel = [pixel[0] / samples, pixel[1] / samples, pixel[2] / samples, 1.0];
+        }
+
+        Ok(image)
+    }
+
+    /// Copies the accumulation buffer back to the CPU as stored: summed samples in rgb, sample count in alpha
+    pub async fn read_accumulation_sums(&self, device: &Device, queue: &Queue) -> Result<HdrImage, String> {
+        let width = self.uniforms.dimensions.x as u32;
+        let height = self.uniforms.dimensions.y as u32;
+
+        // Buffer rows must be aligned to 256 bytes
+        let pixel_size = size_of!([f32; 4]) as u32;
+        let bytes_per_row = (width * pixel_size + 255) / 256 * 256;
+        let buffer_size = (bytes_per_row * height) as BufferAddress;
+
+        let readback_buffer = device.create_buffer(&BufferDescriptor {
+            label: Some("ray_trace_readback_buffer"),
+            size: buffer_size,
+            usage: BufferUsage::MAP_READ | BufferUsage::COPY_DST,
+        });
+
+        let mut encoder = device.create_command_encoder(&CommandEncoderDescriptor {
+            label: Some("ray_trace_readback_encoder"),
+        });
+
+        encoder.copy_texture_to_buffer(
+            TextureCopyView {
+                texture: &self.quad.texture().texture,
+                mip_level: 0,
+                array_layer: 0,
+                origin: Origin3d::ZERO,
+            },
+            BufferCopyView {
+                buffer: &readback_buffer,
+                offset: 0,
+                bytes_per_row,
+                rows_per_image: height,
+            },
+            Extent3d {
+                width,
+                height,
+                depth: 1,
+            },
+        );
+
+        queue.submit(&[encoder.finish()]);
+
+        let mapping = readback_buffer.map_read(0, buffer_size);
+        device.poll(Maintain::Wait);
+        let mapping = mapping.await.map_err(|_| "Failed to map the readback buffer".to_string())?;
+
+        let mut pixels = Vec::with_capacity((width * height) as usize);
+        for row in mapping.as_slice().chunks(bytes_per_row as usize) {
+            let row: &[[f32; 4]] = bytemuck::cast_slice(&row[..(width * pixel_size) as usize]);
+            pixels.extend_from_slice(row);
+        }
+
+        Ok(HdrImage { width, height, pixels })
     }
 
-    fn create_texture_bind_group(device: &Device, layout: &BindGroupLayout, width: u32, height: u32) -> BindGroup {
+    /// Continues an earlier accumulation, e.g. from a checkpoint. `sums` must be
+    /// in the format of `read_accumulation_sums` and match the current size.
+    ///
+    /// Only the sample counts and the view (camera and environment, see `Checkpoint::has_view`) are
+    /// taken from `uniforms`. Everything else, like the samples per frame, keeps the current settings.
+    ///
+    /// Sample statistics and denoiser features aren't kept. They start over from zero, which the
+    /// shaders handle by counting their samples separately from the color's.
+    pub fn resume(&mut self, device: &Device, queue: &Queue, uniforms: Uniforms, sums: &HdrImage) {
+        let width = self.uniforms.dimensions.x as u32;
+        let height = self.uniforms.dimensions.y as u32;
+        assert_eq!((sums.width, sums.height), (width, height), "Accumulation size mismatch");
+
+        self.uniforms.sample_number = uniforms.sample_number;
+        self.uniforms.frame_number = uniforms.frame_number;
+        self.uniforms.camera_position = uniforms.camera_position;
+        self.uniforms.camera_lookat = uniforms.camera_lookat;
+        self.uniforms.camera_v_fov = uniforms.camera_v_fov;
+        self.uniforms.environment_intensity = uniforms.environment_intensity;
+        self.uniforms.environment_rotation = uniforms.environment_rotation;
+        self.reproject_from = None;
+        self.set_aside = None;
+        // The shader counts from scratch. `render_frame` copies the count over this.
+        self.uniforms.active_pixels = 0;
+
+        // Buffer rows must be aligned to 256 bytes
+        let pixel_size = size_of!([f32; 4]);
+        let bytes_per_row = (width as usize * pixel_size + 255) / 256 * 256;
+
+        let mut data = vec![0u8; bytes_per_row * height as usize];
+        for (row, pixels) in data.chunks_mut(bytes_per_row).zip(sums.pixels.chunks(width as usize)) {
+            row[..pixels.len() * pixel_size].copy_from_slice(bytemuck::cast_slice(pixels));
+        }
+
+        let upload_buffer = device.create_buffer_with_data(&data, BufferUsage::COPY_SRC);
+        let zero_buffer = device.create_buffer_with_data(&vec![0u8; data.len()], BufferUsage::COPY_SRC);
+
+        let mut encoder = device.create_command_encoder(&CommandEncoderDescriptor {
+            label: Some("ray_trace_resume_encoder"),
+        });
+
+        let copies = [
+            (&upload_buffer, self.quad.texture()),
+            (&zero_buffer, &self.pixel_textures.statistics),
+            (&zero_buffer, &self.pixel_textures.albedo_depth),
+            (&zero_buffer, &self.pixel_textures.normal),
+        ];
+
+        for &(buffer, texture) in copies.iter() {
+            encoder.copy_buffer_to_texture(
+                BufferCopyView {
+                    buffer,
+                    offset: 0,
+                    bytes_per_row: bytes_per_row as u32,
+                    rows_per_image: height,
+                },
+                TextureCopyView {
+                    texture: &texture.texture,
+                    mip_level: 0,
+                    array_layer: 0,
+                    origin: Origin3d::ZERO,
+                },
+                Extent3d {
+                    width,
+                    height,
+                    depth: 1,
+                },
+            );
+        }
+
+        queue.submit(&[encoder.finish()]);
+    }
+
+    /// Accumulation (shown by the quad), sample statistics and feature textures for a new image size
+    fn create_texture_bind_group(device: &Device, layout: &BindGroupLayout, quad_layout: &BindGroupLayout, active_pixel_buffer: &Buffer, width: u32, height: u32) -> (BindGroup, Quad, PixelTextures) {
         let size = Extent3d {
             width,
             height,
@@ -125,9 +675,45 @@ impl RayTracer {
             sample_count: 1,
             dimension: TextureDimension::D2,
             format: Self::FORMAT,
-            usage: TextureUsage::SAMPLED | TextureUsage::STORAGE,
+            // Copied out for headless rendering, checkpoints and reprojection, and back in on resume
+            usage: TextureUsage::SAMPLED | TextureUsage::STORAGE | TextureUsage::COPY_SRC | TextureUsage::COPY_DST,
+        });
+
+        let create_storage_texture = |label| {
+            let texture = device.create_texture(&TextureDescriptor {
+                label: Some(label),
+                size,
+                array_layer_count: 1,
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: TextureDimension::D2,
+                format: Self::FORMAT,
+                // Cleared on resume
+                // Copied from for reprojection
+                usage: TextureUsage::STORAGE | TextureUsage::COPY_SRC | TextureUsage::COPY_DST,
+            });
+            Texture::from_wgpu_texture(device, texture)
+        };
+
+        let fragment_target = device.create_texture(&TextureDescriptor {
+            label: Some("ray_trace_fragment_target"),
+            size,
+            array_layer_count: 1,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: TextureDimension::D2,
+            // Must match the render pipeline's color state
+            format: TextureFormat::Bgra8Unorm,
+            usage: TextureUsage::OUTPUT_ATTACHMENT,
         });
 
+        let pixel_textures = PixelTextures {
+            statistics: create_storage_texture("ray_trace_statistics_texture"),
+            albedo_depth: create_storage_texture("ray_trace_albedo_depth_texture"),
+            normal: create_storage_texture("ray_trace_normal_texture"),
+            fragment_target: Texture::from_wgpu_texture(device, fragment_target),
+        };
+
         let texture_bind_group = device.create_bind_group(&BindGroupDescriptor {
             layout: &layout,
             bindings: &[
@@ -135,14 +721,164 @@ impl RayTracer {
                     binding: 0,
                     resource: BindingResource::TextureView(&texture.create_default_view()),
                 },
+                Binding {
+                    binding: 1,
+                    resource: BindingResource::TextureView(&pixel_textures.statistics.view),
+                },
+                Binding {
+                    binding: 2,
+                    resource: BindingResource::Buffer {
+                        buffer: active_pixel_buffer,
+                        range: 0..size_of!(u32) as _,
+                    },
+                },
+                Binding {
+                    binding: 3,
+                    resource: BindingResource::TextureView(&pixel_textures.albedo_depth.view),
+                },
+                Binding {
+                    binding: 4,
+                    resource: BindingResource::TextureView(&pixel_textures.normal.view),
+                },
             ],
             label: Some("ray_trace_texture_bind_group"),
         });
 
-        texture_bind_group
+        let mut texture = Texture::from_wgpu_texture(device, texture);
+        texture.image_dimensions = Some((width, height));
+        let quad = Quad::new(device, quad_layout, texture, None);
+
+        (texture_bind_group, quad, pixel_textures)
     }
 
-    pub fn new(device: &Device, width: u32, height: u32, target_samples: u32) -> Self {
+    /// Creates a read-only storage buffer. Empty slices get one zeroed element since bindings cannot be empty.
+    fn create_storage_buffer<T: bytemuck::Pod>(device: &Device, data: &[T]) -> Buffer {
+        if data.is_empty() {
+            device.create_buffer_with_data(
+                bytemuck::cast_slice(&[T::zeroed()]),
+                BufferUsage::STORAGE,
+            )
+        } else {
+            device.create_buffer_with_data(
+                bytemuck::cast_slice(data),
+                BufferUsage::STORAGE,
+            )
+        }
+    }
+
+    fn storage_buffer_layout_entry(binding: u32) -> BindGroupLayoutEntry {
+        BindGroupLayoutEntry {
+            binding,
+            visibility: ShaderStage::FRAGMENT | ShaderStage::COMPUTE,
+            ty: BindingType::StorageBuffer {
+                dynamic: false,
+                readonly: true,
+            },
+        }
+    }
+
+    fn storage_buffer_binding<T>(binding: u32, buffer: &Buffer, len: usize) -> Binding {
+        Binding {
+            binding,
+            resource: BindingResource::Buffer {
+                buffer,
+                range: 0..(size_of!(T) * len.max(1)) as _,
+            },
+        }
+    }
+
+    /// Packs every shape array into one buffer of 16 byte records, so together they take a single
+    /// storage buffer binding instead of one per primitive kind (see `shape_record` in the shader)
+    fn pack_shapes(scene: &Scene) -> Vec<[u32; 4]> {
+        fn append<T: bytemuck::Pod>(records: &mut Vec<[u32; 4]>, section: usize, items: &[T]) {
+            records[section / 4][section % 4] = records.len() as u32;
+
+            // Items smaller than a record (CSG nodes) share one, and the last may be partly empty
+            let bytes: &[u8] = bytemuck::cast_slice(items);
+            records.extend(bytes.chunks(size_of!([u32; 4])).map(|chunk| {
+                let mut record = [0u32; 4];
+                bytemuck::cast_slice_mut::<u32, u8>(&mut record)[..chunk.len()].copy_from_slice(chunk);
+                record
+            }));
+        }
+
+        let mut records = vec![[0; 4]; SHAPES_HEADER_RECORDS];
+        append(&mut records, bvh::PRIM_SPHERE as usize, &scene.spheres);
+        append(&mut records, bvh::PRIM_TRIANGLE as usize, &scene.triangles);
+        append(&mut records, bvh::PRIM_RECTANGLE as usize, &scene.rectangles);
+        append(&mut records, bvh::PRIM_PLANE as usize, &scene.planes);
+        append(&mut records, bvh::PRIM_DISK as usize, &scene.disks);
+        append(&mut records, bvh::PRIM_BOX as usize, &scene.boxes);
+        append(&mut records, bvh::PRIM_CYLINDER as usize, &scene.cylinders);
+        append(&mut records, bvh::PRIM_CONE as usize, &scene.cones);
+        append(&mut records, bvh::PRIM_CAPSULE as usize, &scene.capsules);
+        append(&mut records, bvh::PRIM_TORUS as usize, &scene.tori);
+        append(&mut records, bvh::PRIM_CSG as usize, &scene.csgs);
+        append(&mut records, bvh::PRIM_SDF as usize, &scene.sdfs);
+        append(&mut records, SHAPES_VERTICES, &scene.vertices);
+        append(&mut records, SHAPES_CSG_NODES, &scene.csg_nodes);
+        append(&mut records, SHAPES_SDF_NODES, &scene.sdf_nodes);
+
+        records
+    }
+
+    fn create_scene_bind_group(device: &Device, layout: &BindGroupLayout, scene: &Scene) -> BindGroup {
+        let shapes = Self::pack_shapes(scene);
+
+        let material_buffer = Self::create_storage_buffer(device, &scene.materials);
+        let shape_buffer = Self::create_storage_buffer(device, &shapes);
+        let bvh_node_buffer = Self::create_storage_buffer(device, &scene.bvh.nodes);
+        let bvh_primitive_buffer = Self::create_storage_buffer(device, &scene.bvh.primitives);
+        let light_buffer = Self::create_storage_buffer(device, &scene.lights);
+
+        device.create_bind_group(&BindGroupDescriptor {
+            layout,
+            bindings: &[
+                Self::storage_buffer_binding::<GpuMaterial>(0, &material_buffer, scene.materials.len()),
+                Self::storage_buffer_binding::<[u32; 4]>(1, &shape_buffer, shapes.len()),
+                Self::storage_buffer_binding::<GpuBvhNode>(2, &bvh_node_buffer, scene.bvh.nodes.len()),
+                Self::storage_buffer_binding::<u32>(3, &bvh_primitive_buffer, scene.bvh.primitives.len()),
+                Self::storage_buffer_binding::<u32>(4, &light_buffer, scene.lights.len()),
+            ],
+            label: Some("ray_trace_scene_bind_group"),
+        })
+    }
+
+    /// Environment texture with its importance sampling CDFs. Scenes without one get a black 1x1 map.
+    fn create_environment_bind_group(device: &Device, queue: &Queue, layout: &BindGroupLayout, scene: &Scene) -> BindGroup {
+        let black_image;
+        let black_distribution;
+
+        let (image, distribution) = match &scene.environment {
+            Some(environment) => (&environment.image, &environment.distribution),
+            None => {
+                black_image = HdrImage { width: 1, height: 1, pixels: vec![[0.0, 0.0, 0.0, 1.0]] };
+                black_distribution = EnvironmentDistribution::new(&black_image);
+                (&black_image, &black_distribution)
+            }
+        };
+
+        let (texture, commands) = Texture::from_hdr_image(device, image, Some("environment_map"));
+        queue.submit(&[commands]);
+
+        let marginal_buffer = Self::create_storage_buffer(device, &distribution.marginal_cdf);
+        let conditional_buffer = Self::create_storage_buffer(device, &distribution.conditional_cdf);
+
+        device.create_bind_group(&BindGroupDescriptor {
+            layout,
+            bindings: &[
+                Binding {
+                    binding: 0,
+                    resource: BindingResource::TextureView(&texture.view),
+                },
+                Self::storage_buffer_binding::<f32>(1, &marginal_buffer, distribution.marginal_cdf.len()),
+                Self::storage_buffer_binding::<f32>(2, &conditional_buffer, distribution.conditional_cdf.len()),
+            ],
+            label: Some("ray_trace_environment_bind_group"),
+        })
+    }
+
+    pub fn new(device: &Device, queue: &Queue, quad_layout: &BindGroupLayout, width: u32, height: u32, scene: &Scene) -> Self {
         let vert_spirv = include_bytes!("../shaders/raytrace/rt.vert.spv");
         let vert_data = read_spirv(std::io::Cursor::new(vert_spirv.as_ref())).unwrap();
 
@@ -153,37 +889,55 @@ impl RayTracer {
         let vert_module = device.create_shader_module(&vert_data);
         let frag_module = device.create_shader_module(&frag_data);
 
+        let comp_spirv = include_bytes!("../shaders/raytrace_hlsl/raytrace.comp.hlsl.spv");
+        let comp_data = read_spirv(std::io::Cursor::new(comp_spirv.as_ref())).unwrap();
+        let comp_module = device.create_shader_module(&comp_data);
+
+        let storage_texture_layout_entry = |binding| BindGroupLayoutEntry {
+            binding,
+            visibility: ShaderStage::FRAGMENT | ShaderStage::COMPUTE,
+            ty: BindingType::StorageTexture {
+                dimension: TextureViewDimension::D2,
+                component_type: TextureComponentType::Uint,
+                format: Self::FORMAT,
+                readonly: false,
+            },
+        };
+
         let texture_bind_group_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
             bindings: &[
                 // Storage texture
+                storage_texture_layout_entry(0),
+                // Sample statistics
+                storage_texture_layout_entry(1),
+                // Active pixel count
                 BindGroupLayoutEntry {
-                    binding: 0,
-                    visibility: ShaderStage::FRAGMENT,
-                    ty: BindingType::StorageTexture {
-                        dimension: TextureViewDimension::D2,
-                        component_type: TextureComponentType::Uint,
-                        format: Self::FORMAT,
+                    binding: 2,
+                    visibility: ShaderStage::FRAGMENT | ShaderStage::COMPUTE,
+                    ty: BindingType::StorageBuffer {
+                        dynamic: false,
                         readonly: false,
                     },
                 },
+                // Albedo and depth features
+                storage_texture_layout_entry(3),
+                // Normal features
+                storage_texture_layout_entry(4),
             ],
             label: Some("ray_trace_texture_bind_group_layout"),
         });
 
-        let texture_bind_group = Self::create_texture_bind_group(device, &texture_bind_group_layout, width, height);
+        // Cleared every frame, then copied into the next frame's uniforms
+        let active_pixel_buffer = device.create_buffer_with_data(
+            bytemuck::cast_slice(&[0u32]),
+            BufferUsage::STORAGE | BufferUsage::COPY_SRC | BufferUsage::COPY_DST,
+        );
 
-        let uniforms = Uniforms {
-            dimensions: (width as f32, height as f32).into(),
-            sample_number: 1,
-            samples_per_pixel: 2,
-            max_ray_bounces: 10,
+        let (texture_bind_group, quad, pixel_textures) = Self::create_texture_bind_group(
+            device, &texture_bind_group_layout, quad_layout, &active_pixel_buffer, width, height,
+        );
 
-            camera_v_fov: 100.0,
-            _padding1: [0; 2],
-            camera_position: (0.0, 0.0, 5.0).into(),
-            _padding2: [0; 1],
-            camera_lookat: (0.0, 0.0, -1.0).into(),
-        };
+        let uniforms = Uniforms::new(width, height, scene);
 
         let uniform_buffer = device.create_buffer_with_data(
             bytemuck::cast_slice(&[uniforms]), 
@@ -195,7 +949,7 @@ impl RayTracer {
                 // Uniform buffer
                 BindGroupLayoutEntry {
                     binding: 0,
-                    visibility: ShaderStage::FRAGMENT,
+                    visibility: ShaderStage::FRAGMENT | ShaderStage::COMPUTE,
                     ty: BindingType::UniformBuffer {
                         dynamic: false,
                     },
@@ -218,10 +972,52 @@ impl RayTracer {
             label: Some("ray_Trace_uniform_bind_group"),
         });
 
+        let scene_bind_group_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            bindings: &[
+                // Materials
+                Self::storage_buffer_layout_entry(0),
+                // Shapes of every kind, and mesh vertices
+                Self::storage_buffer_layout_entry(1),
+                // BVH nodes
+                Self::storage_buffer_layout_entry(2),
+                // BVH primitive references
+                Self::storage_buffer_layout_entry(3),
+                // Light primitive references
+                Self::storage_buffer_layout_entry(4),
+            ],
+            label: Some("ray_trace_scene_bind_group_layout"),
+        });
+
+        let scene_bind_group = Self::create_scene_bind_group(device, &scene_bind_group_layout, scene);
+
+        let environment_bind_group_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            bindings: &[
+                // Equirectangular map
+                BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: ShaderStage::FRAGMENT | ShaderStage::COMPUTE,
+                    ty: BindingType::SampledTexture {
+                        multisampled: false,
+                        dimension: TextureViewDimension::D2,
+                        component_type: TextureComponentType::Float,
+                    },
+                },
+                // Marginal (row) CDF
+                Self::storage_buffer_layout_entry(1),
+                // Conditional (per row) CDFs
+                Self::storage_buffer_layout_entry(2),
+            ],
+            label: Some("ray_trace_environment_bind_group_layout"),
+        });
+
+        let environment_bind_group = Self::create_environment_bind_group(device, queue, &environment_bind_group_layout, scene);
+
         let pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
             bind_group_layouts: &[
                 &texture_bind_group_layout,
                 &uniform_bind_group_layout,
+                &scene_bind_group_layout,
+                &environment_bind_group_layout,
             ],
         });
 
@@ -248,7 +1044,8 @@ impl RayTracer {
                     format: TextureFormat::Bgra8Unorm,
                     alpha_blend: BlendDescriptor::REPLACE,
                     color_blend: BlendDescriptor::REPLACE,
-                    write_mask: ColorWrite::ALL,
+                    // Output goes to the storage texture instead
+                    write_mask: ColorWrite::empty(),
                 },
             ],
             depth_stencil_state: None,
@@ -261,18 +1058,94 @@ impl RayTracer {
             alpha_to_coverage_enabled: false,
         });
 
+        let compute_pipeline = device.create_compute_pipeline(&ComputePipelineDescriptor {
+            layout: &pipeline_layout,
+            compute_stage: ProgrammableStageDescriptor {
+                module: &comp_module,
+                entry_point: "main",
+            },
+        });
+
+        let wavefront = Wavefront::new(
+            device,
+            [&uniform_bind_group_layout, &scene_bind_group_layout, &environment_bind_group_layout],
+            &Self::targets(&quad, &pixel_textures, &active_pixel_buffer),
+            width,
+            height,
+        );
+
+        let reprojection = Reprojection::new(
+            device,
+            [&uniform_bind_group_layout, &scene_bind_group_layout, &environment_bind_group_layout],
+            &Self::targets(&quad, &pixel_textures, &active_pixel_buffer),
+            width,
+            height,
+        );
+
         Self {
             texture_bind_group, 
             texture_bind_group_layout,
+            quad,
+            pixel_textures,
+            active_pixel_buffer,
 
             uniforms,
             uniform_buffer,
             uniform_bind_group,
 
-            pipeline: render_pipeline,
+            scene_bind_group,
+            environment_bind_group,
+
+            render_pipeline,
+            compute_pipeline,
+            wavefront,
+            reprojection,
+            reproject_from: None,
+            set_aside: None,
+            temporal_reprojection: true,
+            backend: Backend::Fragment,
 
             pause_rendering: false,
-            target_samples,
+            target_samples: scene.render.target_samples,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Checks that every item reads back from where `shape_record` in the shader looks for it
+    fn assert_packed<T: bytemuck::Pod>(records: &[[u32; 4]], section: usize, items: &[T]) {
+        let first = records[section / 4][section % 4] as usize;
+        let bytes: &[u8] = bytemuck::cast_slice(&records[first..]);
+        for (i, item) in items.iter().enumerate() {
+            assert_eq!(&bytes[i * size_of!(T)..(i + 1) * size_of!(T)], bytemuck::bytes_of(item), "section {} item {}", section, i);
+        }
+    }
+
+    #[test]
+    fn packed_shapes_read_back() {
+        for name in &["default", "mesh", "shapes", "quadrics", "csg", "sdf", "motion"] {
+            let path = format!("{}/res/scenes/{}.ron", env!("CARGO_MANIFEST_DIR"), name);
+            let scene = Scene::from_path(path).unwrap();
+            let records = RayTracer::pack_shapes(&scene);
+
+            assert_packed(&records, bvh::PRIM_SPHERE as usize, &scene.spheres);
+            assert_packed(&records, bvh::PRIM_TRIANGLE as usize, &scene.triangles);
+            assert_packed(&records, bvh::PRIM_RECTANGLE as usize, &scene.rectangles);
+            assert_packed(&records, bvh::PRIM_PLANE as usize, &scene.planes);
+            assert_packed(&records, bvh::PRIM_DISK as usize, &scene.disks);
+            assert_packed(&records, bvh::PRIM_BOX as usize, &scene.boxes);
+            assert_packed(&records, bvh::PRIM_CYLINDER as usize, &scene.cylinders);
+            assert_packed(&records, bvh::PRIM_CONE as usize, &scene.cones);
+            assert_packed(&records, bvh::PRIM_CAPSULE as usize, &scene.capsules);
+            assert_packed(&records, bvh::PRIM_TORUS as usize, &scene.tori);
+            assert_packed(&records, bvh::PRIM_CSG as usize, &scene.csgs);
+            assert_packed(&records, bvh::PRIM_SDF as usize, &scene.sdfs);
+            assert_packed(&records, SHAPES_VERTICES, &scene.vertices);
+            assert_packed(&records, SHAPES_CSG_NODES, &scene.csg_nodes);
+            assert_packed(&records, SHAPES_SDF_NODES, &scene.sdf_nodes);
         }
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,285 @@
+use wgpu::*;
+
+use crate::raytrace::{AccumulationTargets, Uniforms};
+use crate::texture::Texture;
+
+/// Must match `numthreads` in reproject.comp.hlsl
+const WORK_GROUP_SIZE: u32 = 8;
+
+/// Most frames of history a pixel keeps through a camera move. Lighting that depends on the view
+/// (reflections, refractions) is stale after reprojection, so old samples have to fade out.
+const MAX_HISTORY_FRAMES: u32 = 32;
+
+/// `Reprojection` uniforms in reproject.comp.hlsl
+#[repr(C)]
+#[derive(Copy, Clone)]
+struct ReprojectionUniforms { // OFFSET + SIZE
+    previous_position: [f32; 3], // 0 + 12
+    previous_v_fov: f32,         // 12 + 4
+    previous_lookat: [f32; 3],   // 16 + 12
+    max_history: f32,            // 28 + 4
+}
+unsafe impl bytemuck::Pod for ReprojectionUniforms {}
+unsafe impl bytemuck::Zeroable for ReprojectionUniforms {}
+
+/// Camera pose an accumulation was rendered from
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct View {
+    position: cgmath::Vector3<f32>,
+    lookat: cgmath::Vector3<f32>,
+    v_fov: f32,
+}
+
+impl View {
+    pub fn of(uniforms: &Uniforms) -> Self {
+        Self {
+            position: uniforms.camera_position,
+            lookat: uniforms.camera_lookat,
+            v_fov: uniforms.camera_v_fov,
+        }
+    }
+}
+
+/// Copies of the targets, sized for the current image. The bind group keeps the uniform buffer alive.
+struct History {
+    bind_group: BindGroup,
+    uniform_buffer: Buffer,
+    image: Texture,
+    statistics: Texture,
+    albedo_depth: Texture,
+    normal: Texture,
+    width: u32,
+    height: u32,
+}
+
+/// Temporal reprojection (see reproject.comp.hlsl).
+///
+/// When the camera moves, the accumulation is copied aside and each pixel of the new view looks up
+/// where its first hit was in the old one. Sums are kept where the first hit depth and normal still
+/// agree, so small camera moves keep most of the converged image instead of starting over.
+pub struct Reprojection {
+    bind_group_layout: BindGroupLayout,
+    pipeline: ComputePipeline,
+    history: History,
+}
+
+impl Reprojection {
+    /// `shared_layouts` are the ray tracer's uniform, scene and environment layouts (sets 1 to 3)
+    pub fn new(device: &Device, shared_layouts: [&BindGroupLayout; 3], targets: &AccumulationTargets, width: u32, height: u32) -> Self {
+        let storage_texture_entry = |binding| BindGroupLayoutEntry {
+            binding,
+            visibility: ShaderStage::COMPUTE,
+            ty: BindingType::StorageTexture {
+                dimension: TextureViewDimension::D2,
+                component_type: TextureComponentType::Uint,
+                format: TextureFormat::Rgba32Float,
+                readonly: false,
+            },
+        };
+
+        // Bindings 0 to 4 match the ray tracer's set 0, which raytrace.hlsli declares
+        let bind_group_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            bindings: &[
+                // Storage texture
+                storage_texture_entry(0),
+                // Sample statistics
+                storage_texture_entry(1),
+                // Active pixel count
+                BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: ShaderStage::COMPUTE,
+                    ty: BindingType::StorageBuffer {
+                        dynamic: false,
+                        readonly: false,
+                    },
+                },
+                // Albedo and depth features
+                storage_texture_entry(3),
+                // Normal features
+                storage_texture_entry(4),
+                // History of each of the above textures
+                storage_texture_entry(5),
+                storage_texture_entry(6),
+                storage_texture_entry(7),
+                storage_texture_entry(8),
+                // Previous camera pose
+                BindGroupLayoutEntry {
+                    binding: 9,
+                    visibility: ShaderStage::COMPUTE,
+                    ty: BindingType::UniformBuffer {
+                        dynamic: false,
+                    },
+                },
+            ],
+            label: Some("reprojection_bind_group_layout"),
+        });
+
+        let [uniform_layout, scene_layout, environment_layout] = shared_layouts;
+        let layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            bind_group_layouts: &[
+                &bind_group_layout,
+                uniform_layout,
+                scene_layout,
+                environment_layout,
+            ],
+        });
+
+        let spirv = include_bytes!("../shaders/raytrace_hlsl/reproject.comp.hlsl.spv");
+        let data = read_spirv(std::io::Cursor::new(spirv.as_ref())).unwrap();
+        let module = device.create_shader_module(&data);
+
+        let pipeline = device.create_compute_pipeline(&ComputePipelineDescriptor {
+            layout: &layout,
+            compute_stage: ProgrammableStageDescriptor {
+                module: &module,
+                entry_point: "main",
+            },
+        });
+
+        let history = Self::create_history(device, &bind_group_layout, targets, width, height);
+
+        Self {
+            bind_group_layout,
+            pipeline,
+            history,
+        }
+    }
+
+    /// Reallocates the history for a new image
+    pub fn resize(&mut self, device: &Device, targets: &AccumulationTargets, width: u32, height: u32) {
+        self.history = Self::create_history(device, &self.bind_group_layout, targets, width, height);
+    }
+
+    fn create_history(device: &Device, layout: &BindGroupLayout, targets: &AccumulationTargets, width: u32, height: u32) -> History {
+        let create_texture = |label| {
+            let texture = device.create_texture(&TextureDescriptor {
+                label: Some(label),
+                size: Extent3d {
+                    width,
+                    height,
+                    depth: 1,
+                },
+                array_layer_count: 1,
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: TextureDimension::D2,
+                format: TextureFormat::Rgba32Float,
+                usage: TextureUsage::STORAGE | TextureUsage::COPY_DST,
+            });
+            Texture::from_wgpu_texture(device, texture)
+        };
+
+        let image = create_texture("reprojection_history_image");
+        let statistics = create_texture("reprojection_history_statistics");
+        let albedo_depth = create_texture("reprojection_history_albedo_depth");
+        let normal = create_texture("reprojection_history_normal");
+
+        let uniform_buffer = device.create_buffer(&BufferDescriptor {
+            label: Some("reprojection_uniforms"),
+            size: size_of!(ReprojectionUniforms) as BufferAddress,
+            usage: BufferUsage::UNIFORM | BufferUsage::COPY_DST,
+        });
+
+        fn texture_binding(binding: u32, texture: &Texture) -> Binding<'_> {
+            Binding {
+                binding,
+                resource: BindingResource::TextureView(&texture.view),
+            }
+        }
+
+        let bind_group = device.create_bind_group(&BindGroupDescriptor {
+            layout,
+            bindings: &[
+                texture_binding(0, targets.image),
+                texture_binding(1, targets.statistics),
+                Binding {
+                    binding: 2,
+                    resource: BindingResource::Buffer {
+                        buffer: targets.active_pixels,
+                        range: 0..size_of!(u32) as BufferAddress,
+                    },
+                },
+                texture_binding(3, targets.albedo_depth),
+                texture_binding(4, targets.normal),
+                texture_binding(5, &image),
+                texture_binding(6, &statistics),
+                texture_binding(7, &albedo_depth),
+                texture_binding(8, &normal),
+                Binding {
+                    binding: 9,
+                    resource: BindingResource::Buffer {
+                        buffer: &uniform_buffer,
+                        range: 0..size_of!(ReprojectionUniforms) as BufferAddress,
+                    },
+                },
+            ],
+            label: Some("reprojection_bind_group"),
+        });
+
+        History {
+            bind_group,
+            uniform_buffer,
+            image,
+            statistics,
+            albedo_depth,
+            normal,
+            width,
+            height,
+        }
+    }
+
+    /// Records moving `targets` from `previous` to the camera in the uniforms, which must already be
+    /// uploaded. `shared_bind_groups` are the ray tracer's uniform, scene and environment bind groups.
+    pub fn encode(&self, device: &Device, encoder: &mut CommandEncoder, shared_bind_groups: [&BindGroup; 3], targets: &AccumulationTargets, previous: &View, samples_per_pixel: u32) {
+        let uniforms = ReprojectionUniforms {
+            previous_position: previous.position.into(),
+            previous_v_fov: previous.v_fov,
+            previous_lookat: previous.lookat.into(),
+            max_history: (MAX_HISTORY_FRAMES * samples_per_pixel) as f32,
+        };
+        let staging_buffer = device.create_buffer_with_data(bytemuck::cast_slice(&[uniforms]), BufferUsage::COPY_SRC);
+        encoder.copy_buffer_to_buffer(&staging_buffer, 0, &self.history.uniform_buffer, 0, size_of!(ReprojectionUniforms) as BufferAddress);
+
+        let copies = [
+            (targets.image, &self.history.image),
+            (targets.statistics, &self.history.statistics),
+            (targets.albedo_depth, &self.history.albedo_depth),
+            (targets.normal, &self.history.normal),
+        ];
+
+        fn copy_view(texture: &Texture) -> TextureCopyView<'_> {
+            TextureCopyView {
+                texture: &texture.texture,
+                mip_level: 0,
+                array_layer: 0,
+                origin: Origin3d::ZERO,
+            }
+        }
+
+        for &(target, history) in copies.iter() {
+            encoder.copy_texture_to_texture(
+                copy_view(target),
+                copy_view(history),
+                Extent3d {
+                    width: self.history.width,
+                    height: self.history.height,
+                    depth: 1,
+                },
+            );
+        }
+
+        let [uniforms, scene, environment] = shared_bind_groups;
+        let mut pass = encoder.begin_compute_pass();
+        pass.set_pipeline(&self.pipeline);
+        pass.set_bind_group(0, &self.history.bind_group, &[]);
+        pass.set_bind_group(1, uniforms, &[]);
+        pass.set_bind_group(2, scene, &[]);
+        pass.set_bind_group(3, environment, &[]);
+        // Rounded up, the shader skips invocations outside the image
+        pass.dispatch(
+            (self.history.width + WORK_GROUP_SIZE - 1) / WORK_GROUP_SIZE,
+            (self.history.height + WORK_GROUP_SIZE - 1) / WORK_GROUP_SIZE,
+            1,
+        );
+    }
+}
@@ -0,0 +1,1635 @@
+use serde::Deserialize;
+
+use crate::mesh::{GpuTriangle, GpuVertex, Mesh};
+use crate::bvh::{self, Aabb, Bvh};
+use crate::environment::{Environment, EnvironmentDescription};
+
+use std::collections::BTreeMap;
+
+// Must match the `MAT_*` defines in the ray tracing shader. Emissive comes last, since every
+// type before it has a wavefront shading queue.
+pub const MAT_METAL: u32 = 1;
+pub const MAT_LAMBERTIAN: u32 = 2;
+pub const MAT_DIELECTRIC: u32 = 3;
+pub const MAT_CONDUCTOR: u32 = 4;
+pub const MAT_ROUGH_DIELECTRIC: u32 = 5;
+pub const MAT_EMISSIVE: u32 = 6;
+
+/// Smallest GGX alpha. Smoother surfaces would make the microfacet distribution overflow.
+const MIN_GGX_ALPHA: f32 = 1e-3;
+
+// CSG node operations. Must match the `CSG_*` defines in the ray tracing shader.
+pub const CSG_LEAF: u32 = 0;
+pub const CSG_UNION: u32 = 1;
+pub const CSG_INTERSECTION: u32 = 2;
+pub const CSG_DIFFERENCE: u32 = 3;
+/// Most spans a ray can have through any node of a CSG tree
+pub const CSG_MAX_SPANS: usize = 4;
+/// Most span lists held at once while evaluating a CSG tree
+pub const CSG_MAX_DEPTH: usize = 4;
+
+// SDF node kinds. Must match the `SDF_*` defines in the ray tracing shader.
+pub const SDF_SPHERE: u32 = 0;
+pub const SDF_ROUNDED_BOX: u32 = 1;
+pub const SDF_TORUS: u32 = 2;
+pub const SDF_MANDELBULB: u32 = 3;
+pub const SDF_UNION: u32 = 4;
+pub const SDF_INTERSECTION: u32 = 5;
+pub const SDF_DIFFERENCE: u32 = 6;
+/// Most distances held at once while evaluating an SDF tree
+pub const SDF_MAX_DEPTH: usize = 8;
+
+/// Radius around its center that a Mandelbulb of unit scale fits in, for any power
+const MANDELBULB_BOUND: f32 = 1.2;
+
+/// Scene file as written on disk (RON)
+#[derive(Deserialize)]
+#[serde(rename = "Scene")]
+struct SceneDescription {
+    camera: CameraDescription,
+    sky: SkyDescription,
+    render: RenderSettings,
+    /// Materials are referenced by name from the objects below
+    materials: BTreeMap<String, MaterialDescription>,
+    spheres: Vec<SphereDescription>,
+    /// Also accepted as `quads`
+    #[serde(default, alias = "quads")]
+    rectangles: Vec<RectangleDescription>,
+    #[serde(default)]
+    planes: Vec<PlaneDescription>,
+    #[serde(default)]
+    disks: Vec<DiskDescription>,
+    #[serde(default)]
+    boxes: Vec<BoxDescription>,
+    #[serde(default)]
+    cylinders: Vec<CylinderDescription>,
+    #[serde(default)]
+    cones: Vec<ConeDescription>,
+    #[serde(default)]
+    capsules: Vec<CapsuleDescription>,
+    #[serde(default)]
+    tori: Vec<TorusDescription>,
+    /// Boolean combinations of shapes
+    #[serde(default)]
+    csg: Vec<CsgDescription>,
+    /// Signed distance fields, rendered by sphere tracing
+    #[serde(default)]
+    sdfs: Vec<SdfDescription>,
+    #[serde(default)]
+    meshes: Vec<MeshDescription>,
+    /// Replaces the sky gradient when set
+    #[serde(default)]
+    environment: Option<EnvironmentDescription>,
+}
+
+/// Camera starting pose
+#[derive(Deserialize, Clone, Copy)]
+pub struct CameraDescription {
+    pub position: [f32; 3],
+    /// Point the camera looks at
+    pub look_at: [f32; 3],
+    /// Vertical field of view in degrees
+    pub v_fov: f32,
+    /// Times the shutter opens and closes. Shapes with a velocity are blurred along their motion
+    /// in between. Instantaneous at time 0 when left out.
+    #[serde(default)]
+    pub shutter: [f32; 2],
+}
+
+/// Background gradient (blended by ray direction's y component)
+#[derive(Deserialize, Clone, Copy)]
+pub struct SkyDescription {
+    pub horizon: [f32; 3],
+    pub zenith: [f32; 3],
+}
+
+#[derive(Deserialize, Clone, Copy)]
+pub struct RenderSettings {
+    /// Rays fired per pixel each frame
+    pub samples_per_pixel: u32,
+    /// Max bounces per ray (path length)
+    pub max_ray_bounces: u32,
+    /// Rendering pauses once this many frames are accumulated
+    pub target_samples: u32,
+    /// Pixels stop sampling once the standard error of their mean luminance falls below this
+    /// fraction of it, and their samples go to noisier pixels. 0 (the default) samples every pixel equally.
+    #[serde(default)]
+    pub adaptive_threshold: f32,
+    /// Cheaper rendering while the camera moves
+    #[serde(default)]
+    pub preview: PreviewSettings,
+}
+
+/// Render quality while the camera moves. Full quality resumes once it stops.
+#[derive(Deserialize, Clone, Copy, Debug)]
+#[serde(default)]
+pub struct PreviewSettings {
+    pub enabled: bool,
+    /// Render resolution relative to the window, in (0, 1]
+    pub scale: f32,
+    /// Max bounces per ray, from 1 to `RenderSettings::max_ray_bounces`. Defaults to
+    /// `DEFAULT_MAX_RAY_BOUNCES`, or fewer if full quality bounces less.
+    pub max_ray_bounces: Option<u32>,
+    /// Seconds without camera movement before switching back to full quality, at most `MAX_IDLE_DELAY`
+    pub idle_delay: f32,
+}
+
+impl PreviewSettings {
+    pub const DEFAULT_MAX_RAY_BOUNCES: u32 = 3;
+    /// Longest `idle_delay` allowed. Longer waits are better served by turning the preview off.
+    pub const MAX_IDLE_DELAY: f32 = 60.0;
+}
+
+impl Default for PreviewSettings {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            scale: 0.5,
+            max_ray_bounces: None,
+            idle_delay: 0.3,
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Copy)]
+pub enum MaterialDescription {
+    Lambertian {
+        albedo: [f32; 3],
+    },
+    Metal {
+        albedo: [f32; 3],
+        fuzz: f32,
+    },
+    Dielectric {
+        index_of_refraction: f32,
+    },
+    /// Metal with a GGX microfacet surface, reflecting by its complex index of refraction
+    Conductor {
+        ior: ConductorIor,
+        /// 0 (mirror) to 1. The GGX alpha is its square.
+        roughness: f32,
+        /// Multiplies the reflectance
+        #[serde(default = "MaterialDescription::white")]
+        tint: [f32; 3],
+    },
+    /// Glass with a GGX microfacet surface, e.g. frosted glass
+    RoughDielectric {
+        index_of_refraction: f32,
+        /// 0 (smooth) to 1. The GGX alpha is its square.
+        roughness: f32,
+    },
+    /// Light source. Emissive spheres, rectangles and disks are sampled directly.
+    Emissive {
+        color: [f32; 3],
+        strength: f32,
+    },
+}
+
+/// Complex index of refraction of a conductor, per RGB channel
+#[derive(Deserialize, Clone, Copy)]
+pub enum ConductorIor {
+    Gold,
+    Copper,
+    Aluminium,
+    /// Refractive index `eta` and extinction coefficient `k`
+    Custom {
+        eta: [f32; 3],
+        k: [f32; 3],
+    },
+}
+
+impl ConductorIor {
+    /// Returns `eta` and `k`. Presets are measured values at about 650, 550 and 450 nm.
+    pub fn eta_k(self) -> ([f32; 3], [f32; 3]) {
+        match self {
+            ConductorIor::Gold => ([0.143, 0.375, 1.442], [3.983, 2.386, 1.603]),
+            ConductorIor::Copper => ([0.200, 0.924, 1.102], [3.913, 2.453, 2.142]),
+            ConductorIor::Aluminium => ([1.657, 0.880, 0.521], [9.224, 6.270, 4.837]),
+            ConductorIor::Custom { eta, k } => (eta, k),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct SphereDescription {
+    center: [f32; 3],
+    /// Negative radii flip the normals (hollow glass)
+    radius: f32,
+    /// Distance moved per unit of time. `center` is where the sphere is at time 0.
+    #[serde(default)]
+    velocity: [f32; 3],
+    material: String,
+}
+
+/// Parallelogram spanned by two edges from a corner
+#[derive(Deserialize)]
+struct RectangleDescription {
+    corner: [f32; 3],
+    edge_u: [f32; 3],
+    edge_v: [f32; 3],
+    material: String,
+}
+
+/// Infinite plane through a point
+#[derive(Deserialize)]
+struct PlaneDescription {
+    point: [f32; 3],
+    /// Front side. Need not be normalized.
+    normal: [f32; 3],
+    material: String,
+}
+
+/// Flat circle facing along its normal
+#[derive(Deserialize)]
+struct DiskDescription {
+    center: [f32; 3],
+    /// Front side. Need not be normalized.
+    normal: [f32; 3],
+    radius: f32,
+    material: String,
+}
+
+/// Box with faces along its local axes
+#[derive(Deserialize)]
+struct BoxDescription {
+    center: [f32; 3],
+    /// Full extents along the box's local axes
+    size: [f32; 3],
+    /// Degrees about the x, y and z axes, applied in that order. Axis-aligned when zero.
+    #[serde(default)]
+    rotation: [f32; 3],
+    /// Distance moved per unit of time. `center` is where the box is at time 0.
+    #[serde(default)]
+    velocity: [f32; 3],
+    /// Axis the box spins about, with a length of the degrees turned per unit of time
+    #[serde(default)]
+    angular_velocity: [f32; 3],
+    material: String,
+}
+
+/// Cylinder between two points, capped at both ends
+#[derive(Deserialize)]
+struct CylinderDescription {
+    start: [f32; 3],
+    end: [f32; 3],
+    radius: f32,
+    material: String,
+}
+
+/// Cone between two points, capped at both ends. Its radius changes linearly from start to end.
+#[derive(Deserialize)]
+struct ConeDescription {
+    start: [f32; 3],
+    end: [f32; 3],
+    start_radius: f32,
+    /// Zero for a pointed cone
+    #[serde(default)]
+    end_radius: f32,
+    material: String,
+}
+
+/// Cylinder between two points with hemispherical ends
+#[derive(Deserialize)]
+struct CapsuleDescription {
+    start: [f32; 3],
+    end: [f32; 3],
+    radius: f32,
+    material: String,
+}
+
+/// Ring around an axis through its center
+#[derive(Deserialize)]
+struct TorusDescription {
+    center: [f32; 3],
+    /// Need not be normalized
+    #[serde(default = "TorusDescription::default_axis")]
+    axis: [f32; 3],
+    /// From the center to the middle of the tube
+    major_radius: f32,
+    /// Of the tube
+    minor_radius: f32,
+    material: String,
+}
+
+impl TorusDescription {
+    fn default_axis() -> [f32; 3] { [0.0, 1.0, 0.0] }
+}
+
+/// Shape built from others with union, intersection and difference, e.g. a lens or a hollow shell
+#[derive(Deserialize)]
+struct CsgDescription {
+    shape: CsgShapeDescription,
+    material: String,
+}
+
+/// Node of a CSG tree. Leaves take the same parameters as the shapes above, and are convex so a
+/// ray crosses each of them at most once.
+#[derive(Deserialize)]
+enum CsgShapeDescription {
+    Sphere {
+        center: [f32; 3],
+        radius: f32,
+    },
+    Box {
+        center: [f32; 3],
+        size: [f32; 3],
+        #[serde(default)]
+        rotation: [f32; 3],
+    },
+    Cylinder {
+        start: [f32; 3],
+        end: [f32; 3],
+        radius: f32,
+    },
+    Cone {
+        start: [f32; 3],
+        end: [f32; 3],
+        start_radius: f32,
+        #[serde(default)]
+        end_radius: f32,
+    },
+    Capsule {
+        start: [f32; 3],
+        end: [f32; 3],
+        radius: f32,
+    },
+    Union(Box<CsgShapeDescription>, Box<CsgShapeDescription>),
+    Intersection(Box<CsgShapeDescription>, Box<CsgShapeDescription>),
+    /// The first shape with the second cut out of it
+    Difference(Box<CsgShapeDescription>, Box<CsgShapeDescription>),
+}
+
+/// OBJ file placed in the scene
+#[derive(Deserialize)]
+struct MeshDescription {
+    /// Relative to the scene file
+    path: String,
+    #[serde(default)]
+    translation: [f32; 3],
+    #[serde(default = "MeshDescription::default_scale")]
+    scale: f32,
+    /// Overrides the MTL materials when set
+    #[serde(default)]
+    material: Option<String>,
+}
+
+impl MeshDescription {
+    fn default_scale() -> f32 { 1.0 }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+/// Matches `Material` in the shader (std430)
+pub struct GpuMaterial {                    // OFFSET + SIZE
+    /// Emitted radiance for emissive materials
+    pub albedo: cgmath::Vector3<f32>,       // 0 + 12
+    pub material_type: u32,                 // 12 + 4
+    /// Complex index of refraction of conductors
+    pub conductor_eta: cgmath::Vector3<f32>, // 16 + 12
+    pub metalic_fuzz: f32,                  // 28 + 4
+    pub conductor_k: cgmath::Vector3<f32>,  // 32 + 12
+    pub index_of_refraction: f32,           // 44 + 4
+    /// GGX roughness of conductors and rough dielectrics
+    pub alpha: f32,                         // 48 + 4
+    _padding: [u32; 3],                     // 52 + 12
+}
+unsafe impl bytemuck::Pod for GpuMaterial {}
+unsafe impl bytemuck::Zeroable for GpuMaterial {}
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+/// Matches `Sphere` in the shader (std430)
+pub struct GpuSphere {                  // OFFSET + SIZE
+    pub center: cgmath::Vector3<f32>,   // 0 + 12
+    pub radius: f32,                    // 12 + 4
+    /// Distance moved per unit of time, `center` is the position at time 0
+    pub velocity: cgmath::Vector3<f32>, // 16 + 12
+    pub material_index: u32,            // 28 + 4
+}
+unsafe impl bytemuck::Pod for GpuSphere {}
+unsafe impl bytemuck::Zeroable for GpuSphere {}
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+/// Matches `Rectangle` in the shader (std430)
+pub struct GpuRectangle {               // OFFSET + SIZE
+    pub corner: cgmath::Vector3<f32>,   // 0 + 12
+    pub material_index: u32,            // 12 + 4
+    pub edge_u: cgmath::Vector3<f32>,   // 16 + 12
+    _padding1: u32,                     // 28 + 4
+    pub edge_v: cgmath::Vector3<f32>,   // 32 + 12
+    _padding2: u32,                     // 44 + 4
+}
+unsafe impl bytemuck::Pod for GpuRectangle {}
+unsafe impl bytemuck::Zeroable for GpuRectangle {}
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+/// Matches `Plane` in the shader (std430)
+pub struct GpuPlane {                   // OFFSET + SIZE
+    pub origin: cgmath::Vector3<f32>,   // 0 + 12
+    pub material_index: u32,            // 12 + 4
+    /// Normalized
+    pub normal: cgmath::Vector3<f32>,   // 16 + 12
+    _padding: u32,                      // 28 + 4
+}
+unsafe impl bytemuck::Pod for GpuPlane {}
+unsafe impl bytemuck::Zeroable for GpuPlane {}
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+/// Matches `Disk` in the shader (std430)
+pub struct GpuDisk {                    // OFFSET + SIZE
+    pub center: cgmath::Vector3<f32>,   // 0 + 12
+    pub radius: f32,                    // 12 + 4
+    /// Normalized
+    pub normal: cgmath::Vector3<f32>,   // 16 + 12
+    pub material_index: u32,            // 28 + 4
+}
+unsafe impl bytemuck::Pod for GpuDisk {}
+unsafe impl bytemuck::Zeroable for GpuDisk {}
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+/// Matches `Box` in the shader (std430)
+pub struct GpuBox {                         // OFFSET + SIZE
+    pub center: cgmath::Vector3<f32>,       // 0 + 12
+    pub material_index: u32,                // 12 + 4
+    pub half_extents: cgmath::Vector3<f32>, // 16 + 12
+    _padding1: u32,                         // 28 + 4
+    /// Local to world rotation as a unit quaternion, vector part first
+    pub rotation: cgmath::Vector4<f32>,     // 32 + 16
+    /// Distance moved per unit of time. `center` and `rotation` are the pose at time 0.
+    pub velocity: cgmath::Vector3<f32>,     // 48 + 12
+    _padding2: u32,                         // 60 + 4
+    /// Spin axis scaled by radians per unit of time, applied after `rotation`
+    pub angular_velocity: cgmath::Vector3<f32>, // 64 + 12
+    _padding3: u32,                         // 76 + 4
+}
+unsafe impl bytemuck::Pod for GpuBox {}
+unsafe impl bytemuck::Zeroable for GpuBox {}
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+/// Matches `Cylinder` in the shader (std430)
+pub struct GpuCylinder {                // OFFSET + SIZE
+    pub a: cgmath::Vector3<f32>,        // 0 + 12
+    pub radius: f32,                    // 12 + 4
+    pub b: cgmath::Vector3<f32>,        // 16 + 12
+    pub material_index: u32,            // 28 + 4
+}
+unsafe impl bytemuck::Pod for GpuCylinder {}
+unsafe impl bytemuck::Zeroable for GpuCylinder {}
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+/// Matches `Cone` in the shader (std430)
+pub struct GpuCone {                    // OFFSET + SIZE
+    pub a: cgmath::Vector3<f32>,        // 0 + 12
+    pub radius_a: f32,                  // 12 + 4
+    pub b: cgmath::Vector3<f32>,        // 16 + 12
+    pub radius_b: f32,                  // 28 + 4
+    pub material_index: u32,            // 32 + 4
+    _padding: [u32; 3],                 // 36 + 12
+}
+unsafe impl bytemuck::Pod for GpuCone {}
+unsafe impl bytemuck::Zeroable for GpuCone {}
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+/// Matches `Capsule` in the shader (std430)
+pub struct GpuCapsule {                 // OFFSET + SIZE
+    pub a: cgmath::Vector3<f32>,        // 0 + 12
+    pub radius: f32,                    // 12 + 4
+    pub b: cgmath::Vector3<f32>,        // 16 + 12
+    pub material_index: u32,            // 28 + 4
+}
+unsafe impl bytemuck::Pod for GpuCapsule {}
+unsafe impl bytemuck::Zeroable for GpuCapsule {}
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+/// Matches `Torus` in the shader (std430)
+pub struct GpuTorus {                   // OFFSET + SIZE
+    pub center: cgmath::Vector3<f32>,   // 0 + 12
+    pub major_radius: f32,              // 12 + 4
+    /// Normalized
+    pub axis: cgmath::Vector3<f32>,     // 16 + 12
+    pub minor_radius: f32,              // 28 + 4
+    pub material_index: u32,            // 32 + 4
+    _padding: [u32; 3],                 // 36 + 12
+}
+unsafe impl bytemuck::Pod for GpuTorus {}
+unsafe impl bytemuck::Zeroable for GpuTorus {}
+
+/// Shape defined by its signed distance function, e.g. a blend of shapes or a fractal
+#[derive(Deserialize)]
+struct SdfDescription {
+    shape: SdfShapeDescription,
+    material: String,
+}
+
+/// Node of an SDF tree. Rotations are in degrees about the x, y and z axes, applied in that order.
+#[derive(Deserialize)]
+enum SdfShapeDescription {
+    Sphere {
+        center: [f32; 3],
+        radius: f32,
+    },
+    RoundedBox {
+        center: [f32; 3],
+        size: [f32; 3],
+        /// Of the edges and corners. At most half the smallest size.
+        rounding: f32,
+        #[serde(default)]
+        rotation: [f32; 3],
+    },
+    /// Ring around the y axis before rotating
+    Torus {
+        center: [f32; 3],
+        major_radius: f32,
+        minor_radius: f32,
+        #[serde(default)]
+        rotation: [f32; 3],
+    },
+    /// Power 8 Mandelbulb by default, symmetric around the y axis before rotating. `scale` is
+    /// about its radius.
+    Mandelbulb {
+        center: [f32; 3],
+        scale: f32,
+        #[serde(default = "SdfShapeDescription::default_power")]
+        power: f32,
+        /// More add detail, and cost
+        #[serde(default = "SdfShapeDescription::default_iterations")]
+        iterations: u32,
+        #[serde(default)]
+        rotation: [f32; 3],
+    },
+    /// Blends the shapes together over about `smoothness` when it is above zero
+    Union {
+        a: Box<SdfShapeDescription>,
+        b: Box<SdfShapeDescription>,
+        #[serde(default)]
+        smoothness: f32,
+    },
+    Intersection {
+        a: Box<SdfShapeDescription>,
+        b: Box<SdfShapeDescription>,
+        #[serde(default)]
+        smoothness: f32,
+    },
+    /// `a` with `b` cut out of it
+    Difference {
+        a: Box<SdfShapeDescription>,
+        b: Box<SdfShapeDescription>,
+        #[serde(default)]
+        smoothness: f32,
+    },
+}
+
+impl SdfShapeDescription {
+    fn default_power() -> f32 { 8.0 }
+    fn default_iterations() -> u32 { 10 }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+/// Matches `Csg` in the shader (std430)
+pub struct GpuCsg {                     // OFFSET + SIZE
+    /// Its tree is `node_count` nodes from here in `Scene::csg_nodes`, in postfix order
+    pub first_node: u32,                // 0 + 4
+    pub node_count: u32,                // 4 + 4
+    pub material_index: u32,            // 8 + 4
+    _padding: u32,                      // 12 + 4
+}
+unsafe impl bytemuck::Pod for GpuCsg {}
+unsafe impl bytemuck::Zeroable for GpuCsg {}
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+/// Matches `CsgNode` in the shader (std430)
+pub struct GpuCsgNode {                 // OFFSET + SIZE
+    /// One of the `CSG_*` constants. Operations combine the two span lists evaluated before them.
+    pub operation: u32,                 // 0 + 4
+    /// Encoded primitive (see `bvh::encode_primitive`) of a leaf
+    pub primitive: u32,                 // 4 + 4
+}
+unsafe impl bytemuck::Pod for GpuCsgNode {}
+unsafe impl bytemuck::Zeroable for GpuCsgNode {}
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+/// Matches `Sdf` in the shader (std430)
+pub struct GpuSdf {                     // OFFSET + SIZE
+    /// Sphere tracing starts and ends at the bounds
+    pub bounds_min: cgmath::Vector3<f32>, // 0 + 12
+    /// Its tree is `node_count` nodes from here in `Scene::sdf_nodes`, in postfix order
+    pub first_node: u32,                // 12 + 4
+    pub bounds_max: cgmath::Vector3<f32>, // 16 + 12
+    pub node_count: u32,                // 28 + 4
+    pub material_index: u32,            // 32 + 4
+    _padding: [u32; 3],                 // 36 + 12
+}
+unsafe impl bytemuck::Pod for GpuSdf {}
+unsafe impl bytemuck::Zeroable for GpuSdf {}
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+/// Matches `SdfNode` in the shader (std430)
+pub struct GpuSdfNode {                 // OFFSET + SIZE
+    pub center: cgmath::Vector3<f32>,   // 0 + 12
+    /// One of the `SDF_*` constants. Operations combine the two distances evaluated before them.
+    pub kind: u32,                      // 12 + 4
+    /// Sphere: radius. Rounded box: half extents and rounding. Torus: major and minor radius.
+    /// Mandelbulb: scale, power and iterations. Operations: smoothness.
+    pub parameters: cgmath::Vector4<f32>, // 16 + 16
+    /// Local to world, vector part in xyz
+    pub rotation: cgmath::Vector4<f32>, // 32 + 16
+}
+unsafe impl bytemuck::Pod for GpuSdfNode {}
+unsafe impl bytemuck::Zeroable for GpuSdfNode {}
+
+impl GpuSdfNode {
+    pub fn rotation(&self) -> cgmath::Quaternion<f32> {
+        cgmath::Quaternion::new(self.rotation.w, self.rotation.x, self.rotation.y, self.rotation.z)
+    }
+}
+
+/// Bounds of a capped cone (or cylinder) from `a` to `b`
+fn capped_cone_bounds(a: cgmath::Vector3<f32>, b: cgmath::Vector3<f32>, radius_a: f32, radius_b: f32) -> Aabb {
+    // Each cap is a disk, whose extent along an axis shrinks with the cone's axis component along it
+    let axis = cgmath::InnerSpace::normalize(b - a);
+    let extent = |n: f32| (1.0 - n * n).max(0.0).sqrt();
+    let extent = cgmath::Vector3::new(extent(axis.x), extent(axis.y), extent(axis.z));
+
+    Aabb::from_points(&[a - extent * radius_a, a + extent * radius_a, b - extent * radius_b, b + extent * radius_b])
+}
+
+impl GpuCylinder {
+    pub fn bounds(&self) -> Aabb {
+        capped_cone_bounds(self.a, self.b, self.radius, self.radius)
+    }
+}
+
+impl GpuCone {
+    pub fn bounds(&self) -> Aabb {
+        capped_cone_bounds(self.a, self.b, self.radius_a, self.radius_b)
+    }
+}
+
+impl GpuCapsule {
+    pub fn bounds(&self) -> Aabb {
+        let extent = cgmath::Vector3::new(self.radius, self.radius, self.radius);
+        Aabb::from_points(&[self.a - extent, self.a + extent, self.b - extent, self.b + extent])
+    }
+}
+
+fn torus_bounds(center: cgmath::Vector3<f32>, axis: cgmath::Vector3<f32>, major_radius: f32, minor_radius: f32) -> Aabb {
+    // The center circle's extent along an axis shrinks with the torus' axis component along it
+    let extent = |n: f32| major_radius * (1.0 - n * n).max(0.0).sqrt() + minor_radius;
+    let extent = cgmath::Vector3::new(extent(axis.x), extent(axis.y), extent(axis.z));
+
+    Aabb {
+        min: center - extent,
+        max: center + extent,
+    }
+}
+
+impl GpuTorus {
+    pub fn bounds(&self) -> Aabb {
+        torus_bounds(self.center, self.axis, self.major_radius, self.minor_radius)
+    }
+}
+
+impl GpuRectangle {
+    pub fn bounds(&self) -> Aabb {
+        let mut bounds = Aabb::from_points(&[
+            self.corner,
+            self.corner + self.edge_u,
+            self.corner + self.edge_v,
+            self.corner + self.edge_u + self.edge_v,
+        ]);
+
+        // Axis-aligned rectangles have flat bounds
+        let padding = cgmath::Vector3::new(1e-4, 1e-4, 1e-4);
+        bounds.min -= padding;
+        bounds.max += padding;
+
+        bounds
+    }
+}
+
+impl GpuDisk {
+    pub fn bounds(&self) -> Aabb {
+        // A circle's extent along an axis shrinks with the normal's component along it
+        let extent = |n: f32| self.radius * (1.0 - n * n).max(0.0).sqrt() + 1e-4;
+        let extent = cgmath::Vector3::new(extent(self.normal.x), extent(self.normal.y), extent(self.normal.z));
+
+        Aabb {
+            min: self.center - extent,
+            max: self.center + extent,
+        }
+    }
+}
+
+impl GpuBox {
+    pub fn rotation(&self) -> cgmath::Quaternion<f32> {
+        cgmath::Quaternion::new(self.rotation.w, self.rotation.x, self.rotation.y, self.rotation.z)
+    }
+
+    pub fn bounds(&self) -> Aabb {
+        rotated_box_bounds(self.center, self.half_extents, self.rotation())
+    }
+
+    /// Where the box is at `time`
+    pub fn at(&self, time: f32) -> Self {
+        use cgmath::Rotation3;
+
+        let turn = self.angular_velocity * time;
+        let angle = cgmath::InnerSpace::magnitude(turn);
+        let mut rotation = self.rotation();
+        if angle > 0.0 {
+            rotation = cgmath::Quaternion::from_axis_angle(turn / angle, cgmath::Rad(angle)) * rotation;
+        }
+
+        Self {
+            center: self.center + time * self.velocity,
+            rotation: rotation.v.extend(rotation.s),
+            ..*self
+        }
+    }
+
+    /// Bounds of the box over `shutter` (open and close times)
+    pub fn swept_bounds(&self, shutter: [f32; 2]) -> Aabb {
+        let (open, close) = (self.at(shutter[0]), self.at(shutter[1]));
+
+        // A spinning box stays within the sphere around its corners
+        if self.angular_velocity != cgmath::Vector3::new(0.0, 0.0, 0.0) {
+            let radius = cgmath::InnerSpace::magnitude(self.half_extents);
+            let extent = cgmath::Vector3::new(radius, radius, radius);
+            return Aabb::from_points(&[open.center - extent, open.center + extent, close.center - extent, close.center + extent]);
+        }
+
+        let mut bounds = open.bounds();
+        bounds.grow(&close.bounds());
+        bounds
+    }
+}
+
+fn rotated_box_bounds(center: cgmath::Vector3<f32>, half_extents: cgmath::Vector3<f32>, rotation: cgmath::Quaternion<f32>) -> Aabb {
+    use cgmath::Rotation;
+
+    let mut corners = Vec::with_capacity(8);
+    for &x in &[-1.0, 1.0] {
+        for &y in &[-1.0, 1.0] {
+            for &z in &[-1.0, 1.0] {
+                let corner = cgmath::Vector3::new(x * half_extents.x, y * half_extents.y, z * half_extents.z);
+                corners.push(center + rotation.rotate_vector(corner));
+            }
+        }
+    }
+
+    Aabb::from_points(&corners)
+}
+
+impl GpuSdf {
+    pub fn bounds(&self) -> Aabb {
+        Aabb {
+            min: self.bounds_min,
+            max: self.bounds_max,
+        }
+    }
+}
+
+impl GpuSphere {
+    pub fn bounds(&self) -> Aabb {
+        // Radius may be negative (hollow spheres)
+        let radius = self.radius.abs();
+        let extent = cgmath::Vector3::new(radius, radius, radius);
+
+        Aabb {
+            min: self.center - extent,
+            max: self.center + extent,
+        }
+    }
+
+    /// Where the sphere is at `time`
+    pub fn at(&self, time: f32) -> Self {
+        Self {
+            center: self.center + time * self.velocity,
+            ..*self
+        }
+    }
+
+    /// Bounds of the sphere over `shutter` (open and close times)
+    pub fn swept_bounds(&self, shutter: [f32; 2]) -> Aabb {
+        let mut bounds = self.at(shutter[0]).bounds();
+        bounds.grow(&self.at(shutter[1]).bounds());
+        bounds
+    }
+}
+
+/// The bounded shapes of a scene being loaded, which go in its BVH
+struct Shapes<'a> {
+    spheres: &'a [GpuSphere],
+    rectangles: &'a [GpuRectangle],
+    disks: &'a [GpuDisk],
+    boxes: &'a [GpuBox],
+    cylinders: &'a [GpuCylinder],
+    cones: &'a [GpuCone],
+    capsules: &'a [GpuCapsule],
+    tori: &'a [GpuTorus],
+    /// Of each CSG shape
+    csg_bounds: &'a [Aabb],
+    sdfs: &'a [GpuSdf],
+}
+
+/// Where CSG leaves go while a scene is loaded. They are appended to the other shapes of their
+/// kind, but only reachable through their CSG shape.
+struct CsgLeaves<'a> {
+    spheres: &'a mut Vec<GpuSphere>,
+    boxes: &'a mut Vec<GpuBox>,
+    cylinders: &'a mut Vec<GpuCylinder>,
+    cones: &'a mut Vec<GpuCone>,
+    capsules: &'a mut Vec<GpuCapsule>,
+}
+
+/// What a part of a CSG tree takes to evaluate
+struct CsgNodeInfo {
+    bounds: Aabb,
+    /// Most spans a ray can have through it
+    max_spans: usize,
+    /// Most span lists held at once while evaluating it
+    depth: usize,
+}
+
+impl CsgShapeDescription {
+    /// Appends this tree's nodes to `nodes` in postfix order, and its leaves to `leaves`
+    fn flatten(&self, material_index: u32, leaves: &mut CsgLeaves, nodes: &mut Vec<GpuCsgNode>) -> Result<CsgNodeInfo, String> {
+        fn leaf<T>(kind: u32, shapes: &mut Vec<T>, shape: T, bounds: Aabb, nodes: &mut Vec<GpuCsgNode>) -> CsgNodeInfo {
+            nodes.push(GpuCsgNode {
+                operation: CSG_LEAF,
+                primitive: bvh::encode_primitive(kind, shapes.len() as u32),
+            });
+            shapes.push(shape);
+
+            CsgNodeInfo { bounds, max_spans: 1, depth: 1 }
+        }
+
+        let (operation, a, b) = match self {
+            CsgShapeDescription::Sphere { center, radius } => {
+                let sphere = GpuSphere {
+                    center: (*center).into(),
+                    radius: positive(*radius, "CSG sphere radius")?,
+                    velocity: cgmath::Vector3::new(0.0, 0.0, 0.0),
+                    material_index,
+                };
+                return Ok(leaf(bvh::PRIM_SPHERE, leaves.spheres, sphere, sphere.bounds(), nodes));
+            }
+            CsgShapeDescription::Box { center, size, rotation } => {
+                let box_primitive = GpuBox::new(*center, *size, *rotation, material_index)?;
+                return Ok(leaf(bvh::PRIM_BOX, leaves.boxes, box_primitive, box_primitive.bounds(), nodes));
+            }
+            CsgShapeDescription::Cylinder { start, end, radius } => {
+                let (a, b) = segment(*start, *end, "CSG cylinder")?;
+                let cylinder = GpuCylinder { a, radius: positive(*radius, "CSG cylinder radius")?, b, material_index };
+                return Ok(leaf(bvh::PRIM_CYLINDER, leaves.cylinders, cylinder, cylinder.bounds(), nodes));
+            }
+            CsgShapeDescription::Cone { start, end, start_radius, end_radius } => {
+                let cone = GpuCone::new(*start, *end, *start_radius, *end_radius, material_index)?;
+                return Ok(leaf(bvh::PRIM_CONE, leaves.cones, cone, cone.bounds(), nodes));
+            }
+            CsgShapeDescription::Capsule { start, end, radius } => {
+                let (a, b) = segment(*start, *end, "CSG capsule")?;
+                let capsule = GpuCapsule { a, radius: positive(*radius, "CSG capsule radius")?, b, material_index };
+                return Ok(leaf(bvh::PRIM_CAPSULE, leaves.capsules, capsule, capsule.bounds(), nodes));
+            }
+            CsgShapeDescription::Union(a, b) => (CSG_UNION, a, b),
+            CsgShapeDescription::Intersection(a, b) => (CSG_INTERSECTION, a, b),
+            CsgShapeDescription::Difference(a, b) => (CSG_DIFFERENCE, a, b),
+        };
+
+        let a = a.flatten(material_index, leaves, nodes)?;
+        let b = b.flatten(material_index, leaves, nodes)?;
+        nodes.push(GpuCsgNode { operation, primitive: 0 });
+
+        // Each span of one side can at most add a span to the other's, or (intersecting) merge two
+        let (bounds, max_spans) = match operation {
+            CSG_UNION => {
+                let mut bounds = a.bounds;
+                bounds.grow(&b.bounds);
+                (bounds, a.max_spans + b.max_spans)
+            }
+            CSG_INTERSECTION => (a.bounds.overlap(&b.bounds), a.max_spans + b.max_spans - 1),
+            _ => (a.bounds, a.max_spans + b.max_spans),
+        };
+
+        // `a`'s spans wait on the stack while `b` is evaluated
+        Ok(CsgNodeInfo { bounds, max_spans, depth: a.depth.max(b.depth + 1) })
+    }
+}
+
+impl SdfShapeDescription {
+    /// Appends this tree's nodes to `nodes` in postfix order. Returns its bounds, and the most
+    /// distances held at once while evaluating it.
+    fn flatten(&self, nodes: &mut Vec<GpuSdfNode>) -> Result<(Aabb, usize), String> {
+        use cgmath::Rotation;
+
+        let node = |center: [f32; 3], kind: u32, parameters: [f32; 4], rotation: cgmath::Quaternion<f32>| GpuSdfNode {
+            center: center.into(),
+            kind,
+            parameters: parameters.into(),
+            rotation: rotation.v.extend(rotation.s),
+        };
+        let sphere_bounds = |center: [f32; 3], radius: f32| {
+            let center: cgmath::Vector3<f32> = center.into();
+            let extent = cgmath::Vector3::new(radius, radius, radius);
+            Aabb { min: center - extent, max: center + extent }
+        };
+
+        let (kind, a, b, smoothness) = match self {
+            SdfShapeDescription::Sphere { center, radius } => {
+                let radius = positive(*radius, "SDF sphere radius")?;
+                nodes.push(node(*center, SDF_SPHERE, [radius, 0.0, 0.0, 0.0], euler_rotation([0.0; 3])));
+                return Ok((sphere_bounds(*center, radius), 1));
+            }
+            SdfShapeDescription::RoundedBox { center, size, rounding, rotation } => {
+                if !size.iter().all(|&size| size.is_finite() && size > 0.0) {
+                    return Err(format!("Rounded box size must be positive, got {:?}", size));
+                }
+                let smallest_half_extent = size.iter().fold(f32::INFINITY, |smallest, &size| smallest.min(size / 2.0));
+                if !(*rounding >= 0.0 && *rounding <= smallest_half_extent) {
+                    return Err(format!("Rounded box rounding must be between 0 and half its smallest size, got {}", rounding));
+                }
+
+                let half_extents = cgmath::Vector3::new(size[0], size[1], size[2]) / 2.0;
+                let rotation = euler_rotation(*rotation);
+                nodes.push(node(*center, SDF_ROUNDED_BOX, [half_extents.x, half_extents.y, half_extents.z, *rounding], rotation));
+                return Ok((rotated_box_bounds((*center).into(), half_extents, rotation), 1));
+            }
+            SdfShapeDescription::Torus { center, major_radius, minor_radius, rotation } => {
+                let major_radius = positive(*major_radius, "SDF torus major radius")?;
+                let minor_radius = positive(*minor_radius, "SDF torus minor radius")?;
+
+                let rotation = euler_rotation(*rotation);
+                let axis = rotation.rotate_vector(cgmath::Vector3::unit_y());
+                nodes.push(node(*center, SDF_TORUS, [major_radius, minor_radius, 0.0, 0.0], rotation));
+                return Ok((torus_bounds((*center).into(), axis, major_radius, minor_radius), 1));
+            }
+            SdfShapeDescription::Mandelbulb { center, scale, power, iterations, rotation } => {
+                let scale = positive(*scale, "Mandelbulb scale")?;
+                if !(*power >= 2.0 && power.is_finite()) {
+                    return Err(format!("Mandelbulb power must be at least 2, got {}", power));
+                }
+                if !(1..=64).contains(iterations) {
+                    return Err(format!("Mandelbulb iterations must be between 1 and 64, got {}", iterations));
+                }
+
+                nodes.push(node(*center, SDF_MANDELBULB, [scale, *power, *iterations as f32, 0.0], euler_rotation(*rotation)));
+                return Ok((sphere_bounds(*center, MANDELBULB_BOUND * scale), 1));
+            }
+            SdfShapeDescription::Union { a, b, smoothness } => (SDF_UNION, a, b, smoothness),
+            SdfShapeDescription::Intersection { a, b, smoothness } => (SDF_INTERSECTION, a, b, smoothness),
+            SdfShapeDescription::Difference { a, b, smoothness } => (SDF_DIFFERENCE, a, b, smoothness),
+        };
+
+        if !(*smoothness >= 0.0 && smoothness.is_finite()) {
+            return Err(format!("SDF smoothness must not be negative, got {}", smoothness));
+        }
+
+        let (a_bounds, a_depth) = a.flatten(nodes)?;
+        let (b_bounds, b_depth) = b.flatten(nodes)?;
+        nodes.push(node([0.0; 3], kind, [*smoothness, 0.0, 0.0, 0.0], euler_rotation([0.0; 3])));
+
+        let bounds = match kind {
+            SDF_UNION => {
+                // Smoothing fills in between the shapes, pushing the surface out by up to a quarter
+                // of the smoothness
+                let mut bounds = a_bounds;
+                bounds.grow(&b_bounds);
+                let padding = cgmath::Vector3::new(1.0, 1.0, 1.0) * (smoothness / 4.0);
+                Aabb { min: bounds.min - padding, max: bounds.max + padding }
+            }
+            // Smoothing only ever removes from intersections and differences
+            SDF_INTERSECTION => a_bounds.overlap(&b_bounds),
+            _ => a_bounds,
+        };
+
+        // `a`'s distance waits on the stack while `b` is evaluated
+        Ok((bounds, a_depth.max(b_depth + 1)))
+    }
+}
+
+/// `degrees` about the x, y and z axes, applied in that order
+fn euler_rotation(degrees: [f32; 3]) -> cgmath::Quaternion<f32> {
+    let [x, y, z] = degrees;
+    cgmath::Quaternion::from(cgmath::Euler::new(cgmath::Deg(x), cgmath::Deg(y), cgmath::Deg(z)))
+}
+
+fn positive(value: f32, what: &str) -> Result<f32, String> {
+    if !(value.is_finite() && value > 0.0) {
+        return Err(format!("{} must be positive, got {}", what, value));
+    }
+    Ok(value)
+}
+
+fn segment(start: [f32; 3], end: [f32; 3], kind: &str) -> Result<(cgmath::Vector3<f32>, cgmath::Vector3<f32>), String> {
+    if start == end {
+        return Err(format!("{} start and end must differ, got {:?}", kind, start));
+    }
+    Ok((start.into(), end.into()))
+}
+
+impl GpuBox {
+    /// `rotation` is in degrees about the x, y and z axes, applied in that order
+    fn new(center: [f32; 3], size: [f32; 3], rotation: [f32; 3], material_index: u32) -> Result<Self, String> {
+        if !size.iter().all(|&size| size.is_finite() && size > 0.0) {
+            return Err(format!("Box size must be positive, got {:?}", size));
+        }
+
+        let rotation = euler_rotation(rotation);
+        let size: cgmath::Vector3<f32> = size.into();
+
+        Ok(Self {
+            center: center.into(),
+            material_index,
+            half_extents: size / 2.0,
+            _padding1: 0,
+            rotation: rotation.v.extend(rotation.s),
+            velocity: cgmath::Vector3::new(0.0, 0.0, 0.0),
+            _padding2: 0,
+            angular_velocity: cgmath::Vector3::new(0.0, 0.0, 0.0),
+            _padding3: 0,
+        })
+    }
+}
+
+impl GpuCone {
+    fn new(start: [f32; 3], end: [f32; 3], start_radius: f32, end_radius: f32, material_index: u32) -> Result<Self, String> {
+        let (a, b) = segment(start, end, "Cone")?;
+        if !(end_radius.is_finite() && end_radius >= 0.0) {
+            return Err(format!("Cone end radius must not be negative, got {}", end_radius));
+        }
+
+        Ok(Self {
+            a,
+            radius_a: positive(start_radius, "Cone start radius")?,
+            b,
+            radius_b: end_radius,
+            material_index,
+            _padding: [0; 3],
+        })
+    }
+}
+
+/// A loaded scene, ready to be uploaded to the GPU
+pub struct Scene {
+    pub camera: CameraDescription,
+    pub sky: SkyDescription,
+    pub render: RenderSettings,
+
+    pub materials: Vec<GpuMaterial>,
+    pub spheres: Vec<GpuSphere>,
+    pub rectangles: Vec<GpuRectangle>,
+    /// Unbounded, so they are tested outside the BVH
+    pub planes: Vec<GpuPlane>,
+    pub disks: Vec<GpuDisk>,
+    pub boxes: Vec<GpuBox>,
+    pub cylinders: Vec<GpuCylinder>,
+    pub cones: Vec<GpuCone>,
+    pub capsules: Vec<GpuCapsule>,
+    pub tori: Vec<GpuTorus>,
+    pub csgs: Vec<GpuCsg>,
+    pub csg_nodes: Vec<GpuCsgNode>,
+    pub sdfs: Vec<GpuSdf>,
+    pub sdf_nodes: Vec<GpuSdfNode>,
+    pub vertices: Vec<GpuVertex>,
+    pub triangles: Vec<GpuTriangle>,
+
+    /// Acceleration structure over all primitives
+    pub bvh: Bvh,
+
+    /// Encoded primitives (see `bvh::encode_primitive`) with emissive materials
+    pub lights: Vec<u32>,
+
+    pub environment: Option<Environment>,
+}
+
+impl MaterialDescription {
+    fn white() -> [f32; 3] { [1.0; 3] }
+
+    pub fn to_gpu(&self) -> GpuMaterial {
+        let mut material = GpuMaterial {
+            albedo: Self::white().into(),
+            material_type: 0,
+            conductor_eta: [0.0; 3].into(),
+            metalic_fuzz: 0.0,
+            conductor_k: [0.0; 3].into(),
+            index_of_refraction: 0.0,
+            alpha: 0.0,
+            _padding: [0; 3],
+        };
+        let ggx_alpha = |roughness: f32| (roughness * roughness).max(MIN_GGX_ALPHA);
+
+        match *self {
+            MaterialDescription::Lambertian { albedo } => {
+                material.material_type = MAT_LAMBERTIAN;
+                material.albedo = albedo.into();
+            }
+            MaterialDescription::Metal { albedo, fuzz } => {
+                material.material_type = MAT_METAL;
+                material.albedo = albedo.into();
+                material.metalic_fuzz = fuzz;
+            }
+            MaterialDescription::Dielectric { index_of_refraction } => {
+                material.material_type = MAT_DIELECTRIC;
+                material.index_of_refraction = index_of_refraction;
+            }
+            MaterialDescription::Conductor { ior, roughness, tint } => {
+                let (eta, k) = ior.eta_k();
+                material.material_type = MAT_CONDUCTOR;
+                material.albedo = tint.into();
+                material.conductor_eta = eta.into();
+                material.conductor_k = k.into();
+                material.alpha = ggx_alpha(roughness);
+            }
+            MaterialDescription::RoughDielectric { index_of_refraction, roughness } => {
+                material.material_type = MAT_ROUGH_DIELECTRIC;
+                material.index_of_refraction = index_of_refraction;
+                material.alpha = ggx_alpha(roughness);
+            }
+            MaterialDescription::Emissive { color, strength } => {
+                material.material_type = MAT_EMISSIVE;
+                material.albedo = [color[0] * strength, color[1] * strength, color[2] * strength].into();
+            }
+        }
+
+        material
+    }
+
+    fn validate(&self, name: &str) -> Result<(), String> {
+        let check = |valid: bool, message: String| if valid { Ok(()) } else { Err(format!("Material '{}': {}", name, message)) };
+        let color = |color: [f32; 3], what: &str| check(
+            color.iter().all(|value| value.is_finite() && *value >= 0.0),
+            format!("{} must be non-negative, got {:?}", what, color),
+        );
+        let roughness = |roughness: f32| check((0.0..=1.0).contains(&roughness), format!("roughness must be between 0 and 1, got {}", roughness));
+        let index_of_refraction = |index_of_refraction: f32| check(
+            index_of_refraction.is_finite() && index_of_refraction > 0.0,
+            format!("index of refraction must be positive, got {}", index_of_refraction),
+        );
+
+        match *self {
+            MaterialDescription::Lambertian { albedo } => color(albedo, "albedo"),
+            MaterialDescription::Metal { albedo, fuzz } => {
+                color(albedo, "albedo")?;
+                check((0.0..=1.0).contains(&fuzz), format!("fuzz must be between 0 and 1, got {}", fuzz))
+            }
+            MaterialDescription::Dielectric { index_of_refraction: ior } => index_of_refraction(ior),
+            MaterialDescription::Conductor { ior, roughness: conductor_roughness, tint } => {
+                let (eta, k) = ior.eta_k();
+                check(eta.iter().chain(k.iter()).all(|value| value.is_finite() && *value >= 0.0), "eta and k must be non-negative".to_string())?;
+                color(tint, "tint")?;
+                roughness(conductor_roughness)
+            }
+            MaterialDescription::RoughDielectric { index_of_refraction: ior, roughness: dielectric_roughness } => {
+                roughness(dielectric_roughness)?;
+                index_of_refraction(ior)
+            }
+            MaterialDescription::Emissive { color: emission, strength } => {
+                color(emission, "color")?;
+                check(strength.is_finite() && strength >= 0.0, format!("strength must be non-negative, got {}", strength))
+            }
+        }
+    }
+}
+
+impl Scene {
+    pub fn from_path<P: AsRef<std::path::Path>>(path: P) -> Result<Self, String> {
+        let text = std::fs::read_to_string(path.as_ref())
+            .map_err(|e| format!("Failed to read scene {:?}: {}", path.as_ref(), e))?;
+
+        let base_directory = path.as_ref().parent().unwrap_or_else(|| std::path::Path::new("."));
+
+        Self::parse(&text, base_directory)
+    }
+
+    /// `base_directory` is used to resolve mesh paths
+    pub fn parse(text: &str, base_directory: &std::path::Path) -> Result<Self, String> {
+        let description: SceneDescription = ron::de::from_str(text).map_err(|e| e.to_string())?;
+
+        for (name, material) in &description.materials {
+            material.validate(name)?;
+        }
+
+        // BTreeMap keeps material indices stable between loads
+        let material_names: Vec<&String> = description.materials.keys().collect();
+        let mut materials: Vec<GpuMaterial> = description.materials.values().map(|m| m.to_gpu()).collect();
+
+        let find_material = |name: &String| -> Result<u32, String> {
+            material_names.iter()
+                .position(|material_name| *material_name == name)
+                .map(|index| index as u32)
+                .ok_or_else(|| format!("Unknown material '{}'", name))
+        };
+
+        let mut spheres = Vec::with_capacity(description.spheres.len());
+        for sphere in &description.spheres {
+            // Negative radii are hollow spheres
+            if !(sphere.radius.is_finite() && sphere.radius != 0.0) {
+                return Err(format!("Sphere radius must be non-zero, got {}", sphere.radius));
+            }
+            spheres.push(GpuSphere {
+                center: sphere.center.into(),
+                radius: sphere.radius,
+                velocity: sphere.velocity.into(),
+                material_index: find_material(&sphere.material)?,
+            });
+        }
+
+        let mut rectangles = Vec::with_capacity(description.rectangles.len());
+        for rectangle in &description.rectangles {
+            let edge_u: cgmath::Vector3<f32> = rectangle.edge_u.into();
+            let edge_v: cgmath::Vector3<f32> = rectangle.edge_v.into();
+            // Zero or parallel edges span no area, which also leaves lights on it unsampleable
+            let area = cgmath::InnerSpace::magnitude(edge_u.cross(edge_v));
+            if !(area.is_finite() && area > 0.0) {
+                return Err(format!("Rectangle edges must be non-zero and not parallel, got {:?} and {:?}", rectangle.edge_u, rectangle.edge_v));
+            }
+            rectangles.push(GpuRectangle {
+                corner: rectangle.corner.into(),
+                material_index: find_material(&rectangle.material)?,
+                edge_u: rectangle.edge_u.into(),
+                _padding1: 0,
+                edge_v: rectangle.edge_v.into(),
+                _padding2: 0,
+            });
+        }
+
+        let normalized = |normal: [f32; 3], kind: &str| -> Result<cgmath::Vector3<f32>, String> {
+            let normal: cgmath::Vector3<f32> = normal.into();
+            let length = cgmath::InnerSpace::magnitude(normal);
+            if !(length.is_finite() && length > 0.0) {
+                return Err(format!("{} normal must be non-zero, got {:?}", kind, normal));
+            }
+            Ok(normal / length)
+        };
+
+        let mut planes = Vec::with_capacity(description.planes.len());
+        for plane in &description.planes {
+            planes.push(GpuPlane {
+                origin: plane.point.into(),
+                material_index: find_material(&plane.material)?,
+                normal: normalized(plane.normal, "Plane")?,
+                _padding: 0,
+            });
+        }
+
+        let mut disks = Vec::with_capacity(description.disks.len());
+        for disk in &description.disks {
+            if !(disk.radius.is_finite() && disk.radius > 0.0) {
+                return Err(format!("Disk radius must be positive, got {}", disk.radius));
+            }
+            disks.push(GpuDisk {
+                center: disk.center.into(),
+                radius: disk.radius,
+                normal: normalized(disk.normal, "Disk")?,
+                material_index: find_material(&disk.material)?,
+            });
+        }
+
+        let mut boxes = Vec::with_capacity(description.boxes.len());
+        for box_description in &description.boxes {
+            let material_index = find_material(&box_description.material)?;
+            let mut box_primitive = GpuBox::new(box_description.center, box_description.size, box_description.rotation, material_index)?;
+            box_primitive.velocity = box_description.velocity.into();
+            box_primitive.angular_velocity = cgmath::Vector3::from(box_description.angular_velocity).map(f32::to_radians);
+            boxes.push(box_primitive);
+        }
+
+        let mut cylinders = Vec::with_capacity(description.cylinders.len());
+        for cylinder in &description.cylinders {
+            let (a, b) = segment(cylinder.start, cylinder.end, "Cylinder")?;
+            cylinders.push(GpuCylinder {
+                a,
+                radius: positive(cylinder.radius, "Cylinder radius")?,
+                b,
+                material_index: find_material(&cylinder.material)?,
+            });
+        }
+
+        let mut cones = Vec::with_capacity(description.cones.len());
+        for cone in &description.cones {
+            let material_index = find_material(&cone.material)?;
+            cones.push(GpuCone::new(cone.start, cone.end, cone.start_radius, cone.end_radius, material_index)?);
+        }
+
+        let mut capsules = Vec::with_capacity(description.capsules.len());
+        for capsule in &description.capsules {
+            let (a, b) = segment(capsule.start, capsule.end, "Capsule")?;
+            capsules.push(GpuCapsule {
+                a,
+                radius: positive(capsule.radius, "Capsule radius")?,
+                b,
+                material_index: find_material(&capsule.material)?,
+            });
+        }
+
+        let mut tori = Vec::with_capacity(description.tori.len());
+        for torus in &description.tori {
+            tori.push(GpuTorus {
+                center: torus.center.into(),
+                major_radius: positive(torus.major_radius, "Torus major radius")?,
+                axis: normalized(torus.axis, "Torus")?,
+                minor_radius: positive(torus.minor_radius, "Torus minor radius")?,
+                material_index: find_material(&torus.material)?,
+                _padding: [0; 3],
+            });
+        }
+
+        // Shapes past these counts are CSG leaves
+        let (sphere_count, box_count, cylinder_count, cone_count, capsule_count) =
+            (spheres.len(), boxes.len(), cylinders.len(), cones.len(), capsules.len());
+
+        let mut csgs = Vec::with_capacity(description.csg.len());
+        let mut csg_nodes = Vec::new();
+        let mut csg_bounds = Vec::with_capacity(description.csg.len());
+        let mut csg_leaves = CsgLeaves {
+            spheres: &mut spheres,
+            boxes: &mut boxes,
+            cylinders: &mut cylinders,
+            cones: &mut cones,
+            capsules: &mut capsules,
+        };
+        for csg in &description.csg {
+            let material_index = find_material(&csg.material)?;
+            let first_node = csg_nodes.len() as u32;
+            let info = csg.shape.flatten(material_index, &mut csg_leaves, &mut csg_nodes)?;
+
+            if info.max_spans > CSG_MAX_SPANS {
+                return Err(format!("CSG shape may split a ray into {} spans, at most {} are supported", info.max_spans, CSG_MAX_SPANS));
+            }
+            if info.depth > CSG_MAX_DEPTH {
+                return Err(format!(
+                    "CSG shape is nested too deeply: evaluating it holds {} span lists, at most {} are supported. Nesting in the first shape of an operation instead of the second needs fewer.",
+                    info.depth, CSG_MAX_DEPTH,
+                ));
+            }
+            if info.bounds.is_empty() {
+                return Err("CSG shape is empty: its intersected shapes don't overlap".to_string());
+            }
+
+            csgs.push(GpuCsg {
+                first_node,
+                node_count: csg_nodes.len() as u32 - first_node,
+                material_index,
+                _padding: 0,
+            });
+            csg_bounds.push(info.bounds);
+        }
+
+        let mut sdfs = Vec::with_capacity(description.sdfs.len());
+        let mut sdf_nodes = Vec::new();
+        for sdf in &description.sdfs {
+            let first_node = sdf_nodes.len() as u32;
+            let (bounds, depth) = sdf.shape.flatten(&mut sdf_nodes)?;
+
+            if depth > SDF_MAX_DEPTH {
+                return Err(format!(
+                    "SDF shape is nested too deeply: evaluating it holds {} distances, at most {} are supported. Nesting in `a` of an operation instead of `b` needs fewer.",
+                    depth, SDF_MAX_DEPTH,
+                ));
+            }
+            if bounds.is_empty() {
+                return Err("SDF shape is empty: its intersected shapes don't overlap".to_string());
+            }
+
+            sdfs.push(GpuSdf {
+                bounds_min: bounds.min,
+                first_node,
+                bounds_max: bounds.max,
+                node_count: sdf_nodes.len() as u32 - first_node,
+                material_index: find_material(&sdf.material)?,
+                _padding: [0; 3],
+            });
+        }
+
+        let mut vertices = Vec::new();
+        let mut triangles = Vec::new();
+        for mesh_description in &description.meshes {
+            let mesh = Mesh::from_obj(
+                base_directory.join(&mesh_description.path),
+                mesh_description.translation.into(),
+                // Zero or negative scales flatten or turn the mesh inside out
+                positive(mesh_description.scale, "Mesh scale")?,
+            )?;
+
+            let first_vertex = vertices.len() as u32;
+            vertices.extend_from_slice(&mesh.vertices);
+
+            let override_material = match &mesh_description.material {
+                Some(name) => Some(find_material(name)?),
+                None => None,
+            };
+
+            // MTL materials are appended after the scene's named materials
+            let first_material = materials.len() as u32;
+            if override_material.is_none() {
+                materials.extend(mesh.materials.iter().map(|m| m.to_gpu()));
+            }
+
+            triangles.extend(mesh.triangles.iter().map(|triangle| GpuTriangle {
+                indices: [
+                    first_vertex + triangle.indices[0],
+                    first_vertex + triangle.indices[1],
+                    first_vertex + triangle.indices[2],
+                ],
+                material_index: override_material.unwrap_or(first_material + triangle.material_index),
+            }));
+        }
+
+        let shapes = Shapes {
+            spheres: &spheres[..sphere_count],
+            rectangles: &rectangles,
+            disks: &disks,
+            boxes: &boxes[..box_count],
+            cylinders: &cylinders[..cylinder_count],
+            cones: &cones[..cone_count],
+            capsules: &capsules[..capsule_count],
+            tori: &tori,
+            csg_bounds: &csg_bounds,
+            sdfs: &sdfs,
+        };
+        let bvh = Self::build_bvh(&shapes, description.camera.shutter, &vertices, &triangles);
+
+        // Other emissive shapes are only found by scattered rays
+        let is_emissive = |material_index: u32| materials[material_index as usize].material_type == MAT_EMISSIVE;
+        let sphere_lights = shapes.spheres.iter()
+            .enumerate()
+            .filter(|(_, sphere)| is_emissive(sphere.material_index))
+            .map(|(i, _)| bvh::encode_primitive(bvh::PRIM_SPHERE, i as u32));
+        let rectangle_lights = rectangles.iter()
+            .enumerate()
+            .filter(|(_, rectangle)| is_emissive(rectangle.material_index))
+            .map(|(i, _)| bvh::encode_primitive(bvh::PRIM_RECTANGLE, i as u32));
+        let disk_lights = disks.iter()
+            .enumerate()
+            .filter(|(_, disk)| is_emissive(disk.material_index))
+            .map(|(i, _)| bvh::encode_primitive(bvh::PRIM_DISK, i as u32));
+        let lights = sphere_lights.chain(rectangle_lights).chain(disk_lights).collect();
+
+        let environment = match &description.environment {
+            Some(environment) => Some(Environment::load(environment, base_directory)?),
+            None => None,
+        };
+
+        let scene = Self {
+            camera: description.camera,
+            sky: description.sky,
+            render: description.render,
+            materials,
+            spheres,
+            rectangles,
+            planes,
+            disks,
+            boxes,
+            cylinders,
+            cones,
+            capsules,
+            tori,
+            csgs,
+            csg_nodes,
+            sdfs,
+            sdf_nodes,
+            vertices,
+            triangles,
+            bvh,
+            lights,
+            environment,
+        };
+        scene.validate()?;
+
+        Ok(scene)
+    }
+
+    /// Checks the render settings and camera, which the command line can override after loading.
+    /// Limits match the command line's and the interactive camera's.
+    pub fn validate(&self) -> Result<(), String> {
+        let render = &self.render;
+        if render.target_samples == 0 {
+            return Err("Target samples must be at least 1".to_string());
+        }
+        if !(1..=1024).contains(&render.samples_per_pixel) {
+            return Err(format!("Samples per pixel must be between 1 and 1024, got {}", render.samples_per_pixel));
+        }
+        if !(1..=256).contains(&render.max_ray_bounces) {
+            return Err(format!("Max ray bounces must be between 1 and 256, got {}", render.max_ray_bounces));
+        }
+
+        let preview = &render.preview;
+        if !(preview.scale > 0.0 && preview.scale <= 1.0) {
+            return Err(format!("Preview scale must be in (0, 1], got {}", preview.scale));
+        }
+        if let Some(max_ray_bounces) = preview.max_ray_bounces {
+            if !(1..=render.max_ray_bounces).contains(&max_ray_bounces) {
+                return Err(format!("Preview max ray bounces must be between 1 and the max ray bounces ({}), got {}", render.max_ray_bounces, max_ray_bounces));
+            }
+        }
+        if !(preview.idle_delay >= 0.0 && preview.idle_delay <= PreviewSettings::MAX_IDLE_DELAY) {
+            return Err(format!("Preview idle delay must be between 0 and {} seconds, got {}", PreviewSettings::MAX_IDLE_DELAY, preview.idle_delay));
+        }
+
+        let camera = &self.camera;
+        if !(10.0..=160.0).contains(&camera.v_fov) {
+            return Err(format!("Vertical field of view must be between 10 and 160 degrees, got {}", camera.v_fov));
+        }
+        if camera.position == camera.look_at {
+            return Err("The camera's position and look at point must differ".to_string());
+        }
+        let [open, close] = camera.shutter;
+        if !(open.is_finite() && close.is_finite() && open <= close) {
+            return Err(format!("Shutter must open before it closes, got {:?}", camera.shutter));
+        }
+
+        Ok(())
+    }
+
+    /// Fingerprint of everything that affects rendered radiance apart from the camera and
+    /// environment orientation, used to check that a checkpoint belongs to this scene.
+    /// 64-bit FNV-1a, so it is stable between runs and builds.
+    pub fn content_hash(&self) -> u64 {
+        let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+        let mut write = |bytes: &[u8]| {
+            for &byte in bytes {
+                hash = (hash ^ byte as u64).wrapping_mul(0x0000_0100_0000_01b3);
+            }
+        };
+
+        write(bytemuck::cast_slice(&self.materials));
+        write(bytemuck::cast_slice(&self.spheres));
+        write(bytemuck::cast_slice(&self.rectangles));
+        write(bytemuck::cast_slice(&self.planes));
+        write(bytemuck::cast_slice(&self.disks));
+        write(bytemuck::cast_slice(&self.boxes));
+        write(bytemuck::cast_slice(&self.cylinders));
+        write(bytemuck::cast_slice(&self.cones));
+        write(bytemuck::cast_slice(&self.capsules));
+        write(bytemuck::cast_slice(&self.tori));
+        write(bytemuck::cast_slice(&self.csgs));
+        write(bytemuck::cast_slice(&self.csg_nodes));
+        write(bytemuck::cast_slice(&self.sdfs));
+        write(bytemuck::cast_slice(&self.sdf_nodes));
+        write(bytemuck::cast_slice(&self.vertices));
+        write(bytemuck::cast_slice(&self.triangles));
+        write(bytemuck::cast_slice(&[self.sky.horizon, self.sky.zenith]));
+        write(&self.render.max_ray_bounces.to_le_bytes());
+        write(bytemuck::cast_slice(&self.camera.shutter));
+
+        if let Some(environment) = &self.environment {
+            write(&environment.image.width.to_le_bytes());
+            write(&environment.image.height.to_le_bytes());
+            write(bytemuck::cast_slice(&environment.image.pixels));
+        }
+
+        hash
+    }
+
+    /// Moving shapes are bounded over the whole `shutter` interval
+    fn build_bvh(shapes: &Shapes, shutter: [f32; 2], vertices: &[GpuVertex], triangles: &[GpuTriangle]) -> Bvh {
+        fn bounded<'a, T>(kind: u32, shapes: &'a [T], bounds: impl Fn(&T) -> Aabb + 'a) -> impl Iterator<Item = (u32, Aabb)> + 'a {
+            shapes.iter()
+                .enumerate()
+                .map(move |(i, shape)| (bvh::encode_primitive(kind, i as u32), bounds(shape)))
+        }
+
+        let csg_bounds = shapes.csg_bounds.iter()
+            .enumerate()
+            .map(|(i, bounds)| (bvh::encode_primitive(bvh::PRIM_CSG, i as u32), *bounds));
+
+        let triangle_bounds = triangles.iter()
+            .enumerate()
+            .map(|(i, triangle)| {
+                let points = [
+                    vertices[triangle.indices[0] as usize].position,
+                    vertices[triangle.indices[1] as usize].position,
+                    vertices[triangle.indices[2] as usize].position,
+                ];
+                (bvh::encode_primitive(bvh::PRIM_TRIANGLE, i as u32), Aabb::from_points(&points))
+            });
+
+        let primitives: Vec<(u32, Aabb)> = bounded(bvh::PRIM_SPHERE, shapes.spheres, |sphere| sphere.swept_bounds(shutter))
+            .chain(bounded(bvh::PRIM_RECTANGLE, shapes.rectangles, GpuRectangle::bounds))
+            .chain(bounded(bvh::PRIM_DISK, shapes.disks, GpuDisk::bounds))
+            .chain(bounded(bvh::PRIM_BOX, shapes.boxes, |box_primitive| box_primitive.swept_bounds(shutter)))
+            .chain(bounded(bvh::PRIM_CYLINDER, shapes.cylinders, GpuCylinder::bounds))
+            .chain(bounded(bvh::PRIM_CONE, shapes.cones, GpuCone::bounds))
+            .chain(bounded(bvh::PRIM_CAPSULE, shapes.capsules, GpuCapsule::bounds))
+            .chain(bounded(bvh::PRIM_TORUS, shapes.tori, GpuTorus::bounds))
+            .chain(csg_bounds)
+            .chain(bounded(bvh::PRIM_SDF, shapes.sdfs, GpuSdf::bounds))
+            .chain(triangle_bounds)
+            .collect();
+
+        Bvh::build(&primitives)
+    }
+}
@@ -5,14 +5,21 @@ use wgpu::*;
 
 use crate::timing::Timer;
 use crate::quad::{Quad, QuadBuilder};
-use crate::raytrace::RayTracer;
+use crate::raytrace::{Backend, RayTracer};
 use crate::application::ApplicationState;
+use crate::display::{Display, RenderResolution};
+use crate::denoise::Denoiser;
+use crate::preview::Preview;
+use crate::reproject::View;
+use crate::checkpoint::{Checkpoint, CheckpointOptions};
 
 pub enum Message {
     /// Application should exit
     Quit,
-    /// Accumulator should be reset (when camera is moved). Event is consumed.
+    /// Accumulator should be reset (when the lighting changes). Event is consumed.
     RestartRender,
+    /// Accumulation buffer should be saved to disk. Event is consumed.
+    Screenshot,
     /// Event should not be passed forwrd
     ConsumeEvent,
     /// No action to be taken
@@ -22,7 +29,7 @@ pub enum Message {
 // I plan on using a similar API for various components. For example, a UI, a console, the camera, and so on
 pub trait Runnable {
     /// Called *once* as soon as program main loop begins
-    fn init(&mut self, sdl2: &SDL2);
+    fn init(&mut self, sdl2: &SDL2, raytracer: &mut RayTracer);
     /// Called for *every* event in a frame.
     fn update(&mut self, sdl2: &SDL2, raytracer: &mut RayTracer, event: &Event) -> Message;
     /// Called once per frame
@@ -88,11 +95,32 @@ pub struct System {
     quad_render_pipeline: RenderPipeline,
 
     raytracer: RayTracer,
+    denoiser: Denoiser,
+    display: Display,
+    preview: Preview,
+    /// Full quality render size, relative to the window
+    resolution: RenderResolution,
+
+    checkpoint_options: Option<CheckpointOptions>,
+    scene_hash: u64,
+    /// Loaded at startup, continued once the main loop begins
+    resume_checkpoint: Option<Checkpoint>,
 }
 
 impl System {
     // TODO: Need some way to use RayTracer and render it properly without & vs &mut issues in `run`
-    pub async fn new(width: u32, height: u32) -> Self {
+    pub async fn new(width: u32, height: u32, resolution: RenderResolution, mut scene: crate::scene::Scene, backend: Backend, checkpoint_options: Option<CheckpointOptions>, denoise: bool) -> Self {
+        let (render_width, render_height) = resolution.render_size(width, height);
+
+        let scene_hash = scene.content_hash();
+        let resume_checkpoint = checkpoint_options.as_ref()
+            .and_then(|options| Checkpoint::load_matching(options.path_for(render_width, render_height), scene_hash, render_width, render_height));
+
+        // Start the camera where the checkpoint left off
+        if let Some(checkpoint) = &resume_checkpoint {
+            scene.camera = checkpoint.camera();
+        }
+
         let sdl2 = Self::init_sdl2(width, height);
         let wgpu = Self::init_wgpu(&sdl2.window).await;
         let timer = Timer::from_sdl2_context(&sdl2.sdl2_context);
@@ -102,9 +130,16 @@ impl System {
         let quad_bind_group_layout = Quad::bind_group_layout(&wgpu.device);
         let quad_render_pipeline = Quad::create_render_pipeline(&wgpu.device, &quad_bind_group_layout, wgpu.sc_desc.format, None);
 
-        let raytracer = RayTracer::new(&wgpu.device, width, height, 100);
+        let mut raytracer = RayTracer::new(&wgpu.device, &wgpu.queue, &quad_bind_group_layout, render_width, render_height, &scene);
+        raytracer.backend = backend;
+        raytracer.fit_to_window(&wgpu.device, width, height);
+        let mut denoiser = Denoiser::new(&wgpu.device, &quad_bind_group_layout, &raytracer.accumulation_targets(), render_width, render_height);
+        denoiser.enabled = denoise;
+        denoiser.fit_to_window(&wgpu.device, width, height);
+        let display = Display::new(&wgpu.device, &quad_bind_group_layout, wgpu.sc_desc.format);
+        let preview = Preview::new(scene.render.preview, scene.render.max_ray_bounces);
         
-        let state = ApplicationState::new();
+        let state = ApplicationState::new(&scene);
 
         Self {
             sdl2,
@@ -116,6 +151,14 @@ impl System {
             quad_render_pipeline,
 
             raytracer,
+            denoiser,
+            display,
+            preview,
+            resolution,
+
+            checkpoint_options,
+            scene_hash,
+            resume_checkpoint,
         }
     }
 
@@ -212,13 +255,63 @@ impl System {
     }
 
     fn resize(&mut self, width: u32, height: u32) {
+        let previous_render_size = self.render_size();
+
         self.wgpu.sc_desc.width = width as u32;
         self.wgpu.sc_desc.height = height as u32;
 
         self.wgpu.swap_chain = self.wgpu.device.create_swap_chain(&self.wgpu.render_surface, &self.wgpu.sc_desc);
 
-        // This will trigger the sample count reset
-        self.raytracer.resize(&self.wgpu.device, width, height)
+        // A fixed render resolution carries on, only its letterboxing changes
+        let (render_width, render_height) = self.render_size();
+        if (render_width, render_height) == previous_render_size {
+            self.fit_to_window();
+            return;
+        }
+
+        // The accumulation can't survive the new size, so keep it on disk
+        self.save_checkpoint();
+
+        self.update_render_quality();
+
+        // Pick up where this size left off, e.g. when leaving fullscreen again (not while previewing)
+        let checkpoint = self.checkpoint_options.as_ref()
+            .filter(|_| !self.preview.is_active())
+            .and_then(|options| Checkpoint::load_matching(options.path_for(render_width, render_height), self.scene_hash, render_width, render_height))
+            .filter(|checkpoint| checkpoint.has_view(self.raytracer.uniforms()));
+        if let Some(checkpoint) = checkpoint {
+            self.raytracer.resume(&self.wgpu.device, &self.wgpu.queue, checkpoint.uniforms, &checkpoint.accumulation);
+            println!("Resumed from checkpoint at sample {}", self.raytracer.sample_count());
+        }
+    }
+
+    /// Full quality render size for the current window
+    fn render_size(&self) -> (u32, u32) {
+        self.resolution.render_size(self.wgpu.sc_desc.width, self.wgpu.sc_desc.height)
+    }
+
+    /// Matches the render resolution and bounce count to the window and the preview
+    fn update_render_quality(&mut self) {
+        let (full_width, full_height) = self.render_size();
+        let (width, height) = self.preview.render_size(full_width, full_height);
+        let max_ray_bounces = self.preview.max_ray_bounces();
+
+        // The full quality accumulation is set aside while previewing and reprojected afterwards
+        if self.preview.is_active() {
+            self.raytracer.begin_preview(&self.wgpu.device, &self.quad_bind_group_layout, width, height, max_ray_bounces);
+        } else {
+            self.raytracer.end_preview(&self.wgpu.device, &self.quad_bind_group_layout, width, height, max_ray_bounces);
+        }
+        self.denoiser.resize(&self.wgpu.device, &self.quad_bind_group_layout, &self.raytracer.accumulation_targets(), width, height);
+        self.fit_to_window();
+    }
+
+    /// Letterboxes the render to the window's aspect ratio
+    fn fit_to_window(&mut self) {
+        let (width, height) = (self.wgpu.sc_desc.width, self.wgpu.sc_desc.height);
+
+        self.raytracer.fit_to_window(&self.wgpu.device, width, height);
+        self.denoiser.fit_to_window(&self.wgpu.device, width, height);
     }
 
     // TODO: A lot of this can probably be simplified
@@ -228,32 +321,101 @@ impl System {
         // TODO: Finish implementing timer
         self.timer.start();
         
-        self.state.init(&self.sdl2);
+        self.state.init(&self.sdl2, &mut self.raytracer);
+
+        if let Some(checkpoint) = self.resume_checkpoint.take() {
+            self.raytracer.resume(&self.wgpu.device, &self.wgpu.queue, checkpoint.uniforms, &checkpoint.accumulation);
+            println!("Resumed from checkpoint at sample {}", self.raytracer.sample_count());
+        }
+        let mut last_autosave = std::time::Instant::now();
 
         let mut text_renderer = crate::text::TextRenderer::new("./res/font.ttf", &self.wgpu.device, TextureFormat::Bgra8Unorm);
         
+        // Display settings changed while paused
+        let mut redraw_display = false;
+
+        // GPU time per frame, for comparing backends. Averaged over `TIMED_FRAMES` frames so
+        // only the last of them waits for the GPU.
+        const TIMED_FRAMES: u32 = 16;
+        let mut frame_time_ms = 0.0;
+        let mut timed_frames = 0;
+        let mut timing_start = std::time::Instant::now();
+
         // FIXME: This probably shouldn't be here
         // let mut FPS = 60;
         'run: loop {
             // TODO: Calculate FPS by counting the number of frames rendered in a given second
 
+            let mut render_samples = false;
             if !self.raytracer.pause_rendering {
-                if self.raytracer.sample_count() == self.raytracer.target_samples {
+                // Resumed checkpoints may already be past the target
+                if self.raytracer.sample_count() >= self.raytracer.target_samples {
                     println!("Target sample count reached.");
                     self.raytracer.pause_rendering = true;
-                } else {   
-                    let frame_view = &self.wgpu.swap_chain.get_next_texture().unwrap().view;
+                } else {
+                    render_samples = true;
+                }
+            }
+            if !render_samples {
+                // Time spent paused isn't frame time
+                timed_frames = 0;
+                timing_start = std::time::Instant::now();
+            }
 
-                    // Render directly to the screen
-                    self.raytracer.render_to_frame(&self.wgpu.device, &self.wgpu.queue, frame_view);
+            if render_samples || redraw_display {
+                let frame_view = &self.wgpu.swap_chain.get_next_texture().unwrap().view;
 
-                    let (width, height) = self.sdl2.window.size();
-                    text_renderer.render_text(&mut self.wgpu, frame_view, width, height, 
-                        &format!("Sample {}/{}\n", self.raytracer.sample_count(), self.raytracer.target_samples)
-                    )
+                if render_samples {
+                    self.raytracer.render_frame(&self.wgpu.device, &self.wgpu.queue);
+
+                    timed_frames += 1;
+                    if timed_frames == TIMED_FRAMES {
+                        // Wait for the queued frames so their time can be measured
+                        self.wgpu.device.poll(Maintain::Wait);
+                        frame_time_ms = timing_start.elapsed().as_secs_f32() * 1000.0 / TIMED_FRAMES as f32;
+                        timed_frames = 0;
+                        timing_start = std::time::Instant::now();
+                    }
                 }
+
+                // Tonemap the (filtered) accumulation buffer onto the screen
+                let quad = if self.denoiser.enabled {
+                    self.denoiser.render(&self.wgpu.device, &self.wgpu.queue);
+                    self.denoiser.quad()
+                } else {
+                    self.raytracer.quad()
+                };
+                let uniform_samples = self.raytracer.frame_count() * self.raytracer.samples_per_frame();
+                self.display.render(&self.wgpu.device, &self.wgpu.queue, frame_view, quad, uniform_samples);
+                redraw_display = false;
+
+                let (width, height) = self.sdl2.window.size();
+                text_renderer.render_text(&mut self.wgpu, frame_view, width, height, 
+                    &format!("Sample {}/{}{}{}\nBackend: {:?} ({:.1} ms/frame)\nTonemapper: {:?}, Exposure: {:+.1}\nView: {:?}\nDenoiser: {}\nReprojection: {}\n",
+                        self.raytracer.sample_count(), self.raytracer.target_samples,
+                        if self.raytracer.is_adaptive() { " (adaptive)" } else { "" },
+                        if self.preview.is_active() {
+                            let dimensions = self.raytracer.uniforms().dimensions;
+                            format!(" (preview at {}x{})", dimensions.x, dimensions.y)
+                        } else {
+                            String::new()
+                        },
+                        self.raytracer.backend, frame_time_ms,
+                        self.display.tonemapper, self.display.exposure,
+                        self.display.view,
+                        if self.denoiser.enabled {
+                            format!("{} passes, luminance sigma {}", self.denoiser.settings.iterations, self.denoiser.settings.luminance_sigma)
+                        } else {
+                            "off".to_string()
+                        },
+                        if self.raytracer.temporal_reprojection { "on" } else { "off" },
+                    )
+                )
             }
 
+            // Camera pose before input is handled, to notice it moving
+            let view_before = View::of(self.raytracer.uniforms());
+
             for event in event_pump.poll_iter() {
                 match self.state.update(&self.sdl2, &mut self.raytracer, &event) {
                     Message::Quit => {
@@ -269,6 +431,13 @@ impl System {
                         self.raytracer.reset_samples();
                         continue;
                     }
+                    Message::Screenshot => {
+                        match self.save_screenshot() {
+                            Ok(path) => println!("Saved screenshot {:?}", path),
+                            Err(e) => println!("Screenshot failed: {}", e),
+                        }
+                        continue;
+                    }
                     Message::Nothing => {
                         // No message was returned, nothing to do
                     }
@@ -288,12 +457,76 @@ impl System {
                     } 
 
                     Event::KeyDown { keycode: Some(Keycode::Space), .. } => {
-                        if self.raytracer.sample_count() != self.raytracer.target_samples {
+                        if self.raytracer.sample_count() < self.raytracer.target_samples {
                             self.raytracer.pause_rendering = !self.raytracer.pause_rendering;
                             println!("{} render", if self.raytracer.pause_rendering {"Paused"} else {"Resuming"});
                         }
                     }
 
+                    Event::KeyDown { keycode: Some(Keycode::T), .. } => {
+                        self.display.tonemapper = self.display.tonemapper.next();
+                        println!("Tonemapper: {:?}", self.display.tonemapper);
+                        redraw_display = true;
+                    }
+
+                    Event::KeyDown { keycode: Some(Keycode::H), .. } => {
+                        self.display.view = self.display.view.next();
+                        println!("View: {:?}", self.display.view);
+                        redraw_display = true;
+                    }
+
+                    Event::KeyDown { keycode: Some(Keycode::I), .. } => {
+                        let enabled = !self.preview.is_enabled();
+                        println!("Preview while moving {}", if enabled {"on"} else {"off"});
+                        if self.preview.set_enabled(enabled) {
+                            self.raytracer.pause_rendering = false;
+                            self.update_render_quality();
+                        }
+                    }
+
+                    Event::KeyDown { keycode: Some(Keycode::N), .. } => {
+                        self.denoiser.enabled = !self.denoiser.enabled;
+                        println!("Denoiser {}", if self.denoiser.enabled {"on"} else {"off"});
+                        redraw_display = true;
+                    }
+
+                    Event::KeyDown { keycode: Some(Keycode::Comma), .. } => {
+                        self.denoiser.adjust_iterations(-1);
+                        redraw_display = true;
+                    }
+
+                    Event::KeyDown { keycode: Some(Keycode::Period), .. } => {
+                        self.denoiser.adjust_iterations(1);
+                        redraw_display = true;
+                    }
+
+                    Event::KeyDown { keycode: Some(Keycode::Semicolon), .. } => {
+                        self.denoiser.scale_luminance_sigma(0.5);
+                        redraw_display = true;
+                    }
+
+                    Event::KeyDown { keycode: Some(Keycode::Quote), .. } => {
+                        self.denoiser.scale_luminance_sigma(2.0);
+                        redraw_display = true;
+                    }
+
+                    // All backends accumulate identically, so the render carries on
+                    Event::KeyDown { keycode: Some(Keycode::B), .. } => {
+                        self.raytracer.backend = self.raytracer.backend.next();
+                        println!("Backend: {:?}", self.raytracer.backend);
+                        redraw_display = true;
+                    }
+
+                    Event::KeyDown { keycode: Some(Keycode::PageUp), .. } => {
+                        self.display.adjust_exposure(0.5);
+                        redraw_display = true;
+                    }
+
+                    Event::KeyDown { keycode: Some(Keycode::PageDown), .. } => {
+                        self.display.adjust_exposure(-0.5);
+                        redraw_display = true;
+                    }
+
                     Event::KeyDown { keycode: Some(Keycode::F11), .. } => {                       
                         println!("Toggle fullscreen");
                         
@@ -309,15 +542,83 @@ impl System {
                 }
             }
 
+            let autosave_due = self.checkpoint_options.as_ref()
+                .and_then(|options| options.autosave)
+                .map_or(false, |interval| last_autosave.elapsed() >= interval);
+            if autosave_due {
+                self.save_checkpoint();
+                last_autosave = std::time::Instant::now();
+            }
+
             let keys = event_pump.keyboard_state();
             
             self.state.fixed_update(&self.sdl2, &keys, &mut self.raytracer);
+
+            // Switch to the preview when the camera starts moving, and back once it has been still for a while
+            let now = std::time::Instant::now();
+            let camera_moved = View::of(self.raytracer.uniforms()) != view_before;
+            if (camera_moved && self.preview.camera_moved(now)) || self.preview.update(now) {
+                self.raytracer.pause_rendering = false;
+                self.update_render_quality();
+            }
             
             let delta_time = self.timer.tick();
             // FPS = if delta_time == 0 {60} else {1000 / delta_time};
             Timer::await_fps(60, delta_time);            
         }
         println!("Quitting...");
+        self.save_checkpoint();
+    }
+
+    /// Writes the accumulation to the checkpoint file, if checkpoints are enabled and there is anything to keep
+    fn save_checkpoint(&self) {
+        let options = match &self.checkpoint_options {
+            Some(options) => options,
+            None => return,
+        };
+
+        // Nothing worth keeping at preview quality
+        if self.raytracer.frame_count() == 0 || self.preview.is_active() {
+            return;
+        }
+
+        let uniforms = self.raytracer.uniforms();
+        let path = options.path_for(uniforms.dimensions.x as u32, uniforms.dimensions.y as u32);
+
+        let result = futures::executor::block_on(self.raytracer.read_accumulation_sums(&self.wgpu.device, &self.wgpu.queue))
+            .and_then(|accumulation| {
+                Checkpoint {
+                    scene_hash: self.scene_hash,
+                    uniforms: *uniforms,
+                    accumulation,
+                }.save(&path)
+            });
+
+        match result {
+            Ok(()) => println!("Saved checkpoint at sample {} to {:?}", self.raytracer.sample_count(), path),
+            Err(e) => println!("{}", e),
+        }
+    }
+
+    /// Writes the accumulated image as a tonemapped PNG (as displayed) and a linear EXR.
+    /// Returns the path without an extension.
+    fn save_screenshot(&self) -> Result<std::path::PathBuf, String> {
+        let image = futures::executor::block_on(self.raytracer.read_accumulation(&self.wgpu.device, &self.wgpu.queue))?;
+        let metadata = self.raytracer.uniforms().image_metadata(self.raytracer.frame_count());
+
+        let directory = std::path::Path::new("screenshots");
+        std::fs::create_dir_all(directory).map_err(|e| e.to_string())?;
+
+        let timestamp = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_err(|e| e.to_string())?
+            .as_millis();
+        let path = directory.join(format!("screenshot_{}", timestamp));
+
+        image.save(path.with_extension("png"), self.display.tonemapper, self.display.exposure, &metadata)?;
+        image.save(path.with_extension("exr"), self.display.tonemapper, self.display.exposure, &metadata)?;
+
+        Ok(path)
     }
 
     pub fn create_texture_from_path<P: AsRef<std::path::Path>>(&self, path: P) -> crate::texture::Texture {
@@ -1,5 +1,120 @@
 use image::GenericImageView;
 
+/// Linear RGBA float image (Radiance `.hdr` or OpenEXR `.exr`), rows top to bottom
+pub struct HdrImage {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<[f32; 4]>,
+}
+
+impl HdrImage {
+    pub fn from_path<P: AsRef<std::path::Path>>(path: P) -> Result<Self, String> {
+        let path = path.as_ref();
+        let extension = path.extension()
+            .and_then(|extension| extension.to_str())
+            .map(|extension| extension.to_lowercase());
+
+        match extension.as_deref() {
+            Some("hdr") => Self::from_hdr(path),
+            Some("exr") => Self::from_exr(path),
+            _ => Err(format!("Unsupported HDR image format: {:?}", path)),
+        }
+    }
+
+    /// Writes linear radiance to `.exr`, or tonemapped sRGB to any LDR format `image` supports.
+    ///
+    /// `metadata` is stored as EXR header attributes or PNG text chunks. Other formats drop it.
+    pub fn save<P: AsRef<std::path::Path>>(&self, path: P, tonemapper: crate::display::Tonemapper, exposure: f32, metadata: &[(String, String)]) -> Result<(), String> {
+        let path = path.as_ref();
+        let extension = path.extension()
+            .and_then(|extension| extension.to_str())
+            .map(|extension| extension.to_lowercase());
+
+        let result = match extension.as_deref() {
+            Some("exr") => self.save_exr(path, metadata),
+            Some("png") => self.save_png(path, tonemapper, exposure, metadata),
+            _ => self.to_display_image(tonemapper, exposure).save(path).map_err(|e| e.to_string()),
+        };
+
+        result.map_err(|e| format!("Failed to write {:?}: {}", path, e))
+    }
+
+    fn to_display_image(&self, tonemapper: crate::display::Tonemapper, exposure: f32) -> image::RgbImage {
+        image::RgbImage::from_fn(self.width, self.height, |x, y| {
+            let [r, g, b, _] = self.pixels[(y * self.width + x) as usize];
+            image::Rgb(crate::display::display_color([r, g, b], tonemapper, exposure))
+        })
+    }
+
+    fn save_exr(&self, path: &std::path::Path, metadata: &[(String, String)]) -> Result<(), String> {
+        use exr::prelude::*;
+
+        let channels = SpecificChannels::rgba(|Vec2(x, y)| {
+            let [r, g, b, a] = self.pixels[y * self.width as usize + x];
+            (r, g, b, a)
+        });
+
+        let mut image = Image::from_channels((self.width as usize, self.height as usize), channels);
+        image.layer_data.attributes.software_name = Some(Text::from(concat!(env!("CARGO_PKG_NAME"), " ", env!("CARGO_PKG_VERSION"))));
+        for (key, value) in metadata {
+            // EXR text is limited to latin-1, which metadata values stay within
+            image.layer_data.attributes.other.insert(Text::from(key.as_str()), AttributeValue::Text(Text::from(value.as_str())));
+        }
+
+        image.write().to_file(path).map_err(|e| e.to_string())
+    }
+
+    /// The `image` crate cannot write text chunks, so PNGs are encoded directly
+    fn save_png(&self, path: &std::path::Path, tonemapper: crate::display::Tonemapper, exposure: f32, metadata: &[(String, String)]) -> Result<(), String> {
+        let file = std::fs::File::create(path).map_err(|e| e.to_string())?;
+
+        let mut encoder = png::Encoder::new(std::io::BufWriter::new(file), self.width, self.height);
+        encoder.set_color(png::ColorType::Rgb);
+        encoder.set_depth(png::BitDepth::Eight);
+        for (key, value) in metadata {
+            encoder.add_text_chunk(key.clone(), value.clone()).map_err(|e| e.to_string())?;
+        }
+
+        let mut writer = encoder.write_header().map_err(|e| e.to_string())?;
+        writer.write_image_data(self.to_display_image(tonemapper, exposure).as_raw()).map_err(|e| e.to_string())
+    }
+
+    fn from_hdr(path: &std::path::Path) -> Result<Self, String> {
+        let file = std::fs::File::open(path).map_err(|e| e.to_string())?;
+        let decoder = image::codecs::hdr::HdrDecoder::new(std::io::BufReader::new(file)).map_err(|e| e.to_string())?;
+        let metadata = decoder.metadata();
+
+        let pixels = decoder.read_image_hdr()
+            .map_err(|e| e.to_string())?
+            .iter()
+            .map(|pixel| [pixel[0], pixel[1], pixel[2], 1.0])
+            .collect();
+
+        Ok(Self {
+            width: metadata.width,
+            height: metadata.height,
+            pixels,
+        })
+    }
+
+    fn from_exr(path: &std::path::Path) -> Result<Self, String> {
+        let image = exr::prelude::read_first_rgba_layer_from_file(
+            path,
+            |resolution, _| Self {
+                width: resolution.width() as u32,
+                height: resolution.height() as u32,
+                pixels: vec![[0.0; 4]; resolution.width() * resolution.height()],
+            },
+            |image: &mut Self, position, (r, g, b, a): (f32, f32, f32, f32)| {
+                let index = position.y() * image.width as usize + position.x();
+                image.pixels[index] = [r, g, b, a];
+            },
+        ).map_err(|e| e.to_string())?;
+
+        Ok(image.layer_data.channel_data.pixels)
+    }
+}
+
 pub struct Texture {
     pub texture: wgpu::Texture,
     pub view: wgpu::TextureView,
@@ -99,6 +214,64 @@ impl Texture {
         ))
     }
 
+    /// Uploads an `HdrImage` as an `Rgba32Float` texture
+    pub fn from_hdr_image(device: &wgpu::Device, image: &HdrImage, label: Option<&str>) -> (Self, wgpu::CommandBuffer) {
+        let size = wgpu::Extent3d {
+            width: image.width,
+            height: image.height,
+            depth: 1,
+        };
+
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label,
+            size,
+            array_layer_count: 1,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: wgpu::TextureFormat::Rgba32Float,
+            usage: wgpu::TextureUsage::SAMPLED | wgpu::TextureUsage::COPY_DST,
+        });
+
+        let buffer = device.create_buffer_with_data(
+            bytemuck::cast_slice(&image.pixels),
+            wgpu::BufferUsage::COPY_SRC
+        );
+
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("hdr_texture_buffer_copy_encoder"),
+        });
+
+        encoder.copy_buffer_to_texture(
+            wgpu::BufferCopyView {
+                buffer: &buffer,
+                offset: 0,
+                bytes_per_row: 16 * image.width, // RGBA32F = 16 bytes
+                rows_per_image: image.height,
+            },
+            wgpu::TextureCopyView {
+                texture: &texture,
+                mip_level: 0,
+                array_layer: 0,
+                origin: wgpu::Origin3d::ZERO,
+            },
+            size,
+        );
+
+        let view = texture.create_default_view();
+        let sampler = Self::default_sampler(device);
+
+        (
+            Self {
+                texture,
+                view,
+                sampler,
+                image_dimensions: Some((image.width, image.height)),
+            },
+            encoder.finish()
+        )
+    }
+
     pub fn from_wgpu_texture(device: &wgpu::Device, texture: wgpu::Texture) -> Self {
         let view = texture.create_default_view();
         let sampler = Self::default_sampler(device);
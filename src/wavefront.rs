@@ -0,0 +1,369 @@
+use wgpu::*;
+
+use crate::raytrace::AccumulationTargets;
+
+/// Paths traced at once. Larger images are traced in several waves to bound memory use.
+const WAVE_SIZE: u32 = 1 << 20;
+/// Threads per work group. Must match `WAVEFRONT_GROUP_SIZE` in wavefront_queues.hlsli.
+const GROUP_SIZE: u32 = 64;
+
+// Must match wavefront_queues.hlsli
+const RAY_QUEUE: u32 = 0;
+const SHADOW_QUEUE: u32 = 1;
+const MATERIAL_QUEUE_START: u32 = 2;
+/// One shading queue (and kernel) per non-emissive material type
+const MATERIAL_QUEUE_COUNT: u32 = 5;
+const QUEUE_COUNT: u32 = MATERIAL_QUEUE_START + MATERIAL_QUEUE_COUNT;
+
+// Sizes of `PathState` and `ShadowRay` in wavefront.hlsli
+const PATH_STATE_SIZE: BufferAddress = 128;
+const SHADOW_RAY_SIZE: BufferAddress = 48;
+
+/// `Wave` uniforms in wavefront.hlsli
+#[repr(C)]
+#[derive(Copy, Clone)]
+struct Wave {
+    first_pixel: u32,
+    path_count: u32,
+    path_capacity: u32,
+    _padding: u32,
+}
+unsafe impl bytemuck::Pod for Wave {}
+unsafe impl bytemuck::Zeroable for Wave {}
+
+/// Buffers sized for the current image. The bind group keeps them alive.
+struct State {
+    bind_group: BindGroup,
+    /// Slice of the image being traced, rewritten before each wave
+    wave_buffer: Buffer,
+    path_capacity: u32,
+    pixel_count: u32,
+}
+
+/// Wavefront path tracer (see wavefront.hlsli).
+///
+/// Each bounce is split into compute passes (extend, one shading pass per material type, shadow rays)
+/// that work through queues of paths in storage buffers, instead of one thread tracing a whole path.
+/// Queue lengths are only known on the GPU, so the shading and tracing passes are dispatched indirectly.
+pub struct Wavefront {
+    state_bind_group_layout: BindGroupLayout,
+    state: State,
+
+    /// Indirect dispatch arguments (x, y, z groups) per queue, written by the prepare kernels
+    dispatch_args: Buffer,
+    dispatch_bind_group: BindGroup,
+
+    init_pipeline: ComputePipeline,
+    generate_pipeline: ComputePipeline,
+    extend_pipeline: ComputePipeline,
+    /// Indexed by material queue (material type - 1)
+    shade_pipelines: Vec<ComputePipeline>,
+    shadow_pipeline: ComputePipeline,
+    finalize_pipeline: ComputePipeline,
+    prepare_extend_pipeline: ComputePipeline,
+    prepare_shade_pipeline: ComputePipeline,
+}
+
+impl Wavefront {
+    /// `shared_layouts` are the ray tracer's uniform, scene and environment layouts (sets 1 to 3)
+    pub fn new(device: &Device, shared_layouts: [&BindGroupLayout; 3], targets: &AccumulationTargets, width: u32, height: u32) -> Self {
+        let storage_buffer_entry = |binding| BindGroupLayoutEntry {
+            binding,
+            visibility: ShaderStage::COMPUTE,
+            ty: BindingType::StorageBuffer {
+                dynamic: false,
+                readonly: false,
+            },
+        };
+
+        let storage_texture_entry = |binding| BindGroupLayoutEntry {
+            binding,
+            visibility: ShaderStage::COMPUTE,
+            ty: BindingType::StorageTexture {
+                dimension: TextureViewDimension::D2,
+                component_type: TextureComponentType::Uint,
+                format: TextureFormat::Rgba32Float,
+                readonly: false,
+            },
+        };
+
+        // Bindings 0 to 4 match the ray tracer's set 0, which raytrace.hlsli declares
+        let state_bind_group_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            bindings: &[
+                // Storage texture
+                storage_texture_entry(0),
+                // Sample statistics
+                storage_texture_entry(1),
+                // Active pixel count
+                storage_buffer_entry(2),
+                // Albedo and depth features
+                storage_texture_entry(3),
+                // Normal features
+                storage_texture_entry(4),
+                // Path state
+                storage_buffer_entry(5),
+                // Queue lengths
+                storage_buffer_entry(6),
+                // Ray queue
+                storage_buffer_entry(7),
+                // Material queues
+                storage_buffer_entry(8),
+                // Shadow ray queue
+                storage_buffer_entry(9),
+                // Wave
+                BindGroupLayoutEntry {
+                    binding: 10,
+                    visibility: ShaderStage::COMPUTE,
+                    ty: BindingType::UniformBuffer {
+                        dynamic: false,
+                    },
+                },
+            ],
+            label: Some("wavefront_state_bind_group_layout"),
+        });
+
+        let dispatch_bind_group_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            bindings: &[
+                storage_buffer_entry(0),
+            ],
+            label: Some("wavefront_dispatch_bind_group_layout"),
+        });
+
+        let dispatch_args = device.create_buffer(&BufferDescriptor {
+            label: Some("wavefront_dispatch_args"),
+            size: (QUEUE_COUNT * 3 * size_of!(u32) as u32) as BufferAddress,
+            usage: BufferUsage::STORAGE | BufferUsage::INDIRECT,
+        });
+
+        let dispatch_bind_group = device.create_bind_group(&BindGroupDescriptor {
+            layout: &dispatch_bind_group_layout,
+            bindings: &[
+                Binding {
+                    binding: 0,
+                    resource: BindingResource::Buffer {
+                        buffer: &dispatch_args,
+                        range: 0..(QUEUE_COUNT * 3 * size_of!(u32) as u32) as BufferAddress,
+                    },
+                },
+            ],
+            label: Some("wavefront_dispatch_bind_group"),
+        });
+
+        let [uniform_layout, scene_layout, environment_layout] = shared_layouts;
+        let stage_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            bind_group_layouts: &[
+                &state_bind_group_layout,
+                uniform_layout,
+                scene_layout,
+                environment_layout,
+            ],
+        });
+        let prepare_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            bind_group_layouts: &[
+                &state_bind_group_layout,
+                &dispatch_bind_group_layout,
+            ],
+        });
+
+        let create_pipeline = |layout: &PipelineLayout, spirv: &[u8]| {
+            let data = read_spirv(std::io::Cursor::new(spirv)).unwrap();
+            let module = device.create_shader_module(&data);
+
+            device.create_compute_pipeline(&ComputePipelineDescriptor {
+                layout,
+                compute_stage: ProgrammableStageDescriptor {
+                    module: &module,
+                    entry_point: "main",
+                },
+            })
+        };
+
+        let state = Self::create_state(device, &state_bind_group_layout, targets, width, height);
+
+        Self {
+            state_bind_group_layout,
+            state,
+
+            dispatch_args,
+            dispatch_bind_group,
+
+            init_pipeline: create_pipeline(&stage_layout, include_bytes!("../shaders/raytrace_hlsl/wavefront_init.comp.hlsl.spv")),
+            generate_pipeline: create_pipeline(&stage_layout, include_bytes!("../shaders/raytrace_hlsl/wavefront_generate.comp.hlsl.spv")),
+            extend_pipeline: create_pipeline(&stage_layout, include_bytes!("../shaders/raytrace_hlsl/wavefront_extend.comp.hlsl.spv")),
+            // In material type order (MAT_METAL, MAT_LAMBERTIAN, MAT_DIELECTRIC, MAT_CONDUCTOR, MAT_ROUGH_DIELECTRIC)
+            shade_pipelines: vec![
+                create_pipeline(&stage_layout, include_bytes!("../shaders/raytrace_hlsl/wavefront_shade_metal.comp.hlsl.spv")),
+                create_pipeline(&stage_layout, include_bytes!("../shaders/raytrace_hlsl/wavefront_shade_lambertian.comp.hlsl.spv")),
+                create_pipeline(&stage_layout, include_bytes!("../shaders/raytrace_hlsl/wavefront_shade_dielectric.comp.hlsl.spv")),
+                create_pipeline(&stage_layout, include_bytes!("../shaders/raytrace_hlsl/wavefront_shade_conductor.comp.hlsl.spv")),
+                create_pipeline(&stage_layout, include_bytes!("../shaders/raytrace_hlsl/wavefront_shade_rough_dielectric.comp.hlsl.spv")),
+            ],
+            shadow_pipeline: create_pipeline(&stage_layout, include_bytes!("../shaders/raytrace_hlsl/wavefront_shadow.comp.hlsl.spv")),
+            finalize_pipeline: create_pipeline(&stage_layout, include_bytes!("../shaders/raytrace_hlsl/wavefront_finalize.comp.hlsl.spv")),
+            prepare_extend_pipeline: create_pipeline(&prepare_layout, include_bytes!("../shaders/raytrace_hlsl/wavefront_prepare_extend.comp.hlsl.spv")),
+            prepare_shade_pipeline: create_pipeline(&prepare_layout, include_bytes!("../shaders/raytrace_hlsl/wavefront_prepare_shade.comp.hlsl.spv")),
+        }
+    }
+
+    /// Reallocates the path state and queues for a new image
+    pub fn resize(&mut self, device: &Device, targets: &AccumulationTargets, width: u32, height: u32) {
+        self.state = Self::create_state(device, &self.state_bind_group_layout, targets, width, height);
+    }
+
+    fn create_state(device: &Device, layout: &BindGroupLayout, targets: &AccumulationTargets, width: u32, height: u32) -> State {
+        let pixel_count = width * height;
+        let path_capacity = pixel_count.min(WAVE_SIZE);
+        let paths = path_capacity as BufferAddress;
+        let index_size = size_of!(u32) as BufferAddress;
+
+        let create_buffer = |label, size: BufferAddress, usage| {
+            let buffer = device.create_buffer(&BufferDescriptor {
+                label: Some(label),
+                size,
+                usage,
+            });
+            (buffer, size)
+        };
+
+        let path_buffer = create_buffer("wavefront_paths", paths * PATH_STATE_SIZE, BufferUsage::STORAGE);
+        let queue_count_buffer = create_buffer("wavefront_queue_counts", QUEUE_COUNT as BufferAddress * index_size, BufferUsage::STORAGE);
+        let ray_queue_buffer = create_buffer("wavefront_ray_queue", paths * index_size, BufferUsage::STORAGE);
+        let material_queue_buffer = create_buffer("wavefront_material_queues", MATERIAL_QUEUE_COUNT as BufferAddress * paths * index_size, BufferUsage::STORAGE);
+        let shadow_queue_buffer = create_buffer("wavefront_shadow_queue", paths * SHADOW_RAY_SIZE, BufferUsage::STORAGE);
+        let wave_buffer = create_buffer("wavefront_wave", size_of!(Wave) as BufferAddress, BufferUsage::UNIFORM | BufferUsage::COPY_DST);
+
+        fn buffer_binding(binding: u32, (buffer, size): &(Buffer, BufferAddress)) -> Binding<'_> {
+            Binding {
+                binding,
+                resource: BindingResource::Buffer {
+                    buffer,
+                    range: 0..*size,
+                },
+            }
+        }
+
+        let bind_group = device.create_bind_group(&BindGroupDescriptor {
+            layout,
+            bindings: &[
+                Binding {
+                    binding: 0,
+                    resource: BindingResource::TextureView(&targets.image.view),
+                },
+                Binding {
+                    binding: 1,
+                    resource: BindingResource::TextureView(&targets.statistics.view),
+                },
+                Binding {
+                    binding: 2,
+                    resource: BindingResource::Buffer {
+                        buffer: targets.active_pixels,
+                        range: 0..size_of!(u32) as BufferAddress,
+                    },
+                },
+                Binding {
+                    binding: 3,
+                    resource: BindingResource::TextureView(&targets.albedo_depth.view),
+                },
+                Binding {
+                    binding: 4,
+                    resource: BindingResource::TextureView(&targets.normal.view),
+                },
+                buffer_binding(5, &path_buffer),
+                buffer_binding(6, &queue_count_buffer),
+                buffer_binding(7, &ray_queue_buffer),
+                buffer_binding(8, &material_queue_buffer),
+                buffer_binding(9, &shadow_queue_buffer),
+                buffer_binding(10, &wave_buffer),
+            ],
+            label: Some("wavefront_state_bind_group"),
+        });
+
+        State {
+            bind_group,
+            wave_buffer: wave_buffer.0,
+            path_capacity,
+            pixel_count,
+        }
+    }
+
+    /// Records one frame into the storage texture. `shared_bind_groups` are the ray tracer's uniform, scene
+    /// and environment bind groups.
+    ///
+    /// Generates `max_samples_per_pixel` times. Paths drop out once their pixel has taken its share of
+    /// samples (see `pixel_sample_budget` in raytrace.hlsli), so this only needs to exceed
+    /// `samples_per_pixel` when sampling adaptively.
+    pub fn encode_frame(&self, device: &Device, encoder: &mut CommandEncoder, shared_bind_groups: [&BindGroup; 3], max_samples_per_pixel: u32, max_ray_bounces: u32) {
+        let waves: Vec<Wave> = (0..self.state.pixel_count)
+            .step_by(self.state.path_capacity as usize)
+            .map(|first_pixel| Wave {
+                first_pixel,
+                path_count: (self.state.pixel_count - first_pixel).min(self.state.path_capacity),
+                path_capacity: self.state.path_capacity,
+                _padding: 0,
+            })
+            .collect();
+
+        let wave_size = size_of!(Wave) as BufferAddress;
+        let wave_staging_buffer = device.create_buffer_with_data(bytemuck::cast_slice(&waves), BufferUsage::COPY_SRC);
+
+        for (i, wave) in waves.iter().enumerate() {
+            encoder.copy_buffer_to_buffer(&wave_staging_buffer, i as BufferAddress * wave_size, &self.state.wave_buffer, 0, wave_size);
+
+            let path_groups = (wave.path_count + GROUP_SIZE - 1) / GROUP_SIZE;
+            self.dispatch(encoder, &self.init_pipeline, shared_bind_groups, path_groups);
+
+            for _ in 0..max_samples_per_pixel {
+                // Clears the ray and shadow queues left over from the previous sample
+                self.prepare(encoder, &self.prepare_shade_pipeline);
+                self.dispatch(encoder, &self.generate_pipeline, shared_bind_groups, path_groups);
+
+                for _ in 0..max_ray_bounces {
+                    self.prepare(encoder, &self.prepare_extend_pipeline);
+                    self.dispatch_queue(encoder, &self.shadow_pipeline, shared_bind_groups, SHADOW_QUEUE);
+                    self.dispatch_queue(encoder, &self.extend_pipeline, shared_bind_groups, RAY_QUEUE);
+
+                    self.prepare(encoder, &self.prepare_shade_pipeline);
+                    for (queue, pipeline) in self.shade_pipelines.iter().enumerate() {
+                        self.dispatch_queue(encoder, pipeline, shared_bind_groups, MATERIAL_QUEUE_START + queue as u32);
+                    }
+                }
+
+                // Light sampled at the last bounce
+                self.prepare(encoder, &self.prepare_extend_pipeline);
+                self.dispatch_queue(encoder, &self.shadow_pipeline, shared_bind_groups, SHADOW_QUEUE);
+            }
+
+            self.dispatch(encoder, &self.finalize_pipeline, shared_bind_groups, path_groups);
+        }
+    }
+
+    fn dispatch(&self, encoder: &mut CommandEncoder, pipeline: &ComputePipeline, shared_bind_groups: [&BindGroup; 3], groups: u32) {
+        let mut pass = self.begin_stage(encoder, pipeline, shared_bind_groups);
+        pass.dispatch(groups, 1, 1);
+    }
+
+    /// One thread per entry in `queue`
+    fn dispatch_queue(&self, encoder: &mut CommandEncoder, pipeline: &ComputePipeline, shared_bind_groups: [&BindGroup; 3], queue: u32) {
+        let mut pass = self.begin_stage(encoder, pipeline, shared_bind_groups);
+        pass.dispatch_indirect(&self.dispatch_args, (queue * 3 * size_of!(u32) as u32) as BufferAddress);
+    }
+
+    // Each stage gets its own pass so it sees the previous stage's writes
+    fn begin_stage<'a>(&'a self, encoder: &'a mut CommandEncoder, pipeline: &'a ComputePipeline, [uniforms, scene, environment]: [&'a BindGroup; 3]) -> ComputePass<'a> {
+        let mut pass = encoder.begin_compute_pass();
+        pass.set_pipeline(pipeline);
+        pass.set_bind_group(0, &self.state.bind_group, &[]);
+        pass.set_bind_group(1, uniforms, &[]);
+        pass.set_bind_group(2, scene, &[]);
+        pass.set_bind_group(3, environment, &[]);
+        pass
+    }
+
+    fn prepare(&self, encoder: &mut CommandEncoder, pipeline: &ComputePipeline) {
+        let mut pass = encoder.begin_compute_pass();
+        pass.set_pipeline(pipeline);
+        pass.set_bind_group(0, &self.state.bind_group, &[]);
+        pass.set_bind_group(1, &self.dispatch_bind_group, &[]);
+        pass.dispatch(1, 1, 1);
+    }
+}